@@ -0,0 +1,284 @@
+//! Rendering many equations at once, in parallel.
+//!
+//! A [`Batch`] renders a slice of equations, each with its own [`RenderConfig`], on a set of
+//! worker threads. Every worker owns a [`Storage`] and an output buffer, which are reused for all
+//! the equations it renders, and kept by the batch for subsequent calls to [`Batch::render`].
+//!
+//! ### Examples
+//!
+//! ```rust
+//! use pulldown_latex::{batch::Batch, config::DisplayMode, RenderConfig};
+//!
+//! let block = RenderConfig {
+//!     display_mode: DisplayMode::Block,
+//!     ..RenderConfig::default()
+//! };
+//! let items = [
+//!     (r"\frac{1}{2}", RenderConfig::default()),
+//!     (r"\unknown", RenderConfig::default()),
+//!     (r"\sum_{i=0}^n i", block),
+//! ];
+//!
+//! let mut batch = Batch::new();
+//! let results = batch.render(&items);
+//! assert!(results[0].is_ok());
+//! // The error is reported, but the other equations are still rendered.
+//! assert_eq!(results[1].as_ref().unwrap_err().errors.len(), 1);
+//! assert!(results[2].as_ref().unwrap().starts_with(r#"<math display="block">"#));
+//! ```
+
+use std::{
+    fmt::Display,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use crate::{
+    config::{ParserConfig, RenderConfig},
+    mathml::push_mathml,
+    parser::{
+        error::{ErrorMessage, ParserError},
+        storage::Storage,
+        Parser,
+    },
+};
+
+/// Renders slices of equations in parallel.
+///
+/// The batch keeps one [`Storage`] and one output buffer per worker thread, so that rendering
+/// many equations only allocates the strings that are returned.
+#[derive(Debug)]
+pub struct Batch<'c> {
+    config: ParserConfig<'c>,
+    workers: Vec<Worker>,
+}
+
+#[derive(Debug, Default)]
+struct Worker {
+    storage: Storage,
+    buffer: String,
+}
+
+impl<'c> Batch<'c> {
+    /// Create a batch using the default parser configuration, and as many worker threads as
+    /// reported by [`std::thread::available_parallelism`].
+    pub fn new() -> Self {
+        let threads = thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+        Self::with_config(ParserConfig::default(), threads)
+    }
+
+    /// Create a batch using the given parser configuration for every equation, and the given
+    /// number of worker threads.
+    pub fn with_config(config: ParserConfig<'c>, threads: NonZeroUsize) -> Self {
+        Self {
+            config,
+            workers: (0..threads.get()).map(|_| Worker::default()).collect(),
+        }
+    }
+
+    /// Render every equation of `items` with its [`RenderConfig`], returning the results in the
+    /// same order.
+    ///
+    /// An equation containing errors is still rendered, with the errors displayed in place, and
+    /// its result is a [`BatchError`] holding both the output and the errors.
+    pub fn render(&mut self, items: &[(&str, RenderConfig)]) -> Vec<Result<String, BatchError>> {
+        let config = self.config;
+        let threads = self.workers.len().min(items.len());
+        if threads <= 1 {
+            let worker = &mut self.workers[0];
+            return items
+                .iter()
+                .map(|(input, render_config)| worker.render(input, config, *render_config))
+                .collect();
+        }
+
+        let next = AtomicUsize::new(0);
+        let rendered = thread::scope(|scope| {
+            let handles = self.workers[..threads]
+                .iter_mut()
+                .map(|worker| {
+                    let next = &next;
+                    scope.spawn(move || {
+                        let mut rendered = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some((input, render_config)) = items.get(index) else {
+                                break rendered;
+                            };
+                            rendered.push((index, worker.render(input, config, *render_config)));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut results = Vec::with_capacity(items.len());
+        results.resize_with(items.len(), || None);
+        for (index, result) in rendered.into_iter().flatten() {
+            results[index] = Some(result);
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every item is rendered by a worker"))
+            .collect()
+    }
+}
+
+impl Default for Batch<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Worker {
+    fn render(
+        &mut self,
+        input: &str,
+        config: ParserConfig,
+        render_config: RenderConfig,
+    ) -> Result<String, BatchError> {
+        let errors = render_equation(
+            &mut self.buffer,
+            &mut self.storage,
+            input,
+            config,
+            render_config,
+        );
+        let mathml = self.buffer.as_str().to_owned();
+        if errors.is_empty() {
+            Ok(mathml)
+        } else {
+            Err(BatchError { mathml, errors })
+        }
+    }
+}
+
+/// Render an equation into `buffer`, which is cleared first, and return the errors found while
+/// parsing it. The storage is reset afterward.
+pub(crate) fn render_equation(
+    buffer: &mut String,
+    storage: &mut Storage,
+    input: &str,
+    config: ParserConfig,
+    render_config: RenderConfig,
+) -> Vec<ParserError> {
+    let mut errors = Vec::new();
+    let events = Parser::with_config(input, storage, config).map(|event| {
+        // The renderer only needs the message of the error, so the error itself is kept
+        // aside to be returned to the caller.
+        event.map_err(|error| {
+            let message = ErrorMessage(error.to_string());
+            errors.push(error);
+            message
+        })
+    });
+
+    buffer.clear();
+    push_mathml(buffer, events, render_config).expect("writing to a string should not fail");
+    storage.reset();
+    errors
+}
+
+/// The result of rendering an equation which contains errors.
+#[derive(Debug)]
+pub struct BatchError {
+    /// The rendered equation, where errors are displayed in place.
+    pub mathml: String,
+    /// The errors found while parsing the equation, in order of appearance.
+    pub errors: Vec<ParserError>,
+}
+
+impl Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "equation contains {} error(s)", self.errors.len())?;
+        if let Some(error) = self.errors.first() {
+            write!(f, ", the first being {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.errors
+            .first()
+            .map(|error| error as &(dyn std::error::Error + 'static))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DisplayMode, MathStyle};
+
+    fn render_one(input: &str, config: RenderConfig) -> String {
+        let storage = Storage::new();
+        let mut output = String::new();
+        push_mathml(&mut output, Parser::new(input, &storage), config).unwrap();
+        output
+    }
+
+    #[test]
+    fn matches_sequential_rendering() {
+        let inputs = [
+            r"x^2 + y^2 = z^2",
+            r"\frac{1}{2} \sqrt[3]{x}",
+            r"\newcommand{\R}{\mathbb{R}} f: \R \to \R",
+            r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}",
+            r"\unknown + 1",
+            r"\def\f#1{f(#1)} \f{x} + \f{y}",
+        ];
+        let configs = [
+            RenderConfig::default(),
+            RenderConfig {
+                display_mode: DisplayMode::Block,
+                math_style: MathStyle::ISO,
+                ..RenderConfig::default()
+            },
+        ];
+        let items = (0..200)
+            .map(|i| (inputs[i % inputs.len()], configs[i % configs.len()]))
+            .collect::<Vec<_>>();
+
+        let mut batch = Batch::with_config(ParserConfig::default(), NonZeroUsize::new(4).unwrap());
+        for _ in 0..2 {
+            let results = batch.render(&items);
+            assert_eq!(results.len(), items.len());
+            for ((input, config), result) in items.iter().zip(results) {
+                let expected = render_one(input, *config);
+                match result {
+                    Ok(mathml) => assert_eq!(mathml, expected),
+                    Err(error) => {
+                        assert_eq!(error.mathml, expected);
+                        assert_eq!(input, &r"\unknown + 1");
+                        assert_eq!(error.errors.len(), 1);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn single_thread_and_empty() {
+        let mut batch = Batch::with_config(ParserConfig::default(), NonZeroUsize::MIN);
+        assert!(batch.render(&[]).is_empty());
+        let results = batch.render(&[
+            ("a", RenderConfig::default()),
+            ("}", RenderConfig::default()),
+        ]);
+        assert_eq!(
+            results[0].as_deref().unwrap(),
+            render_one("a", RenderConfig::default())
+        );
+        assert!(results[1].is_err());
+    }
+}
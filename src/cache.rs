@@ -0,0 +1,500 @@
+//! Caching the `MathML` of equations which are rendered many times.
+//!
+//! Documents tend to repeat the same small equations, such as `x`, `\alpha` or `O(n \log n)`. A
+//! [`Cache`] keeps the `MathML` of the equations it renders, keyed on their input, the
+//! [`RenderConfig`] and the parser options in effect, including the definitions of the
+//! [`MacroTable`](crate::parser::MacroTable) given to the parser. When the cache is full, the
+//! least recently used equation is evicted.
+//!
+//! A cache can be shared between threads, and records how often it found the equations it was
+//! asked to render in its [`CacheStats`].
+//!
+//! ### Examples
+//!
+//! ```rust
+//! use pulldown_latex::{cache::Cache, config::ParserConfig, RenderConfig};
+//!
+//! let cache = Cache::new(1024);
+//! for input in [r"\alpha", r"x^2", r"\alpha"] {
+//!     let mathml = cache
+//!         .render(input, ParserConfig::default(), RenderConfig::default())
+//!         .unwrap();
+//!     assert!(mathml.starts_with("<math"));
+//! }
+//!
+//! let stats = cache.stats();
+//! assert_eq!((stats.hits, stats.misses), (1, 2));
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    batch::{render_equation, BatchError},
+    config::{
+        DisplayMode, Limits, MathStyle, ParserConfig, Profile, RenderConfig, Strictness,
+        UnknownCommandPolicy,
+    },
+    parser::{defines_macros, storage::Storage},
+};
+
+/// A bounded cache of rendered equations, evicting the least recently used ones first.
+///
+/// See the [module documentation](self) for more information.
+#[derive(Debug)]
+pub struct Cache {
+    capacity: usize,
+    state: Mutex<State>,
+    /// Storages which are not in use by any thread.
+    storages: Mutex<Vec<Storage>>,
+}
+
+/// How often a [`Cache`] found the equations it was asked to render.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// The number of equations found in the cache.
+    pub hits: u64,
+    /// The number of equations which had to be rendered, and were then stored in the cache unless
+    /// they contained errors.
+    pub misses: u64,
+    /// The number of equations which were rendered without looking them up in the cache.
+    ///
+    /// See [`Cache::render`] for the equations which are never cached.
+    pub skipped: u64,
+    /// The number of equations removed from the cache to make room for new ones.
+    pub evictions: u64,
+}
+
+impl Cache {
+    /// Create a cache holding at most `capacity` equations.
+    ///
+    /// A cache with a capacity of zero stores nothing, but still records its statistics.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(State::default()),
+            storages: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Render the equation, or return its `MathML` if it is already in the cache.
+    ///
+    /// An equation containing errors is still rendered, with the errors displayed in place, and
+    /// its result is a [`BatchError`] holding both the output and the errors. Such equations are
+    /// not stored in the cache.
+    ///
+    /// The cache is not used for an equation which defines macros, such as with `\def` or
+    /// `\newcommand`, since those definitions are usually meant for the equations that follow it.
+    /// It is not used either when the parser is given [`primitives`] or an
+    /// [`UnknownCommandPolicy::Callback`], since the cache cannot tell them apart.
+    ///
+    /// [`primitives`]: ParserConfig::primitives
+    pub fn render(
+        &self,
+        input: &str,
+        parser_config: ParserConfig,
+        render_config: RenderConfig,
+    ) -> Result<Arc<str>, BatchError> {
+        let Some(key) = Key::new(input, &parser_config, &render_config) else {
+            self.lock_state().stats.skipped += 1;
+            return self.render_uncached(input, parser_config, render_config);
+        };
+        if let Some(mathml) = self.lock_state().get(&key) {
+            return Ok(mathml);
+        }
+
+        let mathml = self.render_uncached(input, parser_config, render_config)?;
+        self.lock_state()
+            .insert(key, Arc::clone(&mathml), self.capacity);
+        Ok(mathml)
+    }
+
+    /// The statistics of the cache since it was created.
+    pub fn stats(&self) -> CacheStats {
+        self.lock_state().stats
+    }
+
+    /// The maximum number of equations held by the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of equations currently held by the cache.
+    pub fn len(&self) -> usize {
+        self.lock_state().entries.len()
+    }
+
+    /// Whether the cache holds no equations.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove every equation from the cache, keeping its statistics.
+    pub fn clear(&self) {
+        let mut state = self.lock_state();
+        let stats = state.stats;
+        *state = State {
+            stats,
+            ..State::default()
+        };
+    }
+
+    fn render_uncached(
+        &self,
+        input: &str,
+        parser_config: ParserConfig,
+        render_config: RenderConfig,
+    ) -> Result<Arc<str>, BatchError> {
+        // Parsing happens outside of the lock on the state, so that threads missing the cache do
+        // not wait on each other.
+        let mut storage = lock(&self.storages).pop().unwrap_or_default();
+        let mut mathml = String::new();
+        let errors = render_equation(
+            &mut mathml,
+            &mut storage,
+            input,
+            parser_config,
+            render_config,
+        );
+        lock(&self.storages).push(storage);
+
+        if errors.is_empty() {
+            Ok(Arc::from(mathml))
+        } else {
+            Err(BatchError { mathml, errors })
+        }
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+/// Lock the mutex, ignoring poisoning since the state of the cache is always consistent between
+/// two operations.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Everything affecting the `MathML` of an equation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    input: Box<str>,
+    display_mode: DisplayMode,
+    annotation: Option<Box<str>>,
+    error_color: (u8, u8, u8),
+    xml: bool,
+    xml_prefix: bool,
+    math_style: MathStyle,
+    safe: bool,
+    profile: Profile,
+    self_contained: bool,
+    /// The version of the macro table given to the parser, if any.
+    macros: Option<u64>,
+    limits: Limits,
+    error_recovery: bool,
+    unknown_commands: u8,
+    strict: Strictness,
+}
+
+impl Key {
+    /// The key of the equation, or `None` if it must not be cached.
+    fn new(
+        input: &str,
+        parser_config: &ParserConfig,
+        render_config: &RenderConfig,
+    ) -> Option<Self> {
+        let unknown_commands = match parser_config.unknown_commands {
+            UnknownCommandPolicy::Error => 0,
+            UnknownCommandPolicy::Function => 1,
+            UnknownCommandPolicy::RawText => 2,
+            UnknownCommandPolicy::Callback(_) => return None,
+        };
+        if parser_config.primitives.is_some() || defines_macros(input) {
+            return None;
+        }
+        Some(Self {
+            input: input.into(),
+            display_mode: render_config.display_mode,
+            annotation: render_config.annotation.map(Into::into),
+            error_color: render_config.error_color,
+            xml: render_config.xml,
+            xml_prefix: render_config.xml_prefix,
+            math_style: render_config.math_style,
+            safe: render_config.safe,
+            profile: render_config.profile,
+            self_contained: render_config.self_contained,
+            macros: parser_config.macros.map(|table| table.version()),
+            limits: parser_config.limits,
+            error_recovery: parser_config.error_recovery,
+            unknown_commands,
+            strict: parser_config.strict,
+        })
+    }
+}
+
+/// The index of no node.
+const NIL: usize = usize::MAX;
+
+/// The entries of the cache, in a doubly linked list going from the most recently used to the
+/// least recently used.
+#[derive(Debug)]
+struct State {
+    entries: HashMap<Key, usize>,
+    nodes: Vec<Node>,
+    head: usize,
+    tail: usize,
+    stats: CacheStats,
+}
+
+#[derive(Debug)]
+struct Node {
+    key: Key,
+    mathml: Arc<str>,
+    prev: usize,
+    next: usize,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            nodes: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            stats: CacheStats::default(),
+        }
+    }
+}
+
+impl State {
+    fn get(&mut self, key: &Key) -> Option<Arc<str>> {
+        let Some(&index) = self.entries.get(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        self.unlink(index);
+        self.push_front(index);
+        Some(Arc::clone(&self.nodes[index].mathml))
+    }
+
+    fn insert(&mut self, key: Key, mathml: Arc<str>, capacity: usize) {
+        // Another thread may have rendered the same equation in the meantime.
+        if let Some(&index) = self.entries.get(&key) {
+            self.nodes[index].mathml = mathml;
+            self.unlink(index);
+            self.push_front(index);
+            return;
+        }
+        if capacity == 0 {
+            return;
+        }
+
+        let index = if self.nodes.len() < capacity {
+            self.nodes.push(Node {
+                key: key.clone(),
+                mathml,
+                prev: NIL,
+                next: NIL,
+            });
+            self.nodes.len() - 1
+        } else {
+            let index = self.tail;
+            self.unlink(index);
+            let node = &mut self.nodes[index];
+            self.entries.remove(&node.key);
+            node.key = key.clone();
+            node.mathml = mathml;
+            self.stats.evictions += 1;
+            index
+        };
+        self.entries.insert(key, index);
+        self.push_front(index);
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+        match prev {
+            NIL => self.head = next,
+            _ => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            _ => self.nodes[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = self.head;
+        match self.head {
+            NIL => self.tail = index,
+            head => self.nodes[head].prev = index,
+        }
+        self.head = index;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{mathml::push_mathml, parser::MacroTable, Parser};
+
+    fn render(cache: &Cache, input: &str) -> Arc<str> {
+        cache
+            .render(input, ParserConfig::default(), RenderConfig::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn least_recently_used_eviction() {
+        let cache = Cache::new(2);
+        render(&cache, "a");
+        render(&cache, "b");
+        render(&cache, "a");
+        // `b` is the least recently used, and is evicted.
+        render(&cache, "c");
+        assert_eq!(cache.len(), 2);
+        render(&cache, "a");
+        render(&cache, "b");
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 4,
+                skipped: 0,
+                evictions: 2,
+            }
+        );
+
+        cache.clear();
+        assert!(cache.is_empty());
+        render(&cache, "a");
+        assert_eq!(cache.stats().misses, 5);
+
+        let cache = Cache::new(0);
+        render(&cache, "a");
+        render(&cache, "a");
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn keys() {
+        let cache = Cache::new(16);
+        let block = RenderConfig {
+            display_mode: DisplayMode::Block,
+            ..RenderConfig::default()
+        };
+        let inline = render(&cache, "x");
+        let display = cache.render("x", ParserConfig::default(), block).unwrap();
+        assert_ne!(inline, display);
+        assert_eq!(cache.stats().misses, 2);
+
+        let mut macros = MacroTable::new();
+        macros.def("R", "", r"\mathbb{R}").unwrap();
+        let config = ParserConfig {
+            macros: Some(&macros),
+            ..ParserConfig::default()
+        };
+        let first = cache
+            .render(r"\R", config, RenderConfig::default())
+            .unwrap();
+        assert!(first.contains('ℝ'));
+        macros.def("R", "", r"\mathcal{R}").unwrap();
+        let config = ParserConfig {
+            macros: Some(&macros),
+            ..ParserConfig::default()
+        };
+        let second = cache
+            .render(r"\R", config, RenderConfig::default())
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(cache.stats().hits, 0);
+
+        // A clone shares the equations of the table until either of them changes.
+        let mut clone = macros.clone();
+        let config = ParserConfig {
+            macros: Some(&clone),
+            ..ParserConfig::default()
+        };
+        let cloned = cache
+            .render(r"\R", config, RenderConfig::default())
+            .unwrap();
+        assert_eq!(cloned, second);
+        assert_eq!(cache.stats().hits, 1);
+        clone.def("R", "", r"\mathbb{R}").unwrap();
+        let config = ParserConfig {
+            macros: Some(&clone),
+            ..ParserConfig::default()
+        };
+        let changed = cache
+            .render(r"\R", config, RenderConfig::default())
+            .unwrap();
+        assert_eq!(changed, first);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[test]
+    fn skipped_equations() {
+        let cache = Cache::new(16);
+        for _ in 0..2 {
+            render(&cache, r"\def\f{x} \f");
+            render(&cache, r"\newcommand{\f}{x} \f");
+            assert!(cache
+                .render(
+                    r"\unknown",
+                    ParserConfig::default(),
+                    RenderConfig::default()
+                )
+                .is_err());
+        }
+        assert!(cache.is_empty());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 2,
+                skipped: 4,
+                evictions: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let inputs = [
+            r"\alpha",
+            r"x^2",
+            r"\frac{1}{2}",
+            r"O(n \log n)",
+            r"\sqrt{2}",
+        ];
+        let cache = Cache::new(3);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..100 {
+                        let input = inputs[i % inputs.len()];
+                        let mut expected = String::new();
+                        push_mathml(
+                            &mut expected,
+                            Parser::new(input, &Storage::new()),
+                            RenderConfig::default(),
+                        )
+                        .unwrap();
+                        assert_eq!(&*render(&cache, input), expected);
+                    }
+                });
+            }
+        });
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses, 400);
+        assert_eq!(cache.len(), 3);
+    }
+}
@@ -0,0 +1,153 @@
+//! Integration with [`pulldown-cmark`](pulldown_cmark), available with the `pulldown-cmark`
+//! feature.
+//!
+//! [`MathEvents`] wraps the events of a [`pulldown_cmark::Parser`], and replaces its
+//! [`InlineMath`](pulldown_cmark::Event::InlineMath) and
+//! [`DisplayMath`](pulldown_cmark::Event::DisplayMath) events by the corresponding `MathML`, so
+//! that a Markdown document can be rendered to HTML in a single pass.
+//!
+//! Math events are only produced by `pulldown-cmark` when [`Options::ENABLE_MATH`] is set.
+//!
+//! ### Examples
+//!
+//! ```rust
+//! use pulldown_cmark::{html, Options, Parser};
+//! use pulldown_latex::cmark::MathEvents;
+//!
+//! let markdown = r"The area of a circle is $\pi r^2$.";
+//! let parser = Parser::new_ext(markdown, Options::ENABLE_MATH);
+//!
+//! let mut output = String::new();
+//! html::push_html(&mut output, MathEvents::new(parser));
+//! assert!(output.starts_with(r#"<p>The area of a circle is <math display="inline">"#));
+//! ```
+//!
+//! [`Options::ENABLE_MATH`]: pulldown_cmark::Options::ENABLE_MATH
+
+use std::fmt::Debug;
+
+use pulldown_cmark::{CowStr, Event};
+
+use crate::{
+    config::{DisplayMode, ParserConfig, RenderConfig},
+    mathml::push_mathml,
+    parser::{error::ParserError, storage::Storage, Parser},
+};
+
+type ErrorHook<'c> = Box<dyn FnMut(&str, &ParserError) + 'c>;
+
+/// An iterator adapter rendering the math events of a [`pulldown_cmark::Parser`] to `MathML`.
+///
+/// `InlineMath` events are rendered in [`DisplayMode::Inline`], and `DisplayMath` events in
+/// [`DisplayMode::Block`], whatever the display mode of the [`RenderConfig`]. Both are replaced by
+/// an `InlineHtml` event containing the `MathML`, since they occur within paragraphs. Other events
+/// are yielded as is.
+///
+/// A single [`Storage`] is used for all equations, and is reset between them.
+pub struct MathEvents<'c, I> {
+    events: I,
+    storage: Storage,
+    parser_config: ParserConfig<'c>,
+    render_config: RenderConfig<'c>,
+    on_error: Option<ErrorHook<'c>>,
+}
+
+impl<'c, I> MathEvents<'c, I> {
+    /// Wrap the given events, using the default configurations for the parser and the renderer.
+    pub fn new(events: I) -> Self {
+        Self::with_config(events, ParserConfig::default(), RenderConfig::default())
+    }
+
+    /// Wrap the given events, using the given configurations for the parser and the renderer.
+    pub fn with_config(
+        events: I,
+        parser_config: ParserConfig<'c>,
+        render_config: RenderConfig<'c>,
+    ) -> Self {
+        Self {
+            events,
+            storage: Storage::new(),
+            parser_config,
+            render_config,
+            on_error: None,
+        }
+    }
+
+    /// Call `hook` with the source of the equation for every error found while parsing it.
+    ///
+    /// Errors are still rendered in place of the equation, as with [`push_mathml`]. The hook can
+    /// be used to log them, or to fail the rendering of the document as a whole.
+    ///
+    /// ### Examples
+    ///
+    /// ```rust
+    /// use pulldown_cmark::{html, Options, Parser};
+    /// use pulldown_latex::cmark::MathEvents;
+    ///
+    /// let mut errors = Vec::new();
+    /// let parser = Parser::new_ext(r"$\unknown$ and $$\frac{1}{2}$$", Options::ENABLE_MATH);
+    /// let events = MathEvents::new(parser).on_error(|source, error| {
+    ///     errors.push((source.to_owned(), error.kind().clone()))
+    /// });
+    ///
+    /// let mut output = String::new();
+    /// html::push_html(&mut output, events);
+    /// assert_eq!(errors.len(), 1);
+    /// assert_eq!(errors[0].0, r"\unknown");
+    /// ```
+    pub fn on_error<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&str, &ParserError) + 'c,
+    {
+        self.on_error = Some(Box::new(hook));
+        self
+    }
+
+    fn render(&mut self, source: &str, display_mode: DisplayMode) -> String {
+        let events =
+            Parser::with_config(source, &self.storage, self.parser_config).collect::<Vec<_>>();
+        if let Some(hook) = &mut self.on_error {
+            events
+                .iter()
+                .filter_map(|event| event.as_ref().err())
+                .for_each(|error| hook(source, error));
+        }
+
+        let mut output = String::new();
+        let config = RenderConfig {
+            display_mode,
+            ..self.render_config
+        };
+        push_mathml(&mut output, events, config).expect("writing to a string should not fail");
+        self.storage.reset();
+        output
+    }
+}
+
+impl<'a, I> Iterator for MathEvents<'_, I>
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (source, display_mode) = match self.events.next()? {
+            Event::InlineMath(source) => (source, DisplayMode::Inline),
+            Event::DisplayMath(source) => (source, DisplayMode::Block),
+            event => return Some(event),
+        };
+        Some(Event::InlineHtml(CowStr::from(
+            self.render(&source, display_mode),
+        )))
+    }
+}
+
+impl<I: Debug> Debug for MathEvents<'_, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MathEvents")
+            .field("events", &self.events)
+            .field("parser_config", &self.parser_config)
+            .field("render_config", &self.render_config)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,8 +1,258 @@
-//! Configuration options for the renderer.
+//! Configuration options for the parser and the renderer.
 //!
-//! The configuration of the `mathml` renderer is done through the [`RenderConfig`] struct.
+//! The configuration of the parser is done through the [`ParserConfig`] struct, and the
+//! configuration of the `mathml` renderer is done through the [`RenderConfig`] struct.
 use std::fmt::Display;
 
+use crate::{
+    event::Event,
+    parser::{
+        extension::{PrimitiveSet, UnknownCommand},
+        MacroTable,
+    },
+};
+
+/// Configuration for the [`Parser`](crate::Parser).
+///
+/// The default value is: [`ParserConfig::default`].
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{config::{Limits, ParserConfig}, Parser, Storage};
+///
+/// let config = ParserConfig {
+///     limits: Limits {
+///         max_expansions: Some(100),
+///         ..Limits::default()
+///     },
+///     ..ParserConfig::default()
+/// };
+/// let storage = Storage::new();
+/// let mut parser = Parser::with_config(r"\def\a{\a}\a", &storage, config);
+/// assert!(parser.any(|event| event.is_err()));
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParserConfig<'a> {
+    /// Macros made available to the parser, in addition to the ones defined in the input.
+    pub macros: Option<&'a MacroTable>,
+    /// Primitives implemented in Rust, in addition to the built-in ones.
+    pub primitives: Option<&'a PrimitiveSet>,
+    /// See [`Limits`].
+    pub limits: Limits,
+    /// If true, the parser recovers from errors instead of returning them.
+    ///
+    /// The part of the input that caused the error is skipped up to the end of the current group,
+    /// or the next `&` or `\\` in an environment, and reported as an [`Event::Error`] in the
+    /// stream of events, which stays well-formed. Parsing then resumes normally. Errors caused by
+    /// exceeding the [`Limits`] are also reported as an [`Event::Error`], but parsing does not
+    /// resume afterward.
+    ///
+    /// [`Event::Error`]: crate::event::Event::Error
+    pub error_recovery: bool,
+    /// See [`UnknownCommandPolicy`].
+    pub unknown_commands: UnknownCommandPolicy<'a>,
+    /// See [`Strictness`].
+    pub strict: Strictness,
+}
+
+/// How the parser treats input that it accepts, but that may not render the same way in LaTeX.
+///
+/// See [`WarningKind`] for the kinds of input concerned.
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{
+///     config::{ParserConfig, Strictness},
+///     parser::warning::WarningKind,
+///     Parser, Storage,
+/// };
+///
+/// let config = ParserConfig {
+///     strict: Strictness::Warn,
+///     ..ParserConfig::default()
+/// };
+/// let storage = Storage::new();
+/// let mut parser = Parser::with_config(r"{\bf x} + {{y}}", &storage, config);
+/// assert!(parser.by_ref().all(|event| event.is_ok()));
+///
+/// let warnings = parser.warnings();
+/// assert_eq!(warnings.len(), 2);
+/// assert_eq!(warnings[0].kind, WarningKind::DeprecatedFontCommand("bf".into()));
+/// assert_eq!(warnings[0].span.range, 1..4);
+/// assert_eq!(warnings[1].kind, WarningKind::RedundantBraces);
+/// ```
+///
+/// [`WarningKind`]: crate::parser::warning::WarningKind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Strictness {
+    /// Accept the input silently.
+    ///
+    /// __This is the default value.__
+    #[default]
+    Ignore,
+    /// Accept the input, and record a [`Warning`] that can be retrieved with
+    /// [`Parser::warnings`].
+    ///
+    /// [`Warning`]: crate::parser::warning::Warning
+    /// [`Parser::warnings`]: crate::Parser::warnings
+    Warn,
+    /// Report an [`ErrorKind::Strict`] error, and record the [`Warning`] that caused it.
+    ///
+    /// [`ErrorKind::Strict`]: crate::parser::error::ErrorKind::Strict
+    /// [`Warning`]: crate::parser::warning::Warning
+    Error,
+}
+
+/// What the parser does when it encounters a control sequence that is neither a supported
+/// primitive nor a defined macro.
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{
+///     config::{ParserConfig, UnknownCommandPolicy},
+///     event::{Content, Event},
+///     Parser, Storage,
+/// };
+///
+/// let config = ParserConfig {
+///     unknown_commands: UnknownCommandPolicy::Function,
+///     ..ParserConfig::default()
+/// };
+/// let storage = Storage::new();
+/// let events = Parser::with_config(r"\vendor", &storage, config)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(events, [Event::Content(Content::Function("vendor"))]);
+/// ```
+#[derive(Clone, Copy, Default)]
+pub enum UnknownCommandPolicy<'a> {
+    /// Report an [`ErrorKind::UnknownPrimitive`] error.
+    ///
+    /// __This is the default value.__
+    ///
+    /// [`ErrorKind::UnknownPrimitive`]: crate::parser::error::ErrorKind::UnknownPrimitive
+    #[default]
+    Error,
+    /// Render the name of the control sequence upright, as a [`Content::Function`].
+    ///
+    /// [`Content::Function`]: crate::event::Content::Function
+    Function,
+    /// Render the control sequence as it appears in the input, in the error color.
+    ///
+    /// The control sequence is reported as an [`Event::Error`], without stopping the parser.
+    RawText,
+    /// Call the given function with the control sequence.
+    ///
+    /// If the function returns `None`, an error is reported. Otherwise, the returned events replace
+    /// the control sequence. When more than one event is returned, the events are wrapped in a
+    /// group so that they count as a single element.
+    ///
+    /// The returned events may borrow from the name of the control sequence, or from strings
+    /// allocated with [`UnknownCommand::alloc_str`].
+    ///
+    /// ### Examples
+    ///
+    /// ```rust
+    /// use pulldown_latex::{
+    ///     config::{ParserConfig, UnknownCommandPolicy},
+    ///     event::{Content, Event},
+    ///     parser::extension::UnknownCommand,
+    ///     Parser, Storage,
+    /// };
+    ///
+    /// fn placeholder<'a>(command: &UnknownCommand<'a>) -> Option<Vec<Event<'a>>> {
+    ///     let text = command.alloc_str(&format!("[{}]", command.name()));
+    ///     Some(vec![Event::Content(Content::Text(text))])
+    /// }
+    ///
+    /// let config = ParserConfig {
+    ///     unknown_commands: UnknownCommandPolicy::Callback(&placeholder),
+    ///     ..ParserConfig::default()
+    /// };
+    /// let storage = Storage::new();
+    /// let events = Parser::with_config(r"\vendor", &storage, config)
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(events, [Event::Content(Content::Text("[vendor]"))]);
+    /// ```
+    Callback(&'a (dyn for<'b> Fn(&UnknownCommand<'b>) -> Option<Vec<Event<'b>>> + Sync)),
+}
+
+impl std::fmt::Debug for UnknownCommandPolicy<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Error => f.write_str("Error"),
+            Self::Function => f.write_str("Function"),
+            Self::RawText => f.write_str("RawText"),
+            Self::Callback(_) => f.write_str("Callback(..)"),
+        }
+    }
+}
+
+/// Limits on the resources a parser is allowed to use.
+///
+/// Macros make it easy to write a small input that expands indefinitely, or nests groups deeply
+/// enough to exhaust the stack. When one of these limits is exceeded, the parser reports an error
+/// whose [`ErrorKind::is_resource_limit`](crate::parser::error::ErrorKind::is_resource_limit)
+/// returns `true`, and stops parsing. The groups which are open at that point are still ended, so
+/// that the events can be rendered.
+///
+/// A value of `None` disables the corresponding limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Limits {
+    /// The maximum number of macro expansions.
+    pub max_expansions: Option<usize>,
+    /// The maximum number of macro expansions nested inside one another.
+    pub max_expansion_depth: Option<usize>,
+    /// The maximum number of bytes produced by macro expansions, in total.
+    pub max_expanded_bytes: Option<usize>,
+    /// The maximum number of nested groups, such as braces, `\left`/`\right` pairs and
+    /// environments.
+    pub max_group_depth: Option<usize>,
+    /// The maximum length of the input, in bytes.
+    pub max_input_length: Option<usize>,
+}
+
+impl Default for Limits {
+    /// # Default Value
+    /// ```rust
+    /// # use pulldown_latex::config::Limits;
+    /// const DEFAULT: Limits = Limits {
+    ///     max_expansions: Some(10_000),
+    ///     max_expansion_depth: Some(256),
+    ///     max_expanded_bytes: Some(64 * 1024 * 1024),
+    ///     max_group_depth: Some(128),
+    ///     max_input_length: None,
+    /// };
+    /// assert_eq!(Limits::default(), DEFAULT);
+    /// ```
+    fn default() -> Self {
+        Self {
+            max_expansions: Some(10_000),
+            max_expansion_depth: Some(256),
+            max_expanded_bytes: Some(64 * 1024 * 1024),
+            max_group_depth: Some(128),
+            max_input_length: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Limits that are never exceeded.
+    pub const fn none() -> Self {
+        Self {
+            max_expansions: None,
+            max_expansion_depth: None,
+            max_expanded_bytes: None,
+            max_group_depth: None,
+            max_input_length: None,
+        }
+    }
+}
+
 /// Configuration for the `mathml` renderer.
 ///
 /// The default value is: [`RenderConfig::default`].
@@ -19,10 +269,35 @@ pub struct RenderConfig<'a> {
     /// into the `<math>` element.
     ///
     /// This namespace is unnecessary for modern browsers but can be helpful for other user agents,
-    /// such as Microsoft Word.
+    /// such as Microsoft Word. Along with the namespace, the `mathml` generated is always
+    /// well-formed XML, since it only uses numeric character references.
     pub xml: bool,
+    /// If true, and `xml` is true as well, every element is written with the `m:` prefix, and the
+    /// namespace is bound to that prefix (`xmlns:m="http://www.w3.org/1998/Math/MathML"`).
+    ///
+    /// This is useful to embed the `mathml` in XML documents whose default namespace is another
+    /// one, such as XHTML or DocBook.
+    pub xml_prefix: bool,
     /// See [`MathStyle`].
     pub math_style: MathStyle,
+    /// If true, the `mathml` generated is guaranteed to only contain a fixed set of `MathML`
+    /// elements and attributes, whose values are checked before being written.
+    ///
+    /// Text coming from the input, such as the content of `\text` or the annotation, is always
+    /// escaped. This option additionally protects pages embedding the `mathml` against mistakes in
+    /// the renderer itself, at the cost of some performance.
+    pub safe: bool,
+    /// See [`Profile`].
+    pub profile: Profile,
+    /// If true, the rules of `styles.css` which apply to the `mathml` generated are written as
+    /// `style` attributes on its elements, instead of classes, so that it renders the same without
+    /// the stylesheet. This is useful where the stylesheet cannot be included, such as in emails or
+    /// feeds.
+    ///
+    /// Equation numbers start at 1 in every `<math>` element, instead of counting across the page,
+    /// and the fonts of the stylesheet are left to the user agent. The output of
+    /// [`Profile::MathML3`] does not use the stylesheet, so this has no effect on it.
+    pub self_contained: bool,
 }
 
 impl<'a> RenderConfig<'a> {
@@ -38,13 +313,17 @@ impl<'a> RenderConfig<'a> {
 impl<'a> Default for RenderConfig<'a> {
     /// # Default Value
     /// ```rust
-    /// # use pulldown_latex::config::{RenderConfig, DisplayMode, MathStyle};
+    /// # use pulldown_latex::config::{RenderConfig, DisplayMode, MathStyle, Profile};
     /// const DEFAULT: RenderConfig = RenderConfig {
     ///     display_mode: DisplayMode::Inline,
     ///     annotation: None,
     ///     error_color: (178, 34, 34),
     ///     xml: false,
+    ///     xml_prefix: false,
     ///     math_style: MathStyle::TeX,
+    ///     safe: false,
+    ///     profile: Profile::Core,
+    ///     self_contained: false,
     /// };
     /// assert_eq!(RenderConfig::default(), DEFAULT);
     /// ```
@@ -54,7 +333,11 @@ impl<'a> Default for RenderConfig<'a> {
             annotation: None,
             error_color: (178, 34, 34),
             xml: false,
+            xml_prefix: false,
             math_style: MathStyle::TeX,
+            safe: false,
+            profile: Profile::Core,
+            self_contained: false,
         }
     }
 }
@@ -73,7 +356,7 @@ impl<'a> Default for RenderConfig<'a> {
 /// | `ISO`     | _italic_   | _italic_   | _italic_   | _italic_   |
 /// | `French`  | _italic_   | upright    | upright    | upright    |
 /// | `Upright` | upright    | upright    | upright    | upright    |
-#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MathStyle {
     /// The default style used in TeX.
     ///
@@ -111,6 +394,33 @@ impl MathStyle {
     }
 }
 
+/// The version of `MathML` written by the renderer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// [`MathML Core`], as implemented by modern browsers.
+    ///
+    /// Environments, equation numbers, lines in arrays and some of the content rely on the
+    /// `styles.css` stylesheet provided with this crate.
+    ///
+    /// __This is the default value.__
+    ///
+    /// [`MathML Core`]: https://www.w3.org/TR/mathml-core/
+    #[default]
+    Core,
+    /// [`MathML 3`] presentation markup, understood by older user agents such as MathJax,
+    /// Microsoft Word, LibreOffice and screen readers, which ignore stylesheets.
+    ///
+    /// The layout of environments is described by the attributes of `mtable`, numbered rows are
+    /// written as `mlabeledtr`, boxes and negations as `menclose`, and fonts through the
+    /// `mathvariant` attribute instead of the mathematical alphanumeric symbols of Unicode.
+    ///
+    /// Equation numbers start at 1 in every `math` element. Lines on the edges of an array and
+    /// borders are always solid, and consecutive lines are drawn as a single one.
+    ///
+    /// [`MathML 3`]: https://www.w3.org/TR/MathML3/
+    MathML3,
+}
+
 /// How the math is displayed.
 ///
 /// Semantically, this affects the [`display`] attribute of the [`<math>`] tag in the mathml
@@ -118,7 +428,7 @@ impl MathStyle {
 ///
 /// [`<math>`]: https://developer.mozilla.org/en-US/docs/Web/MathML/Element/math
 /// [`display`]: https://developer.mozilla.org/en-US/docs/Web/MathML/Element/math#display
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub enum DisplayMode {
     /// The math is rendered inline.
     ///
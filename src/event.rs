@@ -7,13 +7,35 @@
 //!
 //! This module tries to be comprehensive in explaining the invariants that are be upheld by the [`Parser`].
 //! If a user of this crate, or a renderer implementor finds a case where the invariants are not
-//! satisfied, then it is a bug in the parser, and should be reported.
+//! satisfied, then it is a bug in the parser, and should be reported. Streams of events that do not
+//! come from the parser can be checked with a [`Validator`](validate::Validator).
+//!
+//! Events borrow from the input and from the [`Storage`] of the parser. They can be collected into
+//! an [`EventBuffer`](buffer::EventBuffer) to outlive both.
+//!
+//! # Serialization
+//!
+//! When the `serde` feature is enabled, the types describing events implement `Serialize` and
+//! `Deserialize`, using serde's default representation. The serialized form is considered part of
+//! the public API, and only changes along with breaking releases.
+//!
+//! Deserialized events borrow their strings from the serialized data, so the format must support
+//! zero-copy deserialization of strings. With `serde_json`, for example, deserializing a string
+//! containing escape sequences such as `\\` fails. An [`EventBuffer`](buffer::EventBuffer) is
+//! serialized the same way as a sequence of events, but owns its strings, and should be used to
+//! deserialize events from such formats.
 //!
 //! [`Parser`]: crate::parser::Parser
+//! [`Storage`]: crate::parser::storage::Storage
 //! [`push_mathml`]: crate::mathml::push_mathml
 //! [`write_mathml`]: crate::mathml::write_mathml
 
-use std::fmt::Display;
+use std::{fmt::Display, ops::Range};
+
+use crate::parser::error::ErrorKind;
+
+pub mod buffer;
+pub mod validate;
 
 /// All events that can be produced by the parser.
 ///
@@ -55,9 +77,10 @@ use std::fmt::Display;
 /// ];
 /// ```
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Event<'a> {
     /// The event is a [`Content`] element.
-    Content(Content<'a>),
+    Content(#[cfg_attr(feature = "serde", serde(borrow))] Content<'a>),
     /// The events following this one constitute a "group" which counts as a single _element_
     /// (i.e., a set of elements within `{}` in `LaTeX`), until the [`Event::End`] event
     /// is reached.
@@ -93,10 +116,62 @@ pub enum Event<'a> {
     /// This is a flow event that is emitted in mathematical environments such as `align`,
     /// `cases`, `array`, etc.
     EnvironmentFlow(EnvironmentFlow),
+    /// A part of the input that could not be parsed, and was skipped.
+    ///
+    /// This event is only emitted when [`ParserConfig::error_recovery`] is enabled, in place of
+    /// the error that would otherwise be returned, or for unknown control sequences when
+    /// [`UnknownCommandPolicy::RawText`] is used. It counts as a single element.
+    ///
+    /// [`ParserConfig::error_recovery`]: crate::config::ParserConfig::error_recovery
+    /// [`UnknownCommandPolicy::RawText`]: crate::config::UnknownCommandPolicy::RawText
+    Error {
+        /// The kind of error that was encountered.
+        kind: ErrorKind,
+        /// The source that was skipped.
+        ///
+        /// This is usually a slice of the input, but can be the result of a macro expansion.
+        source: &'a str,
+        /// The range of the original input where the error is located.
+        span: Range<usize>,
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Map the string borrowed by the event, if any, and keep the rest of the event as is.
+    fn map_str<'b>(self, f: impl FnOnce(&'a str) -> &'b str) -> Event<'b> {
+        match self {
+            Event::Content(content) => Event::Content(match content {
+                Content::Text(text) => Content::Text(f(text)),
+                Content::Number(number) => Content::Number(f(number)),
+                Content::Function(function) => Content::Function(f(function)),
+                Content::Ordinary { content, stretchy } => Content::Ordinary { content, stretchy },
+                Content::LargeOp { content, small } => Content::LargeOp { content, small },
+                Content::BinaryOp { content, small } => Content::BinaryOp { content, small },
+                Content::Relation { content, small } => Content::Relation { content, small },
+                Content::Delimiter { content, size, ty } => {
+                    Content::Delimiter { content, size, ty }
+                }
+                Content::Punctuation(content) => Content::Punctuation(content),
+            }),
+            Event::Begin(grouping) => Event::Begin(grouping),
+            Event::End => Event::End,
+            Event::Visual(visual) => Event::Visual(visual),
+            Event::Script { ty, position } => Event::Script { ty, position },
+            Event::Space { width, height } => Event::Space { width, height },
+            Event::StateChange(state_change) => Event::StateChange(state_change),
+            Event::EnvironmentFlow(flow) => Event::EnvironmentFlow(flow),
+            Event::Error { kind, source, span } => Event::Error {
+                kind,
+                source: f(source),
+                span,
+            },
+        }
+    }
 }
 
 /// Base events that produce `mathml` nodes
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Content<'a> {
     /// Text content that should be typeset following the rules of `LaTeX`'s `text` mode.
     Text(&'a str),
@@ -152,6 +227,7 @@ pub enum Content<'a> {
 
 /// Modifies the visual representation of the following element(s)
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Visual {
     /// The following element is the content of the root.
     SquareRoot,
@@ -177,6 +253,7 @@ pub enum Visual {
 /// Things like subscripts, underscripts, and movable scripts can be represented when using this
 /// `enum` in conjunction with the [`ScriptPosition`] `enum`.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ScriptType {
     /// The 2 following elements are the base and and the subscript
     Subscript,
@@ -188,6 +265,7 @@ pub enum ScriptType {
 
 /// Position of the script. This is used to determine how to render the scripts.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ScriptPosition {
     /// The scripts are rendered to the (bottom and top) right of the operator.
     Right,
@@ -207,6 +285,7 @@ pub enum ScriptPosition {
 /// when entering a new group that is not a `Grouping::Normal`, or a `Grouping::LeftRight`. The
 /// exception to the latter is `StateChange::Style`, which is maintained across all groups.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StateChange {
     /// Changes the font of the content.
     ///
@@ -223,6 +302,7 @@ pub enum StateChange {
 
 /// Available font styles from LaTeX.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Font {
     /// The bold and calligraphic font-face.
     BoldScript,
@@ -259,6 +339,7 @@ pub enum Font {
 /// This is analogous to the different "modes" in `LaTeX`, such as `display`, `text`, etc., which
 /// are set by commands like `\displaystyle`, `\textstyle`, etc.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Style {
     /// Set by the `\displaystyle` command.
     Display,
@@ -272,6 +353,7 @@ pub enum Style {
 
 /// Represents a color change.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ColorChange {
     /// The color to change to.
     ///
@@ -286,6 +368,7 @@ pub struct ColorChange {
 
 /// The target of the color change.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ColorTarget {
     /// The text of the content.
     Text,
@@ -300,6 +383,7 @@ pub enum ColorTarget {
 /// This can be created by a lot of different `LaTeX` commands, such as `{}`, `\left`, `\right`,
 /// `\begin{...}`, `\end{...}`, etc.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Grouping {
     /// A normal form of grouping, usually induced by `{}` or `\begingroup` and `\endgroup` in `LaTeX`.
     Normal,
@@ -387,6 +471,7 @@ impl Grouping {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EnvironmentFlow {
     /// This event specifies an alignment mark in a mathematical environment.
     ///
@@ -477,28 +562,51 @@ pub enum EnvironmentFlow {
     StartLines { lines: Box<[Line]> },
 }
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum GroupingKind {
+/// A grouping construct as written in the input.
+///
+/// Contrary to [`Grouping`], this type describes the syntax used to open and close a group,
+/// e.g., `\begin{pmatrix}` and `\end{pmatrix}`, rather than how it is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GroupingKind {
+    /// A group delimited by braces.
     Normal,
+    /// An optional argument delimited by brackets.
     OptionalArgument,
+    /// A `\begin`/`\end` pair, for which the environment is not known.
     BeginEnd,
+    /// A `\left`/`\right` pair.
     LeftRight,
+    /// The `array` or `darray` environment.
     Array { display: bool },
+    /// One of the `matrix` environments, possibly with a column specification (`*`).
     Matrix { ty: MatrixType, column_spec: bool },
+    /// One of the `cases` environments.
     Cases { left: bool, display: bool },
+    /// The `equation` environment.
     Equation { eq_numbers: bool },
+    /// The `align` environment.
     Align { eq_numbers: bool },
+    /// The `aligned` environment.
     Aligned,
+    /// The `subarray` environment.
     SubArray,
+    /// The `alignat` environment.
     Alignat { eq_numbers: bool },
+    /// The `alignedat` environment.
     Alignedat,
+    /// The `gather` environment.
     Gather { eq_numbers: bool },
+    /// The `gathered` environment.
     Gathered,
+    /// The `multline` environment.
     Multline,
+    /// The `split` environment.
     Split,
 }
 
 impl GroupingKind {
+    /// The string that opens this kind of group.
     pub fn opening_str(&self) -> &'static str {
         match self {
             Self::Normal => "{",
@@ -546,6 +654,7 @@ impl GroupingKind {
         }
     }
 
+    /// The string that closes this kind of group.
     pub fn closing_str(&self) -> &'static str {
         match self {
             Self::Normal => "}",
@@ -594,19 +703,29 @@ impl GroupingKind {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum MatrixType {
+/// The delimiters surrounding a matrix environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatrixType {
+    /// `matrix`, no delimiters.
     Normal,
+    /// `smallmatrix`, no delimiters and a smaller size.
     Small,
+    /// `pmatrix`, surrounded by parentheses.
     Parens,
+    /// `bmatrix`, surrounded by brackets.
     Brackets,
+    /// `Bmatrix`, surrounded by braces.
     Braces,
+    /// `vmatrix`, surrounded by vertical bars.
     Vertical,
+    /// `Vmatrix`, surrounded by double vertical bars.
     DoubleVertical,
 }
 
 /// Represents a column in a matrix or array environment.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ColumnAlignment {
     /// Content in the column is left-aligned.
     Left,
@@ -620,6 +739,7 @@ pub enum ColumnAlignment {
 ///
 /// It can either be a column specification or a vertical separator specification.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ArrayColumn {
     /// A column specification.
     Column(ColumnAlignment),
@@ -629,6 +749,7 @@ pub enum ArrayColumn {
 
 /// Represents a delimiter size.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DelimiterSize {
     /// Corresponds to `\bigl`, `\bigr`, etc.
     Big,
@@ -653,6 +774,7 @@ impl DelimiterSize {
 
 /// Whether the delimiter is an opening, closing, or fence delimiter.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DelimiterType {
     /// Corresponds to the left delimiter.
     Open,
@@ -664,6 +786,7 @@ pub enum DelimiterType {
 
 /// Represents a line in a `LaTeX` environment.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Line {
     /// A solid line.
     Solid,
@@ -674,6 +797,7 @@ pub enum Line {
 /// Sometimes mathematical relations can be made of more than one character, so we need a way to
 /// represent them when one character is not enough.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RelationContent {
     content: (char, Option<char>),
 }
@@ -709,6 +833,7 @@ pub type Glue = (Dimension, Option<Dimension>, Option<Dimension>);
 
 /// Represents a LaTeX dimension.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dimension {
     /// The value of the dimension.
     pub value: f32,
@@ -787,6 +912,7 @@ impl Display for Dimension {
 // From the TeXbook, p. 57, 60, 167.
 /// Represents a dimension unit in LaTeX.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DimensionUnit {
     /// The `em` unit.
     Em,
@@ -0,0 +1,497 @@
+//! An owned sequence of events, independent of the input and of the [`Storage`] of the parser.
+//!
+//! [`Storage`]: crate::parser::storage::Storage
+
+use std::{convert::Infallible, ops::Range};
+
+use super::Event;
+
+/// A sequence of events that owns the strings they refer to.
+///
+/// The events produced by a [`Parser`] borrow from the input and from its [`Storage`], which
+/// makes them impossible to keep around once either is dropped. An `EventBuffer` copies these
+/// strings, so that it can be cached, or sent to another thread, independently of the parser.
+///
+/// A buffer can be built from a parser using [`Iterator::collect`], and given directly to
+/// [`push_mathml`] and [`write_mathml`].
+///
+/// [`Parser`]: crate::parser::Parser
+/// [`Storage`]: crate::parser::storage::Storage
+/// [`push_mathml`]: crate::mathml::push_mathml
+/// [`write_mathml`]: crate::mathml::write_mathml
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{push_mathml, EventBuffer, Parser, RenderConfig, Storage};
+///
+/// let buffer = {
+///     let input = String::from(r"\frac{\text{cats}}{x^2}");
+///     let storage = Storage::new();
+///     Parser::new(&input, &storage)
+///         .collect::<Result<EventBuffer, _>>()
+///         .unwrap()
+/// };
+///
+/// let mut output = String::new();
+/// push_mathml(&mut output, &buffer, RenderConfig::default()).unwrap();
+/// assert!(output.contains("cats"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventBuffer {
+    /// The concatenation of the strings of all events.
+    text: String,
+    /// The events, whose strings are replaced by the empty string, along with the range of
+    /// `text` holding their actual string.
+    events: Vec<(Event<'static>, Range<usize>)>,
+}
+
+impl EventBuffer {
+    /// Create an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy the event to the end of the buffer.
+    pub fn push(&mut self, event: Event<'_>) {
+        let mut range = 0..0;
+        let event = event.map_str(|str| {
+            range = self.text.len()..self.text.len() + str.len();
+            self.text.push_str(str);
+            ""
+        });
+        self.events.push((event, range));
+    }
+
+    /// Iterate over the events of the buffer.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            text: &self.text,
+            events: self.events.iter(),
+        }
+    }
+
+    /// Iterate over the events of the buffer in the given range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub(crate) fn slice(&self, range: Range<usize>) -> Iter<'_> {
+        Iter {
+            text: &self.text,
+            events: self.events[range].iter(),
+        }
+    }
+
+    /// The number of events in the buffer.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Whether the buffer contains no events.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Remove all events from the buffer, keeping the allocated memory for reuse.
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.events.clear();
+    }
+}
+
+impl<'a> Extend<Event<'a>> for EventBuffer {
+    fn extend<T: IntoIterator<Item = Event<'a>>>(&mut self, iter: T) {
+        iter.into_iter().for_each(|event| self.push(event));
+    }
+}
+
+impl<'a> FromIterator<Event<'a>> for EventBuffer {
+    fn from_iter<T: IntoIterator<Item = Event<'a>>>(iter: T) -> Self {
+        let mut buffer = Self::new();
+        buffer.extend(iter);
+        buffer
+    }
+}
+
+/// Renderers take fallible events, so iterating over a reference to the buffer yields events
+/// wrapped in `Ok`. Use [`EventBuffer::iter`] to get the events themselves.
+impl<'b> IntoIterator for &'b EventBuffer {
+    type Item = Result<Event<'b>, Infallible>;
+    type IntoIter = std::iter::Map<Iter<'b>, fn(Event<'b>) -> Result<Event<'b>, Infallible>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter().map(Ok)
+    }
+}
+
+/// An iterator over the events of an [`EventBuffer`].
+///
+/// This `struct` is created by the [`EventBuffer::iter`] method.
+#[derive(Debug, Clone)]
+pub struct Iter<'b> {
+    text: &'b str,
+    events: std::slice::Iter<'b, (Event<'static>, Range<usize>)>,
+}
+
+impl<'b> Iter<'b> {
+    fn resolve(&self, (event, range): &(Event<'static>, Range<usize>)) -> Event<'b> {
+        let text = self.text;
+        event.clone().map_str(|_| &text[range.clone()])
+    }
+}
+
+impl<'b> Iterator for Iter<'b> {
+    type Item = Event<'b>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.events.next()?;
+        Some(self.resolve(entry))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.events.next_back()?;
+        Some(self.resolve(entry))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use std::{borrow::Cow, fmt, ops::Range};
+
+    use serde::{
+        de::{SeqAccess, Visitor},
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    use super::EventBuffer;
+    use crate::{
+        event::{
+            Content, DelimiterSize, DelimiterType, Dimension, EnvironmentFlow, Event, Grouping,
+            RelationContent, ScriptPosition, ScriptType, StateChange, Visual,
+        },
+        parser::error::ErrorKind,
+    };
+
+    /// A buffer is serialized as the sequence of its events, exactly like a `Vec<Event>`.
+    impl Serialize for EventBuffer {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.iter())
+        }
+    }
+
+    /// Unlike [`Event`], a buffer can be deserialized from strings which cannot be borrowed, such
+    /// as strings containing escape sequences in JSON.
+    impl<'de> Deserialize<'de> for EventBuffer {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_seq(BufferVisitor)
+        }
+    }
+
+    struct BufferVisitor;
+
+    impl<'de> Visitor<'de> for BufferVisitor {
+        type Value = EventBuffer;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a sequence of events")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut buffer = EventBuffer::new();
+            while let Some(event) = seq.next_element::<CowEvent>()? {
+                buffer.push(event.as_event());
+            }
+            Ok(buffer)
+        }
+    }
+
+    /// Mirror of [`Event`] whose strings are either borrowed or owned.
+    ///
+    /// The variants must be kept in the same order as in [`Event`].
+    #[derive(Deserialize)]
+    #[serde(rename = "Event")]
+    enum CowEvent<'a> {
+        Content(#[serde(borrow)] CowContent<'a>),
+        Begin(Grouping),
+        End,
+        Visual(Visual),
+        Script {
+            ty: ScriptType,
+            position: ScriptPosition,
+        },
+        Space {
+            width: Option<Dimension>,
+            height: Option<Dimension>,
+        },
+        StateChange(StateChange),
+        EnvironmentFlow(EnvironmentFlow),
+        Error {
+            kind: ErrorKind,
+            #[serde(borrow)]
+            source: Cow<'a, str>,
+            span: Range<usize>,
+        },
+    }
+
+    /// Mirror of [`Content`] whose strings are either borrowed or owned.
+    ///
+    /// The variants must be kept in the same order as in [`Content`].
+    #[derive(Deserialize)]
+    #[serde(rename = "Content")]
+    enum CowContent<'a> {
+        Text(#[serde(borrow)] Cow<'a, str>),
+        Number(#[serde(borrow)] Cow<'a, str>),
+        Function(#[serde(borrow)] Cow<'a, str>),
+        Ordinary {
+            content: char,
+            stretchy: bool,
+        },
+        LargeOp {
+            content: char,
+            small: bool,
+        },
+        BinaryOp {
+            content: char,
+            small: bool,
+        },
+        Relation {
+            content: RelationContent,
+            small: bool,
+        },
+        Delimiter {
+            content: char,
+            size: Option<DelimiterSize>,
+            ty: DelimiterType,
+        },
+        Punctuation(char),
+    }
+
+    impl CowEvent<'_> {
+        fn as_event(&self) -> Event<'_> {
+            match self {
+                CowEvent::Content(content) => Event::Content(match content {
+                    CowContent::Text(text) => Content::Text(text),
+                    CowContent::Number(number) => Content::Number(number),
+                    CowContent::Function(function) => Content::Function(function),
+                    &CowContent::Ordinary { content, stretchy } => {
+                        Content::Ordinary { content, stretchy }
+                    }
+                    &CowContent::LargeOp { content, small } => Content::LargeOp { content, small },
+                    &CowContent::BinaryOp { content, small } => {
+                        Content::BinaryOp { content, small }
+                    }
+                    &CowContent::Relation { content, small } => {
+                        Content::Relation { content, small }
+                    }
+                    &CowContent::Delimiter { content, size, ty } => {
+                        Content::Delimiter { content, size, ty }
+                    }
+                    &CowContent::Punctuation(content) => Content::Punctuation(content),
+                }),
+                CowEvent::Begin(grouping) => Event::Begin(grouping.clone()),
+                CowEvent::End => Event::End,
+                CowEvent::Visual(visual) => Event::Visual(*visual),
+                &CowEvent::Script { ty, position } => Event::Script { ty, position },
+                &CowEvent::Space { width, height } => Event::Space { width, height },
+                CowEvent::StateChange(state_change) => Event::StateChange(*state_change),
+                CowEvent::EnvironmentFlow(flow) => Event::EnvironmentFlow(flow.clone()),
+                CowEvent::Error { kind, source, span } => Event::Error {
+                    kind: kind.clone(),
+                    source,
+                    span: span.clone(),
+                },
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde::de::{self, value};
+
+        use super::*;
+        use crate::event::{DimensionUnit, Line, Style};
+
+        /// A deserializer which only records the variants of the enum it is asked to deserialize.
+        struct VariantNames<'v>(&'v mut &'static [&'static str]);
+
+        impl<'de> Deserializer<'de> for VariantNames<'_> {
+            type Error = value::Error;
+
+            fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+                Err(de::Error::custom("expected an enum"))
+            }
+
+            fn deserialize_enum<V: Visitor<'de>>(
+                self,
+                _: &'static str,
+                variants: &'static [&'static str],
+                _: V,
+            ) -> Result<V::Value, Self::Error> {
+                *self.0 = variants;
+                Err(de::Error::custom("variants recorded"))
+            }
+
+            serde::forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+                byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map struct
+                identifier ignored_any
+            }
+        }
+
+        fn variants<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+            let mut variants: &[&str] = &[];
+            let _ = T::deserialize(VariantNames(&mut variants));
+            variants
+        }
+
+        /// The name of the variant of a serialized enum.
+        fn variant(value: &serde_json::Value) -> &str {
+            match value {
+                serde_json::Value::String(name) => name,
+                serde_json::Value::Object(map) => map.keys().next().unwrap(),
+                _ => panic!("not an enum: {value}"),
+            }
+        }
+
+        #[test]
+        fn mirrors_have_the_same_variants() {
+            // Formats such as bincode identify variants by their index, so the order matters too.
+            assert_eq!(variants::<Event>(), variants::<CowEvent>());
+            assert_eq!(variants::<Content>(), variants::<CowContent>());
+        }
+
+        #[test]
+        fn every_variant_round_trips() {
+            let contents = [
+                Content::Text("say \"hi\""),
+                Content::Number("1\\2"),
+                Content::Function("arc\tcos"),
+                Content::Ordinary {
+                    content: 'x',
+                    stretchy: true,
+                },
+                Content::LargeOp {
+                    content: '∑',
+                    small: false,
+                },
+                Content::BinaryOp {
+                    content: '+',
+                    small: true,
+                },
+                Content::Relation {
+                    content: RelationContent::double_char('<', '='),
+                    small: false,
+                },
+                Content::Delimiter {
+                    content: '(',
+                    size: Some(DelimiterSize::Bigg),
+                    ty: DelimiterType::Open,
+                },
+                Content::Punctuation(','),
+            ];
+            let dimension = Dimension::new(-1.5, DimensionUnit::Em);
+            let events = contents
+                .into_iter()
+                .map(Event::Content)
+                .chain([
+                    Event::Begin(Grouping::LeftRight(Some('('), None)),
+                    Event::End,
+                    Event::Visual(Visual::Fraction(Some(dimension))),
+                    Event::Script {
+                        ty: ScriptType::SubSuperscript,
+                        position: ScriptPosition::AboveBelow,
+                    },
+                    Event::Space {
+                        width: Some(dimension),
+                        height: None,
+                    },
+                    Event::StateChange(StateChange::Style(Style::Script)),
+                    Event::EnvironmentFlow(EnvironmentFlow::NewLine {
+                        spacing: Some(dimension),
+                        horizontal_lines: Box::new([Line::Solid, Line::Dashed]),
+                    }),
+                    Event::Error {
+                        kind: ErrorKind::UnknownPrimitive,
+                        source: "\\foo{\"}",
+                        span: 3..10,
+                    },
+                ])
+                .collect::<Vec<_>>();
+
+            let json = serde_json::to_value(&events).unwrap();
+            let serialized = json.as_array().unwrap();
+            let mut event_variants = serialized.iter().map(variant).collect::<Vec<_>>();
+            event_variants.dedup();
+            assert_eq!(event_variants, variants::<Event>());
+            let content_variants = serialized
+                .iter()
+                .filter_map(|event| event.get("Content"))
+                .map(variant)
+                .collect::<Vec<_>>();
+            assert_eq!(content_variants, variants::<Content>());
+
+            let json = json.to_string();
+            assert!(json.contains(r#"say \"hi\""#), "{json}");
+            let buffer: EventBuffer = serde_json::from_str(&json).unwrap();
+            assert!(buffer.iter().eq(events.iter().cloned()));
+            let buffer: EventBuffer = serde_json::from_reader(json.as_bytes()).unwrap();
+            assert!(buffer.iter().eq(events));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventBuffer;
+    use crate::{config::ParserConfig, mathml::push_mathml, Parser, RenderConfig, Storage};
+
+    #[test]
+    fn outlives_input_and_storage() {
+        let inputs = [
+            r"\operatorname{arccotan} 3.14 + \text{hello} \mathbf{X}_{\text{max}}",
+            r"\begin{array}{c:l} \hline a & b \\ c & \unknown \end{array}",
+        ];
+        let config = ParserConfig {
+            error_recovery: true,
+            ..ParserConfig::default()
+        };
+
+        for input in inputs {
+            let mut expected = String::new();
+            let buffer = {
+                let input = String::from(input);
+                let storage = Storage::new();
+                let parser = Parser::with_config(&input, &storage, config);
+                push_mathml(&mut expected, parser, RenderConfig::default()).unwrap();
+
+                let buffer = Parser::with_config(&input, &storage, config)
+                    .collect::<Result<EventBuffer, _>>()
+                    .unwrap();
+                let events = Parser::with_config(&input, &storage, config)
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                assert!(buffer.iter().eq(events));
+                assert_eq!(buffer.iter().rev().count(), buffer.len());
+                buffer
+            };
+
+            let sent = std::thread::spawn(move || {
+                let mut output = String::new();
+                push_mathml(&mut output, &buffer, RenderConfig::default()).unwrap();
+                output
+            })
+            .join()
+            .unwrap();
+            assert_eq!(sent, expected);
+        }
+    }
+}
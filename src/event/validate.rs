@@ -0,0 +1,488 @@
+//! Validation of streams of events that do not come from the [`Parser`].
+//!
+//! Renderers assume that the events they are given uphold the invariants documented in the
+//! [`event`](super) module, and may panic otherwise. Events produced by the [`Parser`] always
+//! uphold them, but events produced or transformed by other means can be checked by wrapping
+//! them in a [`Validator`].
+//!
+//! [`Parser`]: crate::parser::Parser
+
+use std::{error::Error, fmt::Display};
+
+use super::{ArrayColumn, EnvironmentFlow, Event, Grouping, ScriptType, Visual};
+
+/// An iterator adapter checking that a stream of events upholds the invariants documented in the
+/// [`event`](super) module.
+///
+/// Events are yielded as is, until one of them breaks an invariant. An error describing the
+/// problem is then yielded in its place, and the rest of the stream is dropped. Renderers handle
+/// errors as elements, so the validator then yields the events needed to keep the stream
+/// well-formed: empty groups in place of missing elements, and the end of every open group.
+///
+/// Errors yielded by the stream itself are passed through as [`ValidationError::Source`], and the
+/// events following them are validated as if the error was not there.
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{
+///     event::{validate::Validator, Event, Grouping, Visual},
+///     push_mathml, RenderConfig,
+/// };
+///
+/// // A fraction without a denominator.
+/// let events = [
+///     Event::Visual(Visual::Fraction(None)),
+///     Event::Begin(Grouping::Normal),
+///     Event::End,
+/// ];
+/// let events = events.into_iter().map(Ok::<_, std::convert::Infallible>);
+///
+/// // The missing denominator is rendered as an error, instead of making the renderer panic.
+/// let mut output = String::new();
+/// push_mathml(&mut output, Validator::new(events), RenderConfig::default()).unwrap();
+/// assert!(output.contains("expected an element"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Validator<I> {
+    events: I,
+    /// The groups currently open, the first one being the top level of the stream.
+    groups: Vec<GroupState>,
+    /// The index of the next event.
+    index: usize,
+    done: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct GroupState {
+    /// The number of alignments allowed in a single row, or `None` if the group is not an
+    /// environment.
+    max_alignments: Option<u16>,
+    /// The number of alignments in the current row.
+    alignments: u16,
+    /// The number of elements that must follow before the group can end.
+    expected: usize,
+    /// Whether no event was found in the group yet.
+    at_start: bool,
+}
+
+impl<'a, I, E> Validator<I>
+where
+    I: Iterator<Item = Result<Event<'a>, E>>,
+{
+    /// Wrap a stream of events, such as an [`EventBuffer`](super::buffer::EventBuffer) or an
+    /// iterator over `Result<Event, E>`s.
+    pub fn new<T>(events: T) -> Self
+    where
+        T: IntoIterator<IntoIter = I>,
+    {
+        Self {
+            events: events.into_iter(),
+            groups: vec![GroupState::default()],
+            index: 0,
+            done: false,
+        }
+    }
+
+    fn check(&mut self, event: &Event<'a>) -> Result<(), InvalidEvent> {
+        if let Event::Begin(Grouping::Array(columns)) = event {
+            if !columns
+                .iter()
+                .any(|column| matches!(column, ArrayColumn::Column(_)))
+            {
+                return Err(InvalidEvent::EmptyArray);
+            }
+        }
+        let group = self
+            .groups
+            .last_mut()
+            .expect("the top level is never popped");
+        let at_start = std::mem::take(&mut group.at_start);
+        match event {
+            Event::End => {
+                if group.expected != 0 {
+                    return Err(InvalidEvent::MissingElement);
+                }
+                if self.groups.len() == 1 {
+                    return Err(InvalidEvent::UnbalancedEnd);
+                }
+                self.groups.pop();
+                return Ok(());
+            }
+            Event::EnvironmentFlow(flow) => {
+                if group.expected != 0 {
+                    return Err(InvalidEvent::MissingElement);
+                }
+                let Some(max_alignments) = group.max_alignments else {
+                    return Err(InvalidEvent::FlowOutsideEnvironment);
+                };
+                match flow {
+                    EnvironmentFlow::Alignment if group.alignments >= max_alignments => {
+                        return Err(InvalidEvent::TooManyAlignments);
+                    }
+                    EnvironmentFlow::Alignment => group.alignments += 1,
+                    EnvironmentFlow::NewLine { .. } => group.alignments = 0,
+                    EnvironmentFlow::StartLines { .. } if !at_start => {
+                        return Err(InvalidEvent::MisplacedStartLines);
+                    }
+                    EnvironmentFlow::StartLines { .. } => {}
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        // The event starts an element.
+        group.expected = group.expected.saturating_sub(1);
+        match event {
+            Event::Begin(grouping) => {
+                let max_alignments = match grouping {
+                    Grouping::Normal | Grouping::LeftRight(..) => None,
+                    Grouping::Array(columns) => {
+                        let count = columns
+                            .iter()
+                            .filter(|column| matches!(column, ArrayColumn::Column(_)))
+                            .count();
+                        Some(u16::try_from(count - 1).unwrap_or(u16::MAX))
+                    }
+                    Grouping::Matrix { .. } | Grouping::Align { .. } | Grouping::Aligned => {
+                        Some(u16::MAX)
+                    }
+                    Grouping::Cases { .. } | Grouping::Split => Some(1),
+                    Grouping::Alignat { pairs, .. } | Grouping::Alignedat { pairs } => {
+                        Some(pairs.saturating_mul(2).saturating_sub(1))
+                    }
+                    Grouping::Equation { .. }
+                    | Grouping::SubArray { .. }
+                    | Grouping::Gather { .. }
+                    | Grouping::Gathered
+                    | Grouping::Multline => Some(0),
+                };
+                self.groups.push(GroupState {
+                    max_alignments,
+                    alignments: 0,
+                    expected: 0,
+                    at_start: true,
+                });
+            }
+            Event::Visual(visual) => {
+                group.expected += match visual {
+                    Visual::SquareRoot | Visual::Negation => 1,
+                    Visual::Root | Visual::Fraction(_) => 2,
+                };
+            }
+            Event::Script { ty, .. } => {
+                group.expected += match ty {
+                    ScriptType::Subscript | ScriptType::Superscript => 2,
+                    ScriptType::SubSuperscript => 3,
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn element_found(&mut self) {
+        let group = self
+            .groups
+            .last_mut()
+            .expect("the top level is never popped");
+        group.expected = group.expected.saturating_sub(1);
+    }
+
+    /// Yield the next event needed to end the stream after an error, replacing missing elements
+    /// by empty groups, and closing the groups that are still open.
+    fn close(&mut self) -> Option<Event<'a>> {
+        let event = match self.groups.last() {
+            Some(group) if group.expected != 0 => Event::Begin(Grouping::Normal),
+            _ if self.groups.len() > 1 => Event::End,
+            _ => return None,
+        };
+        self.check(&event)
+            .expect("closing events uphold the invariants");
+        Some(event)
+    }
+
+    /// Check that the stream can end after the events seen so far.
+    fn check_end(&self) -> Result<(), InvalidEvent> {
+        let group = self.groups.last().expect("the top level is never popped");
+        if group.expected != 0 {
+            Err(InvalidEvent::MissingElement)
+        } else if self.groups.len() != 1 {
+            Err(InvalidEvent::UnclosedGroup)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, I, E> Iterator for Validator<I>
+where
+    I: Iterator<Item = Result<Event<'a>, E>>,
+{
+    type Item = Result<Event<'a>, ValidationError<E>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return self.close().map(Ok);
+        }
+        let index = self.index;
+        self.index += 1;
+        let kind = match self.events.next() {
+            None => {
+                self.done = true;
+                self.check_end().err()?
+            }
+            Some(Err(error)) => {
+                // Renderers handle errors as elements.
+                self.element_found();
+                return Some(Err(ValidationError::Source(error)));
+            }
+            Some(Ok(event)) => match self.check(&event) {
+                Ok(()) => return Some(Ok(event)),
+                Err(kind) => kind,
+            },
+        };
+        self.done = true;
+        self.element_found();
+        Some(Err(ValidationError::Invalid { index, kind }))
+    }
+}
+
+/// An error yielded by a [`Validator`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError<E> {
+    /// An error yielded by the validated stream itself, such as a
+    /// [`ParserError`](crate::ParserError).
+    Source(E),
+    /// An event breaking an invariant.
+    Invalid {
+        /// The index of the event in the stream. When the stream ends unexpectedly, this is the
+        /// number of events in the stream.
+        index: usize,
+        /// The invariant that was broken.
+        kind: InvalidEvent,
+    },
+}
+
+impl<E: Display> Display for ValidationError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::Source(error) => error.fmt(f),
+            ValidationError::Invalid { index, kind } => {
+                write!(f, "invalid event at index {index}: {kind}")
+            }
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for ValidationError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ValidationError::Source(error) => Some(error),
+            ValidationError::Invalid { kind, .. } => Some(kind),
+        }
+    }
+}
+
+/// The invariant broken by an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum InvalidEvent {
+    /// An element was expected after an [`Event::Visual`] or an [`Event::Script`], but an
+    /// [`Event::End`] or an [`Event::EnvironmentFlow`] was found, or the stream ended.
+    MissingElement,
+    /// An [`Event::End`] was found outside of any group.
+    UnbalancedEnd,
+    /// The stream ended before all groups were closed.
+    UnclosedGroup,
+    /// An [`Event::EnvironmentFlow`] was found outside of an environment, or directly inside of
+    /// a `Grouping::Normal` or `Grouping::LeftRight` nested in an environment.
+    FlowOutsideEnvironment,
+    /// An [`EnvironmentFlow::Alignment`] was found in an environment which does not allow as many
+    /// of them in a single row.
+    ///
+    /// `Grouping::Matrix`, `Grouping::Align` and `Grouping::Aligned` allow any number of
+    /// alignments, `Grouping::Array` one less than its number of columns, `Grouping::Cases` and
+    /// `Grouping::Split` one, `Grouping::Alignat` and `Grouping::Alignedat` one less than twice
+    /// their number of pairs, and the other environments none.
+    TooManyAlignments,
+    /// An [`EnvironmentFlow::StartLines`] was found elsewhere than right after the
+    /// [`Event::Begin`] of an environment.
+    MisplacedStartLines,
+    /// A `Grouping::Array` does not contain any [`ArrayColumn::Column`].
+    EmptyArray,
+}
+
+impl Display for InvalidEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            InvalidEvent::MissingElement => "expected an element",
+            InvalidEvent::UnbalancedEnd => "found the end of a group that was not opened",
+            InvalidEvent::UnclosedGroup => "the stream ended before all groups were closed",
+            InvalidEvent::FlowOutsideEnvironment => {
+                "found an environment flow event outside of an environment"
+            }
+            InvalidEvent::TooManyAlignments => "too many alignments in the current row",
+            InvalidEvent::MisplacedStartLines => {
+                "horizontal lines must be given right at the start of an environment"
+            }
+            InvalidEvent::EmptyArray => "an array must have at least one column",
+        })
+    }
+}
+
+impl Error for InvalidEvent {}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::{InvalidEvent, ValidationError, Validator};
+    use crate::event::{
+        ArrayColumn, ColumnAlignment, Content, EnvironmentFlow, Event, Grouping, Line,
+        ScriptPosition, ScriptType, Visual,
+    };
+
+    const X: Event = Event::Content(Content::Ordinary {
+        content: 'x',
+        stretchy: false,
+    });
+
+    fn validate(events: Vec<Event>) -> Result<(), (usize, InvalidEvent)> {
+        Validator::new(events.into_iter().map(Ok::<_, Infallible>))
+            .try_for_each(|event| event.map(drop))
+            .map_err(|error| match error {
+                ValidationError::Invalid { index, kind } => (index, kind),
+                ValidationError::Source(error) => match error {},
+            })
+    }
+
+    #[test]
+    fn parser_output_is_valid() {
+        let inputs = [
+            r"x_1^2 + \frac{a}{b} - \sqrt[3]{x} \not= \sqrt{2} \sum\limits_{i=0}^n i",
+            r"\begin{darray}{|c:c|} \hline a & b \\[2pt] \hline c & \end{darray}",
+            r"\begin{alignat}{2} a &= b & c &= d \\ e &= f \end{alignat}",
+            r"\left( \begin{cases} a & b \\ c & d \end{cases} \right) \color{red} \mathbf{x}",
+        ];
+        let storage = crate::Storage::new();
+        for input in inputs {
+            let parser = crate::Parser::new(input, &storage);
+            assert!(
+                Validator::new(parser).all(|event| event.is_ok()),
+                "input: {input}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_streams() {
+        let script = script_event();
+        let cases = [
+            (vec![Event::End], (0, InvalidEvent::UnbalancedEnd)),
+            (
+                vec![Event::Begin(Grouping::Normal), X],
+                (2, InvalidEvent::UnclosedGroup),
+            ),
+            (
+                vec![script.clone(), X, Event::Visual(Visual::Root), X, X],
+                (5, InvalidEvent::MissingElement),
+            ),
+            (
+                vec![Event::Begin(Grouping::Normal), script, X, X, Event::End],
+                (4, InvalidEvent::MissingElement),
+            ),
+            (
+                vec![
+                    Event::Begin(Grouping::Aligned),
+                    Event::Begin(Grouping::Normal),
+                    Event::EnvironmentFlow(EnvironmentFlow::Alignment),
+                ],
+                (2, InvalidEvent::FlowOutsideEnvironment),
+            ),
+            (
+                vec![
+                    Event::Begin(Grouping::Cases { left: true }),
+                    X,
+                    Event::EnvironmentFlow(EnvironmentFlow::Alignment),
+                    X,
+                    Event::EnvironmentFlow(EnvironmentFlow::Alignment),
+                ],
+                (4, InvalidEvent::TooManyAlignments),
+            ),
+            (
+                vec![
+                    Event::Begin(Grouping::Matrix {
+                        alignment: ColumnAlignment::Center,
+                    }),
+                    X,
+                    Event::EnvironmentFlow(EnvironmentFlow::StartLines {
+                        lines: Box::new([Line::Solid]),
+                    }),
+                ],
+                (2, InvalidEvent::MisplacedStartLines),
+            ),
+            (
+                vec![Event::Begin(Grouping::Array(Box::new([
+                    ArrayColumn::Separator(Line::Solid),
+                ])))],
+                (0, InvalidEvent::EmptyArray),
+            ),
+        ];
+        for (events, expected) in cases {
+            assert_eq!(validate(events.clone()), Err(expected), "{events:?}");
+        }
+    }
+
+    #[test]
+    fn stops_after_error() {
+        let events = [Event::End, X].map(Ok::<_, Infallible>);
+        assert_eq!(Validator::new(events).count(), 1);
+    }
+
+    #[test]
+    fn closes_stream_after_error() {
+        let events = [
+            Event::Begin(Grouping::Normal),
+            Event::Visual(Visual::Root),
+            Event::End,
+            X,
+        ];
+        let closed = Validator::new(events.map(Ok::<_, Infallible>))
+            .map(|event| event.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            closed,
+            [
+                Some(Event::Begin(Grouping::Normal)),
+                Some(Event::Visual(Visual::Root)),
+                None,
+                Some(Event::Begin(Grouping::Normal)),
+                Some(Event::End),
+                Some(Event::End),
+            ]
+        );
+
+        let inputs = [
+            vec![Event::Begin(Grouping::Aligned), script_event(), X],
+            vec![
+                Event::Begin(Grouping::Normal),
+                Event::Begin(Grouping::Normal),
+            ],
+            vec![Event::Visual(Visual::Fraction(None)), X],
+        ];
+        for events in inputs {
+            let mut output = String::new();
+            let events = Validator::new(events.into_iter().map(Ok::<_, Infallible>));
+            crate::push_mathml(&mut output, events, Default::default()).unwrap();
+        }
+    }
+
+    fn script_event() -> Event<'static> {
+        Event::Script {
+            ty: ScriptType::SubSuperscript,
+            position: ScriptPosition::Right,
+        }
+    }
+}
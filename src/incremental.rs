@@ -0,0 +1,1252 @@
+//! Incremental parsing and rendering of an equation being edited.
+//!
+//! An [`Equation`] keeps the events and the `MathML` of its input up to date as edits are
+//! applied to it. Instead of parsing the whole input again, an edit only re-parses the top-level
+//! elements it touches, such as a group, a fraction or a script, along with their neighbours. In
+//! a top-level environment, such as `align`, only the rows touched by the edit and their
+//! neighbours are re-parsed.
+//!
+//! Likewise, only the re-parsed elements or rows are rendered again, and their `MathML` is spliced
+//! into the previous one. The elements following them are rendered again as long as the spacing
+//! or the numbering of equations they get differs from before.
+//!
+//! The whole input is parsed again when the previous input contains errors, or when either the
+//! previous or the new input contains a comment or a macro definition, since those can change
+//! the meaning of input far away from the edit.
+//!
+//! ### Examples
+//!
+//! ```rust
+//! use pulldown_latex::incremental::Equation;
+//!
+//! let mut equation = Equation::new(r"\begin{align} a &= b \\ c &= d \end{align}");
+//!
+//! // Replace the `d` by `\frac{1}{2}`.
+//! let change = equation.edit(29..30, r"\frac{1}{2}");
+//! assert_eq!(equation.input(), r"\begin{align} a &= b \\ c &= \frac{1}{2} \end{align}");
+//! // Only the second row was parsed and rendered again, along with its neighbour.
+//! assert_eq!(&equation.input()[change.reparsed], r"a &= b \\ c &= \frac{1}{2}");
+//! assert_eq!(&equation.input()[change.rendered], r"a &= b \\ c &= \frac{1}{2}");
+//! // The part of the output that changed.
+//! assert!(equation.mathml()[change.inserted_output].contains("<mn>1</mn>"));
+//! ```
+
+use std::{
+    convert::Infallible,
+    iter,
+    ops::{ControlFlow, Range},
+};
+
+use crate::{
+    config::{ParserConfig, RenderConfig},
+    event::{validate::Validator, EnvironmentFlow, Event, ScriptType, Visual},
+    mathml::{push_elements, push_equation, push_mathml, Boundary, Checkpoint},
+    parser::{
+        defines_macros,
+        error::{ErrorMessage, ParserError},
+        storage::Storage,
+        Parser,
+    },
+    EventBuffer,
+};
+
+/// An equation whose events and `MathML` are kept up to date as it is edited.
+///
+/// See the [module documentation](self) for more information.
+#[derive(Debug)]
+pub struct Equation<'c> {
+    input: String,
+    parser_config: ParserConfig<'c>,
+    render_config: RenderConfig<'c>,
+    storage: Storage,
+    events: EventBuffer,
+    /// The range of the input each event comes from.
+    spans: Vec<Range<usize>>,
+    /// The top-level elements of the events, or nothing if the input contains errors.
+    elements: Vec<Element>,
+    /// The length of the `MathML` before the first element.
+    header: usize,
+    /// The length of the `MathML` after the last element.
+    footer: usize,
+    errors: Vec<ParserError>,
+    mathml: String,
+}
+
+/// What changed in an [`Equation`] after an edit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The range of the new input which was parsed again.
+    pub reparsed: Range<usize>,
+    /// The range of the new input whose `MathML` was rendered again.
+    pub rendered: Range<usize>,
+    /// The range of the previous events which were replaced.
+    pub removed_events: Range<usize>,
+    /// The range of the new events which replaced them.
+    pub inserted_events: Range<usize>,
+    /// The range of the previous `MathML` which was replaced.
+    pub removed_output: Range<usize>,
+    /// The range of the new `MathML` which replaced it.
+    pub inserted_output: Range<usize>,
+}
+
+/// A top-level element of the events, along with where its rendering starts.
+#[derive(Debug, Clone)]
+struct Element {
+    /// The range of the events making up the element.
+    events: Range<usize>,
+    /// The range of the input the element comes from.
+    span: Range<usize>,
+    /// The range of the `MathML` rendered from the element.
+    output: Range<usize>,
+    /// The position of the renderer before the element.
+    checkpoint: Checkpoint,
+    /// The offset of the `MathML` of each row, relative to the start of the element, if it is a
+    /// mathematical environment whose rows are reported by the renderer.
+    rows: Vec<usize>,
+}
+
+impl<'c> Equation<'c> {
+    /// Parse and render the given input, using the default configurations for the parser and the
+    /// renderer.
+    pub fn new(input: impl Into<String>) -> Self {
+        Self::with_config(input, ParserConfig::default(), RenderConfig::default())
+    }
+
+    /// Parse and render the given input, using the given configurations for the parser and the
+    /// renderer.
+    ///
+    /// If the `annotation` of the `render_config` is set, it is replaced by the current input
+    /// every time the equation is rendered.
+    pub fn with_config(
+        input: impl Into<String>,
+        parser_config: ParserConfig<'c>,
+        render_config: RenderConfig<'c>,
+    ) -> Self {
+        let mut equation = Self {
+            input: input.into(),
+            parser_config,
+            render_config,
+            storage: Storage::new(),
+            events: EventBuffer::new(),
+            spans: Vec::new(),
+            elements: Vec::new(),
+            header: 0,
+            footer: 0,
+            errors: Vec::new(),
+            mathml: String::new(),
+        };
+        equation.parse_all();
+        equation
+    }
+
+    /// The current input of the equation.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// The events of the current input.
+    ///
+    /// If the input contains errors, this is empty, and the errors are available through
+    /// [`Equation::errors`].
+    pub fn events(&self) -> &EventBuffer {
+        &self.events
+    }
+
+    /// The range of the input each of the [`Equation::events`] comes from.
+    pub fn spans(&self) -> &[Range<usize>] {
+        &self.spans
+    }
+
+    /// The errors found while parsing the current input.
+    pub fn errors(&self) -> &[ParserError] {
+        &self.errors
+    }
+
+    /// The `MathML` of the current input, where errors are displayed in place.
+    pub fn mathml(&self) -> &str {
+        &self.mathml
+    }
+
+    /// Replace the given byte range of the input by `replacement`, and update the events and the
+    /// `MathML` of the equation.
+    ///
+    /// ### Panics
+    ///
+    /// Panics if the range is out of bounds, or if its bounds do not lie on a `char` boundary, like
+    /// [`String::replace_range`].
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) -> Change {
+        let could_be_partial = self.errors.is_empty() && is_context_free(&self.input);
+
+        self.input.replace_range(range.clone(), replacement);
+        let edit = Edit {
+            delta: replacement.len() as isize - range.len() as isize,
+            range,
+        };
+
+        if could_be_partial && is_context_free(&self.input) {
+            if let Some(change) = self.edit_partial(&edit) {
+                return change;
+            }
+        }
+
+        let old_events = std::mem::take(&mut self.events);
+        let old_mathml = std::mem::take(&mut self.mathml);
+        self.parse_all();
+        let old_events = old_events.iter().collect::<Vec<_>>();
+        let new_events = self.events.iter().collect::<Vec<_>>();
+        let (removed_events, inserted_events) = diff(&old_events, &new_events);
+        let (removed_output, inserted_output) = {
+            let (removed, inserted) = diff(old_mathml.as_bytes(), self.mathml.as_bytes());
+            char_boundaries(&self.mathml, removed, inserted)
+        };
+
+        Change {
+            reparsed: 0..self.input.len(),
+            rendered: 0..self.input.len(),
+            removed_events,
+            inserted_events,
+            removed_output,
+            inserted_output,
+        }
+    }
+
+    /// Parse and render the whole input.
+    ///
+    /// The stream of events may not be well-formed after an error, so it is validated before
+    /// being rendered.
+    fn parse_all(&mut self) {
+        self.events.clear();
+        self.spans.clear();
+        self.elements.clear();
+        self.errors.clear();
+        self.mathml.clear();
+
+        let items = Parser::with_config(&self.input, &self.storage, self.parser_config)
+            .into_offset_iter()
+            .collect::<Vec<_>>();
+        if items.iter().all(|(event, _)| event.is_ok()) {
+            for (event, span) in items {
+                self.events.push(event.expect("all events are ok"));
+                self.spans.push(span);
+            }
+            self.storage.reset();
+            self.render_all();
+            return;
+        }
+
+        let config = render_config(self.render_config, &self.input);
+        push_mathml(
+            &mut self.mathml,
+            Validator::new(items.iter().map(|(event, _)| match event {
+                Ok(event) => Ok(event.clone()),
+                Err(error) => Err(ErrorMessage(error.to_string())),
+            })),
+            config,
+        )
+        .expect("writing to a string should not fail");
+        self.errors = items
+            .into_iter()
+            .filter_map(|(event, _)| event.err())
+            .collect();
+        self.storage.reset();
+    }
+
+    /// Render the events, which are validated so that the renderer never panics while the input
+    /// is being edited, and record the top-level elements.
+    fn render_all(&mut self) {
+        let config = render_config(self.render_config, &self.input);
+        let mut boundaries = Vec::new();
+        let mut well_formed = true;
+        push_equation(
+            &mut self.mathml,
+            Validator::new(&self.events).inspect(|event| well_formed &= event.is_ok()),
+            config,
+            |boundary| boundaries.push(boundary),
+        )
+        .expect("writing to a string should not fail");
+        self.header = boundaries.first().map_or(0, |boundary| boundary.output);
+        // The elements of events which are not well-formed are unknown, so that the next edit
+        // parses the whole input again.
+        if well_formed {
+            self.elements = self.elements_from(0, 0, &boundaries);
+        }
+        self.footer = self.mathml.len() - self.body_end();
+    }
+
+    /// The elements delimited by the boundaries reported while rendering the events from index
+    /// `events`, whose `MathML` starts at offset `output`.
+    fn elements_from(&self, events: usize, output: usize, boundaries: &[Boundary]) -> Vec<Element> {
+        let mut elements = Vec::new();
+        // The element started by the last boundary, which is complete once the next one is
+        // reported.
+        let mut open: Option<Element> = None;
+        for boundary in boundaries {
+            let event = events + boundary.events;
+            let offset = output + boundary.output;
+            let Some(checkpoint) = boundary.checkpoint else {
+                if let Some(element) = &mut open {
+                    element.rows.push(offset - element.output.start);
+                }
+                continue;
+            };
+            if let Some(mut element) = open.take() {
+                element.events.end = event;
+                element.output.end = offset;
+                element.span = covering(&self.spans[element.events.clone()]);
+                elements.push(element);
+            }
+            open = Some(Element {
+                events: event..event,
+                span: 0..0,
+                output: offset..offset,
+                checkpoint,
+                rows: Vec::new(),
+            });
+        }
+        elements
+    }
+
+    /// The offset of the `MathML` after the last element.
+    fn body_end(&self) -> usize {
+        self.elements
+            .last()
+            .map_or(self.header, |element| element.output.end)
+    }
+
+    /// Re-parse and render again the part of the input affected by the edit. Returns `None` if
+    /// the edit cannot be handled without parsing the whole input.
+    fn edit_partial(&mut self, edit: &Edit) -> Option<Change> {
+        let elements = &self.elements;
+        // The elements touching the edit are `first..last`.
+        let first = elements.partition_point(|element| element.span.end < edit.range.start);
+        let last = first
+            + elements[first..].partition_point(|element| element.span.start <= edit.range.end);
+
+        if last == first + 1 && matches!(self.event(elements[first].events.start), Event::Begin(_))
+        {
+            let change = self.edit_rows(first, edit);
+            if change.is_some() {
+                return change;
+            }
+        }
+
+        // Re-parse the touched elements along with their neighbours, so that scripts and
+        // arguments find their base or their command again.
+        let elements = &self.elements;
+        let region = first.saturating_sub(1)..(last + 1).min(elements.len());
+        if region.is_empty() {
+            return None;
+        }
+        let removed = elements[region.start].events.start..elements[region.end - 1].events.end;
+        let start = elements[region.start].span.start.min(edit.range.start);
+        let end = edit.shift(elements[region.end - 1].span.end.max(edit.range.end));
+
+        let (new_events, new_spans) = parse_fragment(
+            &mut self.storage,
+            self.parser_config,
+            &self.input[start..end],
+        )?;
+        let new_events = new_events.iter().collect::<Vec<_>>();
+        // The right neighbour must be parsed as it was before, otherwise the elements following
+        // it may be parsed differently as well.
+        if last < elements.len() {
+            let new_last = top_level_elements(&new_events).pop()?;
+            if !new_events[new_last]
+                .iter()
+                .cloned()
+                .eq(self.events.slice(elements[last].events.clone()))
+            {
+                return None;
+            }
+        }
+
+        let (removed_events, inserted_events) = self.splice(
+            removed,
+            None,
+            &new_events,
+            new_spans
+                .into_iter()
+                .map(|span| span.start + start..span.end + start),
+            edit,
+        );
+        let (removed_output, inserted_output, rendered) =
+            self.render_elements(region, new_events.len(), edit);
+        Some(Change {
+            reparsed: start..end,
+            rendered,
+            removed_events,
+            inserted_events,
+            removed_output,
+            inserted_output,
+        })
+    }
+
+    /// Re-parse and render again the rows of the top-level environment at `index` touched by the
+    /// edit.
+    fn edit_rows(&mut self, index: usize, edit: &Edit) -> Option<Change> {
+        let element = &self.elements[index];
+        let begin = element.events.start;
+        let end = element.events.end - 1;
+        if !matches!(self.event(end), Event::End) {
+            return None;
+        }
+        let env_span = self.spans[begin].clone();
+        // The events produced by the `\begin` of the environment, which all come from the whole
+        // environment.
+        let header = begin
+            + 1
+            + self
+                .events
+                .slice(begin + 1..end)
+                .zip(&self.spans[begin + 1..end])
+                .take_while(|(event, span)| {
+                    **span == env_span
+                        && matches!(
+                            event,
+                            Event::EnvironmentFlow(EnvironmentFlow::StartLines { .. })
+                                | Event::StateChange(_)
+                        )
+                })
+                .count();
+        if header == end {
+            return None;
+        }
+        let header_end = self.spans[header].start;
+        let footer_start = self.spans[end - 1].end;
+        // The edit must be strictly inside of the environment, between its header and footer.
+        if header_end <= env_span.start
+            || footer_start >= env_span.end
+            || edit.range.start < header_end
+            || edit.range.end > footer_start
+        {
+            return None;
+        }
+
+        // The index of the `NewLine` events ending each row, and of the `End` of the environment.
+        let mut row_ends = Vec::new();
+        let mut depth = 0usize;
+        for (index, event) in self.events.slice(header..end).enumerate() {
+            match event {
+                Event::Begin(_) => depth += 1,
+                Event::End => depth -= 1,
+                Event::EnvironmentFlow(EnvironmentFlow::NewLine { .. }) if depth == 0 => {
+                    row_ends.push(header + index)
+                }
+                _ => {}
+            }
+        }
+        row_ends.push(end);
+        let row_start = |row: usize| match row {
+            0 => header_end,
+            _ => self.spans[row_ends[row - 1]].end,
+        };
+        let row_end = |row: usize| match row_ends[row] {
+            index if index == end => footer_start,
+            index => self.spans[index].start,
+        };
+        let row_first_event = |row: usize| match row {
+            0 => header,
+            _ => row_ends[row - 1] + 1,
+        };
+
+        // The rows covering the edit, which may start or end in the `\\` between two rows.
+        let first = (0..row_ends.len())
+            .rev()
+            .find(|&row| row_start(row) <= edit.range.start)?;
+        let last = (first..row_ends.len()).find(|&row| row_end(row) >= edit.range.end)?;
+        // The neighbouring rows are parsed again as well, and must be parsed as they were before,
+        // since the edit may change the meaning of the `\\` between two rows.
+        let leading = match first {
+            0 => 0,
+            _ => row_ends[first - 1] + 1 - row_first_event(first - 1),
+        };
+        let trailing = match row_ends.get(last + 1) {
+            Some(&next) => next - row_ends[last],
+            None => 0,
+        };
+        let first = first.saturating_sub(1);
+        let last = (last + 1).min(row_ends.len() - 1);
+        let start = row_start(first);
+        let end_offset = edit.shift(row_end(last));
+        let first_event = row_first_event(first);
+        let last_event = row_ends[last];
+
+        let prefix = &self.input[env_span.start..header_end];
+        let suffix = &self.input[edit.shift(footer_start)..edit.shift(env_span.end)];
+        let wrapped = format!("{prefix}{}{suffix}", &self.input[start..end_offset]);
+        let (new_events, new_spans) =
+            parse_fragment(&mut self.storage, self.parser_config, &wrapped)?;
+        let new_events = new_events.iter().collect::<Vec<_>>();
+
+        // The fragment must still be a single environment, beginning like the original one.
+        if top_level_elements(&new_events).len() != 1
+            || new_events.len() < header - begin + 1
+            || !new_events[..header - begin]
+                .iter()
+                .cloned()
+                .eq(self.events.slice(begin..header))
+            || new_events.last() != Some(&Event::End)
+        {
+            return None;
+        }
+        let inner = header - begin..new_events.len() - 1;
+        let new_inner = &new_events[inner.clone()];
+        if new_inner.len() < leading + trailing
+            || !new_inner[..leading]
+                .iter()
+                .cloned()
+                .eq(self.events.slice(first_event..first_event + leading))
+            || !new_inner[new_inner.len() - trailing..]
+                .iter()
+                .cloned()
+                .eq(self.events.slice(last_event - trailing..last_event))
+        {
+            return None;
+        }
+        let offset = start as isize - prefix.len() as isize;
+        let middle = prefix.len()..prefix.len() + end_offset - start;
+        if new_spans[inner.clone()]
+            .iter()
+            .any(|span| span.start < middle.start || span.end > middle.end)
+        {
+            return None;
+        }
+
+        let rows = self.elements[index].rows.len() == row_ends.len();
+        let element_events = self.elements[index].events.len() + new_inner.len();
+        let (removed_events, inserted_events) = self.splice(
+            first_event..last_event,
+            Some(begin),
+            new_inner,
+            new_spans[inner].iter().map(|span| {
+                (span.start as isize + offset) as usize..(span.end as isize + offset) as usize
+            }),
+            edit,
+        );
+        let (removed_output, inserted_output, rendered) = if rows {
+            let (removed, inserted) = self.render_rows(
+                index,
+                first..last + 1,
+                header,
+                first_event..last_event,
+                new_inner.len(),
+                edit,
+            );
+            (removed, inserted, start..end_offset)
+        } else {
+            self.render_elements(
+                index..index + 1,
+                element_events - (last_event - first_event),
+                edit,
+            )
+        };
+        Some(Change {
+            reparsed: start..end_offset,
+            rendered,
+            removed_events,
+            inserted_events,
+            removed_output,
+            inserted_output,
+        })
+    }
+
+    /// Render the elements in `region` of the previous elements again, once their events have
+    /// been replaced by `inserted` events, along with the elements following them until one is
+    /// rendered from the same checkpoint as before.
+    ///
+    /// The element before the region is rendered again as well, since it may look ahead at the
+    /// first atom of the region, along with the spaces and state changes between them. Returns
+    /// the ranges of the previous and new `MathML` which differ, and the range of the input which
+    /// was rendered again.
+    fn render_elements(
+        &mut self,
+        region: Range<usize>,
+        inserted: usize,
+        edit: &Edit,
+    ) -> (Range<usize>, Range<usize>, Range<usize>) {
+        let elements = &self.elements;
+        let removed = elements[region.start].events.start..elements[region.end - 1].events.end;
+        let inserted_end = removed.start + inserted;
+        let mut first = region.start;
+        while first > 0 && self.is_transparent(&elements[first - 1]) {
+            first -= 1;
+        }
+        let first = first.saturating_sub(1);
+        let (events_start, output_start) =
+            (elements[first].events.start, elements[first].output.start);
+
+        let config = render_config(self.render_config, &self.input);
+        let events = self.events.slice(events_start..self.events.len());
+        let mut rendered = String::new();
+        let mut boundaries = Vec::new();
+        // The previous element following the region which the renderer reaches next. Once it is
+        // reached from the same checkpoint as before, it is kept along with the ones after it.
+        let mut next = region.end;
+        let mut converged = false;
+        push_elements(
+            &mut rendered,
+            Validator::new(events.map(Ok::<_, Infallible>)),
+            config,
+            elements[first].checkpoint,
+            |boundary| {
+                boundaries.push(boundary);
+                let Some(checkpoint) = boundary.checkpoint else {
+                    return ControlFlow::Continue(());
+                };
+                // The index of the next event in the previous events, if it follows the region.
+                let Some(event) = (events_start + boundary.events)
+                    .checked_sub(inserted_end)
+                    .map(|event| event + removed.end)
+                else {
+                    return ControlFlow::Continue(());
+                };
+                while next < elements.len() && elements[next].events.start < event {
+                    next += 1;
+                }
+                converged = elements.get(next).is_some_and(|element| {
+                    element.events.start == event && element.checkpoint == checkpoint
+                });
+                if converged {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            },
+        )
+        .expect("writing to a string should not fail");
+
+        let resume = if converged { next } else { elements.len() };
+        let old_end = match elements.get(resume) {
+            Some(element) => element.output.start,
+            None => self.body_end(),
+        };
+        let new_elements = self.elements_from(events_start, output_start, &boundaries);
+        let rendered_input = match (new_elements.first(), new_elements.last()) {
+            (Some(first), Some(last)) => first.span.start..last.span.end,
+            _ => edit.shift(edit.range.end)..edit.shift(edit.range.end),
+        };
+        let delta_events = inserted as isize - removed.len() as isize;
+        let delta_output = rendered.len() as isize - (old_end - output_start) as isize;
+        self.shift_elements(resume, delta_events, delta_output, edit);
+        self.elements.splice(first..resume, new_elements);
+        let (removed_output, inserted_output) =
+            self.splice_output(output_start..old_end, &rendered);
+        (removed_output, inserted_output, rendered_input)
+    }
+
+    /// Render the `rows` of the top-level environment at `index` again, once the events in
+    /// `removed` have been replaced by `inserted` events.
+    ///
+    /// The rows are rendered after the `\begin` of the environment and its `header` events, and
+    /// after the `\\` preceding them unless they are the first ones, up to the `\\` or the `\end`
+    /// following them. Returns the ranges of the previous and new `MathML` which differ.
+    fn render_rows(
+        &mut self,
+        index: usize,
+        rows: Range<usize>,
+        header: usize,
+        removed: Range<usize>,
+        inserted: usize,
+        edit: &Edit,
+    ) -> (Range<usize>, Range<usize>) {
+        let element = &self.elements[index];
+        let begin = element.events.start;
+        // The `\\` or the `\end` following the rows.
+        let end = removed.start + inserted;
+        let from = removed.start - usize::from(rows.start > 0);
+        let length = header - begin + end + 1 - from;
+        let events = self
+            .events
+            .slice(begin..header)
+            .chain(self.events.slice(from..end + 1));
+
+        let config = render_config(self.render_config, &self.input);
+        let mut rendered = String::new();
+        let mut starts = Vec::new();
+        push_elements(
+            &mut rendered,
+            Validator::new(events.map(Ok::<_, Infallible>)),
+            config,
+            element.checkpoint,
+            |boundary| {
+                if boundary.checkpoint.is_none() {
+                    starts.push(boundary.output);
+                }
+                if boundary.events == length {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            },
+        )
+        .expect("writing to a string should not fail");
+
+        // The rows start after the `\begin`, or after the `\\` preceding them.
+        let starts = &starts[usize::from(rows.start > 0)..];
+        let rendered = &rendered[starts[0]..];
+        let old_start = element.output.start + element.rows[rows.start];
+        let old_end = element
+            .rows
+            .get(rows.end)
+            .map_or(element.output.end, |row| element.output.start + row);
+        // The start of the row following the rows after a `\\` is not one of theirs.
+        let new_rows = starts[..starts.len() - usize::from(rows.end < element.rows.len())]
+            .iter()
+            .map(|start| element.rows[rows.start] + start - starts[0])
+            .collect::<Vec<_>>();
+        let delta_events = inserted as isize - removed.len() as isize;
+        let delta_output = rendered.len() as isize - (old_end - old_start) as isize;
+
+        self.shift_elements(index + 1, delta_events, delta_output, edit);
+        let element = &mut self.elements[index];
+        let following = element.rows.split_off(rows.end);
+        element.rows.truncate(rows.start);
+        element.rows.extend(new_rows);
+        element
+            .rows
+            .extend(following.into_iter().map(|row| shift(row, delta_output)));
+        element.events.end = shift(element.events.end, delta_events);
+        element.output.end = shift(element.output.end, delta_output);
+        element.span.end = edit.shift(element.span.end);
+        self.splice_output(old_start..old_end, rendered)
+    }
+
+    /// Shift the elements from `from` on, which follow the edit, by the difference in the number
+    /// of events and in the length of the `MathML` before them.
+    fn shift_elements(&mut self, from: usize, events: isize, output: isize, edit: &Edit) {
+        for element in &mut self.elements[from..] {
+            element.events = shift(element.events.start, events)..shift(element.events.end, events);
+            element.output = shift(element.output.start, output)..shift(element.output.end, output);
+            element.span = edit.shift(element.span.start)..edit.shift(element.span.end);
+        }
+    }
+
+    /// Replace the `removed` part of the `MathML` by `rendered`, along with the annotation if
+    /// there is one. Returns the ranges of the previous and new `MathML` which differ.
+    fn splice_output(
+        &mut self,
+        removed: Range<usize>,
+        rendered: &str,
+    ) -> (Range<usize>, Range<usize>) {
+        let (old, new) = diff(
+            &self.mathml.as_bytes()[removed.clone()],
+            rendered.as_bytes(),
+        );
+        let mut removed_output = removed.start + old.start..removed.start + old.end;
+        let mut inserted_output = removed.start + new.start..removed.start + new.end;
+        let old_footer = self.mathml.len() - self.footer;
+        self.mathml.replace_range(removed, rendered);
+
+        if self.render_config.annotation.is_some() {
+            // The annotation is the input, which is written after the last element.
+            let mut empty = String::new();
+            let config = render_config(self.render_config, &self.input);
+            push_mathml(
+                &mut empty,
+                iter::empty::<Result<Event, Infallible>>(),
+                config,
+            )
+            .expect("writing to a string should not fail");
+            let footer = &empty[self.header..];
+            let footer_start = self.mathml.len() - self.footer;
+            let (old, new) = diff(&self.mathml.as_bytes()[footer_start..], footer.as_bytes());
+            if removed_output.is_empty() && inserted_output.is_empty() {
+                removed_output.start = old_footer + old.start;
+                inserted_output.start = footer_start + new.start;
+            }
+            removed_output.end = old_footer + old.end;
+            inserted_output.end = footer_start + new.end;
+            self.mathml.replace_range(footer_start.., footer);
+            self.footer = footer.len();
+        }
+        char_boundaries(&self.mathml, removed_output, inserted_output)
+    }
+
+    /// Replace the events in `removed` by `inserted`, shifting the spans of the following events
+    /// by the length of the edit. The span of `enclosing`, and of the events sharing it, is
+    /// extended to include the edit. Returns the ranges of the previous and new events which
+    /// differ.
+    fn splice(
+        &mut self,
+        removed: Range<usize>,
+        enclosing: Option<usize>,
+        inserted: &[Event],
+        inserted_spans: impl Iterator<Item = Range<usize>>,
+        edit: &Edit,
+    ) -> (Range<usize>, Range<usize>) {
+        let (old, new) = diff(
+            &self.events.slice(removed.clone()).collect::<Vec<_>>(),
+            inserted,
+        );
+        let changed = (
+            removed.start + old.start..removed.start + old.end,
+            removed.start + new.start..removed.start + new.end,
+        );
+
+        let old_events = std::mem::take(&mut self.events);
+        let old_spans = std::mem::take(&mut self.spans);
+        let enclosing = enclosing.map(|index| old_spans[index].clone());
+        let shift = |span: &Range<usize>| {
+            if Some(span) == enclosing.as_ref() {
+                span.start..edit.shift(span.end)
+            } else if span.start >= edit.range.end {
+                edit.shift(span.start)..edit.shift(span.end)
+            } else {
+                span.clone()
+            }
+        };
+
+        self.events.extend(old_events.iter().take(removed.start));
+        self.spans
+            .extend(old_spans[..removed.start].iter().map(shift));
+        self.events.extend(inserted.iter().cloned());
+        self.spans.extend(inserted_spans);
+        self.events.extend(old_events.iter().skip(removed.end));
+        self.spans
+            .extend(old_spans[removed.end..].iter().map(shift));
+        changed
+    }
+
+    /// The event at the given index.
+    fn event(&self, index: usize) -> Event<'_> {
+        self.events
+            .slice(index..index + 1)
+            .next()
+            .expect("the index is in bounds")
+    }
+
+    /// Whether the element is skipped when the renderer looks ahead at the next atom, i.e., it is
+    /// a space or a state change.
+    fn is_transparent(&self, element: &Element) -> bool {
+        element.events.len() == 1
+            && matches!(
+                self.event(element.events.start),
+                Event::Space { .. } | Event::StateChange(_)
+            )
+    }
+}
+
+struct Edit {
+    /// The range of the previous input which was replaced.
+    range: Range<usize>,
+    /// The difference between the length of the replacement and the length of the range.
+    delta: isize,
+}
+
+impl Edit {
+    /// Map an offset of the previous input which is not before the edit to the new input.
+    fn shift(&self, offset: usize) -> usize {
+        (offset as isize + self.delta) as usize
+    }
+}
+
+/// The render configuration, whose annotation, if any, is replaced by the input.
+fn render_config<'a>(config: RenderConfig<'a>, input: &'a str) -> RenderConfig<'a> {
+    RenderConfig {
+        annotation: config.annotation.map(|_| input),
+        ..config
+    }
+}
+
+/// Parse a fragment of input on its own, returning its events and their spans, or `None` if it
+/// contains errors or its events are not well-formed.
+fn parse_fragment(
+    storage: &mut Storage,
+    config: ParserConfig,
+    fragment: &str,
+) -> Option<(EventBuffer, Vec<Range<usize>>)> {
+    let mut events = EventBuffer::new();
+    let mut spans = Vec::new();
+    let result = Parser::with_config(fragment, storage, config)
+        .into_offset_iter()
+        .try_for_each(|(event, span)| {
+            events.push(event?);
+            spans.push(span);
+            Ok::<_, ParserError>(())
+        });
+    storage.reset();
+    let well_formed = Validator::new(&events).all(|event| event.is_ok());
+    result
+        .ok()
+        .filter(|()| well_formed)
+        .map(|()| (events, spans))
+}
+
+/// The ranges of the events making up each top-level element of the stream.
+fn top_level_elements(events: &[Event]) -> Vec<Range<usize>> {
+    let mut elements = Vec::new();
+    // The number of elements still expected in each open group.
+    let mut expected = vec![0usize];
+    let mut start = 0;
+    for (index, event) in events.iter().enumerate() {
+        let current = expected.last_mut().expect("the top level is never popped");
+        match event {
+            Event::End => {
+                expected.pop();
+            }
+            Event::EnvironmentFlow(_) => {}
+            _ => {
+                *current = current.saturating_sub(1);
+                match event {
+                    Event::Begin(_) => expected.push(0),
+                    Event::Visual(Visual::SquareRoot | Visual::Negation) => *current += 1,
+                    Event::Visual(Visual::Root | Visual::Fraction(_)) => *current += 2,
+                    Event::Script {
+                        ty: ScriptType::Subscript | ScriptType::Superscript,
+                        ..
+                    } => *current += 2,
+                    Event::Script {
+                        ty: ScriptType::SubSuperscript,
+                        ..
+                    } => *current += 3,
+                    _ => {}
+                }
+            }
+        }
+        if expected.is_empty() {
+            // The stream is unbalanced, which cannot happen in a stream without errors.
+            return Vec::new();
+        }
+        if expected == [0] {
+            elements.push(start..index + 1);
+            start = index + 1;
+        }
+    }
+    elements
+}
+
+/// Whether the meaning of every part of the input is independent of the rest of the input,
+/// i.e., it contains no comments and no macro definitions.
+fn is_context_free(input: &str) -> bool {
+    // A `%` is escaped when it follows an odd number of backslashes.
+    let has_comment = input.match_indices('%').any(|(index, _)| {
+        input[..index]
+            .bytes()
+            .rev()
+            .take_while(|&byte| byte == b'\\')
+            .count()
+            % 2
+            == 0
+    });
+    !has_comment && !defines_macros(input)
+}
+
+/// The range of the input covered by the spans.
+fn covering(spans: &[Range<usize>]) -> Range<usize> {
+    let start = spans.iter().map(|span| span.start).min().unwrap_or(0);
+    let end = spans.iter().map(|span| span.end).max().unwrap_or(0);
+    start..end
+}
+
+/// Add a possibly negative difference to an offset.
+fn shift(offset: usize, delta: isize) -> usize {
+    (offset as isize + delta) as usize
+}
+
+/// The range of `old` and `new` which differ, once their common prefix and suffix are removed.
+fn diff<T: PartialEq>(old: &[T], new: &[T]) -> (Range<usize>, Range<usize>) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (prefix..old.len() - suffix, prefix..new.len() - suffix)
+}
+
+/// Move the ranges of the previous and new `MathML` which differ to `char` boundaries of the new
+/// `MathML`. The common prefix and suffix are the same in both strings, so moving the bounds to a
+/// `char` boundary moves them by the same amount in both.
+fn char_boundaries(
+    mathml: &str,
+    removed: Range<usize>,
+    inserted: Range<usize>,
+) -> (Range<usize>, Range<usize>) {
+    let start = floor_char_boundary(mathml, inserted.start);
+    let end = ceil_char_boundary(mathml, inserted.end);
+    (start..removed.end + end - inserted.end, start..end)
+}
+
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DisplayMode, Profile};
+
+    /// The render configurations the edits are checked with, which write the `MathML` through
+    /// every writer.
+    fn render_configs() -> [RenderConfig<'static>; 4] {
+        [
+            RenderConfig::default(),
+            RenderConfig {
+                display_mode: DisplayMode::Block,
+                annotation: Some(""),
+                xml: true,
+                xml_prefix: true,
+                safe: true,
+                ..RenderConfig::default()
+            },
+            RenderConfig {
+                self_contained: true,
+                ..RenderConfig::default()
+            },
+            RenderConfig {
+                display_mode: DisplayMode::Block,
+                annotation: Some(""),
+                safe: true,
+                profile: Profile::MathML3,
+                ..RenderConfig::default()
+            },
+        ]
+    }
+
+    /// Apply the edit, and check that the equation is the same as one parsed and rendered from
+    /// scratch.
+    fn check_edit(equation: &mut Equation, range: Range<usize>, replacement: &str) -> Change {
+        let old_input = equation.input().to_owned();
+        let old_mathml = equation.mathml().to_owned();
+        let old_events = equation.events().iter().collect::<EventBuffer>();
+        let change = equation.edit(range.clone(), replacement);
+
+        let expected = Equation::with_config(
+            equation.input(),
+            equation.parser_config,
+            equation.render_config,
+        );
+        let context = format!("{old_input:?} with {range:?} replaced by {replacement:?}");
+        assert_eq!(equation.events(), expected.events(), "{context}");
+        assert_eq!(equation.spans(), expected.spans(), "{context}");
+        assert_eq!(equation.mathml(), expected.mathml(), "{context}");
+        if equation.errors().is_empty() {
+            let mut mathml = String::new();
+            let config = render_config(equation.render_config, equation.input());
+            push_mathml(&mut mathml, Validator::new(equation.events()), config).unwrap();
+            assert_eq!(equation.mathml(), mathml, "{context}");
+        }
+        assert_eq!(
+            equation.errors().len(),
+            expected.errors().len(),
+            "{context}"
+        );
+
+        let mut patched = old_mathml;
+        patched.replace_range(
+            change.removed_output.clone(),
+            &equation.mathml()[change.inserted_output.clone()],
+        );
+        assert_eq!(patched, equation.mathml(), "{context}");
+        let old_events = old_events.iter().collect::<Vec<_>>();
+        let new_events = equation.events().iter().collect::<Vec<_>>();
+        assert_eq!(
+            old_events[..change.removed_events.start],
+            new_events[..change.inserted_events.start],
+            "{context}"
+        );
+        assert_eq!(
+            old_events[change.removed_events.end..],
+            new_events[change.inserted_events.end..],
+            "{context}"
+        );
+        change
+    }
+
+    #[test]
+    fn partial_reparse() {
+        let mut equation = Equation::new(r"a + \frac{b}{c} - x^2 + {y}");
+        let change = check_edit(&mut equation, 10..11, "bb");
+        assert_eq!(&equation.input()[change.reparsed], r"+ \frac{bb}{c} -");
+
+        let mut equation = Equation::new(r"x + y");
+        let change = check_edit(&mut equation, 1..1, "^2");
+        assert_eq!(&equation.input()[change.reparsed], r"x^2 +");
+        assert_eq!(change.removed_events, 0..1);
+        assert_eq!(change.inserted_events, 0..3);
+    }
+
+    #[test]
+    fn environment_rows() {
+        let rows = (0..50)
+            .map(|i| format!(r"x_{{{i}}} &= \sqrt{{{i}}}"))
+            .collect::<Vec<_>>()
+            .join(r" \\ ");
+        let mut equation = Equation::new(format!(r"\begin{{align}} {rows} \end{{align}}"));
+        let position = equation.input().find(r"\sqrt{25}").unwrap() + 7;
+        let change = check_edit(&mut equation, position..position, "+1");
+        assert_eq!(
+            equation.input()[change.reparsed].trim(),
+            r"x_{24} &= \sqrt{24} \\ x_{25} &= \sqrt{2+15} \\ x_{26} &= \sqrt{26}"
+        );
+
+        // Adding and removing rows.
+        let position = position + 4;
+        let change = check_edit(&mut equation, position..position, r" a \\ b");
+        assert_eq!(
+            equation.input()[change.reparsed].trim(),
+            r"x_{24} &= \sqrt{24} \\ x_{25} &= \sqrt{2+15} a \\ b \\ x_{26} &= \sqrt{26}"
+        );
+        let position = equation.input().find(r"\\ x_{30}").unwrap();
+        let change = check_edit(&mut equation, position..position + 2, "");
+        assert_eq!(
+            equation.input()[change.reparsed].trim(),
+            r"x_{28} &= \sqrt{28} \\ x_{29} &= \sqrt{29}  x_{30} &= \sqrt{30} \\ x_{31} &= \sqrt{31}"
+        );
+        // Errors are reported, and fixed.
+        check_edit(&mut equation, position..position, "}");
+        assert_eq!(equation.errors().len(), 1);
+        check_edit(&mut equation, position..position + 1, "");
+        assert!(equation.errors().is_empty());
+
+        let mut equation =
+            Equation::new(r"\begin{array}{cc} \hline a & b \\ \hline c & d \end{array}");
+        check_edit(&mut equation, 38..39, r"\frac12");
+        check_edit(&mut equation, 26..26, r"\hline");
+        check_edit(&mut equation, 18..24, "");
+    }
+
+    #[test]
+    fn bounded_rendering() {
+        let terms = (0..200)
+            .map(|i| format!(r"\frac{{{i}}}{{x}}"))
+            .collect::<Vec<_>>()
+            .join(" + ");
+        for config in render_configs() {
+            let mut equation = Equation::with_config(&terms, ParserConfig::default(), config);
+            // The fraction before the re-parsed elements looks ahead at them, and the fraction
+            // after them is rendered from the same checkpoint as before.
+            let position = equation.input().find(r"\frac{100}").unwrap() + 7;
+            let change = check_edit(&mut equation, position..position, "1");
+            assert_eq!(
+                &equation.input()[change.rendered],
+                r"\frac{99}{x} + \frac{1100}{x} +"
+            );
+            // A state change applies to every element following it.
+            let change = check_edit(&mut equation, 0..0, r"\color{red}");
+            assert_eq!(change.rendered, 0..equation.input().len());
+            let position = equation.input().find(r"\frac{150}").unwrap() + 6;
+            let change = check_edit(&mut equation, position..position + 1, "");
+            assert_eq!(
+                &equation.input()[change.rendered],
+                r"\frac{149}{x} + \frac{50}{x} +"
+            );
+        }
+
+        let rows = (0..200)
+            .map(|i| format!(r"x_{{{i}}} &= {i}"))
+            .collect::<Vec<_>>()
+            .join(r" \\ ");
+        let mut equation = Equation::new(format!(r"\begin{{align}} {rows} \end{{align}}"));
+        let position = equation.input().find("= 100 ").unwrap() + 5;
+        let change = check_edit(&mut equation, position..position, "1");
+        assert_eq!(
+            equation.input()[change.rendered].trim(),
+            r"x_{99} &= 99 \\ x_{100} &= 1001 \\ x_{101} &= 101"
+        );
+    }
+
+    #[test]
+    fn full_reparse() {
+        let mut equation = Equation::new(r"\def\f{x} \f + y");
+        let change = check_edit(&mut equation, 15..16, "z");
+        assert_eq!(change.reparsed, 0..equation.input().len());
+
+        let mut equation = Equation::new(r"a + b");
+        check_edit(&mut equation, 1..1, "% comment\n");
+        check_edit(&mut equation, 0..0, r"\frac");
+        check_edit(&mut equation, 0..5, "");
+        check_edit(&mut equation, 0..0, r"\left(");
+        check_edit(&mut equation, 0..6, r"");
+    }
+
+    #[test]
+    fn random_edits() {
+        const SNIPPETS: &[&str] = &[
+            "x",
+            "2",
+            "^",
+            "_",
+            "{",
+            "}",
+            " ",
+            "+",
+            "&",
+            r"\\",
+            r"\frac",
+            r"\sqrt",
+            r"\left(",
+            r"\right)",
+            r"\alpha",
+            "'",
+            r"\not",
+            r"\text{a b}",
+            r"\begin{matrix}",
+            r"\end{matrix}",
+            r"\displaystyle",
+            r"\limits",
+            r"\sum",
+            r"\sin",
+            r"\color{red}",
+            r"\quad",
+        ];
+        const INPUTS: &[&str] = &[
+            r"a + b^2 - \frac{c}{d}",
+            r"\begin{align} a &= b \\ c &= d \\ e &= f \end{align}",
+            r"\sum_{i=0}^n i' \left( x \right) {y}_1 \sqrt[3]{z}",
+            r"\begin{pmatrix} a & b \\ c & d \end{pmatrix} + \begin{cases} a & b \\ c \end{cases}",
+            r"\begin{align} a \\ b \end{align} \begin{gather} c \\ d \end{gather}",
+        ];
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+        for (input, config) in INPUTS.iter().flat_map(|input| {
+            render_configs()
+                .into_iter()
+                .map(move |config| (input, config))
+        }) {
+            let mut equation = Equation::with_config(*input, ParserConfig::default(), config);
+            for _ in 0..100 {
+                let input = equation.input();
+                let boundaries = (0..=input.len())
+                    .filter(|&i| input.is_char_boundary(i))
+                    .collect::<Vec<_>>();
+                let start = boundaries[random(boundaries.len())];
+                let end = match random(3) {
+                    0 => start,
+                    _ => *boundaries
+                        .iter()
+                        .filter(|&&i| i >= start)
+                        .nth(random(4))
+                        .unwrap_or(&start),
+                };
+                let replacement = match random(2) {
+                    0 => "",
+                    _ => SNIPPETS[random(SNIPPETS.len())],
+                };
+                let removed = input[start..end].to_owned();
+                check_edit(&mut equation, start..end, replacement);
+                // Undo edits introducing errors, so that most edits are made to valid input.
+                if !equation.errors().is_empty() {
+                    check_edit(&mut equation, start..start + replacement.len(), &removed);
+                }
+            }
+        }
+    }
+}
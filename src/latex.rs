@@ -0,0 +1,1347 @@
+//! A LaTeX writer, which turns a stream of events back into LaTeX source.
+//!
+//! The output is canonical: the same events always produce the same source, whatever the
+//! input they were parsed from. Macros are fully expanded, since the events do not keep track of
+//! them, and synonyms such as `\le` and `\leq` are written in a single way. This makes the writer
+//! useful to normalize user input, or to get rid of private macros before publishing.
+//!
+//! This module is available through the [`push_latex`] and [`write_latex`] functions.
+
+use std::io;
+
+use crate::{
+    event::{
+        ArrayColumn, ColorChange, ColorTarget, ColumnAlignment, Content, DelimiterSize,
+        DelimiterType, Dimension, DimensionUnit, EnvironmentFlow, Event, Font, Grouping,
+        GroupingKind, Line, MatrixType, ScriptPosition, ScriptType, StateChange, Style, Visual,
+    },
+    symbols,
+};
+
+struct LatexWriter<'e, 'a> {
+    events: &'e [Event<'a>],
+    output: String,
+}
+
+impl<'e, 'a> LatexWriter<'e, 'a> {
+    /// Create a writer for the given events, appending to the given output.
+    fn new(events: &'e [Event<'a>], output: String) -> Self {
+        Self { events, output }
+    }
+
+    /// Write the events of a top-level element, followed by the flow events or unbalanced `End`
+    /// events which ended it early, if any, and return the output.
+    ///
+    /// `open_fonts` is the number of font changes at the top level whose braces are still open.
+    fn write_top_level(mut self, open_fonts: &mut usize) -> String {
+        let mut index = 0;
+        while index < self.events.len() {
+            if let Event::End = self.events[index] {
+                // An `End` event at the top level is unbalanced, and is skipped.
+                self.close_braces(open_fonts);
+                index += 1;
+            } else {
+                index = self.group_item(index, open_fonts);
+            }
+        }
+        self.output
+    }
+
+    /// Write a string to the output, separating it from what precedes it when both would
+    /// otherwise be lexed as a single token.
+    fn push(&mut self, str: &str) {
+        let Some(first) = str.chars().next() else {
+            return;
+        };
+        if needs_separator(&self.output, first) {
+            self.output.push(' ');
+        }
+        self.output.push_str(str);
+    }
+
+    fn control_sequence(&mut self, name: &str) {
+        self.push("\\");
+        self.output.push_str(name);
+    }
+
+    /// Render the element starting at `index` to a separate string, returning it along with the
+    /// index following the element.
+    fn render(&mut self, index: usize) -> (String, usize) {
+        let output = std::mem::take(&mut self.output);
+        let next = self.element(index);
+        (std::mem::replace(&mut self.output, output), next)
+    }
+
+    /// Write the events of a group, up to the `End` event closing it, and return the index of
+    /// that event.
+    fn group_content(&mut self, mut index: usize) -> usize {
+        let mut open_fonts = 0;
+        while let Some(event) = self.events.get(index) {
+            if let Event::End = event {
+                break;
+            }
+            index = self.group_item(index, &mut open_fonts);
+        }
+        self.close_braces(&mut open_fonts);
+        index
+    }
+
+    /// Write the element starting at `index` in a group, or the flow event found instead, and
+    /// return the index following it.
+    ///
+    /// Font changes are written with a brace that stays open until the end of the group or the
+    /// next flow event, and `open_fonts` counts these braces.
+    fn group_item(&mut self, index: usize, open_fonts: &mut usize) -> usize {
+        match &self.events[index] {
+            Event::EnvironmentFlow(flow) => {
+                self.close_braces(open_fonts);
+                self.environment_flow(flow);
+                index + 1
+            }
+            Event::StateChange(StateChange::Font(font)) => {
+                self.control_sequence(font_command(*font));
+                self.output.push('{');
+                *open_fonts += 1;
+                index + 1
+            }
+            _ => self.element(index),
+        }
+    }
+
+    fn close_braces(&mut self, count: &mut usize) {
+        for _ in 0..*count {
+            self.output.push('}');
+        }
+        *count = 0;
+    }
+
+    /// Write the element starting at `index`, and return the index following it.
+    fn element(&mut self, index: usize) -> usize {
+        let Some(event) = self.events.get(index) else {
+            return index;
+        };
+        match event {
+            Event::Content(content) => self.content(content),
+            Event::Begin(grouping) => return self.group(index, grouping),
+            Event::End | Event::EnvironmentFlow(_) => return index,
+            Event::Visual(visual) => return self.visual(index, *visual),
+            Event::Script { ty, position } => return self.script(index, *ty, *position),
+            Event::Space { width, height } => self.space(*width, *height),
+            Event::StateChange(state_change) => self.state_change(state_change),
+            Event::Error { source, .. } => self.push(source),
+        }
+        index + 1
+    }
+
+    /// Write the element starting at `index` as the argument of a command or a script, adding
+    /// braces around it unless it is already a group or a single token.
+    fn argument(&mut self, index: usize) -> usize {
+        let (argument, next) = self.render(index);
+        let argument = self.as_argument(argument, index);
+        self.push(&argument);
+        next
+    }
+
+    fn content(&mut self, content: &Content) {
+        match *content {
+            Content::Text("\u{a0}") => self.control_sequence("nobreakspace"),
+            Content::Text(text) => {
+                self.control_sequence("text");
+                self.output.push('{');
+                self.output.push_str(text);
+                self.output.push('}');
+            }
+            Content::Number(number) => self.push(number),
+            Content::Function(name) => self.function(name, false),
+            Content::Ordinary { content, .. } => match content {
+                '′' => self.push("'"),
+                c if c.is_ascii_alphanumeric() => self.push(c.encode_utf8(&mut [0; 4])),
+                c => self.character(c, symbols::ordinary(c)),
+            },
+            Content::LargeOp { small: true, .. } => self.control_sequence("smallint"),
+            Content::LargeOp { content, .. } => {
+                self.character(content, symbols::large_op(content).map(|(name, _)| name))
+            }
+            Content::BinaryOp { content, .. } => match content {
+                '+' => self.push("+"),
+                '−' => self.push("-"),
+                '∗' => self.push("*"),
+                c => self.character(c, symbols::binary(c)),
+            },
+            Content::Relation { content, small } => {
+                let mut buf = [0; 8];
+                let str = content.encode_utf8_to_buf(&mut buf);
+                let str = std::str::from_utf8(str).expect("relations are valid utf-8");
+                let mut chars = str.chars();
+                let first = chars.next().expect("relations are not empty");
+                let name = match (chars.next(), small) {
+                    (Some(second), _) => symbols::multirelation(first, second),
+                    (None, true) => small_relation(first),
+                    (None, false) if first.is_ascii() => None,
+                    (None, false) => symbols::relation(first),
+                };
+                match name {
+                    Some(name) => self.control_sequence(name),
+                    None => self.push(str),
+                }
+            }
+            Content::Delimiter { content, size, ty } => {
+                if let Some(size) = size {
+                    self.control_sequence(match (size, ty) {
+                        (DelimiterSize::Big, DelimiterType::Open) => "bigl",
+                        (DelimiterSize::Big, DelimiterType::Close) => "bigr",
+                        (DelimiterSize::Big, DelimiterType::Fence) => "bigm",
+                        (DelimiterSize::BIG, DelimiterType::Open) => "Bigl",
+                        (DelimiterSize::BIG, DelimiterType::Close) => "Bigr",
+                        (DelimiterSize::BIG, DelimiterType::Fence) => "Bigm",
+                        (DelimiterSize::Bigg, DelimiterType::Open) => "biggl",
+                        (DelimiterSize::Bigg, DelimiterType::Close) => "biggr",
+                        (DelimiterSize::Bigg, DelimiterType::Fence) => "biggm",
+                        (DelimiterSize::BIGG, DelimiterType::Open) => "Biggl",
+                        (DelimiterSize::BIGG, DelimiterType::Close) => "Biggr",
+                        (DelimiterSize::BIGG, DelimiterType::Fence) => "Biggm",
+                    });
+                }
+                self.delimiter(content, ty);
+            }
+            Content::Punctuation(c) => match c {
+                '.' | ',' | ';' => self.push(c.encode_utf8(&mut [0; 4])),
+                c => self.character(c, symbols::ordinary(c)),
+            },
+        }
+    }
+
+    /// Write a character through its control sequence if it has one, or as is otherwise.
+    fn character(&mut self, c: char, control_sequence: Option<&str>) {
+        match control_sequence {
+            Some(name) => self.control_sequence(name),
+            None => self.push(c.encode_utf8(&mut [0; 4])),
+        }
+    }
+
+    /// Write a function name, using `\operatorname` when the function is not built-in, or when
+    /// `limits` modifiers must follow it and the built-in function does not accept them.
+    fn function(&mut self, name: &str, modifiers: bool) {
+        match function_command(name) {
+            Some((command, accepts_modifiers)) if accepts_modifiers || !modifiers => {
+                self.control_sequence(command)
+            }
+            _ => {
+                self.control_sequence("operatorname");
+                self.output.push('{');
+                self.output.push_str(name);
+                self.output.push('}');
+            }
+        }
+    }
+
+    fn delimiter(&mut self, content: char, ty: DelimiterType) {
+        match delimiter_token(content, ty) {
+            Some(token) => self.push(token),
+            None => self.character(content, None),
+        }
+    }
+
+    fn group(&mut self, index: usize, grouping: &Grouping) -> usize {
+        let end = self.group_end(index);
+        if self.fraction_group(index, end, grouping) {
+            return end + 1;
+        }
+        let next = |index: usize| self.events.get(index);
+        match grouping {
+            Grouping::Normal => match (next(index + 1), next(index + 2), next(index + 3)) {
+                (Some(Event::StateChange(StateChange::Font(font))), _, _) => {
+                    self.control_sequence(font_command(*font));
+                    self.output.push('{');
+                    self.group_content(index + 2);
+                    self.output.push('}');
+                    return end + 1;
+                }
+                (
+                    Some(Event::StateChange(StateChange::Color(ColorChange {
+                        color,
+                        target: ColorTarget::Text,
+                    }))),
+                    Some(Event::Begin(Grouping::Normal)),
+                    _,
+                ) if self.element_end(index + 2) == end => {
+                    self.control_sequence("textcolor");
+                    self.output.push_str(&color_argument(*color));
+                    self.argument(index + 2);
+                    return end + 1;
+                }
+                (
+                    Some(Event::StateChange(StateChange::Color(ColorChange {
+                        color,
+                        target: ColorTarget::Background,
+                    }))),
+                    Some(Event::Content(Content::Text(text))),
+                    Some(Event::End),
+                ) => {
+                    self.control_sequence("colorbox");
+                    self.output.push_str(&color_argument(*color));
+                    self.output.push('{');
+                    self.output.push_str(text);
+                    self.output.push('}');
+                    return end + 1;
+                }
+                (
+                    Some(Event::StateChange(StateChange::Color(ColorChange {
+                        color: border,
+                        target: ColorTarget::Border,
+                    }))),
+                    Some(Event::StateChange(StateChange::Color(ColorChange {
+                        color: background,
+                        target: ColorTarget::Background,
+                    }))),
+                    Some(Event::Content(Content::Text(text))),
+                ) if end == index + 4 => {
+                    self.control_sequence("fcolorbox");
+                    self.output.push_str(&color_argument(*border));
+                    self.output.push_str(&color_argument(*background));
+                    self.output.push('{');
+                    self.output.push_str(text);
+                    self.output.push('}');
+                    return end + 1;
+                }
+                _ => {
+                    self.push("{");
+                    self.group_content(index + 1);
+                    self.output.push('}');
+                    return end + 1;
+                }
+            },
+            Grouping::LeftRight(left, right) => {
+                if let Some(Event::Begin(Grouping::Matrix { alignment })) = next(index + 1) {
+                    let ty = match (left, right) {
+                        (Some('('), Some(')')) => Some(MatrixType::Parens),
+                        (Some('['), Some(']')) => Some(MatrixType::Brackets),
+                        (Some('{'), Some('}')) => Some(MatrixType::Braces),
+                        (Some('|'), Some('|')) => Some(MatrixType::Vertical),
+                        (Some('‖'), Some('‖')) => Some(MatrixType::DoubleVertical),
+                        _ => None,
+                    };
+                    if let Some(ty) = ty.filter(|_| self.element_end(index + 1) == end) {
+                        self.matrix(index + 1, ty, *alignment);
+                        return end + 1;
+                    }
+                }
+                self.control_sequence("left");
+                match left {
+                    Some(left) => self.delimiter(*left, DelimiterType::Open),
+                    None => self.output.push('.'),
+                }
+                self.group_content(index + 1);
+                self.control_sequence("right");
+                match right {
+                    Some(right) => self.delimiter(*right, DelimiterType::Close),
+                    None => self.output.push('.'),
+                }
+                return end + 1;
+            }
+            Grouping::Matrix { alignment } => {
+                let ty = if self.environment_style(index) == Some(Style::Text) {
+                    MatrixType::Small
+                } else {
+                    MatrixType::Normal
+                };
+                self.matrix(index, ty, *alignment);
+                return end + 1;
+            }
+            _ => {}
+        }
+
+        let display = self.environment_style(index) == Some(Style::Display);
+        let kind = match grouping {
+            Grouping::Array(_) => GroupingKind::Array { display },
+            Grouping::Cases { left } => GroupingKind::Cases {
+                left: *left,
+                display,
+            },
+            Grouping::Equation { eq_numbers } => GroupingKind::Equation {
+                eq_numbers: *eq_numbers,
+            },
+            Grouping::Align { eq_numbers } => GroupingKind::Align {
+                eq_numbers: *eq_numbers,
+            },
+            Grouping::Aligned => GroupingKind::Aligned,
+            Grouping::SubArray { .. } => GroupingKind::SubArray,
+            Grouping::Alignat { eq_numbers, .. } => GroupingKind::Alignat {
+                eq_numbers: *eq_numbers,
+            },
+            Grouping::Alignedat { .. } => GroupingKind::Alignedat,
+            Grouping::Gather { eq_numbers } => GroupingKind::Gather {
+                eq_numbers: *eq_numbers,
+            },
+            Grouping::Gathered => GroupingKind::Gathered,
+            Grouping::Multline => GroupingKind::Multline,
+            Grouping::Split => GroupingKind::Split,
+            Grouping::Normal | Grouping::LeftRight(..) | Grouping::Matrix { .. } => {
+                unreachable!("handled above")
+            }
+        };
+        self.push(kind.opening_str());
+        match grouping {
+            Grouping::Array(columns) => {
+                self.output.push('{');
+                for column in columns.iter() {
+                    self.output.push(match column {
+                        ArrayColumn::Column(alignment) => alignment_char(*alignment),
+                        ArrayColumn::Separator(Line::Solid) => '|',
+                        ArrayColumn::Separator(Line::Dashed) => ':',
+                    });
+                }
+                self.output.push('}');
+            }
+            Grouping::SubArray { alignment } => {
+                self.output.push('{');
+                self.output.push(alignment_char(*alignment));
+                self.output.push('}');
+            }
+            Grouping::Alignat { pairs, .. } | Grouping::Alignedat { pairs } => {
+                self.output.push_str(&format!("{{{pairs}}}"));
+            }
+            _ => {}
+        }
+        let skip_style = display
+            && matches!(
+                kind,
+                GroupingKind::Array { .. } | GroupingKind::Cases { .. }
+            );
+        self.environment_content(index + 1, skip_style);
+        self.push(kind.closing_str());
+        end + 1
+    }
+
+    /// Write a group produced by a fraction-like command, such as `\binom` or `\dfrac`, returning
+    /// whether the group was one.
+    fn fraction_group(&mut self, index: usize, end: usize, grouping: &Grouping) -> bool {
+        let (style, fraction) = match self.events.get(index + 1) {
+            Some(Event::StateChange(StateChange::Style(style))) => (Some(*style), index + 2),
+            _ => (None, index + 1),
+        };
+        let Some(Event::Visual(Visual::Fraction(bar_size))) = self.events.get(fraction) else {
+            return false;
+        };
+        if self.element_end(fraction) != end {
+            return false;
+        }
+        let (left, right) = match grouping {
+            Grouping::Normal if style.is_some() => (None, None),
+            Grouping::LeftRight(left, right) => (*left, *right),
+            _ => return false,
+        };
+        let binom = *bar_size == Some(Dimension::new(0., DimensionUnit::Em))
+            && (left, right) == (Some('('), Some(')'));
+        let command = match (style, binom) {
+            (None, true) => "binom",
+            (Some(Style::Display), true) => "dbinom",
+            (Some(Style::Text), true) => "tbinom",
+            (Some(Style::Display), false) if bar_size.is_none() && left.is_none() => "dfrac",
+            (Some(Style::Text), false) if bar_size.is_none() && left.is_none() => "tfrac",
+            _ => {
+                self.genfrac(fraction, left, right, *bar_size, style);
+                return true;
+            }
+        };
+        self.control_sequence(command);
+        let next = self.argument(fraction + 1);
+        self.argument(next);
+        true
+    }
+
+    /// Write a `\genfrac` command, where `index` is the index of the fraction, and return the index
+    /// following it.
+    fn genfrac(
+        &mut self,
+        index: usize,
+        left: Option<char>,
+        right: Option<char>,
+        bar_size: Option<Dimension>,
+        style: Option<Style>,
+    ) -> usize {
+        self.control_sequence("genfrac");
+        for (delimiter, ty) in [(left, DelimiterType::Open), (right, DelimiterType::Close)] {
+            match delimiter {
+                Some(delimiter) => self.delimiter(delimiter, ty),
+                None => self.output.push_str("{}"),
+            }
+        }
+        self.output.push('{');
+        if let Some(bar_size) = bar_size {
+            self.output.push_str(&dimension(bar_size));
+        }
+        self.output.push('}');
+        self.output.push_str(match style {
+            None => "{}",
+            Some(Style::Display) => "{0}",
+            Some(Style::Text) => "{1}",
+            Some(Style::Script) => "{2}",
+            Some(Style::ScriptScript) => "{3}",
+        });
+        let next = self.argument(index + 1);
+        self.argument(next)
+    }
+
+    /// Write a matrix environment, where `index` is the index of the `Matrix` grouping.
+    fn matrix(&mut self, index: usize, ty: MatrixType, alignment: ColumnAlignment) {
+        let column_spec = alignment != ColumnAlignment::Center;
+        let kind = GroupingKind::Matrix { ty, column_spec };
+        self.push(kind.opening_str());
+        if column_spec {
+            self.output.push('[');
+            self.output.push(alignment_char(alignment));
+            self.output.push(']');
+        }
+        self.environment_content(index + 1, ty == MatrixType::Small);
+        self.push(kind.closing_str());
+    }
+
+    /// The style set at the start of the environment opened at `index`, after its horizontal
+    /// lines, if any.
+    fn environment_style(&self, mut index: usize) -> Option<Style> {
+        index += 1;
+        if let Some(Event::EnvironmentFlow(EnvironmentFlow::StartLines { .. })) =
+            self.events.get(index)
+        {
+            index += 1;
+        }
+        match self.events.get(index) {
+            Some(Event::StateChange(StateChange::Style(style))) => Some(*style),
+            _ => None,
+        }
+    }
+
+    /// Write the content of an environment starting at `index`, skipping the style set at its
+    /// start when `skip_style` is true, since it is implied by the environment.
+    fn environment_content(&mut self, mut index: usize, skip_style: bool) {
+        self.output.push(' ');
+        if let Some(Event::EnvironmentFlow(flow @ EnvironmentFlow::StartLines { .. })) =
+            self.events.get(index)
+        {
+            self.environment_flow(flow);
+            index += 1;
+        }
+        if skip_style {
+            index += 1;
+        }
+        self.group_content(index);
+        self.space_separator();
+    }
+
+    /// Write a space, unless the output already ends with one. Spaces are used to make
+    /// environments more readable.
+    fn space_separator(&mut self) {
+        if !self.output.ends_with(' ') {
+            self.output.push(' ');
+        }
+    }
+
+    fn environment_flow(&mut self, flow: &EnvironmentFlow) {
+        match flow {
+            EnvironmentFlow::Alignment => {
+                self.space_separator();
+                self.output.push_str("& ");
+            }
+            EnvironmentFlow::NewLine {
+                spacing,
+                horizontal_lines,
+            } => {
+                self.space_separator();
+                self.output.push_str("\\\\");
+                if let Some(spacing) = spacing {
+                    self.output.push('[');
+                    self.output.push_str(&dimension(*spacing));
+                    self.output.push(']');
+                }
+                self.horizontal_lines(horizontal_lines);
+                self.output.push(' ');
+            }
+            EnvironmentFlow::StartLines { lines } => {
+                self.horizontal_lines(lines);
+                self.output.push(' ');
+            }
+        }
+    }
+
+    fn horizontal_lines(&mut self, lines: &[Line]) {
+        for line in lines {
+            self.space_separator();
+            self.output.push_str(match line {
+                Line::Solid => "\\hline",
+                Line::Dashed => "\\hdashline",
+            });
+        }
+    }
+
+    fn visual(&mut self, index: usize, visual: Visual) -> usize {
+        match visual {
+            Visual::SquareRoot => {
+                if let Some(Event::Space {
+                    width: Some(width),
+                    height: Some(_),
+                }) = self.events.get(index + 1)
+                {
+                    if width.value == 0. {
+                        self.control_sequence("surd");
+                        return index + 2;
+                    }
+                }
+                self.control_sequence("sqrt");
+                self.argument(index + 1)
+            }
+            Visual::Root => {
+                let (radicand, next) = self.render(index + 1);
+                let (root_index, next) = self.render(next);
+                self.control_sequence("sqrt");
+                self.output.push('[');
+                self.output.push_str(&root_index);
+                self.output.push(']');
+                let radicand = self.as_argument(radicand, index + 1);
+                self.push(&radicand);
+                next
+            }
+            Visual::Fraction(None) => {
+                self.control_sequence("frac");
+                let next = self.argument(index + 1);
+                self.argument(next)
+            }
+            Visual::Fraction(bar_size) => self.genfrac(index, None, None, bar_size, None),
+            Visual::Negation => {
+                // The argument of `\not` is the next token, after expansion.
+                self.control_sequence("not");
+                let (argument, next) = self.render(index + 1);
+                self.push(&argument);
+                next
+            }
+        }
+    }
+
+    fn script(&mut self, index: usize, ty: ScriptType, position: ScriptPosition) -> usize {
+        let base = index + 1;
+        let first_script = self.element_end(base);
+
+        // Accents, such as `\hat{x}`, are written as a base with a single stretchy character
+        // above or below it.
+        if position == ScriptPosition::AboveBelow
+            && self.element_end(first_script) == first_script + 1
+        {
+            if let Some(Event::Content(Content::Ordinary { content, stretchy })) =
+                self.events.get(first_script)
+            {
+                let command = match ty {
+                    ScriptType::Superscript => symbols::accent(*content, *stretchy),
+                    ScriptType::Subscript if *stretchy => symbols::underscript(*content),
+                    _ => None,
+                };
+                if let Some(command) = command {
+                    self.control_sequence(command);
+                    self.argument(base);
+                    return first_script + 1;
+                }
+            }
+        }
+
+        // Extensible arrows, such as `\xrightarrow[below]{above}`.
+        if let (
+            Some(Event::Content(Content::Relation {
+                content,
+                small: false,
+            })),
+            ScriptPosition::AboveBelow,
+        ) = (self.events.get(base), position)
+        {
+            let command = match content.encode_utf8_to_buf(&mut [0; 8]) {
+                b if b == "←".as_bytes() => Some("xleftarrow"),
+                b if b == "→".as_bytes() => Some("xrightarrow"),
+                _ => None,
+            };
+            let below_is_group = matches!(
+                self.events.get(first_script),
+                Some(Event::Begin(Grouping::Normal))
+            );
+            match (command, ty) {
+                (Some(command), ScriptType::Superscript) => {
+                    self.control_sequence(command);
+                    return self.argument(first_script);
+                }
+                (Some(command), ScriptType::SubSuperscript) if below_is_group => {
+                    self.control_sequence(command);
+                    let (below, next) = self.render(first_script);
+                    self.output.push('[');
+                    self.output.push_str(&below[1..below.len() - 1]);
+                    self.output.push(']');
+                    return self.argument(next);
+                }
+                _ => {}
+            }
+        }
+
+        let modifier = match (self.events.get(base), position) {
+            (Some(Event::Content(Content::LargeOp { content, small })), _) => {
+                let movable = !small && symbols::large_op(*content).is_some_and(|(_, m)| m);
+                match position {
+                    ScriptPosition::AboveBelow => Some("limits"),
+                    ScriptPosition::Right if movable => Some("nolimits"),
+                    _ => None,
+                }
+            }
+            (Some(Event::Content(Content::Function(name))), _) => {
+                let movable = movable_function(name);
+                match position {
+                    ScriptPosition::AboveBelow => Some("limits"),
+                    ScriptPosition::Right if movable => Some("nolimits"),
+                    _ => None,
+                }
+            }
+            (_, ScriptPosition::AboveBelow) if !self.is_brace(base) => {
+                return self.overset(index, ty);
+            }
+            _ => None,
+        };
+
+        let (base_str, next) = self.render(base);
+        match self.events.get(base) {
+            Some(Event::Content(Content::Function(name))) if modifier.is_some() => {
+                self.function(name, true);
+            }
+            Some(Event::Script { .. }) if self.is_command_script(base) => self.push(&base_str),
+            Some(Event::Script { .. }) | None => {
+                self.output.push('{');
+                self.output.push_str(&base_str);
+                self.output.push('}');
+            }
+            _ => self.push(&base_str),
+        }
+        if let Some(modifier) = modifier {
+            self.control_sequence(modifier);
+        }
+
+        match ty {
+            ScriptType::Subscript => {
+                self.output.push('_');
+                self.argument(next)
+            }
+            ScriptType::Superscript => {
+                self.output.push('^');
+                self.argument(next)
+            }
+            ScriptType::SubSuperscript => {
+                self.output.push('_');
+                let next = self.argument(next);
+                self.output.push('^');
+                self.argument(next)
+            }
+        }
+    }
+
+    /// Whether the script starting at `index` is written as a command, such as `\hat{x}` or
+    /// `\overset{a}{b}`, which can be the base of another script without braces.
+    fn is_command_script(&self, index: usize) -> bool {
+        matches!(
+            (self.events.get(index), self.events.get(index + 1)),
+            (
+                Some(Event::Script {
+                    position: ScriptPosition::AboveBelow,
+                    ..
+                }),
+                Some(event),
+            ) if !matches!(event, Event::Content(Content::LargeOp { .. } | Content::Function(_)))
+        )
+    }
+
+    /// Whether the element starting at `index` is a brace, such as `\overbrace{x}`, whose scripts
+    /// are placed above and below it by default.
+    fn is_brace(&self, index: usize) -> bool {
+        let Some(Event::Script {
+            position: ScriptPosition::AboveBelow,
+            ..
+        }) = self.events.get(index)
+        else {
+            return false;
+        };
+        let script = self.element_end(index + 1);
+        matches!(
+            self.events.get(script),
+            Some(Event::Content(Content::Ordinary {
+                content: '⏞' | '⏠' | '⏟' | '⏡' | '⏝',
+                stretchy: true,
+            }))
+        )
+    }
+
+    /// Write scripts placed above and below a base that is not an operator, using `\overset`
+    /// and `\underset`.
+    fn overset(&mut self, index: usize, ty: ScriptType) -> usize {
+        let (base, first_index) = self.render(index + 1);
+        let base = self.as_argument(base, index + 1);
+        let (first, next) = self.render(first_index);
+        let first = self.as_argument(first, first_index);
+        match ty {
+            ScriptType::Subscript => {
+                self.control_sequence("underset");
+                self.push(&first);
+                self.push(&base);
+                next
+            }
+            ScriptType::Superscript => {
+                self.control_sequence("overset");
+                self.push(&first);
+                self.push(&base);
+                next
+            }
+            ScriptType::SubSuperscript => {
+                let (second, second_next) = self.render(next);
+                let second = self.as_argument(second, next);
+                self.control_sequence("underset");
+                self.push(&first);
+                self.output.push('{');
+                self.control_sequence("overset");
+                self.push(&second);
+                self.push(&base);
+                self.output.push('}');
+                second_next
+            }
+        }
+    }
+
+    /// Turn the rendered element starting at `index` into an argument, by surrounding it with
+    /// braces unless it is already a group or a single token.
+    fn as_argument(&self, rendered: String, index: usize) -> String {
+        let group = matches!(self.events.get(index), Some(Event::Begin(Grouping::Normal)))
+            && rendered.starts_with('{');
+        if group || is_single_token(&rendered) {
+            rendered
+        } else {
+            format!("{{{rendered}}}")
+        }
+    }
+
+    fn space(&mut self, width: Option<Dimension>, height: Option<Dimension>) {
+        if let Some(width) = width {
+            let named = match width.unit {
+                DimensionUnit::Em => [
+                    (3. / 18., ","),
+                    (4. / 18., ":"),
+                    (5. / 18., ";"),
+                    (0.5, "enspace"),
+                    (1., "quad"),
+                    (2., "qquad"),
+                    (-3. / 18., "!"),
+                    (-4. / 18., "negmedspace"),
+                    (-5. / 18., "negthickspace"),
+                ]
+                .into_iter()
+                .find_map(|(value, name)| (value == width.value).then_some(name)),
+                _ => None,
+            };
+            match (named, width.unit) {
+                (Some(name), _) => self.control_sequence(name),
+                (None, DimensionUnit::Mu) => {
+                    self.control_sequence("mkern");
+                    self.output.push_str(&dimension(width));
+                }
+                (None, _) => {
+                    self.control_sequence("hspace");
+                    self.output.push('{');
+                    self.output.push_str(&dimension(width));
+                    self.output.push('}');
+                }
+            }
+        }
+        if height.is_some() {
+            self.control_sequence("mathstrut");
+        }
+    }
+
+    fn state_change(&mut self, state_change: &StateChange) {
+        match state_change {
+            StateChange::Font(font) => {
+                self.control_sequence(font_command(*font));
+                self.output.push_str("{}");
+            }
+            StateChange::Color(ColorChange { color, target }) => match target {
+                ColorTarget::Text => {
+                    self.control_sequence("color");
+                    self.output.push_str(&color_argument(*color));
+                }
+                // Background and border colors only exist for boxes of text, which are handled
+                // along with their group.
+                ColorTarget::Background | ColorTarget::Border => {}
+            },
+            StateChange::Style(style) => self.control_sequence(match style {
+                Style::Display => "displaystyle",
+                Style::Text => "textstyle",
+                Style::Script => "scriptstyle",
+                Style::ScriptScript => "scriptscriptstyle",
+            }),
+        }
+    }
+
+    /// Return the index of the `End` event closing the group opened at `index`.
+    fn group_end(&self, index: usize) -> usize {
+        let mut depth = 0usize;
+        for (offset, event) in self.events[index..].iter().enumerate() {
+            match event {
+                Event::Begin(_) => depth += 1,
+                Event::End => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return index + offset;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.events.len()
+    }
+
+    /// Return the index following the element starting at `index`.
+    fn element_end(&self, index: usize) -> usize {
+        let count = match self.events.get(index) {
+            None | Some(Event::End | Event::EnvironmentFlow(_)) => return index,
+            Some(Event::Begin(_)) => return self.group_end(index) + 1,
+            Some(Event::Visual(Visual::SquareRoot | Visual::Negation)) => 1,
+            Some(Event::Visual(Visual::Root | Visual::Fraction(_))) => 2,
+            Some(Event::Script {
+                ty: ScriptType::Subscript | ScriptType::Superscript,
+                ..
+            }) => 2,
+            Some(Event::Script {
+                ty: ScriptType::SubSuperscript,
+                ..
+            }) => 3,
+            Some(_) => 0,
+        };
+        (0..count).fold(index + 1, |next, _| self.element_end(next))
+    }
+}
+
+/// Whether a space must be written between the end of the output and the given character, so
+/// that they are not lexed as a single token.
+fn needs_separator(output: &str, next: char) -> bool {
+    let Some(last) = output.chars().next_back() else {
+        return false;
+    };
+    if next.is_ascii_alphabetic() {
+        let letters = output.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        if letters.len() == output.len() {
+            return false;
+        }
+        // A control word, or the unit of a dimension.
+        let backslashes = letters.len() - letters.trim_end_matches('\\').len();
+        backslashes % 2 == 1 || letters.ends_with(|c: char| c.is_ascii_digit())
+    } else if next.is_ascii_digit() || next == '.' || next == ',' {
+        last.is_ascii_digit()
+    } else {
+        false
+    }
+}
+
+/// Whether the string is a single character, or a single control sequence.
+fn is_single_token(str: &str) -> bool {
+    let mut chars = str.chars();
+    match (chars.next(), chars.next()) {
+        (Some('{' | '}' | '^' | '_' | '&' | '#' | '%' | '~' | '$'), None) => false,
+        (Some(_), None) => true,
+        (Some('\\'), Some(c)) if c.is_ascii_alphabetic() => chars.all(|c| c.is_ascii_alphabetic()),
+        (Some('\\'), Some(_)) => chars.next().is_none(),
+        _ => false,
+    }
+}
+
+fn font_command(font: Option<Font>) -> &'static str {
+    match font {
+        None => "mathnormal",
+        Some(Font::BoldScript) => "mathbfcal",
+        Some(Font::BoldItalic) => "mathbfit",
+        Some(Font::Bold) => "mathbf",
+        Some(Font::Fraktur) => "mathfrak",
+        Some(Font::Script) => "mathcal",
+        Some(Font::Monospace) => "mathtt",
+        Some(Font::SansSerif) => "mathsf",
+        Some(Font::DoubleStruck) => "mathbb",
+        Some(Font::Italic) => "mathit",
+        Some(Font::BoldFraktur) => "mathbffrak",
+        Some(Font::SansSerifBoldItalic) => "mathbfsfit",
+        Some(Font::SansSerifItalic) => "mathsfit",
+        Some(Font::BoldSansSerif) => "mathbfsfup",
+        Some(Font::UpRight) => "mathrm",
+    }
+}
+
+/// The built-in command for a function, along with whether it accepts `\limits` and `\nolimits`.
+fn function_command(name: &str) -> Option<(&'static str, bool)> {
+    Some(match name {
+        "arccos" => ("arccos", false),
+        "cos" => ("cos", false),
+        "csc" => ("csc", false),
+        "exp" => ("exp", false),
+        "ker" => ("ker", false),
+        "sinh" => ("sinh", false),
+        "arcsin" => ("arcsin", false),
+        "cosh" => ("cosh", false),
+        "deg" => ("deg", false),
+        "lg" => ("lg", false),
+        "ln" => ("ln", false),
+        "arctan" => ("arctan", false),
+        "cot" => ("cot", false),
+        "det" => ("det", false),
+        "hom" => ("hom", false),
+        "log" => ("log", false),
+        "sec" => ("sec", false),
+        "tan" => ("tan", false),
+        "arg" => ("arg", false),
+        "coth" => ("coth", false),
+        "dim" => ("dim", false),
+        "sin" => ("sin", false),
+        "tanh" => ("tanh", false),
+        "sgn" => ("sgn", false),
+        "mod" => ("bmod", false),
+        "lim" => ("lim", true),
+        "Pr" => ("Pr", true),
+        "sup" => ("sup", true),
+        "max" => ("max", true),
+        "inf" => ("inf", true),
+        "gcd" => ("gcd", true),
+        "min" => ("min", true),
+        "lim inf" => ("liminf", true),
+        "lim sup" => ("limsup", true),
+        _ => return None,
+    })
+}
+
+/// Whether the scripts of the function are placed above and below it in display style.
+fn movable_function(name: &str) -> bool {
+    matches!(
+        name,
+        "lim" | "Pr" | "sup" | "max" | "inf" | "gcd" | "min" | "lim inf" | "lim sup"
+    )
+}
+
+fn small_relation(c: char) -> Option<&'static str> {
+    Some(match c {
+        '∣' => "shortmid",
+        '∥' => "shortparallel",
+        '⌣' => "smallsmile",
+        '∤' => "nshortmid",
+        '∦' => "nshortparallel",
+        _ => return None,
+    })
+}
+
+/// The token producing the delimiter, preferring one of the given type.
+fn delimiter_token(content: char, ty: DelimiterType) -> Option<&'static str> {
+    let tokens: &[(&str, DelimiterType)] = match content {
+        '(' => &[
+            ("(", DelimiterType::Open),
+            ("\\lparen", DelimiterType::Open),
+        ],
+        ')' => &[
+            (")", DelimiterType::Close),
+            ("\\rparen", DelimiterType::Close),
+        ],
+        '[' => &[("[", DelimiterType::Open)],
+        ']' => &[("]", DelimiterType::Close)],
+        '{' => &[("\\{", DelimiterType::Open)],
+        '}' => &[("\\}", DelimiterType::Close)],
+        '⦇' => &[("\\llparenthesis", DelimiterType::Open)],
+        '⦈' => &[("\\rrparenthesis", DelimiterType::Close)],
+        '⟮' => &[("\\lgroup", DelimiterType::Open)],
+        '⟯' => &[("\\rgroup", DelimiterType::Close)],
+        '⟦' => &[("\\lBrack", DelimiterType::Open)],
+        '⟧' => &[("\\rBrack", DelimiterType::Close)],
+        '⦃' => &[("\\lBrace", DelimiterType::Open)],
+        '⦄' => &[("\\rBrace", DelimiterType::Close)],
+        '⟨' => &[("\\langle", DelimiterType::Open)],
+        '⟩' => &[("\\rangle", DelimiterType::Close)],
+        '⟪' => &[("\\lAngle", DelimiterType::Open)],
+        '⟫' => &[("\\rAngle", DelimiterType::Close)],
+        '⦉' => &[("\\llangle", DelimiterType::Open)],
+        '⦊' => &[("\\rrangle", DelimiterType::Close)],
+        '⌊' => &[("\\lfloor", DelimiterType::Open)],
+        '⌋' => &[("\\rfloor", DelimiterType::Close)],
+        '⌈' => &[("\\lceil", DelimiterType::Open)],
+        '⌉' => &[("\\rceil", DelimiterType::Close)],
+        '┌' => &[("\\ulcorner", DelimiterType::Open)],
+        '┐' => &[("\\urcorner", DelimiterType::Close)],
+        '└' => &[("\\llcorner", DelimiterType::Open)],
+        '┘' => &[("\\lrcorner", DelimiterType::Close)],
+        '⎰' => &[("\\lmoustache", DelimiterType::Open)],
+        '⎱' => &[("\\rmoustache", DelimiterType::Close)],
+        '\\' => &[("\\backslash", DelimiterType::Fence)],
+        '|' => &[
+            ("\\lvert", DelimiterType::Open),
+            ("\\rvert", DelimiterType::Close),
+            ("\\vert", DelimiterType::Fence),
+        ],
+        '‖' => &[
+            ("\\lVert", DelimiterType::Open),
+            ("\\rVert", DelimiterType::Close),
+            ("\\Vert", DelimiterType::Fence),
+        ],
+        '/' => &[("/", DelimiterType::Fence)],
+        '↑' => &[("\\uparrow", DelimiterType::Fence)],
+        '⇑' => &[("\\Uparrow", DelimiterType::Fence)],
+        '↓' => &[("\\downarrow", DelimiterType::Fence)],
+        '⇓' => &[("\\Downarrow", DelimiterType::Fence)],
+        '↕' => &[("\\updownarrow", DelimiterType::Fence)],
+        '⇕' => &[("\\Updownarrow", DelimiterType::Fence)],
+        _ => return None,
+    };
+    tokens
+        .iter()
+        .find(|(_, token_ty)| *token_ty == ty)
+        .or(tokens.first())
+        .map(|(token, _)| *token)
+}
+
+fn alignment_char(alignment: ColumnAlignment) -> char {
+    match alignment {
+        ColumnAlignment::Left => 'l',
+        ColumnAlignment::Center => 'c',
+        ColumnAlignment::Right => 'r',
+    }
+}
+
+fn color_argument((r, g, b): (u8, u8, u8)) -> String {
+    format!("{{#{r:02x}{g:02x}{b:02x}}}")
+}
+
+fn dimension(dimension: Dimension) -> String {
+    let unit = match dimension.unit {
+        DimensionUnit::Em => "em",
+        DimensionUnit::Mu => "mu",
+        DimensionUnit::Ex => "ex",
+        DimensionUnit::Pt => "pt",
+        DimensionUnit::Pc => "pc",
+        DimensionUnit::In => "in",
+        DimensionUnit::Bp => "bp",
+        DimensionUnit::Cm => "cm",
+        DimensionUnit::Mm => "mm",
+        DimensionUnit::Dd => "dd",
+        DimensionUnit::Cc => "cc",
+        DimensionUnit::Sp => "sp",
+    };
+    format!("{}{}", dimension.value, unit)
+}
+
+/// Tracks the events of the top-level element being received, to know when it is complete.
+#[derive(Default)]
+struct ElementTracker {
+    /// The depth of the groups opened since the start of the element.
+    depth: usize,
+    /// The number of elements still needed to complete the element, outside of groups.
+    needed: usize,
+}
+
+impl ElementTracker {
+    /// Account for the next event of the element, and return whether the element is complete.
+    fn push(&mut self, event: &Event) -> bool {
+        if self.depth > 0 {
+            match event {
+                Event::Begin(_) => self.depth += 1,
+                Event::End => self.depth -= 1,
+                _ => {}
+            }
+            return self.depth == 0 && self.needed == 0;
+        }
+        let arguments = match event {
+            // Flow events and unbalanced `End` events end the element, even if it is missing
+            // arguments.
+            Event::End | Event::EnvironmentFlow(_) => {
+                self.needed = 0;
+                return true;
+            }
+            Event::Begin(_) => {
+                self.depth = 1;
+                0
+            }
+            Event::Visual(Visual::SquareRoot | Visual::Negation) => 1,
+            Event::Visual(Visual::Root | Visual::Fraction(_)) => 2,
+            Event::Script {
+                ty: ScriptType::Subscript | ScriptType::Superscript,
+                ..
+            } => 2,
+            Event::Script {
+                ty: ScriptType::SubSuperscript,
+                ..
+            } => 3,
+            _ => 0,
+        };
+        // The event starts an argument of the element being received, if any.
+        self.needed = self.needed.saturating_sub(1) + arguments;
+        self.depth == 0 && self.needed == 0
+    }
+}
+
+/// Write the events one top-level element at a time, as soon as each element is complete.
+///
+/// Only the end of the output is kept between elements, to separate it from the next one.
+fn write_elements<'a, I, E>(
+    events: I,
+    mut write: impl FnMut(&str) -> io::Result<()>,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    E: std::error::Error,
+{
+    let mut element = Vec::new();
+    let mut tracker = ElementTracker::default();
+    let mut output = String::new();
+    let mut open_fonts = 0;
+
+    let mut flush = |element: &mut Vec<Event<'a>>| {
+        output =
+            LatexWriter::new(element, std::mem::take(&mut output)).write_top_level(&mut open_fonts);
+        element.clear();
+        let kept = kept_suffix(&output);
+        write(&output[..kept])?;
+        output.drain(..kept);
+        io::Result::Ok(())
+    };
+    for event in events {
+        let event = event.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let complete = tracker.push(&event);
+        element.push(event);
+        if complete {
+            flush(&mut element)?;
+        }
+    }
+    // The last element may be missing some of its arguments.
+    if !element.is_empty() {
+        flush(&mut element)?;
+    }
+
+    // The fonts opened at the top level are closed at the end of the input.
+    output.extend(std::iter::repeat('}').take(open_fonts));
+    write(&output)
+}
+
+/// The index of the end of the output that must be kept to know whether the next token needs a
+/// separator: its last character, along with the control word or dimension it may end.
+fn kept_suffix(output: &str) -> usize {
+    let letters = output.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let before = letters.trim_end_matches('\\');
+    before
+        .char_indices()
+        .next_back()
+        .map_or(0, |(index, _)| index)
+}
+
+/// Takes a [`Parser`], an [`EventBuffer`], or any `IntoIterator<Item = Result<Event<'_>, E>>` as
+/// input, and writes the corresponding LaTeX into the given string.
+///
+/// The LaTeX is written one top-level element at a time, as soon as the events of the element
+/// are received. If the input yields an error, writing stops and the error is returned as an
+/// [`io::Error`] of kind [`InvalidData`](io::ErrorKind::InvalidData). [`Event::Error`]s are
+/// written back as their source.
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{latex::push_latex, Parser, Storage};
+///
+/// let storage = Storage::new();
+/// let parser = Parser::new(r"\def\R{\mathbb{R}} f\colon \R \to \R, x \mapsto x^{2}", &storage);
+/// let mut output = String::new();
+/// push_latex(&mut output, parser).unwrap();
+/// assert_eq!(output, r"f\colon\mathbb{R}\rightarrow\mathbb{R},x\mapsto x^{2}");
+/// ```
+///
+/// [`Parser`]: crate::parser::Parser
+/// [`EventBuffer`]: crate::event::buffer::EventBuffer
+pub fn push_latex<'a, I, E>(string: &mut String, events: I) -> io::Result<()>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    E: std::error::Error,
+{
+    write_elements(events, |output| {
+        string.push_str(output);
+        Ok(())
+    })
+}
+
+/// Takes a [`Parser`], an [`EventBuffer`], or any `IntoIterator<Item = Result<Event<'_>, E>>` as
+/// input, and writes the corresponding LaTeX into the given writer.
+///
+/// The LaTeX is written and errors are handled as in [`push_latex`].
+///
+/// [`Parser`]: crate::parser::Parser
+/// [`EventBuffer`]: crate::event::buffer::EventBuffer
+pub fn write_latex<'a, I, W, E>(mut writer: W, events: I) -> io::Result<()>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    W: io::Write,
+    E: std::error::Error,
+{
+    write_elements(events, |output| writer.write_all(output.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{push_latex, write_latex};
+    use crate::{Parser, Storage};
+
+    fn to_latex(input: &str) -> String {
+        let storage = Storage::new();
+        let mut output = String::new();
+        push_latex(&mut output, Parser::new(input, &storage)).unwrap();
+        output
+    }
+
+    #[test]
+    fn canonical() {
+        let cases = [
+            (r"a \le b \leq c", r"a\leq b\leq c"),
+            (
+                r"\def\abs#1{\left|#1\right|} \abs{x}",
+                r"\left\lvert{x}\right\rvert",
+            ),
+            (r"x_1^2 + x^{2}_{1}", r"x_1^2+x_{1}^{2}"),
+            (r"\bf x", r"\mathbf{x}"),
+            (r"\dfrac a b", r"\dfrac ab"),
+            (r"\sum\limits_{i=0}^n i", r"\sum\limits_{i=0}^ni"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(to_latex(input), expected, "input: {input}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let inputs = [
+            r"\frac{1}{\Bigl(\sqrt{\phi \sqrt{5}}-\phi\Bigr)}",
+            r"\hat{a} + \overbrace{a+b}^{n} + \xrightarrow[b]{a}",
+            r"\begin{pmatrix} a & b \\ c & d \end{pmatrix}",
+            r"\begin{array}{c|l:r} \hline a & b & c \\[2pt] d & e & f \end{array}",
+            r"\begin{align} x &= 1 \\ y &= 2 \end{align}",
+            r"\begin{darray}{c} \hline a \\ b \end{darray} \begin{dcases} \hline a & b \end{dcases}",
+            r"\textcolor{red}{x} \colorbox{blue}{text} \fcolorbox{red}{blue}{text}",
+            r"\binom{n}{k} \genfrac[]{1pt}{0}{a}{b} \sqrt[3]{x}",
+            r"\operatorname{foo}\limits_a \lim_{x \to 0} \not= \hspace{1cm} \,",
+        ];
+        for input in inputs {
+            let storage = Storage::new();
+            let expected = Parser::new(input, &storage)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let output = to_latex(input);
+            let events = Parser::new(&output, &storage)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(events, expected, "input: {input}\noutput: {output}");
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let storage = Storage::new();
+        let mut output = Vec::new();
+        let parser = Parser::new(r"\sqrt{x} + \mathbf{y} z \unknown", &storage);
+        assert!(write_latex(&mut output, parser).is_err());
+        // The last character is kept until the next element, to know whether a space must
+        // separate them.
+        assert_eq!(output, br"\sqrt{x}+\mathbf{y");
+    }
+}
@@ -4,16 +4,28 @@
 
 #![doc = include_str!("../docs/usage.md")]
 
+pub mod batch;
+pub mod cache;
+#[cfg(feature = "pulldown-cmark")]
+pub mod cmark;
 pub mod config;
 pub mod event;
+pub mod incremental;
+pub mod latex;
 pub mod mathml;
+pub mod mixed;
 pub mod parser;
+pub mod render;
+mod symbols;
+pub mod tree;
 
 #[doc(inline)]
 pub use config::RenderConfig;
 #[doc(inline)]
-pub use event::Event;
+pub use event::{buffer::EventBuffer, Event};
+#[doc(inline)]
+pub use latex::{push_latex, write_latex};
 #[doc(inline)]
 pub use mathml::{push_mathml, write_mathml};
 #[doc(inline)]
-pub use parser::{error::ParserError, storage::Storage, Parser};
+pub use parser::{document::Document, error::ParserError, storage::Storage, Parser};
@@ -0,0 +1,333 @@
+//! The `pulldown-latex` command-line tool, which renders LaTeX math to `MathML`.
+//!
+//! Run `pulldown-latex --help` for usage.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, BufWriter, Read, Write},
+    process::ExitCode,
+};
+
+use pulldown_latex::{
+    config::{DisplayMode, MathStyle, Profile},
+    push_mathml, write_mathml, Parser, RenderConfig, Storage,
+};
+
+const USAGE: &str = "\
+Render LaTeX math to MathML.
+
+Usage: pulldown-latex [OPTIONS] [FILE]...
+
+Reads an equation from each FILE, or from standard input if no FILE or `-` is given,
+and writes the corresponding MathML to standard output.
+
+Options:
+  -e, --expr <LATEX>         Render LATEX instead of reading files
+  -d, --display <MODE>       Display mode: `inline` (default) or `block`
+  -s, --math-style <STYLE>   Math style: `tex` (default), `iso`, `french` or `upright`
+  -p, --profile <PROFILE>    MathML version: `core` (default) or `mathml3`
+  -x, --xml                  Write the MathML namespace on the `<math>` element
+      --xml-prefix           Bind the namespace to the `m:` prefix of every element (implies --xml)
+  -a, --annotation           Include the LaTeX source as an annotation
+  -c, --error-color <COLOR>  Color of errors, as `#rrggbb` (default `#b22222`)
+  -l, --lines                Render every non-empty line of the input as its own equation
+      --safe                 Only write allowlisted MathML elements and attributes
+      --self-contained       Inline the rules of styles.css as style attributes
+      --events               Print the parsed events instead of MathML
+      --fail-on-error        Report parse errors on standard error and exit with status 1
+  -h, --help                 Print this help
+  -V, --version              Print the version
+";
+
+/// Where an equation comes from, used when reporting errors.
+enum Source {
+    Stdin,
+    Expr,
+    File(String),
+}
+
+impl Source {
+    fn name(&self) -> &str {
+        match self {
+            Source::Stdin => "<stdin>",
+            Source::Expr => "<expr>",
+            Source::File(path) => path,
+        }
+    }
+}
+
+struct Options {
+    sources: Vec<Source>,
+    exprs: Vec<String>,
+    display_mode: DisplayMode,
+    math_style: MathStyle,
+    profile: Profile,
+    xml: bool,
+    xml_prefix: bool,
+    annotation: bool,
+    error_color: (u8, u8, u8),
+    lines: bool,
+    safe: bool,
+    self_contained: bool,
+    events: bool,
+    fail_on_error: bool,
+}
+
+enum Command {
+    Run(Options),
+    Help,
+    Version,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options {
+        sources: Vec::new(),
+        exprs: Vec::new(),
+        display_mode: DisplayMode::Inline,
+        math_style: MathStyle::TeX,
+        profile: Profile::Core,
+        xml: false,
+        xml_prefix: false,
+        annotation: false,
+        error_color: RenderConfig::default().error_color,
+        lines: false,
+        safe: false,
+        self_contained: false,
+        events: false,
+        fail_on_error: false,
+    };
+    let mut only_files = false;
+
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with('-') {
+            options.sources.push(match arg.as_str() {
+                "-" => Source::Stdin,
+                _ => Source::File(arg),
+            });
+            continue;
+        }
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        if inline_value.is_some() && !takes_value(flag) {
+            return Err(format!("`{flag}` does not take a value"));
+        }
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for `{name}`"))
+        };
+
+        match flag {
+            "--" => only_files = true,
+            "-e" | "--expr" => {
+                let expr = value(flag)?;
+                options.exprs.push(expr);
+                options.sources.push(Source::Expr);
+            }
+            "-d" | "--display" => {
+                options.display_mode = match value(flag)?.as_str() {
+                    "inline" => DisplayMode::Inline,
+                    "block" => DisplayMode::Block,
+                    other => return Err(format!("unknown display mode `{other}`")),
+                }
+            }
+            "-s" | "--math-style" => {
+                options.math_style = match value(flag)?.to_ascii_lowercase().as_str() {
+                    "tex" => MathStyle::TeX,
+                    "iso" => MathStyle::ISO,
+                    "french" => MathStyle::French,
+                    "upright" => MathStyle::Upright,
+                    other => return Err(format!("unknown math style `{other}`")),
+                }
+            }
+            "-p" | "--profile" => {
+                options.profile = match value(flag)?.to_ascii_lowercase().as_str() {
+                    "core" => Profile::Core,
+                    "mathml3" => Profile::MathML3,
+                    other => return Err(format!("unknown profile `{other}`")),
+                }
+            }
+            "-c" | "--error-color" => {
+                let color = value(flag)?;
+                options.error_color =
+                    parse_color(&color).ok_or_else(|| format!("invalid color `{color}`"))?;
+            }
+            "-x" | "--xml" => options.xml = true,
+            "--xml-prefix" => {
+                options.xml = true;
+                options.xml_prefix = true;
+            }
+            "-a" | "--annotation" => options.annotation = true,
+            "-l" | "--lines" => options.lines = true,
+            "--safe" => options.safe = true,
+            "--self-contained" => options.self_contained = true,
+            "--events" => options.events = true,
+            "--fail-on-error" => options.fail_on_error = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            _ => return Err(format!("unknown option `{arg}`")),
+        }
+    }
+
+    if options.sources.is_empty() {
+        options.sources.push(Source::Stdin);
+    }
+    Ok(Command::Run(options))
+}
+
+fn takes_value(flag: &str) -> bool {
+    matches!(
+        flag,
+        "--expr" | "--display" | "--math-style" | "--profile" | "--error-color"
+    )
+}
+
+/// Parse a color given as `#rrggbb` or `rrggbb`.
+fn parse_color(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |range| u8::from_str_radix(&hex[range], 16).ok();
+    Some((channel(0..2)?, channel(2..4)?, channel(4..6)?))
+}
+
+/// Renders equations to the output, and keeps track of the errors found along the way.
+struct Renderer<W> {
+    options: Options,
+    storage: Storage,
+    output: W,
+    diagnostics: String,
+    failed: bool,
+}
+
+impl<W: Write> Renderer<W> {
+    fn render_source(&mut self, source: &Source, input: &str) -> io::Result<()> {
+        if !self.options.lines {
+            return self.render(source, 1, input);
+        }
+        for (index, line) in input.lines().enumerate() {
+            if !line.trim().is_empty() {
+                self.render(source, index + 1, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render a single equation, found at `line_number` of the source.
+    fn render(&mut self, source: &Source, line_number: usize, input: &str) -> io::Result<()> {
+        let events = Parser::new(input, &self.storage).collect::<Vec<_>>();
+        for error in events.iter().filter_map(|event| event.as_ref().err()) {
+            self.failed = true;
+            if self.options.fail_on_error {
+                let span = error.span();
+                let _ = writeln!(
+                    self.diagnostics,
+                    "{}:{}:{}: {}",
+                    source.name(),
+                    line_number + span.line - 1,
+                    span.column,
+                    error
+                );
+            }
+        }
+
+        if self.options.events {
+            for event in &events {
+                match event {
+                    Ok(event) => writeln!(self.output, "{event:?}")?,
+                    Err(error) => writeln!(self.output, "Err({:?})", error.kind())?,
+                }
+            }
+        } else {
+            let config = RenderConfig {
+                display_mode: self.options.display_mode,
+                annotation: self.options.annotation.then_some(input),
+                error_color: self.options.error_color,
+                xml: self.options.xml,
+                xml_prefix: self.options.xml_prefix,
+                math_style: self.options.math_style,
+                safe: self.options.safe,
+                profile: self.options.profile,
+                self_contained: self.options.self_contained,
+            };
+            if self.options.lines {
+                // Keep one line of output per equation, even when errors span several lines.
+                let mut mathml = String::new();
+                push_mathml(&mut mathml, events, config)?;
+                writeln!(self.output, "{}", mathml.replace('\n', "&#10;"))?;
+            } else {
+                write_mathml(&mut self.output, events, config)?;
+                writeln!(self.output)?;
+            }
+        }
+        self.storage.reset();
+        Ok(())
+    }
+}
+
+fn run(options: Options) -> io::Result<bool> {
+    let stdout = io::stdout();
+    let mut renderer = Renderer {
+        options,
+        storage: Storage::new(),
+        output: BufWriter::new(stdout.lock()),
+        diagnostics: String::new(),
+        failed: false,
+    };
+
+    let sources = std::mem::take(&mut renderer.options.sources);
+    let mut exprs = std::mem::take(&mut renderer.options.exprs).into_iter();
+    for source in &sources {
+        let input = match source {
+            Source::Stdin => {
+                let mut input = String::new();
+                io::stdin().read_to_string(&mut input)?;
+                input
+            }
+            Source::Expr => exprs.next().expect("every expression has a source"),
+            Source::File(path) => fs::read_to_string(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{path}: {e}")))?,
+        };
+        renderer.render_source(source, &input)?;
+        if !renderer.diagnostics.is_empty() {
+            renderer.output.flush()?;
+            eprint!("{}", renderer.diagnostics);
+            renderer.diagnostics.clear();
+        }
+    }
+    renderer.output.flush()?;
+
+    Ok(!(renderer.failed && renderer.options.fail_on_error))
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            print!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Version) => {
+            println!("pulldown-latex {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(options) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
@@ -1,82 +1,77 @@
 //! A simple MathML Core renderer.
 //!
 //! This crate provides a "simple" `mathml` renderer which is available through the
-//! [`push_mathml`] and [`write_mathml`] functions.
+//! [`push_mathml`] and [`write_mathml`] functions. It can also write `MathML` 3 for older user
+//! agents, see [`Profile`].
+
+mod inline;
+mod legacy;
+mod prefix;
+mod sanitize;
+mod tokenize;
 
 use std::{
-    collections::VecDeque,
+    cell::Cell,
     io::{self, Write},
+    ops::ControlFlow,
 };
 
+use self::{
+    inline::Inliner,
+    legacy::{Hold, Holder, Table, Unheld},
+    prefix::Prefixer,
+    sanitize::Sanitizer,
+};
 use crate::{
-    config::{DisplayMode, RenderConfig},
+    config::{Profile, RenderConfig},
     event::{
-        ArrayColumn, ColorChange, ColorTarget, ColumnAlignment, Content, DelimiterType,
-        EnvironmentFlow, Event, Font, Grouping, Line, ScriptPosition, ScriptType, StateChange,
-        Style, Visual,
+        ArrayColumn, ColumnAlignment, Content, DelimiterType, Dimension, Event, Font, Grouping,
+        Line, ScriptType, Style, Visual,
     },
+    render::{self, render, render_from, Atom, Context, Parent, Renderer, State},
 };
 
-struct MathmlWriter<'a, I: Iterator, W> {
-    input: ManyPeek<I>,
+struct MathmlWriter<'a, W> {
+    /// A [`Holder`] with `Profile::MathML3`, and an [`Unheld`] writer otherwise.
     writer: W,
     config: RenderConfig<'a>,
-    env_stack: Vec<Environment>,
-    state_stack: Vec<State>,
-    previous_atom: Option<Atom>,
+    /// The tables being rendered with `Profile::MathML3`, from the outermost to the innermost.
+    tables: Vec<Table>,
+    /// The number of the last numbered row.
+    equations: usize,
 }
 
-impl<'a, I, W, E> MathmlWriter<'a, I, W>
-where
-    I: Iterator<Item = Result<Event<'a>, E>>,
-    W: io::Write,
-    E: std::error::Error,
-{
-    fn new(input: I, writer: W, config: RenderConfig<'a>) -> Self {
-        // Size of the buffer is arbitrary for performance guess.
-        let mut state_stack = Vec::with_capacity(16);
-        state_stack.push(State {
-            font: None,
-            text_color: None,
-            border_color: None,
-            background_color: None,
-            style: None,
-        });
-        let env_stack = Vec::with_capacity(16);
+impl<'a, W: Hold> MathmlWriter<'a, W> {
+    fn new(writer: W, config: RenderConfig<'a>) -> Self {
         Self {
-            input: ManyPeek::new(input),
             writer,
             config,
-            env_stack,
-            state_stack,
-            previous_atom: None,
+            tables: Vec::new(),
+            equations: 0,
         }
     }
 
-    fn open_tag(&mut self, tag: &str, classes: Option<&str>) -> io::Result<()> {
+    /// Open a tag, along with the attributes corresponding to the state.
+    ///
+    /// The style is not written on elements in the position of a script, i.e., if `script` is
+    /// true.
+    fn open_tag(
+        &mut self,
+        tag: &str,
+        classes: Option<&str>,
+        state: &State,
+        script: bool,
+    ) -> io::Result<()> {
         let State {
             text_color,
             border_color,
             background_color,
             style,
-            font: _,
-        } = *self.state();
+            ..
+        } = *state;
         write!(self.writer, "<{}", tag)?;
         if let Some(style) = style {
-            if !matches!(
-                self.env_stack.last(),
-                Some(
-                    Environment::Script {
-                        ty: ScriptType::Subscript | ScriptType::Superscript,
-                        count: 0,
-                        ..
-                    } | Environment::Script {
-                        ty: ScriptType::SubSuperscript,
-                        count: 0 | 1,
-                        ..
-                    }
-                )
-            ) {
+            if !script {
                 let args = match style {
                     Style::Display => (true, 0),
                     Style::Text => (false, 0),
@@ -91,6 +86,22 @@ where
             }
         }
 
+        if self.config.profile == Profile::MathML3 {
+            // Borders are drawn by an `menclose` element instead, see `enter_group`.
+            if let Some((r, g, b)) = text_color {
+                write!(self.writer, " mathcolor=\"#{r:02x}{g:02x}{b:02x}\"")?;
+            }
+            if let Some((r, g, b)) = background_color {
+                write!(self.writer, " mathbackground=\"#{r:02x}{g:02x}{b:02x}\"")?;
+            }
+            if let Some(classes) = classes {
+                // The only class written for this profile is the one of small operators.
+                debug_assert_eq!(classes, "small");
+                self.writer.write_all(b" mathsize=\"70%\"")?;
+            }
+            return Ok(());
+        }
+
         let prefix = |style_written: &mut bool| {
             if !*style_written {
                 *style_written = true;
@@ -128,526 +139,163 @@ where
         Ok(())
     }
 
-    fn write_event(&mut self, event: Result<Event<'a>, E>) -> io::Result<()> {
-        match event {
-            Ok(Event::Content(content)) => self.write_content(content, false),
-            Ok(Event::Begin(grouping)) => {
-                // Mathematical environments do something different with state compared to things
-                // like left/right and {}.
-                //
-                // - They do not inherit state from their parent, their state is reset to default
-                // upon entering.
-                // - State changes occuring within them are also reset when crossing alignments or
-                // newlines (`&` or `\\`).
-                //
-                // For these reasons, they don't use the `open_tag` method.
-                // TODO: Make `StateChange::Style` be maintained across math_env groups.
-                self.previous_atom = None;
-                if grouping.is_math_env() {
-                    self.state_stack.push(State::default())
-                } else {
-                    let last_state = *self.state();
-                    self.state_stack.push(last_state);
-                    while let Some(Ok(Event::StateChange(state_change))) = self.input.peek_first() {
-                        let state_change = *state_change;
-                        self.handle_state_change(state_change);
-                        self.input.next();
-                    }
-                    self.open_tag("mrow", None)?;
-                    self.writer.write_all(b">")?;
-                    // Every state appliable to the style of the mrow is reset, i.e., everything
-                    // except font.
-                    *self.state_stack.last_mut().expect("state stack is empty") = State {
-                        font: self.state().font,
-                        ..State::default()
-                    };
-                }
-
-                macro_rules! env_horizontal_lines {
-                    () => {
-                        if let Some(Ok(Event::EnvironmentFlow(EnvironmentFlow::StartLines {
-                            lines,
-                        }))) = self.input.peek_first()
-                        {
-                            env_horizontal_lines(&mut self.writer, lines)?;
-                            self.input.next();
-                        }
-                    };
-                }
-
-                let env_group = match grouping {
-                    Grouping::Normal => EnvGrouping::Normal,
-                    Grouping::LeftRight(opening, closing) => {
-                        if let Some(delim) = opening {
-                            self.open_tag("mo", None)?;
-                            self.writer.write_all(b" stretchy=\"true\">")?;
-                            let mut buf = [0u8; 4];
-                            self.writer
-                                .write_all(delim.encode_utf8(&mut buf).as_bytes())?;
-                            self.writer.write_all(b"</mo>")?;
-                        }
-                        self.previous_atom = Some(Atom::Open);
-                        EnvGrouping::LeftRight { closing }
-                    }
-                    Grouping::Align { eq_numbers } => {
-                        self.writer
-                            .write_all(b"<mtable class=\"menv-alignlike menv-align")?;
-                        if eq_numbers {
-                            self.writer.write_all(b" menv-with-eqn")?;
-                        }
-                        self.writer.write_all(b"\"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Align
-                    }
-                    Grouping::Matrix { alignment } => {
-                        self.writer.write_all(b"<mtable class=\"menv-arraylike")?;
-                        self.writer.write_all(match alignment {
-                            ColumnAlignment::Left => b" menv-cells-left\"",
-                            ColumnAlignment::Center => b"\"",
-                            ColumnAlignment::Right => b" menv-cells-right\"",
-                        })?;
-                        self.writer.write_all(b"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Matrix
-                    }
-                    Grouping::Cases { left } => {
-                        self.writer.write_all(b"<mrow>")?;
-                        if left {
-                            self.writer.write_all(b"<mo stretchy=\"true\">{</mo>")?;
-                        }
-                        self.writer
-                            .write_all(b"<mtable class=\"menv-cells-left menv-cases\"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Cases {
-                            left,
-                            used_align: false,
-                        }
-                    }
-                    Grouping::Array(cols) => {
-                        self.writer
-                            .write_all(b"<mtable class=\"menv-arraylike\"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b">")?;
-                        let index = array_newline(&mut self.writer, &cols)?;
-                        EnvGrouping::Array {
-                            cols,
-                            cols_index: index,
-                        }
-                    }
-                    Grouping::Aligned => {
-                        self.writer
-                            .write_all(b"<mtable class=\"menv-alignlike menv-align\"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Align
-                    }
-                    Grouping::SubArray { alignment } => {
-                        self.writer.write_all(b"<mtable")?;
-                        match alignment {
-                            crate::event::ColumnAlignment::Left => {
-                                self.writer.write_all(b" class=\"menv-cells-left\"")?
-                            }
-                            crate::event::ColumnAlignment::Center => (),
-                            crate::event::ColumnAlignment::Right => {
-                                self.writer.write_all(b" class=\"menv-cells-right\"")?
-                            }
-                        }
-                        self.writer.write_all(b"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::SubArray
-                    }
-                    Grouping::Alignat { pairs, eq_numbers } => {
-                        self.writer.write_all(b"<mtable class=\"menv-alignlike")?;
-                        if eq_numbers {
-                            self.writer.write_all(b" menv-with-eqn")?;
-                        }
-                        self.writer.write_all(b"\"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Alignat {
-                            pairs,
-                            columns_used: 0,
-                        }
-                    }
-                    Grouping::Alignedat { pairs } => {
-                        self.writer.write_all(b"<mtable class=\"menv-alignlike\"")?;
-                        self.writer.write_all(b"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Alignat {
-                            pairs,
-                            columns_used: 0,
-                        }
-                    }
-                    Grouping::Gather { eq_numbers } => {
-                        self.writer.write_all(b"<mtable")?;
-                        if eq_numbers {
-                            self.writer.write_all(b" class=\"menv-with-eqn\"")?;
-                        }
-                        self.writer.write_all(b"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Gather
-                    }
-                    Grouping::Gathered => {
-                        self.writer.write_all(b"<mtable><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Gather
-                    }
-                    Grouping::Multline => {
-                        self.writer
-                            .write_all(b"<mtable class=\"menv-multline\"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Multline
-                    }
-                    Grouping::Split => {
-                        self.writer
-                            .write_all(b"<mtable class=\"menv-alignlike\"><mtr")?;
-                        env_horizontal_lines!();
-                        self.writer.write_all(b"><mtd>")?;
-                        EnvGrouping::Split { used_align: false }
-                    }
-                    Grouping::Equation { eq_numbers } => {
-                        self.writer.write_all(b"<mtable")?;
-                        if eq_numbers {
-                            self.writer.write_all(b" class=\"menv-with-eqn\"")?;
-                        }
-                        self.writer.write_all(b"><mtr><mtd>")?;
-                        EnvGrouping::Equation
-                    }
-                };
-                self.env_stack.push(Environment::from(env_group));
-                Ok(())
-            }
-            Ok(Event::End) => {
-                let env = self
-                    .env_stack
-                    .pop()
-                    .expect("cannot pop an environment in group end");
-                let Environment::Group(grouping) = env else {
-                    panic!("unexpected environment in group end");
-                };
-                self.state_stack
-                    .pop()
-                    .expect("cannot pop a state in group end");
-                self.previous_atom = Some(Atom::Inner);
-                match grouping {
-                    EnvGrouping::Normal => self.writer.write_all(b"</mrow>"),
-                    EnvGrouping::LeftRight { closing } => {
-                        if let Some(delim) = closing {
-                            self.open_tag("mo", None)?;
-                            self.writer.write_all(b" stretchy=\"true\">")?;
-                            let mut buf = [0u8; 4];
-                            self.writer
-                                .write_all(delim.encode_utf8(&mut buf).as_bytes())?;
-                            self.writer.write_all(b"</mo>")?;
-                        }
-                        self.previous_atom = Some(Atom::Close);
-                        self.writer.write_all(b"</mrow>")
-                    }
-                    EnvGrouping::Matrix
-                    | EnvGrouping::Align
-                    | EnvGrouping::SubArray
-                    | EnvGrouping::Gather
-                    | EnvGrouping::Multline
-                    | EnvGrouping::Equation
-                    | EnvGrouping::Split { .. }
-                    | EnvGrouping::Alignat { .. } => {
-                        self.writer.write_all(b"</mtd></mtr></mtable>")
-                    }
-                    EnvGrouping::Array { cols, cols_index } => {
-                        self.writer.write_all(b"</mtd>")?;
-                        cols[cols_index..]
-                            .iter()
-                            .map_while(|col| match col {
-                                ArrayColumn::Separator(line) => Some(line),
-                                _ => None,
-                            })
-                            .try_for_each(|line| {
-                                self.writer.write_all(match line {
-                                    Line::Solid => {
-                                        b"<mtd class=\"menv-right-solid menv-border-only\"></mtd>"
-                                    }
-                                    Line::Dashed => {
-                                        b"<mtd class=\"menv-right-dashed menv-border-only\"></mtd>"
-                                    }
-                                })
-                            })?;
-                        self.writer.write_all(b"</mtr></mtable>")
-                    }
-                    EnvGrouping::Cases { left, .. } => {
-                        self.writer.write_all(b"</mtd></mtr></mtable>")?;
-                        if !left {
-                            self.writer.write_all(b"<mo stretchy=\"true\">}</mo>")?;
-                        }
-                        self.writer.write_all(b"</mrow>")
-                    }
-                }
-            }
-            Ok(Event::Visual(visual)) => {
-                if visual == Visual::Negation {
-                    match self.input.peek_first() {
-                        Some(Ok(Event::Content(
-                            content @ Content::Ordinary { .. }
-                            | content @ Content::Relation { .. }
-                            | content @ Content::BinaryOp { .. }
-                            | content @ Content::LargeOp { .. }
-                            | content @ Content::Delimiter { .. }
-                            | content @ Content::Punctuation(_),
-                        ))) => {
-                            let content = *content;
-                            self.write_content(content, true)?;
-                            self.input.next();
-                        }
-                        _ => {
-                            self.open_tag("mrow", Some("mop-negated"))?;
-                            self.writer.write_all(b">")?;
-                            self.env_stack.push(Environment::from(visual));
-                        }
-                    }
-                    return Ok(());
-                }
-
-                let env = Environment::from(visual);
-                self.env_stack.push(env);
-                self.open_tag(visual_tag(visual), None)?;
-                if let Visual::Fraction(Some(dim)) = visual {
-                    write!(self.writer, " linethickness=\"{}\"", dim)?;
-                }
-
-                self.writer.write_all(b">")
-            }
-
-            Ok(Event::Script { ty, position }) => {
-                let state = self.state();
-                let above_below = match position {
-                    ScriptPosition::Right => false,
-                    ScriptPosition::AboveBelow => true,
-                    ScriptPosition::Movable => {
-                        state.style == Some(Style::Display)
-                            || (state.style.is_none()
-                                && self.config.display_mode == DisplayMode::Block)
-                    }
-                };
-                let env = Environment::from((ty, above_below));
-                self.env_stack.push(env);
-                self.open_tag(script_tag(ty, above_below), None)?;
-                self.writer.write_all(b">")
-            }
-
-            Ok(Event::Space { width, height }) => {
-                if let Some(width) = width {
-                    write!(self.writer, "<mspace width=\"{}\"", width)?;
-                    if width.value < 0. {
-                        write!(self.writer, " style=\"margin-left: {}\"", width)?;
-                    }
-                }
-                if let Some(height) = height {
-                    write!(self.writer, " height=\"{}\"", height)?;
-                }
-                self.writer.write_all(b" />")
-            }
-            Ok(Event::StateChange(state_change)) => {
-                self.handle_state_change(state_change);
-                Ok(())
+    /// Write the function application operator after a function, if something follows it.
+    fn function_application(&mut self, cx: &mut Context) -> io::Result<()> {
+        if let Some(atom) = cx.next_atom() {
+            self.writer.write_all("<mo>\u{2061}</mo>".as_bytes())?;
+            if !matches!(atom, Atom::Open | Atom::Punct | Atom::Close) {
+                self.writer
+                    .write_all("<mspace width=\"0.1667em\" />".as_bytes())?;
             }
-            Ok(Event::EnvironmentFlow(EnvironmentFlow::NewLine {
-                spacing,
-                horizontal_lines,
-            })) => {
-                *self
-                    .state_stack
-                    .last_mut()
-                    .expect("state stack should not be empty") = State::default();
-                self.previous_atom = None;
-
-                if let Some(Environment::Group(EnvGrouping::Array { cols, cols_index })) =
-                    self.env_stack.last()
-                {
-                    array_close_line(&mut self.writer, &cols[*cols_index..])?;
-                } else if let Some(Environment::Group(EnvGrouping::Equation { .. })) =
-                    self.env_stack.last()
-                {
-                    // LaTeX does _nothing_ when a newline is encountered in an eqution, we do the
-                    // same thing.
-                    return Ok(());
-                } else {
-                    self.writer.write_all(b"</mtd></mtr><mtr")?;
-                }
-
-                if let Some(spacing) = spacing {
-                    write!(self.writer, " style=\"height: {}\">", spacing)?;
-                    if let Some(Environment::Group(EnvGrouping::Array { cols, cols_index })) =
-                        self.env_stack.last_mut()
-                    {
-                        let mut index = array_newline(&mut self.writer, cols)?;
-                        while index < *cols_index {
-                            array_align(&mut self.writer, cols, &mut index)?;
-                        }
-                        array_close_line(&mut self.writer, &cols[index..])?;
-                    } else {
-                        self.writer
-                            .write_all(b"<mtd class=\"menv-nonumber\"></mtd></mtr><mtr")?;
-                    }
-                }
-                env_horizontal_lines(&mut self.writer, &horizontal_lines)?;
-
-                match self.env_stack.last_mut() {
-                    Some(Environment::Group(
-                        EnvGrouping::Cases { used_align, .. } | EnvGrouping::Split { used_align },
-                    )) => {
-                        *used_align = false;
-                        self.writer.write_all(b"><mtd>")
-                    }
-                    Some(Environment::Group(
-                        EnvGrouping::Matrix
-                        | EnvGrouping::Align
-                        | EnvGrouping::Gather
-                        | EnvGrouping::SubArray
-                        | EnvGrouping::Multline,
-                    )) => self.writer.write_all(b"><mtd>"),
-                    Some(Environment::Group(EnvGrouping::Array { cols, cols_index })) => {
-                        self.writer.write_all(b">")?;
-                        let new_index = array_newline(&mut self.writer, cols)?;
-                        *cols_index = new_index;
-                        Ok(())
-                    }
-                    Some(Environment::Group(EnvGrouping::Alignat { columns_used, .. })) => {
-                        *columns_used = 0;
-                        self.writer.write_all(b"><mtd>")
-                    }
+        }
+        Ok(())
+    }
 
-                    _ => panic!("newline not allowed in current environment"),
-                }
-            }
-            Ok(Event::EnvironmentFlow(EnvironmentFlow::Alignment)) => {
-                *self.state_stack.last_mut().expect("state stack is empty") = State::default();
-                self.previous_atom = None;
-                match self.env_stack.last_mut() {
-                    Some(Environment::Group(
-                        EnvGrouping::Cases {
-                            used_align: false, ..
-                        }
-                        | EnvGrouping::Split { used_align: false },
-                    )) => self.writer.write_all(b"</mtd><mtd>"),
-                    Some(Environment::Group(EnvGrouping::Align | EnvGrouping::Matrix)) => {
-                        self.writer.write_all(b"</mtd><mtd>")
-                    }
-                    Some(Environment::Group(EnvGrouping::Alignat {
-                        pairs,
-                        columns_used,
-                    })) if *columns_used / 2 <= *pairs => {
-                        *columns_used += 1;
-                        self.writer.write_all(b"</mtd><mtd>")
-                    }
-                    Some(Environment::Group(EnvGrouping::Array { cols, cols_index })) => {
-                        array_align(&mut self.writer, cols, cols_index)
-                    }
-                    _ => panic!("alignment not allowed in current environment"),
-                }
-            }
+    fn write<I, E>(mut self, input: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Result<Event<'a>, E>>,
+        E: std::error::Error,
+    {
+        self.write_header()?;
+        let display_mode = self.config.display_mode;
+        render(input, &mut self, display_mode)?;
+        self.write_footer()
+    }
 
-            Ok(Event::EnvironmentFlow(EnvironmentFlow::StartLines { .. })) => {
-                panic!("unexpected StartLines event found")
-            }
+    /// Write the start of the `math` element, and of the `semantics` element if there is an
+    /// annotation.
+    fn write_header(&mut self) -> io::Result<()> {
+        // Safety: this function must only write valid utf-8 to the writer.
+        // How is the writer used?:
+        // - using `write_all` with a utf-8 string.
+        // - using `write!` with a utf-8 string, and the parameters must all be valid utf-8 since
+        //      they are formatted using the `Display` trait.
+        write!(
+            self.writer,
+            "<math display=\"{}\"",
+            self.config.display_mode
+        )?;
+        if self.config.xml {
+            self.writer.write_all(if self.config.xml_prefix {
+                b" xmlns:m=\"http://www.w3.org/1998/Math/MathML\""
+            } else {
+                b" xmlns=\"http://www.w3.org/1998/Math/MathML\""
+            })?;
+        }
+        self.writer.write_all(b">")?;
+        if self.config.annotation.is_some() {
+            self.writer.write_all(b"<semantics><mrow>")?;
+        }
+        Ok(())
+    }
 
-            Err(e) => {
-                let error_color = self.config.error_color;
-                write!(
-                    self.writer,
-                    "<merror style=\"border-color: #{:x}{:x}{:x}\"><mtext>",
-                    error_color.0, error_color.1, error_color.2
-                )?;
-                self.writer.write_all(e.to_string().as_bytes())?;
-                self.writer.write_all(b"</mtext></merror>")
-            }
+    /// Write the annotation, if any, and the end of the `math` element.
+    fn write_footer(&mut self) -> io::Result<()> {
+        if let Some(annotation) = self.config.annotation {
+            self.writer.write_all(b"</mrow>")?;
+            self.writer
+                .write_all(b"<annotation encoding=\"application/x-tex\">")?;
+            write_escaped(&mut self.writer, annotation)?;
+            self.writer.write_all(b"</annotation>")?;
+            self.writer.write_all(b"</semantics>")?;
         }
+        self.writer.write_all(b"</math>")
     }
+}
+
+impl<'a, W: Hold> Renderer<'a> for MathmlWriter<'a, W> {
+    type Group = EnvGrouping;
+    type Error = io::Error;
 
-    fn write_content(&mut self, content: Content<'a>, negate: bool) -> io::Result<()> {
-        let mut buf = [0u8; 4];
+    fn content(&mut self, content: Content<'a>, negated: bool, cx: &mut Context) -> io::Result<()> {
+        let script = is_script(cx);
         match content {
             Content::Text(text) => {
-                self.open_tag("mtext", None)?;
+                self.open_tag("mtext", None, cx.state(), script)?;
                 self.writer.write_all(b">")?;
-                let trimmed = text.trim();
-                if text.starts_with(char::is_whitespace) {
-                    self.writer.write_all(b"&nbsp;")?;
+                // Non-breaking spaces are kept as they are, while other whitespace at the edges of
+                // the text would be collapsed by the user agent.
+                let is_padding = |c: char| c.is_whitespace() && c != '\u{a0}';
+                let trimmed = text.trim_matches(is_padding);
+                if text.starts_with(is_padding) {
+                    self.writer.write_all(b"&#160;")?;
                 }
-                self.writer.write_all(trimmed.as_bytes())?;
-                if text.ends_with(char::is_whitespace) {
-                    self.writer.write_all(b"&nbsp;")?;
+                write_escaped(&mut self.writer, trimmed)?;
+                if text.ends_with(is_padding) {
+                    self.writer.write_all(b"&#160;")?;
                 }
-                self.set_previous_atom(Atom::Ord);
                 self.writer.write_all(b"</mtext>")
             }
             Content::Number(number) => {
-                self.open_tag("mn", None)?;
-                self.writer.write_all(b">")?;
-                let buf = &mut [0u8; 4];
-                number.chars().try_for_each(|c| {
-                    let content = self.state().font.map_or(c, |v| v.map_char(c));
-                    let bytes = content.encode_utf8(buf);
-                    self.writer.write_all(bytes.as_bytes())
-                })?;
-                self.set_previous_atom(Atom::Ord);
+                self.open_tag("mn", None, cx.state(), script)?;
+                let font = cx.state().font;
+                match (font, self.config.profile) {
+                    (Some(font), Profile::MathML3) => {
+                        write!(
+                            self.writer,
+                            " mathvariant=\"{}\">",
+                            legacy::mathvariant(font)
+                        )?;
+                        write_escaped(&mut self.writer, number)?;
+                    }
+                    _ => {
+                        self.writer.write_all(b">")?;
+                        number.chars().try_for_each(|c| {
+                            let content = font.map_or(c, |v| v.map_char(c));
+                            write_escaped_char(&mut self.writer, content)
+                        })?;
+                    }
+                }
                 self.writer.write_all(b"</mn>")
             }
             Content::Function(str) => {
                 if matches!(
-                    self.previous_atom,
+                    cx.previous_atom(),
                     Some(Atom::Inner | Atom::Close | Atom::Ord)
                 ) {
                     self.writer
                         .write_all("<mspace width=\"0.1667em\" />".as_bytes())?;
                 }
 
-                self.open_tag("mi", None)?;
+                self.open_tag("mi", None, cx.state(), script)?;
                 self.writer.write_all(if str.chars().count() == 1 {
                     b" mathvariant=\"normal\">"
                 } else {
                     b">"
                 })?;
-                self.writer.write_all(str.as_bytes())?;
-                self.set_previous_atom(Atom::Op);
+                write_escaped(&mut self.writer, str)?;
                 self.writer.write_all(b"</mi>")?;
 
-                if let Some(Environment::Script { fn_application, .. }) = self.env_stack.last_mut()
-                {
-                    *fn_application = true;
-                } else if let Some(atom) = self.next_atom() {
-                    self.writer.write_all("<mo>\u{2061}</mo>".as_bytes())?;
-                    if !matches!(atom, Atom::Open | Atom::Punct | Atom::Close) {
-                        self.writer
-                            .write_all("<mspace width=\"0.1667em\" />".as_bytes())?;
-                    }
-                };
-
+                // The function application of a function in a script follows the script.
+                if !matches!(cx.parent(), Some(Parent::Script { .. })) {
+                    self.function_application(cx)?;
+                }
                 Ok(())
             }
             Content::Ordinary { content, stretchy } => {
                 if stretchy {
                     self.writer.write_all(b"<mo stretchy=\"true\">")?;
-                    self.writer
-                        .write_all(content.encode_utf8(&mut buf).as_bytes())?;
+                    write_escaped_char(&mut self.writer, content)?;
                     self.writer.write_all(b"</mo>")
                 } else {
-                    self.open_tag("mi", None)?;
+                    self.open_tag("mi", None, cx.state(), script)?;
 
                     let content = match (
-                        self.state().font,
+                        cx.state().font,
                         self.config.math_style.should_be_upright(content),
                     ) {
                         (Some(Font::UpRight), _) | (None, true) => {
                             self.writer.write_all(b" mathvariant=\"normal\">")?;
                             content
                         }
+                        (Some(font), _) if self.config.profile == Profile::MathML3 => {
+                            write!(
+                                self.writer,
+                                " mathvariant=\"{}\">",
+                                legacy::mathvariant(font)
+                            )?;
+                            content
+                        }
                         (Some(font), _) => {
                             self.writer.write_all(b">")?;
                             font.map_char(content)
@@ -658,81 +306,55 @@ where
                         }
                     };
 
-                    let buf = &mut [0u8; 4];
-                    let bytes = content.encode_utf8(buf);
-                    self.writer.write_all(bytes.as_bytes())?;
-                    if negate {
+                    write_escaped_char(&mut self.writer, content)?;
+                    if negated {
                         self.writer.write_all("\u{0338}".as_bytes())?;
                     }
-                    self.set_previous_atom(Atom::Ord);
                     self.writer.write_all(b"</mi>")
                 }
             }
-            // TexBook p. 153 and 157 for math classes.
-            // TexBook p. 170 for spacing.
-            // TexBook p. 438-443 for type setting rules (especially important for Binary Ops)
             Content::BinaryOp { content, small } => {
-                let tag = if matches!(
-                    self.previous_atom,
-                    Some(Atom::Inner | Atom::Close | Atom::Ord)
-                ) && !matches!(
-                    self.env_stack.last(),
-                    Some(
-                        Environment::Script { .. }
-                            | Environment::Visual {
-                                ty: Visual::Root | Visual::Fraction(_) | Visual::SquareRoot,
-                                ..
-                            }
-                    )
-                ) && matches!(
-                    self.next_atom(),
-                    Some(Atom::Inner | Atom::Bin | Atom::Op | Atom::Ord | Atom::Open)
-                ) {
-                    self.set_previous_atom(Atom::Bin);
+                let tag = if cx.atom() == Some(Atom::Bin) {
                     "mo"
                 } else {
-                    self.set_previous_atom(Atom::Ord);
                     "mi"
                 };
 
-                self.open_tag(tag, small.then_some("small"))?;
+                self.open_tag(tag, small.then_some("small"), cx.state(), script)?;
                 self.writer.write_all(b">")?;
-                self.writer
-                    .write_all(content.encode_utf8(&mut buf).as_bytes())?;
-                if negate {
+                write_escaped_char(&mut self.writer, content)?;
+                if negated {
                     self.writer.write_all("\u{0338}".as_bytes())?;
                 }
                 write!(self.writer, "</{}>", tag)
             }
             Content::Relation { content, small } => {
                 let mut buf = [0; 8];
-                self.open_tag("mo", small.then_some("small"))?;
+                self.open_tag("mo", small.then_some("small"), cx.state(), script)?;
                 self.writer.write_all(b">")?;
-                self.writer
-                    .write_all(content.encode_utf8_to_buf(&mut buf))?;
-                if negate {
+                let content = std::str::from_utf8(content.encode_utf8_to_buf(&mut buf))
+                    .expect("relations are encoded as utf-8");
+                write_escaped(&mut self.writer, content)?;
+                if negated {
                     self.writer.write_all("\u{0338}".as_bytes())?;
                 }
-                self.set_previous_atom(Atom::Rel);
                 self.writer.write_all(b"</mo>")
             }
 
             Content::LargeOp { content, small } => {
-                self.open_tag("mo", None)?;
+                self.open_tag("mo", None, cx.state(), script)?;
                 if small {
                     self.writer.write_all(b" largeop=\"false\"")?;
                 }
                 self.writer.write_all(b" movablelimits=\"false\">")?;
-                self.writer
-                    .write_all(content.encode_utf8(&mut buf).as_bytes())?;
-                if negate {
+                write_escaped_char(&mut self.writer, content)?;
+                if negated {
                     self.writer.write_all("\u{0338}".as_bytes())?;
                 }
-                self.set_previous_atom(Atom::Op);
                 self.writer.write_all(b"</mo>")
             }
             Content::Delimiter { content, size, ty } => {
-                self.open_tag("mo", None)?;
+                self.open_tag("mo", None, cx.state(), script)?;
                 write!(
                     self.writer,
                     " symmetric=\"{0}\" stretchy=\"{0}\"",
@@ -741,191 +363,491 @@ where
                 if let Some(size) = size {
                     write!(
                         self.writer,
-                        "minsize=\"{0}em\" maxsize=\"{0}em\"",
+                        " minsize=\"{0}em\" maxsize=\"{0}em\"",
                         size.to_em()
                     )?;
                 }
 
                 self.writer.write_all(b">")?;
-                self.writer
-                    .write_all(content.encode_utf8(&mut buf).as_bytes())?;
-                if negate {
+                write_escaped_char(&mut self.writer, content)?;
+                if negated {
                     self.writer.write_all("\u{0338}".as_bytes())?;
                 }
-                self.set_previous_atom(match ty {
-                    DelimiterType::Open => Atom::Open,
-                    DelimiterType::Fence => Atom::Punct,
-                    DelimiterType::Close => Atom::Close,
-                });
                 self.writer.write_all(b"</mo>")
             }
             Content::Punctuation(content) => {
-                self.open_tag("mo", None)?;
+                self.open_tag("mo", None, cx.state(), script)?;
                 self.writer.write_all(b">")?;
-                self.writer
-                    .write_all(content.encode_utf8(&mut buf).as_bytes())?;
-                if negate {
+                write_escaped_char(&mut self.writer, content)?;
+                if negated {
                     self.writer.write_all("\u{0338}".as_bytes())?;
                 }
-                self.set_previous_atom(Atom::Punct);
                 self.writer.write_all(b"</mo>")
             }
         }
     }
 
-    fn handle_state_change(&mut self, state_change: StateChange) {
-        let state = self.state_stack.last_mut().expect("state stack is empty");
-        match state_change {
-            StateChange::Font(font) => state.font = font,
-            StateChange::Color(ColorChange { color, target }) => match target {
-                ColorTarget::Text => state.text_color = Some(color),
-                ColorTarget::Border => state.border_color = Some(color),
-                ColorTarget::Background => state.background_color = Some(color),
-            },
-            StateChange::Style(style) => state.style = Some(style),
+    fn enter_group(
+        &mut self,
+        grouping: Grouping,
+        lines: &[Line],
+        cx: &mut Context,
+    ) -> io::Result<EnvGrouping> {
+        // Mathematical environments do not inherit the state of their parent, so they don't use
+        // the `open_tag` method.
+        let mut enclosed = false;
+        if !grouping.is_math_env() {
+            enclosed = self.config.profile == Profile::MathML3 && cx.state().border_color.is_some();
+            if enclosed {
+                self.open_tag("menclose", None, cx.state(), is_script(cx))?;
+                self.writer.write_all(b" notation=\"box\">")?;
+            } else {
+                self.open_tag("mrow", None, cx.state(), is_script(cx))?;
+                self.writer.write_all(b">")?;
+            }
+        } else if self.config.profile == Profile::MathML3 {
+            return self.enter_table(grouping, lines);
         }
+
+        Ok(match grouping {
+            Grouping::Normal => EnvGrouping::Normal { enclosed },
+            Grouping::LeftRight(opening, closing) => {
+                if let Some(delim) = opening {
+                    self.writer.write_all(b"<mo stretchy=\"true\">")?;
+                    write_escaped_char(&mut self.writer, delim)?;
+                    self.writer.write_all(b"</mo>")?;
+                }
+                EnvGrouping::LeftRight { closing, enclosed }
+            }
+            Grouping::Align { eq_numbers } => {
+                self.writer
+                    .write_all(b"<mtable class=\"menv-alignlike menv-align")?;
+                if eq_numbers {
+                    self.writer.write_all(b" menv-with-eqn")?;
+                }
+                self.writer.write_all(b"\"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Align
+            }
+            Grouping::Matrix { alignment } => {
+                self.writer.write_all(b"<mtable class=\"menv-arraylike")?;
+                self.writer.write_all(match alignment {
+                    ColumnAlignment::Left => b" menv-cells-left\"",
+                    ColumnAlignment::Center => b"\"",
+                    ColumnAlignment::Right => b" menv-cells-right\"",
+                })?;
+                self.writer.write_all(b"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Matrix
+            }
+            Grouping::Cases { left } => {
+                self.writer.write_all(b"<mrow>")?;
+                if left {
+                    self.writer.write_all(b"<mo stretchy=\"true\">{</mo>")?;
+                }
+                self.writer
+                    .write_all(b"<mtable class=\"menv-cells-left menv-cases\"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Cases {
+                    left,
+                    used_align: false,
+                }
+            }
+            Grouping::Array(cols) => {
+                self.writer
+                    .write_all(b"<mtable class=\"menv-arraylike\"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b">")?;
+                let index = array_newline(&mut self.writer, &cols)?;
+                EnvGrouping::Array {
+                    cols,
+                    cols_index: index,
+                }
+            }
+            Grouping::Aligned => {
+                self.writer
+                    .write_all(b"<mtable class=\"menv-alignlike menv-align\"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Align
+            }
+            Grouping::SubArray { alignment } => {
+                self.writer.write_all(b"<mtable")?;
+                match alignment {
+                    ColumnAlignment::Left => {
+                        self.writer.write_all(b" class=\"menv-cells-left\"")?
+                    }
+                    ColumnAlignment::Center => (),
+                    ColumnAlignment::Right => {
+                        self.writer.write_all(b" class=\"menv-cells-right\"")?
+                    }
+                }
+                self.writer.write_all(b"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::SubArray
+            }
+            Grouping::Alignat { pairs, eq_numbers } => {
+                self.writer.write_all(b"<mtable class=\"menv-alignlike")?;
+                if eq_numbers {
+                    self.writer.write_all(b" menv-with-eqn")?;
+                }
+                self.writer.write_all(b"\"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Alignat {
+                    pairs,
+                    columns_used: 0,
+                }
+            }
+            Grouping::Alignedat { pairs } => {
+                self.writer.write_all(b"<mtable class=\"menv-alignlike\"")?;
+                self.writer.write_all(b"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Alignat {
+                    pairs,
+                    columns_used: 0,
+                }
+            }
+            Grouping::Gather { eq_numbers } => {
+                self.writer.write_all(b"<mtable")?;
+                if eq_numbers {
+                    self.writer.write_all(b" class=\"menv-with-eqn\"")?;
+                }
+                self.writer.write_all(b"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Gather
+            }
+            Grouping::Gathered => {
+                self.writer.write_all(b"<mtable><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Gather
+            }
+            Grouping::Multline => {
+                self.writer
+                    .write_all(b"<mtable class=\"menv-multline\"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Multline
+            }
+            Grouping::Split => {
+                self.writer
+                    .write_all(b"<mtable class=\"menv-alignlike\"><mtr")?;
+                env_horizontal_lines(&mut self.writer, lines)?;
+                self.writer.write_all(b"><mtd>")?;
+                EnvGrouping::Split { used_align: false }
+            }
+            Grouping::Equation { eq_numbers } => {
+                self.writer.write_all(b"<mtable")?;
+                if eq_numbers {
+                    self.writer.write_all(b" class=\"menv-with-eqn\"")?;
+                }
+                self.writer.write_all(b"><mtr><mtd>")?;
+                EnvGrouping::Equation
+            }
+        })
     }
 
-    fn set_previous_atom(&mut self, atom: Atom) {
-        if !matches!(
-            self.env_stack.last(),
-            Some(
-                Environment::Visual {
-                    ty: Visual::Root | Visual::Fraction(_),
-                    count: 0
-                } | Environment::Script {
-                    ty: ScriptType::Subscript | ScriptType::Superscript,
-                    count: 0,
-                    ..
-                } | Environment::Script {
-                    ty: ScriptType::SubSuperscript,
-                    count: 0 | 1,
-                    ..
+    fn exit_group(&mut self, group: EnvGrouping, cx: &mut Context) -> io::Result<()> {
+        let group_end = |enclosed| -> &[u8] {
+            if enclosed {
+                b"</menclose>"
+            } else {
+                b"</mrow>"
+            }
+        };
+        match group {
+            EnvGrouping::Normal { enclosed } => self.writer.write_all(group_end(enclosed)),
+            EnvGrouping::LeftRight { closing, enclosed } => {
+                if let Some(delim) = closing {
+                    self.open_tag("mo", None, cx.state(), is_script(cx))?;
+                    self.writer.write_all(b" stretchy=\"true\">")?;
+                    write_escaped_char(&mut self.writer, delim)?;
+                    self.writer.write_all(b"</mo>")?;
                 }
-            )
-        ) {
-            self.previous_atom = Some(atom);
+                self.writer.write_all(group_end(enclosed))
+            }
+            _ if self.config.profile == Profile::MathML3 => self.exit_table(group),
+            EnvGrouping::Matrix
+            | EnvGrouping::Align
+            | EnvGrouping::SubArray
+            | EnvGrouping::Gather
+            | EnvGrouping::Multline
+            | EnvGrouping::Equation
+            | EnvGrouping::Split { .. }
+            | EnvGrouping::Alignat { .. } => self.writer.write_all(b"</mtd></mtr></mtable>"),
+            EnvGrouping::Array { cols, cols_index } => {
+                self.writer.write_all(b"</mtd>")?;
+                cols[cols_index..]
+                    .iter()
+                    .map_while(|col| match col {
+                        ArrayColumn::Separator(line) => Some(line),
+                        _ => None,
+                    })
+                    .try_for_each(|line| {
+                        self.writer.write_all(match line {
+                            Line::Solid => {
+                                b"<mtd class=\"menv-right-solid menv-border-only\"></mtd>"
+                            }
+                            Line::Dashed => {
+                                b"<mtd class=\"menv-right-dashed menv-border-only\"></mtd>"
+                            }
+                        })
+                    })?;
+                self.writer.write_all(b"</mtr></mtable>")
+            }
+            EnvGrouping::Cases { left, .. } => {
+                self.writer.write_all(b"</mtd></mtr></mtable>")?;
+                if !left {
+                    self.writer.write_all(b"<mo stretchy=\"true\">}</mo>")?;
+                }
+                self.writer.write_all(b"</mrow>")
+            }
         }
     }
 
-    fn next_atom(&mut self) -> Option<Atom> {
-        let mut index = 0;
-        loop {
-            let next = match self.input.peeked_nth(index) {
-                None => self.input.peek_next()?,
-                Some(next) => {
-                    index += 1;
-                    next
-                }
-            };
+    fn enter_script(
+        &mut self,
+        ty: ScriptType,
+        above_below: bool,
+        cx: &mut Context,
+    ) -> io::Result<()> {
+        self.open_tag(script_tag(ty, above_below), None, cx.state(), false)?;
+        self.writer.write_all(b">")
+    }
 
-            break match next {
-                Ok(
-                    Event::StateChange(_)
-                    | Event::Space { .. }
-                    | Event::Visual(Visual::Negation)
-                    | Event::Script { .. },
-                ) => continue,
-                Ok(Event::End | Event::EnvironmentFlow(_)) | Err(_) => None,
-                Ok(Event::Visual(_) | Event::Begin(_)) => Some(Atom::Inner),
-                Ok(Event::Content(content)) => match content {
-                    Content::BinaryOp { .. } => Some(Atom::Bin),
-                    Content::LargeOp { .. } => Some(Atom::Op),
-                    Content::Relation { .. } => Some(Atom::Rel),
-                    Content::Delimiter {
-                        ty: DelimiterType::Open,
-                        ..
-                    } => Some(Atom::Open),
-                    Content::Delimiter {
-                        ty: DelimiterType::Close,
-                        ..
-                    } => Some(Atom::Close),
-                    Content::Punctuation(_) => Some(Atom::Punct),
-                    _ => Some(Atom::Ord),
-                },
-            };
+    fn exit_script(
+        &mut self,
+        ty: ScriptType,
+        above_below: bool,
+        function: bool,
+        cx: &mut Context,
+    ) -> io::Result<()> {
+        write!(self.writer, "</{}>", script_tag(ty, above_below))?;
+        if function {
+            self.function_application(cx)?;
         }
+        Ok(())
     }
 
-    fn state(&self) -> &State {
-        self.state_stack.last().expect("state stack is empty")
+    fn enter_visual(&mut self, visual: Visual, cx: &mut Context) -> io::Result<()> {
+        if visual == Visual::Negation {
+            if self.config.profile == Profile::MathML3 {
+                self.open_tag("menclose", None, cx.state(), is_script(cx))?;
+                return self.writer.write_all(b" notation=\"updiagonalstrike\">");
+            }
+            self.open_tag("mrow", Some("mop-negated"), cx.state(), is_script(cx))?;
+            return self.writer.write_all(b">");
+        }
+
+        self.open_tag(visual_tag(visual), None, cx.state(), false)?;
+        if let Visual::Fraction(Some(dim)) = visual {
+            write!(self.writer, " linethickness=\"{}\"", dim)?;
+        }
+        self.writer.write_all(b">")
     }
 
-    fn write(mut self) -> io::Result<()> {
-        // Safety: this function must only write valid utf-8 to the writer.
-        // How is the writer used?:
-        // - using `write_all` with a utf-8 string.
-        // - using `write!` with a utf-8 string, and the parameters must all be valid utf-8 since
-        //      they are formatted using the `Display` trait.
-        write!(
-            self.writer,
-            "<math display=\"{}\"",
-            self.config.display_mode
-        )?;
-        if self.config.xml {
-            self.writer
-                .write_all(b" xmlns=\"http://www.w3.org/1998/Math/MathML\"")?;
+    fn exit_visual(&mut self, visual: Visual, _: &mut Context) -> io::Result<()> {
+        if visual == Visual::Negation && self.config.profile == Profile::MathML3 {
+            return self.writer.write_all(b"</menclose>");
         }
-        self.writer.write_all(b">")?;
-        if self.config.annotation.is_some() {
-            self.writer.write_all(b"<semantics><mrow>")?;
+        write!(self.writer, "</{}>", visual_tag(visual))
+    }
+
+    fn space(
+        &mut self,
+        width: Option<Dimension>,
+        height: Option<Dimension>,
+        _: &mut Context,
+    ) -> io::Result<()> {
+        if let Some(width) = width {
+            write!(self.writer, "<mspace width=\"{}\"", width)?;
+            // The margin makes up for negative widths, which MathML Core ignores.
+            if width.value < 0. && self.config.profile == Profile::Core {
+                write!(self.writer, " style=\"margin-left: {}\"", width)?;
+            }
+        }
+        if let Some(height) = height {
+            write!(self.writer, " height=\"{}\"", height)?;
         }
+        self.writer.write_all(b" />")
+    }
 
-        while let Some(event) = self.input.next() {
-            self.write_event(event)?;
-
-            while let Some((tag, count, fn_application)) =
-                self.env_stack.last_mut().and_then(|env| match env {
-                    Environment::Group(_) => None,
-                    Environment::Visual { ty, count } => Some((visual_tag(*ty), count, None)),
-                    Environment::Script {
-                        ty,
-                        above_below,
-                        count,
-                        fn_application,
-                    } => Some((script_tag(*ty, *above_below), count, Some(*fn_application))),
-                })
-            {
-                if *count != 0 {
-                    *count -= 1;
-                    break;
+    fn new_line(
+        &mut self,
+        group: &mut EnvGrouping,
+        spacing: Option<Dimension>,
+        lines: &[Line],
+        _: &mut Context,
+    ) -> io::Result<()> {
+        if self.config.profile == Profile::MathML3 {
+            return match group {
+                EnvGrouping::Normal { .. } | EnvGrouping::LeftRight { .. } => {
+                    panic!("newline not allowed in current environment")
                 }
-                self.writer.write_all(b"</")?;
-                self.writer.write_all(tag.as_bytes())?;
-                self.writer.write_all(b">")?;
-                self.set_previous_atom(Atom::Inner);
-                self.env_stack.pop();
+                EnvGrouping::Equation => Ok(()),
+                EnvGrouping::Cases { used_align, .. } | EnvGrouping::Split { used_align } => {
+                    *used_align = false;
+                    self.table_new_line(spacing, lines)
+                }
+                EnvGrouping::Alignat { columns_used, .. } => {
+                    *columns_used = 0;
+                    self.table_new_line(spacing, lines)
+                }
+                _ => self.table_new_line(spacing, lines),
+            };
+        }
 
-                if fn_application.unwrap_or(false) {
-                    if let Some(atom) = self.next_atom() {
-                        self.writer.write_all("<mo>\u{2061}</mo>".as_bytes())?;
+        match group {
+            EnvGrouping::Array { cols, cols_index } => {
+                array_close_line(&mut self.writer, &cols[*cols_index..])?;
+            }
+            // LaTeX does _nothing_ when a newline is encountered in an eqution, we do the same
+            // thing.
+            EnvGrouping::Equation => return Ok(()),
+            _ => self.writer.write_all(b"</mtd></mtr><mtr")?,
+        }
 
-                        if !matches!(atom, Atom::Open | Atom::Punct | Atom::Close) {
-                            self.writer
-                                .write_all("<mspace width=\"0.1667em\" />".as_bytes())?;
-                        }
-                    }
+        if let Some(spacing) = spacing {
+            write!(self.writer, " style=\"height: {}\">", spacing)?;
+            if let EnvGrouping::Array { cols, cols_index } = group {
+                let mut index = array_newline(&mut self.writer, cols)?;
+                while index < *cols_index {
+                    array_align(&mut self.writer, cols, &mut index)?;
                 }
+                array_close_line(&mut self.writer, &cols[index..])?;
+            } else {
+                self.writer
+                    .write_all(b"<mtd class=\"menv-nonumber\"></mtd></mtr><mtr")?;
             }
         }
+        env_horizontal_lines(&mut self.writer, lines)?;
 
-        if !self.env_stack.is_empty() || self.state_stack.len() != 1 {
-            panic!("unbalanced environment stack or state stack");
+        match group {
+            EnvGrouping::Cases { used_align, .. } | EnvGrouping::Split { used_align } => {
+                *used_align = false;
+                self.writer.write_all(b"><mtd>")
+            }
+            EnvGrouping::Matrix
+            | EnvGrouping::Align
+            | EnvGrouping::Gather
+            | EnvGrouping::SubArray
+            | EnvGrouping::Multline => self.writer.write_all(b"><mtd>"),
+            EnvGrouping::Array { cols, cols_index } => {
+                self.writer.write_all(b">")?;
+                let new_index = array_newline(&mut self.writer, cols)?;
+                *cols_index = new_index;
+                Ok(())
+            }
+            EnvGrouping::Alignat { columns_used, .. } => {
+                *columns_used = 0;
+                self.writer.write_all(b"><mtd>")
+            }
+
+            _ => panic!("newline not allowed in current environment"),
         }
+    }
 
-        if let Some(annotation) = self.config.annotation {
-            self.writer.write_all(b"</mrow>")?;
-            write!(
-                self.writer,
-                "<annotation encoding=\"application/x-tex\">{}</annotation>",
-                annotation
-            )?;
-            self.writer.write_all(b"</semantics>")?;
+    fn alignment(&mut self, group: &mut EnvGrouping, _: &mut Context) -> io::Result<()> {
+        let legacy = self.config.profile == Profile::MathML3;
+        match group {
+            EnvGrouping::Cases {
+                used_align: false, ..
+            }
+            | EnvGrouping::Split { used_align: false }
+            | EnvGrouping::Align
+            | EnvGrouping::Matrix => {}
+            EnvGrouping::Alignat {
+                pairs,
+                columns_used,
+            } if *columns_used / 2 <= *pairs => {
+                *columns_used += 1;
+            }
+            EnvGrouping::Array { cols, cols_index } if !legacy => {
+                return array_align(&mut self.writer, cols, cols_index)
+            }
+            EnvGrouping::Array { .. } => {}
+            _ => panic!("alignment not allowed in current environment"),
         }
-        self.writer.write_all(b"</math>")
+        if legacy {
+            self.table_alignment()
+        } else {
+            self.writer.write_all(b"</mtd><mtd>")
+        }
+    }
+
+    fn error(&mut self, source: &'a str, _: &mut Context) -> io::Result<()> {
+        let error_color = self.config.error_color;
+        write!(
+            self.writer,
+            "<merror style=\"border-color: #{:02x}{:02x}{:02x}\"><mtext>",
+            error_color.0, error_color.1, error_color.2
+        )?;
+        write_escaped(&mut self.writer, source)?;
+        self.writer.write_all(b"</mtext></merror>")
+    }
+
+    fn parse_error(&mut self, error: &dyn std::error::Error, _: &mut Context) -> io::Result<()> {
+        let error_color = self.config.error_color;
+        write!(
+            self.writer,
+            "<merror style=\"border-color: #{:02x}{:02x}{:02x}\"><mtext>",
+            error_color.0, error_color.1, error_color.2
+        )?;
+        write_escaped(&mut self.writer, &error.to_string())?;
+        self.writer.write_all(b"</mtext></merror>")
+    }
+}
+
+/// Whether the element being rendered is a script, rather than the base of a script.
+fn is_script(cx: &Context) -> bool {
+    matches!(cx.parent(), Some(Parent::Script { argument: 1.., .. }))
+}
+
+/// Write text coming from the input, escaping the characters which are markup in HTML and XML.
+///
+/// Only numeric character references are used, since named ones such as `&nbsp;` are not defined
+/// in XML. Characters which are not allowed in XML are replaced by U+FFFD.
+fn write_escaped<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    let mut rest = text;
+    while let Some((index, c, escaped)) = rest
+        .char_indices()
+        .find_map(|(index, c)| escape(c).map(|escaped| (index, c, escaped)))
+    {
+        writer.write_all(&rest.as_bytes()[..index])?;
+        writer.write_all(escaped.as_bytes())?;
+        rest = &rest[index + c.len_utf8()..];
+    }
+    writer.write_all(rest.as_bytes())
+}
+
+fn write_escaped_char<W: Write>(writer: &mut W, c: char) -> io::Result<()> {
+    match escape(c) {
+        Some(escaped) => writer.write_all(escaped.as_bytes()),
+        None => writer.write_all(c.encode_utf8(&mut [0; 4]).as_bytes()),
     }
 }
 
+/// The escaped form of a character, if it needs one.
+fn escape(c: char) -> Option<&'static str> {
+    Some(match c {
+        '&' => "&amp;",
+        '<' => "&lt;",
+        '>' => "&gt;",
+        '"' => "&quot;",
+        '\u{a0}' => "&#160;",
+        '\t' | '\n' | '\r' => return None,
+        '\0'..='\u{1f}' | '\u{7f}' | '\u{fffe}' | '\u{ffff}' => "\u{fffd}",
+        _ => return None,
+    })
+}
+
 fn array_newline<W: Write>(writer: &mut W, cols: &[ArrayColumn]) -> io::Result<usize> {
     let mut index = 0;
     writer.write_all(b"<mtd")?;
@@ -1078,22 +1000,15 @@ fn env_horizontal_lines<W: Write>(writer: &mut W, lines: &[Line]) -> io::Result<
     Ok(())
 }
 
-enum Atom {
-    Bin,
-    Op,
-    Rel,
-    Open,
-    Close,
-    Punct,
-    Ord,
-    Inner,
-}
-
 #[derive(Debug, Clone, PartialEq)]
 enum EnvGrouping {
-    Normal,
+    /// `enclosed` is true if the group is written as an `menclose` element instead of an `mrow`.
+    Normal {
+        enclosed: bool,
+    },
     LeftRight {
         closing: Option<char>,
+        enclosed: bool,
     },
     Array {
         cols: Box<[ArrayColumn]>,
@@ -1118,55 +1033,6 @@ enum EnvGrouping {
     Equation,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum Environment {
-    Group(EnvGrouping),
-    Visual {
-        ty: Visual,
-        count: u8,
-    },
-    Script {
-        ty: ScriptType,
-        above_below: bool,
-        count: u8,
-        fn_application: bool,
-    },
-}
-
-impl From<EnvGrouping> for Environment {
-    fn from(v: EnvGrouping) -> Self {
-        Self::Group(v)
-    }
-}
-
-impl From<(ScriptType, bool)> for Environment {
-    fn from((ty, above_below): (ScriptType, bool)) -> Self {
-        let count = match ty {
-            ScriptType::Subscript => 2,
-            ScriptType::Superscript => 2,
-            ScriptType::SubSuperscript => 3,
-        };
-        Self::Script {
-            ty,
-            above_below,
-            count,
-            fn_application: false,
-        }
-    }
-}
-
-impl From<Visual> for Environment {
-    fn from(v: Visual) -> Self {
-        let count = match v {
-            Visual::SquareRoot => 1,
-            Visual::Root => 2,
-            Visual::Fraction(_) => 2,
-            Visual::Negation => 1,
-        };
-        Self::Visual { ty: v, count }
-    }
-}
-
 fn script_tag(ty: ScriptType, above_below: bool) -> &'static str {
     match (ty, above_below) {
         (ScriptType::Subscript, false) => "msub",
@@ -1187,54 +1053,6 @@ fn visual_tag(visual: Visual) -> &'static str {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
-struct State {
-    font: Option<Font>,
-    text_color: Option<(u8, u8, u8)>,
-    border_color: Option<(u8, u8, u8)>,
-    background_color: Option<(u8, u8, u8)>,
-    style: Option<Style>,
-}
-
-struct ManyPeek<I: Iterator> {
-    iter: I,
-    peeked: VecDeque<I::Item>,
-}
-
-impl<I: Iterator> ManyPeek<I> {
-    fn new(iter: I) -> Self {
-        Self {
-            iter,
-            peeked: VecDeque::new(),
-        }
-    }
-
-    fn peek_next(&mut self) -> Option<&I::Item> {
-        self.peeked.push_back(self.iter.next()?);
-        self.peeked.back()
-    }
-
-    fn peeked_nth(&self, n: usize) -> Option<&I::Item> {
-        self.peeked.get(n)
-    }
-
-    fn peek_first(&mut self) -> Option<&I::Item> {
-        if self.peeked.is_empty() {
-            self.peek_next()
-        } else {
-            self.peeked.front()
-        }
-    }
-}
-
-impl<I: Iterator> Iterator for ManyPeek<I> {
-    type Item = I::Item;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.peeked.pop_front().or_else(|| self.iter.next())
-    }
-}
-
 impl Font {
     /// Map a character to its mathvariant equivalent.
     fn map_char(self, c: char) -> char {
@@ -1390,32 +1208,276 @@ impl Font {
     }
 }
 
-/// Takes a [`Parser`], or any `Iterator<Item = Result<Event<'_>, E>>` as input, and renders
-/// the MathML into the given string.
+/// Takes a [`Parser`], an [`EventBuffer`], or any `IntoIterator<Item = Result<Event<'_>, E>>` as
+/// input, and renders the MathML into the given string.
 ///
 /// [`Parser`]: crate::parser::Parser
+/// [`EventBuffer`]: crate::event::buffer::EventBuffer
 pub fn push_mathml<'a, I, E>(
     string: &mut String,
     parser: I,
     config: RenderConfig<'a>,
 ) -> io::Result<()>
 where
-    I: Iterator<Item = Result<Event<'a>, E>>,
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
     E: std::error::Error,
 {
     // SAFETY: The MathmlWriter guarantees that all writes to the writer are valid utf-8.
-    MathmlWriter::new(parser, unsafe { string.as_mut_vec() }, config).write()
+    write_mathml(unsafe { string.as_mut_vec() }, parser, config)
 }
 
-/// Takes a [`Parser`], or any `Iterator<Item = Result<Event<'_>, E>>`, as input and renders the
-/// MathML into the given writer.
+/// Takes a [`Parser`], an [`EventBuffer`], or any `IntoIterator<Item = Result<Event<'_>, E>>`, as
+/// input and renders the MathML into the given writer.
 ///
 /// [`Parser`]: crate::parser::Parser
+/// [`EventBuffer`]: crate::event::buffer::EventBuffer
 pub fn write_mathml<'a, I, W, E>(writer: W, parser: I, config: RenderConfig<'a>) -> io::Result<()>
 where
-    I: Iterator<Item = Result<Event<'a>, E>>,
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    W: io::Write,
+    E: std::error::Error,
+{
+    let equations = Cell::new(0);
+    pipeline(writer, config, &equations, WriteEquation(parser))
+}
+
+/// Work done with a [`MathmlWriter`], whichever writers its output goes through.
+trait Job<'a> {
+    fn run<W: Hold>(self, writer: MathmlWriter<'a, W>) -> io::Result<()>;
+}
+
+/// Run the job with a writer whose output goes through the writers required by the
+/// configuration, before reaching `writer`. Equations numbered by the inliner are counted in
+/// `equations`.
+///
+/// The inliner comes first, so that the sanitizer checks the styles it writes, and it writes the
+/// elements it is given back as they are, along with ASCII declarations. The sanitizer only
+/// removes whole tags and attributes, and writes ASCII in their place, and the prefixer only
+/// writes ASCII between the bytes it is given, so their output is valid utf-8 as well.
+fn pipeline<'a, W, J>(
+    writer: W,
+    config: RenderConfig<'a>,
+    equations: &Cell<usize>,
+    job: J,
+) -> io::Result<()>
+where
     W: io::Write,
+    J: Job<'a>,
+{
+    fn inlined<'a, W: io::Write, J: Job<'a>>(
+        writer: W,
+        config: RenderConfig<'a>,
+        equations: &Cell<usize>,
+        job: J,
+    ) -> io::Result<()> {
+        match config.profile {
+            Profile::MathML3 => job.run(MathmlWriter::new(Holder::new(writer), config)),
+            Profile::Core if config.self_contained => job.run(MathmlWriter::new(
+                Unheld(Inliner::new(writer, equations)),
+                config,
+            )),
+            Profile::Core => job.run(MathmlWriter::new(Unheld(writer), config)),
+        }
+    }
+
+    match (config.safe, config.xml && config.xml_prefix) {
+        (false, false) => inlined(writer, config, equations, job),
+        (true, false) => inlined(Sanitizer::new(writer), config, equations, job),
+        (false, true) => inlined(Prefixer::new(writer), config, equations, job),
+        (true, true) => inlined(
+            Sanitizer::new(Prefixer::new(writer)),
+            config,
+            equations,
+            job,
+        ),
+    }
+}
+
+/// Render a whole equation.
+struct WriteEquation<I>(I);
+
+impl<'a, I, E> Job<'a> for WriteEquation<I>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    E: std::error::Error,
+{
+    fn run<W: Hold>(self, writer: MathmlWriter<'a, W>) -> io::Result<()> {
+        writer.write(self.0)
+    }
+}
+
+/// The position of the renderer between two top-level elements, from which rendering can be
+/// resumed with [`push_elements`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Checkpoint {
+    render: render::Checkpoint,
+    /// The number of the last numbered row.
+    equations: usize,
+}
+
+/// A position of the `MathML` reported by [`push_equation`] and [`push_elements`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Boundary {
+    /// The number of events rendered so far.
+    pub(crate) events: usize,
+    /// The length of the string at this position.
+    pub(crate) output: usize,
+    /// The checkpoint before the next top-level element, or `None` at the start of a row of a
+    /// top-level environment.
+    pub(crate) checkpoint: Option<Checkpoint>,
+}
+
+/// Like [`push_mathml`], but calling `boundary` before the first top-level element, after every
+/// top-level element, and at the start of every row of a top-level environment.
+///
+/// Rows are only reported when their `MathML` is written as soon as it is rendered, i.e., with
+/// [`Profile::Core`] and without [`RenderConfig::self_contained`].
+pub(crate) fn push_equation<'a, I, E>(
+    string: &mut String,
+    events: I,
+    config: RenderConfig<'a>,
+    boundary: impl FnMut(Boundary),
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    E: std::error::Error,
+{
+    let mut boundary = boundary;
+    push_with_boundaries(string, events, config, None, |position| {
+        boundary(position);
+        ControlFlow::Continue(())
+    })
+}
+
+/// Render the top-level elements of the events, without the `math` element around them, starting
+/// from the checkpoint. Boundaries are reported as with [`push_equation`], and rendering stops
+/// when `boundary` breaks.
+pub(crate) fn push_elements<'a, I, E>(
+    string: &mut String,
+    events: I,
+    config: RenderConfig<'a>,
+    checkpoint: Checkpoint,
+    boundary: impl FnMut(Boundary) -> ControlFlow<()>,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    E: std::error::Error,
+{
+    push_with_boundaries(string, events, config, Some(checkpoint), boundary)
+}
+
+/// Render the events, along with the `math` element around them unless a checkpoint to start
+/// from is given.
+fn push_with_boundaries<'a, I, E>(
+    string: &mut String,
+    events: I,
+    config: RenderConfig<'a>,
+    checkpoint: Option<Checkpoint>,
+    boundary: impl FnMut(Boundary) -> ControlFlow<()>,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    E: std::error::Error,
+{
+    let written = Cell::new(string.len());
+    let equations = Cell::new(checkpoint.map_or(0, |checkpoint| checkpoint.equations));
+    let job = WriteElements {
+        events,
+        checkpoint,
+        written: &written,
+        equations: &equations,
+        boundary,
+    };
+    // SAFETY: The MathmlWriter guarantees that all writes to the writer are valid utf-8.
+    let writer = Counter {
+        writer: unsafe { string.as_mut_vec() },
+        written: &written,
+    };
+    pipeline(writer, config, &equations, job)
+}
+
+/// Render events, reporting the boundaries of the top-level elements.
+struct WriteElements<'c, I, F> {
+    events: I,
+    /// The checkpoint to start from, or `None` to write a whole equation.
+    checkpoint: Option<Checkpoint>,
+    /// The number of bytes written to the string.
+    written: &'c Cell<usize>,
+    /// The number of the last row numbered by the inliner.
+    equations: &'c Cell<usize>,
+    boundary: F,
+}
+
+impl<'a, I, E, F> Job<'a> for WriteElements<'_, I, F>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
     E: std::error::Error,
+    F: FnMut(Boundary) -> ControlFlow<()>,
 {
-    MathmlWriter::new(parser, writer, config).write()
+    fn run<W: Hold>(mut self, mut writer: MathmlWriter<'a, W>) -> io::Result<()> {
+        let whole = self.checkpoint.is_none();
+        let checkpoint = self.checkpoint.unwrap_or_default();
+        let inlined = writer.config.self_contained && writer.config.profile == Profile::Core;
+        let rows = writer.config.profile == Profile::Core && !writer.config.self_contained;
+        writer.equations = checkpoint.equations;
+        if whole {
+            writer.write_header()?;
+        }
+
+        let start = (self.boundary)(Boundary {
+            events: 0,
+            output: self.written.get(),
+            checkpoint: Some(checkpoint),
+        });
+        if start.is_continue() {
+            let display_mode = writer.config.display_mode;
+            render_from(
+                self.events,
+                &mut writer,
+                display_mode,
+                checkpoint.render,
+                |writer, boundary| {
+                    let checkpoint = match boundary.checkpoint {
+                        Some(render) => Some(Checkpoint {
+                            render,
+                            equations: match inlined {
+                                true => self.equations.get(),
+                                false => writer.equations,
+                            },
+                        }),
+                        None if !rows => return ControlFlow::Continue(()),
+                        None => None,
+                    };
+                    (self.boundary)(Boundary {
+                        events: boundary.events,
+                        output: self.written.get(),
+                        checkpoint,
+                    })
+                },
+            )?;
+        }
+
+        if whole {
+            writer.write_footer()?;
+        }
+        Ok(())
+    }
+}
+
+/// A writer counting the bytes written through it.
+struct Counter<'c, W> {
+    writer: W,
+    written: &'c Cell<usize>,
+}
+
+impl<W: Write> Write for Counter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.written.set(self.written.get() + written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
 }
@@ -0,0 +1,582 @@
+//! The writer used when [`RenderConfig::self_contained`](crate::RenderConfig::self_contained) is
+//! set.
+//!
+//! Most rules of `styles.css` depend on the position of elements in their table, e.g., the
+//! alignment of the odd columns of `align`, so the inliner gathers each table into a tree before
+//! writing it back with the declarations of the stylesheet as `style` attributes. Other elements
+//! are written as they come, since the rules selecting them only depend on their parent.
+
+use std::{
+    cell::Cell,
+    io::{self, Write},
+};
+
+use super::tokenize::{Token, Tokenizer};
+
+/// The declarations of the `text-align: right` rule, in the order of the stylesheet.
+const ALIGN_RIGHT: &[(&str, &str)] = &[
+    ("text-align", "right"),
+    ("text-align", "-webkit-right"),
+    ("text-align", "-moz-right"),
+];
+
+/// The background of the `mop-negated` rule, striking through the operator.
+const NEGATION_STRIKE: &str = "linear-gradient(to top left, rgba(0, 0, 0, 0) 0%, \
+    rgba(0, 0, 0, 0) calc(50% - 0.8px), rgba(0, 0, 0, 1) 50%, rgba(0, 0, 0, 0) calc(50% + 0.8px), \
+    rgba(0, 0, 0, 0) 100%)";
+
+/// A writer passing through the `MathML` written to it, with the `class` attributes replaced by
+/// the declarations of the stylesheet they select.
+pub(super) struct Inliner<'c, W> {
+    writer: W,
+    tokenizer: Tokenizer,
+    /// The names of the open elements outside of tables, from the `math` element to the innermost
+    /// one.
+    names: Vec<String>,
+    /// The open elements of the table being gathered, from the table to the innermost one.
+    table: Vec<Element>,
+    /// The number of the last numbered row.
+    equations: &'c Cell<usize>,
+}
+
+impl<'c, W: Write> Inliner<'c, W> {
+    pub(super) fn new(writer: W, equations: &'c Cell<usize>) -> Self {
+        Self {
+            writer,
+            tokenizer: Tokenizer::default(),
+            names: Vec::new(),
+            table: Vec::new(),
+            equations,
+        }
+    }
+
+    fn push_text(&mut self, text: &[u8]) -> io::Result<()> {
+        let Some(parent) = self.table.last_mut() else {
+            return self.writer.write_all(text);
+        };
+        if let Some(Node::Text(previous)) = parent.children.last_mut() {
+            previous.extend_from_slice(text);
+        } else {
+            parent.children.push(Node::Text(text.to_vec()));
+        }
+        Ok(())
+    }
+
+    fn push_tag(&mut self, tag: &[u8]) -> io::Result<()> {
+        let tag = std::str::from_utf8(tag)
+            .ok()
+            .and_then(|tag| tag.strip_prefix('<')?.strip_suffix('>'))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed tag"))?;
+        let unbalanced = || io::Error::new(io::ErrorKind::InvalidData, "unbalanced end tag");
+
+        if tag.starts_with('/') {
+            if self.table.is_empty() {
+                let name = self.names.pop().ok_or_else(unbalanced)?;
+                return write!(self.writer, "</{name}>");
+            }
+            let element = self.table.pop().ok_or_else(unbalanced)?;
+            return self.push_element(element);
+        }
+
+        let mut element = Element::parse(tag);
+        if self.table.is_empty() && element.name != "mtable" {
+            declare_own(&mut element, self.names.last().map(String::as_str));
+            element.write_start_tag(&mut self.writer)?;
+            if !element.empty_tag {
+                self.names.push(element.name);
+            }
+            return Ok(());
+        }
+        if element.empty_tag {
+            return self.push_element(element);
+        }
+        self.table.push(element);
+        Ok(())
+    }
+
+    /// Add a whole element to the table being gathered, or write it if it is the table itself.
+    fn push_element(&mut self, mut element: Element) -> io::Result<()> {
+        match self.table.last_mut() {
+            Some(parent) => parent.children.push(Node::Element(element)),
+            None => {
+                let parent = self.names.last().map(String::as_str);
+                let mut equations = self.equations.get();
+                apply_stylesheet(&mut element, parent, &mut equations);
+                self.equations.set(equations);
+                element.write_to(&mut self.writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Inliner<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut chunk = buf;
+        while let Some(token) = self.tokenizer.next(&mut chunk) {
+            match token {
+                Token::Text(text) => self.push_text(text)?,
+                Token::Tag(tag) => self.push_tag(&tag)?,
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+enum Node {
+    Element(Element),
+    /// Escaped text, written back as is.
+    Text(Vec<u8>),
+}
+
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+    /// Whether the element was written as an empty element tag, e.g., `<mspace />`.
+    empty_tag: bool,
+    /// The declarations selected by the classes of the element and its position, in the order in
+    /// which they are written.
+    declarations: Vec<(&'static str, &'static str)>,
+}
+
+impl Element {
+    /// Parse the content of a start tag, between its `<` and `>`.
+    fn parse(tag: &str) -> Self {
+        let (tag, empty_tag) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_len = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        let (name, mut rest) = tag.split_at(name_len);
+        let mut attributes = Vec::new();
+        while let Some((attribute, value)) = rest.split_once('=') {
+            let Some((value, next)) = value
+                .trim_start()
+                .strip_prefix('"')
+                .and_then(|value| value.split_once('"'))
+            else {
+                break;
+            };
+            attributes.push((attribute.trim().to_owned(), value.to_owned()));
+            rest = next;
+        }
+        Self {
+            name: name.to_owned(),
+            attributes,
+            children: Vec::new(),
+            empty_tag,
+            declarations: Vec::new(),
+        }
+    }
+
+    fn has_class(&self, class: &str) -> bool {
+        self.attributes
+            .iter()
+            .any(|(attribute, value)| attribute == "class" && value.split(' ').any(|c| c == class))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    fn elements_mut(&mut self) -> impl Iterator<Item = &mut Element> {
+        self.children.iter_mut().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// Add declarations to the element, overriding the previous values of their properties,
+    /// including the longhands of shorthand properties, such as `padding-left` for `padding`.
+    fn declare(&mut self, declarations: &[(&'static str, &'static str)]) {
+        self.declarations.retain(|(property, _)| {
+            !declarations.iter().any(|(new, _)| {
+                property == new
+                    || property
+                        .strip_prefix(new)
+                        .is_some_and(|rest| rest.starts_with('-'))
+            })
+        });
+        self.declarations.extend_from_slice(declarations);
+    }
+
+    /// Write the start tag of the element, with its declarations in its `style` attribute.
+    fn write_start_tag<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "<{}", self.name)?;
+        let mut declarations = self
+            .declarations
+            .iter()
+            .map(|(property, value)| format!("{property}: {value}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        for (attribute, value) in &self.attributes {
+            match attribute.as_str() {
+                "class" => {}
+                // The declarations of the `style` attribute take precedence over the stylesheet.
+                "style" if !declarations.is_empty() => {
+                    write!(writer, " style=\"{declarations}; {value}\"")?;
+                    declarations.clear();
+                }
+                _ => write!(writer, " {attribute}=\"{value}\"")?,
+            }
+        }
+        if !declarations.is_empty() {
+            write!(writer, " style=\"{declarations}\"")?;
+        }
+        writer.write_all(if self.empty_tag { b" />" } else { b">" })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write_start_tag(writer)?;
+        if self.empty_tag {
+            return Ok(());
+        }
+        for child in &self.children {
+            match child {
+                Node::Element(element) => element.write_to(writer)?,
+                Node::Text(text) => writer.write_all(text)?,
+            }
+        }
+        write!(writer, "</{}>", self.name)
+    }
+}
+
+/// Add the declarations of the rules selecting the element through its name, its classes and the
+/// name of its parent, i.e., every rule except the ones for the rows and cells of tables.
+fn declare_own(element: &mut Element, parent: Option<&str>) {
+    match element.name.as_str() {
+        "mtable" => element.declare(&[
+            ("border-color", "currentColor"),
+            ("border-spacing", "0"),
+            ("border-collapse", "collapse"),
+        ]),
+        "mo" => {
+            if element.has_class("small") {
+                element.declare(&[("font-size", "70%")]);
+            }
+            if element.has_class("mop-negated") {
+                element.declare(&[("background", NEGATION_STRIKE)]);
+            }
+        }
+        "mtext" if parent == Some("merror") => element.declare(&[
+            ("font-family", "monospace"),
+            ("white-space-collapse", "preserve"),
+            ("display", "block"),
+            ("text-align", "left"),
+        ]),
+        _ => {}
+    }
+}
+
+/// Add the declarations of the stylesheet to the element and its descendants, numbering the rows
+/// of the tables with equation numbers from `equations`.
+fn apply_stylesheet(element: &mut Element, parent: Option<&str>, equations: &mut usize) {
+    declare_own(element, parent);
+    if element.name == "mtable" {
+        apply_table(element, equations);
+    } else {
+        apply_children(element, equations);
+    }
+}
+
+fn apply_children(element: &mut Element, equations: &mut usize) {
+    for node in &mut element.children {
+        if let Node::Element(child) = node {
+            apply_stylesheet(child, Some(&element.name), equations);
+        }
+    }
+}
+
+/// Add the declarations of the stylesheet to the rows and cells of the table, and to the content
+/// of its cells.
+fn apply_table(table: &mut Element, equations: &mut usize) {
+    let align_like = table.has_class("menv-alignlike");
+    let align = table.has_class("menv-align");
+    let array_like = table.has_class("menv-arraylike");
+    let cases = table.has_class("menv-cases");
+    let cells_left = table.has_class("menv-cells-left");
+    let cells_right = table.has_class("menv-cells-right");
+    let matrix = table.has_class("menv-matrix");
+    let multline = table.has_class("menv-multline");
+    let with_eqn = table.has_class("menv-with-eqn");
+
+    let row_count = table.elements().count();
+    for (row_index, row) in table.elements_mut().enumerate() {
+        let last_row = row_index + 1 == row_count;
+        let hline = row.has_class("menv-hline");
+        if hline {
+            row.declare(&[("border-top", "0.06em solid")]);
+        } else if row.has_class("menv-hdashline") {
+            row.declare(&[("border-top", "0.06em dashed")]);
+        }
+
+        let cell_count = row.elements().count();
+        let numbered = with_eqn && !row.elements().any(|cell| cell.has_class("menv-nonumber"));
+        let number_shown = !(last_row && row.elements().any(Element::is_empty));
+        for (index, cell) in row.elements_mut().enumerate() {
+            let nth = index + 1;
+            let first = index == 0;
+            let last = nth == cell_count;
+
+            cell.declare(&[("padding-left", "0"), ("padding-right", "0")]);
+            if last_row && cell.is_empty() && cell_count == 1 {
+                cell.declare(&[("display", "none")]);
+            }
+            if (align_like && nth % 2 == 1)
+                || cells_right
+                || (multline && last)
+                || cell.has_class("cell-right")
+            {
+                cell.declare(ALIGN_RIGHT);
+            }
+            if (align_like && nth % 2 == 0)
+                || cells_left
+                || (multline && first)
+                || cell.has_class("cell-left")
+            {
+                cell.declare(&[("text-align", "left")]);
+            }
+            if multline {
+                cell.declare(&[("width", "100%")]);
+            }
+            if align && nth >= 3 && nth % 2 == 1 {
+                cell.declare(&[("padding-left", "1em")]);
+            }
+            if cases && first {
+                cell.declare(&[("padding-right", "1em")]);
+            }
+            if array_like {
+                cell.declare(&[("padding-left", "0.4em"), ("padding-right", "0.4em")]);
+            }
+            if matrix && first {
+                cell.declare(&[("padding-left", "0")]);
+            }
+            if matrix && last {
+                cell.declare(&[("padding-right", "0")]);
+            }
+            for (class, declaration) in [
+                ("menv-left-solid", ("border-left", "0.06em solid")),
+                ("menv-right-solid", ("border-right", "0.06em solid")),
+                ("menv-left-dashed", ("border-left", "0.06em dashed")),
+                ("menv-right-dashed", ("border-right", "0.06em dashed")),
+            ] {
+                if cell.has_class(class) {
+                    cell.declare(&[declaration]);
+                }
+            }
+            if hline && cell.has_class("menv-nonumber") && cell_count == 1 {
+                cell.declare(&[("padding-top", "0.1em"), ("padding-bottom", "0")]);
+            }
+            if cell.has_class("menv-border-only") && cell.is_empty() {
+                cell.declare(&[("width", "0.1em"), ("padding", "0"), ("margin", "0")]);
+            }
+
+            apply_children(cell, equations);
+        }
+
+        // The stylesheet writes equation numbers with a counter, in a pseudo-element after the
+        // row.
+        if numbered {
+            *equations += 1;
+            if number_shown {
+                let mut number = Element::parse("mtd");
+                number.declare(&[
+                    ("position", "absolute"),
+                    ("right", "0"),
+                    ("padding", "0 0.4em"),
+                ]);
+                let mut text = Element::parse("mtext");
+                text.children
+                    .push(Node::Text(format!("({equations})").into_bytes()));
+                number.children.push(Node::Element(text));
+                row.children.push(Node::Element(number));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{push_mathml, Parser, RenderConfig, Storage};
+
+    fn render(input: &str) -> String {
+        let storage = Storage::new();
+        let mut output = String::new();
+        push_mathml(
+            &mut output,
+            Parser::new(input, &storage),
+            RenderConfig {
+                self_contained: true,
+                ..RenderConfig::default()
+            },
+        )
+        .unwrap();
+        output
+    }
+
+    fn inline(markup: &str) -> String {
+        let equations = Cell::new(0);
+        let mut inliner = Inliner::new(Vec::new(), &equations);
+        inliner.write_all(markup.as_bytes()).unwrap();
+        String::from_utf8(inliner.writer).unwrap()
+    }
+
+    /// The classes of each compound selector of the selector, along with the element they select,
+    /// and whether they are negated by `:not`.
+    fn selector_classes(selector: &str) -> Vec<(&str, &str, bool)> {
+        let mut classes = Vec::new();
+        for (index, compound) in selector.match_indices("m|") {
+            let compound = &selector[index + compound.len()..];
+            let negated =
+                selector[..index].matches(":not(").count() > selector[..index].matches(')').count();
+            let name_len = compound
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(compound.len());
+            let mut rest = &compound[name_len..];
+            while let Some(class) = rest.strip_prefix('.') {
+                let len = class
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+                    .unwrap_or(class.len());
+                classes.push((&compound[..name_len], &class[..len], negated));
+                rest = &class[len..];
+            }
+        }
+        classes
+    }
+
+    /// A table with two rows, the last one with a single empty cell, and elements outside of
+    /// tables, with the given classes on the elements of the given names.
+    fn fixture(classes: &[(&str, &str)]) -> String {
+        let tag = |name: &str| {
+            let classes = classes
+                .iter()
+                .filter(|(element, _)| *element == name)
+                .map(|(_, class)| *class)
+                .collect::<Vec<_>>();
+            if classes.is_empty() {
+                format!("<{name}>")
+            } else {
+                format!("<{name} class=\"{}\">", classes.join(" "))
+            }
+        };
+        let (table, row, cell) = (tag("mtable"), tag("mtr"), tag("mtd"));
+        format!(
+            "<math>{table}{row}{cell}<mi>a</mi></mtd>{cell}<mi>b</mi></mtd>{cell}<mi>c</mi></mtd>\
+            </mtr>{row}{cell}</mtd></mtr></mtable>{}+</mo><merror>{}e</mtext></merror></math>",
+            tag("mo"),
+            tag("mtext"),
+        )
+    }
+
+    #[test]
+    fn stylesheet_classes() {
+        let stylesheet = include_str!("../../styles.css");
+        let mut selectors = Vec::new();
+        for rule in stylesheet.split('}') {
+            let Some((prelude, _)) = rule.split_once('{') else {
+                continue;
+            };
+            let prelude = match prelude.rfind("*/") {
+                Some(end) => &prelude[end + 2..],
+                None => prelude,
+            };
+            selectors.extend(prelude.split(',').map(selector_classes));
+        }
+
+        let mut classes = selectors
+            .iter()
+            .flatten()
+            .map(|(_, class, _)| *class)
+            .collect::<Vec<_>>();
+        classes.sort_unstable();
+        classes.dedup();
+        assert!(classes.contains(&"menv-with-eqn"), "{classes:?}");
+        for class in classes {
+            // The class must change the output of the inliner for one of the selectors using it,
+            // along with the classes this selector requires.
+            let handled = selectors
+                .iter()
+                .filter(|selector| selector.iter().any(|(_, c, _)| *c == class))
+                .any(|selector| {
+                    let mut required = selector
+                        .iter()
+                        .filter(|(_, c, negated)| !negated && *c != class)
+                        .map(|(element, c, _)| (*element, *c))
+                        .collect::<Vec<_>>();
+                    let without = inline(&fixture(&required));
+                    required.extend(
+                        selector
+                            .iter()
+                            .filter(|(_, c, _)| *c == class)
+                            .map(|(element, c, _)| (*element, *c)),
+                    );
+                    let with = inline(&fixture(&required));
+                    assert!(!with.contains("class="), "{with}");
+                    with != without
+                });
+            assert!(
+                handled,
+                "the `{class}` class of the stylesheet is not inlined"
+            );
+        }
+    }
+
+    #[test]
+    fn split_tags() {
+        let output = r#"<math display="inline"><mo class="small">&lt;</mo><mtable class="menv-cells-left"><mtr style="height: 1em"><mtd></mtd></mtr></mtable><mspace width="1em" /></math>"#;
+        let inlined = r#"<math display="inline"><mo style="font-size: 70%">&lt;</mo><mtable style="border-color: currentColor; border-spacing: 0; border-collapse: collapse"><mtr style="height: 1em"><mtd style="padding-left: 0; padding-right: 0; display: none; text-align: left"></mtd></mtr></mtable><mspace width="1em" /></math>"#;
+        for split in 0..output.len() {
+            let equations = Cell::new(0);
+            let mut inliner = Inliner::new(Vec::new(), &equations);
+            let (first, second) = output.as_bytes().split_at(split);
+            inliner.write_all(first).unwrap();
+            inliner.write_all(second).unwrap();
+            assert_eq!(String::from_utf8(inliner.writer).unwrap(), inlined);
+        }
+    }
+
+    #[test]
+    fn equation_numbers() {
+        let output = render(r"\begin{align} a &= b \\[1em] c &= d \\ \end{align}");
+        assert!(!output.contains("class="), "{output}");
+        assert_eq!(output.matches("<mtext>(1)</mtext>").count(), 1, "{output}");
+        assert_eq!(output.matches("<mtext>(2)</mtext>").count(), 1, "{output}");
+        assert!(!output.contains("(3)"), "{output}");
+        // The spacing row is not numbered.
+        assert!(output.contains(
+            r#"<mtr style="height: 1em"><mtd style="padding-left: 0; padding-right: 0; text-align: right; text-align: -webkit-right; text-align: -moz-right"></mtd></mtr>"#
+        ));
+        assert!(output.contains(
+            r#"<mtd style="position: absolute; right: 0; padding: 0 0.4em"><mtext>(1)</mtext></mtd></mtr>"#
+        ));
+        assert!(output.contains(
+            r#"<mtd style="padding-left: 0; padding-right: 0; text-align: right; text-align: -webkit-right; text-align: -moz-right"><mi>a</mi></mtd>"#
+        ));
+    }
+
+    #[test]
+    fn array_lines() {
+        let output = render(r"\begin{array}{|l:c|} \hline a & b \\[1em] c & d \end{array}");
+        assert!(!output.contains("class="), "{output}");
+        assert!(output.contains(r#"<mtr style="border-top: 0.06em solid">"#));
+        assert!(output.contains(
+            r#"<mtd style="text-align: left; padding-left: 0.4em; padding-right: 0.4em; border-left: 0.06em solid; border-right: 0.06em dashed"><mi>a</mi></mtd>"#
+        ));
+        assert!(output.contains(r#"<mtr style="height: 1em">"#));
+    }
+}
@@ -0,0 +1,571 @@
+//! The output of the renderer when [`RenderConfig::profile`](crate::RenderConfig::profile) is
+//! [`Profile::MathML3`](crate::config::Profile::MathML3).
+//!
+//! `MathML` 3 describes the layout of a table through the attributes of its `mtable` element,
+//! some of which, such as `rowlines`, are only known once every row has been rendered. The content
+//! of a table is therefore held back by the [`Holder`] until the end of the table.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use super::{EnvGrouping, MathmlWriter};
+use crate::event::{ArrayColumn, ColumnAlignment, Dimension, Font, Grouping, Line};
+
+/// A writer to which a [`MathmlWriter`] writes, whose output can be held back by tables.
+pub(super) trait Hold: Write {
+    /// Hold back everything written until the matching call to `release`.
+    fn hold(&mut self);
+
+    /// The output held back since the last call to `hold`.
+    fn held(&mut self) -> &mut Vec<u8>;
+
+    /// Write `start`, the output held back since the last call to `hold`, and `end`.
+    fn release(&mut self, start: &[u8], end: &[u8]) -> io::Result<()>;
+}
+
+/// A writer whose output can be held back, until the start tag of the table containing it is
+/// known.
+pub(super) struct Holder<W> {
+    writer: W,
+    held: Vec<Vec<u8>>,
+}
+
+impl<W: Write> Holder<W> {
+    pub(super) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            held: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write> Hold for Holder<W> {
+    fn hold(&mut self) {
+        self.held.push(Vec::new());
+    }
+
+    fn held(&mut self) -> &mut Vec<u8> {
+        self.held.last_mut().expect("no output is held back")
+    }
+
+    fn release(&mut self, start: &[u8], end: &[u8]) -> io::Result<()> {
+        let held = self.held.pop().expect("no output is held back");
+        self.write_all(start)?;
+        self.write_all(&held)?;
+        self.write_all(end)
+    }
+}
+
+impl<W: Write> Write for Holder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.held.last_mut() {
+            Some(held) => {
+                held.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            None => self.writer.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.held.last_mut() {
+            Some(held) => {
+                held.extend_from_slice(buf);
+                Ok(())
+            }
+            None => self.writer.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A writer passing everything written to it through, used with
+/// [`Profile::Core`](crate::config::Profile::Core), whose tables are written as they are
+/// rendered.
+pub(super) struct Unheld<W>(pub(super) W);
+
+impl<W: Write> Hold for Unheld<W> {
+    fn hold(&mut self) {
+        unreachable!("output is only held back with `Profile::MathML3`")
+    }
+
+    fn held(&mut self) -> &mut Vec<u8> {
+        unreachable!("output is only held back with `Profile::MathML3`")
+    }
+
+    fn release(&mut self, _: &[u8], _: &[u8]) -> io::Result<()> {
+        unreachable!("output is only held back with `Profile::MathML3`")
+    }
+}
+
+impl<W: Write> Write for Unheld<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// A table being rendered.
+pub(super) struct Table {
+    layout: Layout,
+    /// Whether every row starts with an equation number.
+    numbered: bool,
+    /// The line above the first row.
+    top: Option<Line>,
+    /// The lines below every row but the current one.
+    row_lines: Vec<Option<Line>>,
+    /// The offsets in the held output of the start of the current and the previous rows.
+    row_start: usize,
+    previous_row_start: usize,
+    /// The offset in the held output of the content of the first cell of the current row.
+    content_start: usize,
+    /// The number of cells in the current row.
+    cells: usize,
+    /// The number of cells in the widest row.
+    columns: usize,
+}
+
+enum Layout {
+    /// Columns aligned right and left alternately, with space between every pair if `spaced`.
+    Pairs { spaced: bool },
+    /// Columns aligned the same way, with the given space between them.
+    Uniform {
+        alignment: ColumnAlignment,
+        spacing: Option<&'static str>,
+    },
+    /// The columns of an `array`, where `lines[i]` is the line between the columns `i` and
+    /// `i + 1`.
+    Array {
+        columns: Vec<ColumnAlignment>,
+        lines: Vec<Option<Line>>,
+        left: Option<Line>,
+        right: Option<Line>,
+    },
+    /// The first row aligned left, and the last one aligned right.
+    Multline,
+}
+
+impl Layout {
+    fn array(cols: &[ArrayColumn]) -> Self {
+        let mut columns = Vec::new();
+        let mut lines = Vec::new();
+        let mut left = None;
+        for col in cols {
+            match *col {
+                ArrayColumn::Column(alignment) => {
+                    columns.push(alignment);
+                    lines.push(None);
+                }
+                ArrayColumn::Separator(line) => {
+                    lines.last_mut().unwrap_or(&mut left).get_or_insert(line);
+                }
+            }
+        }
+        let right = lines.pop().flatten();
+        Self::Array {
+            columns,
+            lines,
+            left,
+            right,
+        }
+    }
+}
+
+impl Table {
+    fn start_row<W: Hold>(&mut self, writer: &mut W, equations: &mut usize) -> io::Result<()> {
+        self.previous_row_start = self.row_start;
+        self.row_start = writer.held().len();
+        if self.numbered {
+            *equations += 1;
+            write!(
+                writer,
+                "<mlabeledtr><mtd><mtext>({})</mtext></mtd><mtd>",
+                equations
+            )?;
+        } else {
+            writer.write_all(b"<mtr><mtd>")?;
+        }
+        self.content_start = writer.held().len();
+        self.cells = 1;
+        Ok(())
+    }
+
+    fn end_row<W: Hold>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.columns = self.columns.max(self.cells);
+        writer.write_all(if self.numbered {
+            b"</mtd></mlabeledtr>"
+        } else {
+            b"</mtd></mtr>"
+        })
+    }
+
+    /// The start tags of the table, given the line below its last row.
+    fn start_tags(&self, bottom: Option<Line>) -> (String, bool) {
+        let mut notation = Vec::new();
+        if self.top.is_some() {
+            notation.push("top");
+        }
+        if bottom.is_some() {
+            notation.push("bottom");
+        }
+        if let Layout::Array { left, right, .. } = &self.layout {
+            if left.is_some() {
+                notation.push("left");
+            }
+            if right.is_some() {
+                notation.push("right");
+            }
+        }
+
+        let mut tags = String::new();
+        if !notation.is_empty() {
+            tags.push_str("<menclose notation=\"");
+            tags.push_str(&notation.join(" "));
+            tags.push_str("\">");
+        }
+        tags.push_str("<mtable");
+        let mut attribute = |name: &str, values: &mut dyn Iterator<Item = &str>| {
+            write!(tags, " {name}=\"").expect("writing to a string does not fail");
+            for (index, value) in values.enumerate() {
+                if index != 0 {
+                    tags.push(' ');
+                }
+                tags.push_str(value);
+            }
+            tags.push('"');
+        };
+
+        match &self.layout {
+            Layout::Pairs { spaced } => {
+                attribute(
+                    "columnalign",
+                    &mut (0..self.columns).map(|index| alternate(index, "right", "left")),
+                );
+                if self.columns > 1 {
+                    attribute(
+                        "columnspacing",
+                        &mut (1..self.columns).map(|index| {
+                            alternate(index, if *spaced { "1em" } else { "0em" }, "0em")
+                        }),
+                    );
+                }
+            }
+            Layout::Uniform { alignment, spacing } => {
+                if *alignment != ColumnAlignment::Center {
+                    attribute(
+                        "columnalign",
+                        &mut std::iter::once(column_align(*alignment)),
+                    );
+                }
+                if let Some(spacing) = spacing {
+                    attribute("columnspacing", &mut std::iter::once(*spacing));
+                }
+            }
+            Layout::Array { columns, lines, .. } => {
+                if columns.iter().any(|col| *col != ColumnAlignment::Center) {
+                    attribute(
+                        "columnalign",
+                        &mut columns.iter().map(|col| column_align(*col)),
+                    );
+                }
+                if lines.iter().any(Option::is_some) {
+                    attribute(
+                        "columnlines",
+                        &mut lines.iter().map(|line| line_style(*line)),
+                    );
+                }
+            }
+            Layout::Multline => {}
+        }
+        if self.row_lines.iter().any(Option::is_some) {
+            attribute(
+                "rowlines",
+                &mut self.row_lines.iter().map(|line| line_style(*line)),
+            );
+        }
+        tags.push('>');
+        (tags, !notation.is_empty())
+    }
+}
+
+impl<'a, W: Hold> MathmlWriter<'a, W> {
+    /// Enter a mathematical environment, which is rendered as a table.
+    pub(super) fn enter_table(
+        &mut self,
+        grouping: Grouping,
+        lines: &[Line],
+    ) -> io::Result<EnvGrouping> {
+        let uniform = |alignment, spacing| Layout::Uniform { alignment, spacing };
+        let (layout, numbered, group) = match grouping {
+            Grouping::Align { eq_numbers } => (
+                Layout::Pairs { spaced: true },
+                eq_numbers,
+                EnvGrouping::Align,
+            ),
+            Grouping::Aligned => (Layout::Pairs { spaced: true }, false, EnvGrouping::Align),
+            Grouping::Alignat { pairs, eq_numbers } => (
+                Layout::Pairs { spaced: false },
+                eq_numbers,
+                EnvGrouping::Alignat {
+                    pairs,
+                    columns_used: 0,
+                },
+            ),
+            Grouping::Alignedat { pairs } => (
+                Layout::Pairs { spaced: false },
+                false,
+                EnvGrouping::Alignat {
+                    pairs,
+                    columns_used: 0,
+                },
+            ),
+            Grouping::Split => (
+                Layout::Pairs { spaced: false },
+                false,
+                EnvGrouping::Split { used_align: false },
+            ),
+            Grouping::Matrix { alignment } => {
+                (uniform(alignment, None), false, EnvGrouping::Matrix)
+            }
+            Grouping::SubArray { alignment } => (
+                uniform(alignment, Some("0em")),
+                false,
+                EnvGrouping::SubArray,
+            ),
+            Grouping::Cases { left } => {
+                self.writer.write_all(b"<mrow>")?;
+                if left {
+                    self.writer.write_all(b"<mo stretchy=\"true\">{</mo>")?;
+                }
+                (
+                    uniform(ColumnAlignment::Left, Some("1em")),
+                    false,
+                    EnvGrouping::Cases {
+                        left,
+                        used_align: false,
+                    },
+                )
+            }
+            Grouping::Gather { eq_numbers } => (
+                uniform(ColumnAlignment::Center, None),
+                eq_numbers,
+                EnvGrouping::Gather,
+            ),
+            Grouping::Gathered => (
+                uniform(ColumnAlignment::Center, None),
+                false,
+                EnvGrouping::Gather,
+            ),
+            Grouping::Multline => (Layout::Multline, false, EnvGrouping::Multline),
+            Grouping::Equation { eq_numbers } => (
+                uniform(ColumnAlignment::Center, None),
+                eq_numbers,
+                EnvGrouping::Equation,
+            ),
+            Grouping::Array(cols) => (
+                Layout::array(&cols),
+                false,
+                EnvGrouping::Array {
+                    cols,
+                    cols_index: 0,
+                },
+            ),
+            Grouping::Normal | Grouping::LeftRight(..) => {
+                unreachable!("groups are not mathematical environments")
+            }
+        };
+
+        let mut table = Table {
+            layout,
+            numbered,
+            top: lines.first().copied(),
+            row_lines: Vec::new(),
+            row_start: 0,
+            previous_row_start: 0,
+            content_start: 0,
+            cells: 0,
+            columns: 0,
+        };
+        self.writer.hold();
+        table.start_row(&mut self.writer, &mut self.equations)?;
+        self.tables.push(table);
+        Ok(group)
+    }
+
+    /// Start a new row of the current table.
+    pub(super) fn table_new_line(
+        &mut self,
+        spacing: Option<Dimension>,
+        lines: &[Line],
+    ) -> io::Result<()> {
+        let table = self.tables.last_mut().expect("no table is being rendered");
+        // The space added below the row can only make it taller.
+        if let Some(spacing) = spacing.filter(|spacing| spacing.value > 0.) {
+            write!(self.writer, "<mspace depth=\"{}\" />", spacing)?;
+        }
+        table.end_row(&mut self.writer)?;
+        table.row_lines.push(lines.first().copied());
+        table.start_row(&mut self.writer, &mut self.equations)
+    }
+
+    /// Start a new cell in the current row of the current table.
+    pub(super) fn table_alignment(&mut self) -> io::Result<()> {
+        let table = self.tables.last_mut().expect("no table is being rendered");
+        table.cells += 1;
+        self.writer.write_all(b"</mtd><mtd>")
+    }
+
+    /// Exit the current table, writing it along with its start tag.
+    pub(super) fn exit_table(&mut self, group: EnvGrouping) -> io::Result<()> {
+        let mut table = self.tables.pop().expect("no table is being rendered");
+        let mut bottom = None;
+        let mut last_row_start = table.row_start;
+        if self.writer.held().len() == table.content_start && !table.row_lines.is_empty() {
+            // A `\\` at the end of the environment does not start a new row, but the lines
+            // following it are drawn below the table.
+            self.writer.held().truncate(table.row_start);
+            bottom = table.row_lines.pop().flatten();
+            last_row_start = table.previous_row_start;
+            if table.numbered {
+                self.equations -= 1;
+            }
+        } else {
+            table.end_row(&mut self.writer)?;
+        }
+
+        if let Layout::Multline = table.layout {
+            if !table.row_lines.is_empty() {
+                let held = self.writer.held();
+                let after_tag = |start: usize| start + "<mtr".len();
+                let last = after_tag(last_row_start);
+                held.splice(last..last, *b" columnalign=\"right\"");
+                held.splice(after_tag(0)..after_tag(0), *b" columnalign=\"left\"");
+            }
+        }
+
+        let (start, enclosed) = table.start_tags(bottom);
+        self.writer.release(
+            start.as_bytes(),
+            if enclosed {
+                b"</mtable></menclose>"
+            } else {
+                b"</mtable>"
+            },
+        )?;
+
+        if let EnvGrouping::Cases { left, .. } = group {
+            if !left {
+                self.writer.write_all(b"<mo stretchy=\"true\">}</mo>")?;
+            }
+            self.writer.write_all(b"</mrow>")?;
+        }
+        Ok(())
+    }
+}
+
+/// The value of the `mathvariant` attribute corresponding to a font.
+pub(super) fn mathvariant(font: Font) -> &'static str {
+    match font {
+        Font::BoldScript => "bold-script",
+        Font::BoldItalic => "bold-italic",
+        Font::Bold => "bold",
+        Font::Fraktur => "fraktur",
+        Font::Script => "script",
+        Font::Monospace => "monospace",
+        Font::SansSerif => "sans-serif",
+        Font::DoubleStruck => "double-struck",
+        Font::Italic => "italic",
+        Font::BoldFraktur => "bold-fraktur",
+        Font::SansSerifBoldItalic => "sans-serif-bold-italic",
+        Font::SansSerifItalic => "sans-serif-italic",
+        Font::BoldSansSerif => "bold-sans-serif",
+        Font::UpRight => "normal",
+    }
+}
+
+fn alternate(index: usize, even: &'static str, odd: &'static str) -> &'static str {
+    if index % 2 == 0 {
+        even
+    } else {
+        odd
+    }
+}
+
+fn column_align(alignment: ColumnAlignment) -> &'static str {
+    match alignment {
+        ColumnAlignment::Left => "left",
+        ColumnAlignment::Center => "center",
+        ColumnAlignment::Right => "right",
+    }
+}
+
+fn line_style(line: Option<Line>) -> &'static str {
+    match line {
+        None => "none",
+        Some(Line::Solid) => "solid",
+        Some(Line::Dashed) => "dashed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_output() {
+        let mut holder = Holder::new(Vec::new());
+        holder.write_all(b"<math>").unwrap();
+        holder.hold();
+        holder.write_all(b"<mtr>").unwrap();
+        holder.hold();
+        holder.write_all(b"<mi>a</mi>").unwrap();
+        holder.release(b"<mtd>", b"</mtd>").unwrap();
+        holder.release(b"<mtable>", b"</mtable>").unwrap();
+        holder.write_all(b"</math>").unwrap();
+        assert_eq!(
+            String::from_utf8(holder.writer).unwrap(),
+            "<math><mtable><mtr><mtd><mi>a</mi></mtd></mtable></math>"
+        );
+    }
+
+    #[test]
+    fn array_layout() {
+        use ArrayColumn::{Column, Separator};
+        use ColumnAlignment::{Center, Left, Right};
+
+        let Layout::Array {
+            columns,
+            lines,
+            left,
+            right,
+        } = Layout::array(&[
+            Separator(Line::Dashed),
+            Separator(Line::Solid),
+            Column(Left),
+            Column(Center),
+            Separator(Line::Solid),
+            Separator(Line::Dashed),
+            Column(Right),
+            Separator(Line::Solid),
+        ])
+        else {
+            unreachable!()
+        };
+        assert_eq!(columns, [Left, Center, Right]);
+        assert_eq!(lines, [None, Some(Line::Solid)]);
+        assert_eq!(left, Some(Line::Dashed));
+        assert_eq!(right, Some(Line::Solid));
+    }
+}
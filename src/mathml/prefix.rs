@@ -0,0 +1,65 @@
+//! The writer used when [`RenderConfig::xml_prefix`](crate::RenderConfig::xml_prefix) is set.
+
+use std::io::{self, Write};
+
+use super::tokenize::{Token, Tokenizer};
+
+/// The prefix bound to the `MathML` namespace.
+const PREFIX: &[u8] = b"m:";
+
+/// A writer adding the [`PREFIX`] to the name of every start and end tag written to it.
+pub(super) struct Prefixer<W> {
+    writer: W,
+    tokenizer: Tokenizer,
+}
+
+impl<W: Write> Prefixer<W> {
+    pub(super) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            tokenizer: Tokenizer::default(),
+        }
+    }
+}
+
+impl<W: Write> Write for Prefixer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut chunk = buf;
+        while let Some(token) = self.tokenizer.next(&mut chunk) {
+            match token {
+                Token::Text(text) => self.writer.write_all(text)?,
+                Token::Tag(tag) => {
+                    // The prefix goes after the `/` of an end tag.
+                    let name_start = if tag.get(1) == Some(&b'/') { 2 } else { 1 };
+                    self.writer.write_all(&tag[..name_start])?;
+                    self.writer.write_all(PREFIX)?;
+                    self.writer.write_all(&tag[name_start..])?;
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_tags() {
+        let output =
+            r#"<math display="inline"><mi>a</mi><mo>&lt;</mo><mspace width="1em" /></math>"#;
+        let prefixed = r#"<m:math display="inline"><m:mi>a</m:mi><m:mo>&lt;</m:mo><m:mspace width="1em" /></m:math>"#;
+        for split in 0..output.len() {
+            let mut prefixer = Prefixer::new(Vec::new());
+            let (first, second) = output.as_bytes().split_at(split);
+            prefixer.write_all(first).unwrap();
+            prefixer.write_all(second).unwrap();
+            assert_eq!(String::from_utf8(prefixer.writer).unwrap(), prefixed);
+        }
+    }
+}
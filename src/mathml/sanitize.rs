@@ -0,0 +1,390 @@
+//! The writer used when [`RenderConfig::safe`](crate::RenderConfig::safe) is set.
+//!
+//! Instead of trusting every code path of the renderer, the sanitizer parses each tag it writes,
+//! and writes it again with only the elements and attributes of an allowlist. Attribute values are
+//! checked against the syntax the renderer uses for them, and text is guaranteed not to contain
+//! markup.
+
+use std::io::{self, Write};
+
+use super::tokenize::{Token, Tokenizer};
+
+/// The elements which may appear in safe output.
+const ELEMENTS: &[&str] = &[
+    "annotation",
+    "math",
+    "menclose",
+    "merror",
+    "mfrac",
+    "mi",
+    "mlabeledtr",
+    "mn",
+    "mo",
+    "mover",
+    "mroot",
+    "mrow",
+    "mspace",
+    "msqrt",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+    "semantics",
+];
+
+/// The attributes which may appear in safe output, whose values are only made of keywords,
+/// numbers and colors written as `#rrggbb`.
+const ATTRIBUTES: &[&str] = &[
+    "columnalign",
+    "columnlines",
+    "columnspacing",
+    "depth",
+    "display",
+    "displaystyle",
+    "height",
+    "largeop",
+    "linethickness",
+    "mathbackground",
+    "mathcolor",
+    "mathsize",
+    "mathvariant",
+    "maxsize",
+    "minsize",
+    "movablelimits",
+    "notation",
+    "rowlines",
+    "scriptlevel",
+    "stretchy",
+    "symmetric",
+    "width",
+];
+
+/// The properties which may appear in the `style` attribute of safe output.
+const STYLE_PROPERTIES: &[&str] = &[
+    "background-color",
+    "border",
+    "border-collapse",
+    "border-color",
+    "border-left",
+    "border-right",
+    "border-spacing",
+    "border-top",
+    "color",
+    "font-family",
+    "font-size",
+    "height",
+    "margin-left",
+    "padding",
+    "padding-bottom",
+    "padding-left",
+    "padding-right",
+    "padding-top",
+    "text-align",
+    "white-space-collapse",
+];
+
+/// The properties which may appear in the `style` attribute of safe output with one of the values
+/// written by the inliner, since other values could move the equation over the rest of the page.
+const STYLE_KEYWORDS: &[(&str, &[&str])] = &[
+    ("display", &["block", "none"]),
+    ("margin", &["0"]),
+    ("position", &["absolute"]),
+    ("right", &["0"]),
+    ("width", &["0.1em", "100%"]),
+];
+
+/// The attributes and style properties whose negative lengths may not go below
+/// [`MIN_NEGATIVE_LENGTH`], since a large negative space could move the equation over the rest of
+/// the page.
+const BOUNDED_LENGTHS: &[&str] = &["margin-left", "width"];
+
+/// The smallest negative length allowed in [`BOUNDED_LENGTHS`], in `em`.
+const MIN_NEGATIVE_LENGTH: f32 = -2.;
+
+/// The units written by the renderer, along with their approximate size in `em`.
+const UNITS: &[(&str, f32)] = &[
+    ("em", 1.),
+    ("ex", 0.5),
+    ("mu", 1. / 18.),
+    ("pt", 0.1),
+    ("pc", 1.2),
+    ("in", 7.227),
+    ("cm", 2.845),
+    ("mm", 0.2845),
+];
+
+const MATHML_NAMESPACE: &str = "http://www.w3.org/1998/Math/MathML";
+
+/// A writer passing through the `MathML` written to it, except for the elements and attributes
+/// which are not allowed.
+///
+/// Elements which are not allowed are removed along with their end tag, but their content is
+/// kept.
+pub(super) struct Sanitizer<W> {
+    writer: W,
+    tokenizer: Tokenizer,
+    /// Whether each of the open elements was kept.
+    open: Vec<bool>,
+}
+
+impl<W: Write> Sanitizer<W> {
+    pub(super) fn new(writer: W) -> Self {
+        Self {
+            writer,
+            tokenizer: Tokenizer::default(),
+            open: Vec::new(),
+        }
+    }
+
+    fn write_text(&mut self, text: &[u8]) -> io::Result<()> {
+        // Text is already escaped by the renderer, but a stray `>` is escaped nonetheless.
+        for (index, part) in text.split(|&byte| byte == b'>').enumerate() {
+            if index != 0 {
+                self.writer.write_all(b"&gt;")?;
+            }
+            self.writer.write_all(part)?;
+        }
+        Ok(())
+    }
+
+    fn write_tag(&mut self, tag: &[u8]) -> io::Result<()> {
+        let Some(tag) = std::str::from_utf8(tag)
+            .ok()
+            .and_then(|tag| tag.strip_prefix('<')?.strip_suffix('>'))
+        else {
+            return Ok(());
+        };
+
+        if let Some(name) = tag.strip_prefix('/') {
+            if self.open.pop() == Some(true) && ELEMENTS.contains(&name.trim()) {
+                write!(self.writer, "</{}>", name.trim())?;
+            }
+            return Ok(());
+        }
+
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_len = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        let (name, mut rest) = tag.split_at(name_len);
+        let mut attributes = Vec::new();
+        let kept = ELEMENTS.contains(&name)
+            && loop {
+                rest = rest.trim_start();
+                if rest.is_empty() {
+                    break true;
+                }
+                let Some((attribute, value, next)) = attribute(rest) else {
+                    break false;
+                };
+                attributes.push((attribute, value));
+                rest = next;
+            };
+
+        if !self_closing {
+            self.open.push(kept);
+        }
+        if !kept {
+            return Ok(());
+        }
+        write!(self.writer, "<{name}")?;
+        for (attribute, value) in attributes {
+            if is_allowed(attribute, value) {
+                write!(self.writer, " {attribute}=\"{value}\"")?;
+            }
+        }
+        self.writer
+            .write_all(if self_closing { b" />" } else { b">" })
+    }
+}
+
+impl<W: Write> Write for Sanitizer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut chunk = buf;
+        while let Some(token) = self.tokenizer.next(&mut chunk) {
+            match token {
+                Token::Text(text) => self.write_text(text)?,
+                Token::Tag(tag) => self.write_tag(&tag)?,
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Parse an attribute of the form `name="value"`, returning its name, value, and the rest of the
+/// tag.
+fn attribute(input: &str) -> Option<(&str, &str, &str)> {
+    let (name, rest) = input.split_once('=')?;
+    let rest = rest.strip_prefix('"')?;
+    let (value, rest) = rest.split_once('"')?;
+    let name = name.trim();
+    name.bytes()
+        .all(|byte| byte.is_ascii_lowercase() || b"-:".contains(&byte))
+        .then_some((name, value, rest))
+}
+
+fn is_allowed(attribute: &str, value: &str) -> bool {
+    match attribute {
+        "xmlns" | "xmlns:m" => value == MATHML_NAMESPACE,
+        "encoding" => value == "application/x-tex",
+        "class" => value.bytes().all(|byte| {
+            byte.is_ascii_lowercase() || byte.is_ascii_digit() || b" -".contains(&byte)
+        }),
+        "style" => value.split(';').all(|declaration| {
+            let Some((property, value)) = declaration.split_once(':') else {
+                return false;
+            };
+            let property = property.trim();
+            match STYLE_KEYWORDS.iter().find(|(name, _)| *name == property) {
+                Some((_, values)) => values.contains(&value.trim()),
+                None => {
+                    STYLE_PROPERTIES.contains(&property)
+                        && is_style_value(value)
+                        && (!BOUNDED_LENGTHS.contains(&property) || is_bounded(value))
+                }
+            }
+        }),
+        _ => {
+            ATTRIBUTES.contains(&attribute)
+                && value
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || b" .-#%".contains(&byte))
+                && (!BOUNDED_LENGTHS.contains(&attribute) || is_bounded(value))
+        }
+    }
+}
+
+/// Whether every negative length of the value is a length in one of the [`UNITS`] that is not
+/// below [`MIN_NEGATIVE_LENGTH`].
+fn is_bounded(value: &str) -> bool {
+    value
+        .split_whitespace()
+        .filter(|word| word.starts_with('-'))
+        .all(|length| {
+            UNITS.iter().any(|(unit, em)| {
+                length
+                    .strip_suffix(unit)
+                    .and_then(|number| number.parse::<f32>().ok())
+                    .is_some_and(|number| number * em >= MIN_NEGATIVE_LENGTH)
+            })
+        })
+}
+
+/// Whether the value of a style property is made of lengths, percentages, keywords, and colors
+/// written as `#rrggbb` or `rgb(r g b)`.
+fn is_style_value(value: &str) -> bool {
+    let value = value.trim();
+    let rest = match value.find("rgb(") {
+        Some(start) => {
+            let Some(end) = value[start..].find(')') else {
+                return false;
+            };
+            let channels = &value[start + 4..start + end];
+            if !channels
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || byte == b' ')
+            {
+                return false;
+            }
+            [&value[..start], &value[start + end + 1..]].concat()
+        }
+        None => value.to_owned(),
+    };
+    rest.bytes()
+        .all(|byte| byte.is_ascii_alphanumeric() || b" .-#%".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(chunks: &[&str]) -> String {
+        let mut sanitizer = Sanitizer::new(Vec::new());
+        for chunk in chunks {
+            sanitizer.write_all(chunk.as_bytes()).unwrap();
+        }
+        String::from_utf8(sanitizer.writer).unwrap()
+    }
+
+    #[test]
+    fn allowlist() {
+        assert_eq!(
+            sanitize(&[
+                r#"<math display="block" onload="alert(1)"><mi mathvariant="normal">x</mi>"#,
+                r#"<script src="x">y</script><mspace width="1em" /></math>"#,
+            ]),
+            r#"<math display="block"><mi mathvariant="normal">x</mi>y<mspace width="1em" /></math>"#
+        );
+        assert_eq!(
+            sanitize(&[
+                r#"<mrow style="color: rgb(1 2 3); border: 0.06em solid rgb(4 5 6)">"#,
+                r#"<mo style="background: url(x)">a</mo><mtext class="x y">b"#,
+                r#"</mtext></mrow>"#
+            ]),
+            r#"<mrow style="color: rgb(1 2 3); border: 0.06em solid rgb(4 5 6)"><mo>a</mo><mtext class="x y">b</mtext></mrow>"#
+        );
+    }
+
+    #[test]
+    fn style_keywords() {
+        assert_eq!(
+            sanitize(&[
+                r#"<mtd style="position: absolute; right: 0; padding: 0 0.4em"></mtd>"#,
+                r#"<mtd style="width: 100%; display: none"></mtd>"#,
+                r#"<mtd style="position: fixed; right: 0"></mtd>"#,
+                r#"<mtd style="width: 100vw"></mtd><mtd style="margin: -5em"></mtd>"#,
+            ]),
+            concat!(
+                r#"<mtd style="position: absolute; right: 0; padding: 0 0.4em"></mtd>"#,
+                r#"<mtd style="width: 100%; display: none"></mtd>"#,
+                "<mtd></mtd><mtd></mtd><mtd></mtd>",
+            )
+        );
+    }
+
+    #[test]
+    fn negative_lengths() {
+        assert_eq!(
+            sanitize(&[
+                r#"<mspace width="-1em" style="margin-left: -1em" />"#,
+                r#"<mspace width="-0.3em" height="-5em" />"#,
+                r#"<mspace width="-1000em" style="margin-left: -1000em" />"#,
+                r#"<mspace width="-99626406000000000000pt" />"#,
+                r#"<mspace width="-infem" style="margin-left: -5" />"#,
+            ]),
+            concat!(
+                r#"<mspace width="-1em" style="margin-left: -1em" />"#,
+                r#"<mspace width="-0.3em" height="-5em" />"#,
+                "<mspace /><mspace /><mspace />",
+            )
+        );
+    }
+
+    #[test]
+    fn split_tags() {
+        let output = r#"<math display="inline"><mi>a</mi><mo stretchy="true">&gt;</mo></math>"#;
+        for split in 0..output.len() {
+            assert_eq!(sanitize(&[&output[..split], &output[split..]]), output);
+        }
+        assert_eq!(
+            sanitize(&["<mtext>a > b</mtext>"]),
+            "<mtext>a &gt; b</mtext>"
+        );
+        assert_eq!(
+            sanitize(&[r#"<mi title="a > b">x</mi>"#, "<mi"]),
+            "<mi>x</mi>"
+        );
+    }
+}
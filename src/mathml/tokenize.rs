@@ -0,0 +1,112 @@
+//! The splitting of the `MathML` written by the renderer into text and tags, for the writers which
+//! rewrite it.
+
+/// A part of the `MathML` written to a [`Tokenizer`].
+pub(super) enum Token<'b> {
+    /// Text between two tags, which may come in several parts.
+    Text(&'b [u8]),
+    /// A whole tag, from its `<` to its `>`.
+    Tag(Vec<u8>),
+}
+
+/// Splits the `MathML` written to it, in chunks of any size, into text and whole tags.
+///
+/// Text and attribute values are escaped by the renderer, so every `<` starts a tag. A `>` only
+/// ends it outside of quotes.
+#[derive(Default)]
+pub(super) struct Tokenizer {
+    /// The tag being written, from its `<`, if its `>` has not been written yet.
+    tag: Option<Vec<u8>>,
+    in_quotes: bool,
+}
+
+impl Tokenizer {
+    /// The next token of the chunk, which is advanced past it, or `None` once the chunk is
+    /// consumed. The part of a tag at the end of the chunk is kept until its `>` is written.
+    pub(super) fn next<'b>(&mut self, chunk: &mut &'b [u8]) -> Option<Token<'b>> {
+        while !chunk.is_empty() {
+            let rest: &'b [u8] = chunk;
+            match &mut self.tag {
+                None => match rest.iter().position(|&byte| byte == b'<') {
+                    Some(0) => {
+                        self.tag = Some(vec![b'<']);
+                        *chunk = &rest[1..];
+                    }
+                    Some(end) => {
+                        *chunk = &rest[end..];
+                        return Some(Token::Text(&rest[..end]));
+                    }
+                    None => {
+                        *chunk = &[];
+                        return Some(Token::Text(rest));
+                    }
+                },
+                Some(tag) => {
+                    let end = rest.iter().position(|&byte| {
+                        if byte == b'"' {
+                            self.in_quotes = !self.in_quotes;
+                        }
+                        byte == b'>' && !self.in_quotes
+                    });
+                    match end {
+                        Some(end) => {
+                            tag.extend_from_slice(&rest[..=end]);
+                            *chunk = &rest[end + 1..];
+                            return self.tag.take().map(Token::Tag);
+                        }
+                        None => {
+                            tag.extend_from_slice(rest);
+                            *chunk = &[];
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_tags() {
+        let output = r#"<mi>a</mi><mo title="a > b">&gt;</mo><mspace width="1em" />b"#;
+        let expected = [
+            "<mi>",
+            "a",
+            "</mi>",
+            r#"<mo title="a > b">"#,
+            "&gt;",
+            "</mo>",
+            r#"<mspace width="1em" />"#,
+            "b",
+        ];
+        for split in 0..output.len() {
+            let mut tokenizer = Tokenizer::default();
+            let mut tokens = Vec::<Vec<u8>>::new();
+            let mut text = false;
+            for mut chunk in [&output.as_bytes()[..split], &output.as_bytes()[split..]] {
+                while let Some(token) = tokenizer.next(&mut chunk) {
+                    match token {
+                        // Text split between the two chunks is joined back.
+                        Token::Text(part) if text => tokens
+                            .last_mut()
+                            .expect("text was written")
+                            .extend_from_slice(part),
+                        Token::Text(part) => {
+                            tokens.push(part.to_vec());
+                            text = true;
+                        }
+                        Token::Tag(tag) => {
+                            tokens.push(tag);
+                            text = false;
+                        }
+                    }
+                }
+            }
+            assert_eq!(tokens, expected.map(str::as_bytes), "split at {split}");
+        }
+    }
+}
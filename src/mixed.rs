@@ -0,0 +1,534 @@
+//! Documents mixing text and math, such as Markdown or plain-text comments.
+//!
+//! A [`Splitter`] cuts such a document into [`Segment`]s of text and math, and [`push_mixed`]
+//! and [`write_mixed`] render a whole document, replacing its math by `MathML`.
+//!
+//! # Delimiters
+//!
+//! The following delimiters are recognized:
+//! - `$...$` and `\(...\)` for inline math,
+//! - `$$...$$` and `\[...\]` for display math,
+//! - `\begin{env}...\end{env}` for display math, where `env` is one of `equation`, `align`,
+//!   `gather`, `alignat` (along with their starred versions), or `multline`. The environment is
+//!   part of the math given to the parser.
+//!
+//! To avoid mistaking amounts of money for math, `$...$` follows the rules of pandoc: the opening
+//! `$` must be followed by a non-space character, and the closing `$` must be preceded by a
+//! non-space character and must not be followed by a digit. Inline math written with `$` also
+//! cannot span a blank line. In `costs $5 and $10`, no math is found.
+//!
+//! Outside of math, `\$` is an escaped dollar sign, and `\\` an escaped backslash, neither of
+//! which start math. Code spans delimited by backticks, such as `` `$HOME` ``, including fenced
+//! code blocks, never contain math. Delimiters that are not closed are left as text.
+
+use std::{borrow::Cow, io, ops::Range};
+
+use crate::{
+    config::{DisplayMode, RenderConfig},
+    mathml, Document,
+};
+
+/// A part of a document, either text or math.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment<'a> {
+    /// Text outside of math, as written in the document.
+    Text {
+        /// The text.
+        content: &'a str,
+        /// The range of the document where the text is located.
+        span: Range<usize>,
+    },
+    /// Math, to be given to the [`Parser`](crate::Parser).
+    Math {
+        /// The math, without its delimiters, except for environments, which are kept.
+        content: &'a str,
+        /// The display mode implied by the delimiters.
+        display_mode: DisplayMode,
+        /// The range of the document where the math is located, including its delimiters.
+        span: Range<usize>,
+    },
+}
+
+impl Segment<'_> {
+    /// The range of the document where the segment is located.
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Segment::Text { span, .. } | Segment::Math { span, .. } => span.clone(),
+        }
+    }
+}
+
+/// An iterator over the [`Segment`]s of a document.
+///
+/// Consecutive segments are contiguous, and cover the whole document.
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{config::DisplayMode, mixed::{Segment, Splitter}};
+///
+/// let document = r"It costs \$5, and `$HOME` is $x^2$: $$\int f$$";
+/// let math = Splitter::new(document)
+///     .filter_map(|segment| match segment {
+///         Segment::Math { content, display_mode, .. } => Some((content, display_mode)),
+///         Segment::Text { .. } => None,
+///     })
+///     .collect::<Vec<_>>();
+///
+/// assert_eq!(
+///     math,
+///     [("x^2", DisplayMode::Inline), (r"\int f", DisplayMode::Block)]
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Splitter<'a> {
+    input: &'a str,
+    position: usize,
+    /// The math found after the text that is about to be yielded.
+    next_math: Option<Math>,
+    /// The index at which the search for the end of inline math opened by a `$` last stopped
+    /// without finding it, so that a `$` before this index does not open inline math either.
+    unclosed_dollar_end: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Math {
+    content: Range<usize>,
+    display_mode: DisplayMode,
+    span: Range<usize>,
+}
+
+/// Environments recognized outside of math delimiters.
+const ENVIRONMENTS: [&str; 9] = [
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "gather",
+    "gather*",
+    "alignat",
+    "alignat*",
+    "multline",
+];
+
+impl<'a> Splitter<'a> {
+    /// Create a splitter over the given document.
+    pub fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            position: 0,
+            next_math: None,
+            unclosed_dollar_end: 0,
+        }
+    }
+
+    /// Find the first math of the document starting at or after `index`.
+    fn find_math(&mut self, mut index: usize) -> Option<Math> {
+        let bytes = self.input.as_bytes();
+        while let Some(&byte) = bytes.get(index) {
+            match byte {
+                b'`' => index = self.skip_code_span(index),
+                b'\\' => {
+                    let rest = &self.input[index..];
+                    let math = if rest.starts_with("\\(") {
+                        self.delimited(index, "\\(", "\\)", DisplayMode::Inline)
+                    } else if rest.starts_with("\\[") {
+                        self.delimited(index, "\\[", "\\]", DisplayMode::Block)
+                    } else if rest.starts_with("\\begin{") {
+                        self.environment(index)
+                    } else {
+                        None
+                    };
+                    if math.is_some() {
+                        return math;
+                    }
+                    // Skip escaped characters, such as `\$` and `\\`.
+                    index += if bytes.get(index + 1).is_some_and(u8::is_ascii) {
+                        2
+                    } else {
+                        1
+                    };
+                }
+                b'$' if bytes.get(index + 1) == Some(&b'$') => {
+                    match self.delimited(index, "$$", "$$", DisplayMode::Block) {
+                        Some(math) => return Some(math),
+                        None => index += 2,
+                    }
+                }
+                b'$' => match self.inline_dollar(index) {
+                    Some(math) => return Some(math),
+                    None => index += 1,
+                },
+                _ => index += 1,
+            }
+        }
+        None
+    }
+
+    /// Return the index following the code span starting at `index`, or following its opening
+    /// backticks if it is not closed.
+    fn skip_code_span(&self, index: usize) -> usize {
+        let ticks = self.input[index..]
+            .bytes()
+            .take_while(|&b| b == b'`')
+            .count();
+        let content_start = index + ticks;
+        let mut search = content_start;
+        while let Some(offset) = self.input[search..].find('`') {
+            let start = search + offset;
+            let count = self.input[start..]
+                .bytes()
+                .take_while(|&b| b == b'`')
+                .count();
+            if count == ticks {
+                return start + count;
+            }
+            search = start + count;
+        }
+        content_start
+    }
+
+    /// Find math delimited by `open` at `index` and the next `close`.
+    fn delimited(
+        &self,
+        index: usize,
+        open: &str,
+        close: &str,
+        display_mode: DisplayMode,
+    ) -> Option<Math> {
+        let content_start = index + open.len();
+        let content_end = self.find_closing(content_start, close)?;
+        Some(Math {
+            content: content_start..content_end,
+            display_mode,
+            span: index..content_end + close.len(),
+        })
+    }
+
+    /// Find the first `close` at or after `index` in math, skipping escaped characters.
+    fn find_closing(&self, mut index: usize, close: &str) -> Option<usize> {
+        let bytes = self.input.as_bytes();
+        while index < bytes.len() {
+            if bytes[index..].starts_with(close.as_bytes()) {
+                return Some(index);
+            }
+            index += match bytes[index] {
+                b'\\' if bytes.get(index + 1).is_some_and(u8::is_ascii) => 2,
+                _ => 1,
+            };
+        }
+        None
+    }
+
+    fn environment(&self, index: usize) -> Option<Math> {
+        let name_start = index + "\\begin{".len();
+        let name_end = name_start + self.input[name_start..].find('}')?;
+        let name = &self.input[name_start..name_end];
+        if !ENVIRONMENTS.contains(&name) {
+            return None;
+        }
+        let end = format!("\\end{{{name}}}");
+        let end_start = self.find_closing(name_end + 1, &end)?;
+        let span = index..end_start + end.len();
+        Some(Math {
+            content: span.clone(),
+            display_mode: DisplayMode::Block,
+            span,
+        })
+    }
+
+    /// Find inline math delimited by a single `$` at `index`, following the rules of pandoc.
+    ///
+    /// The closing `$` is searched for in a single pass, which stops at the first blank line.
+    fn inline_dollar(&mut self, index: usize) -> Option<Math> {
+        let content_start = index + 1;
+        let rest = &self.input[content_start..];
+        if index < self.unclosed_dollar_end || rest.chars().next().map_or(true, char::is_whitespace)
+        {
+            return None;
+        }
+        let bytes = self.input.as_bytes();
+        let mut end = content_start;
+        // Whether only whitespace was found since the last line break.
+        let mut blank_line = false;
+        while let Some(&byte) = bytes.get(end) {
+            match byte {
+                b'$' => {
+                    let preceded_by_space = self.input[content_start..end]
+                        .chars()
+                        .next_back()
+                        .map_or(true, char::is_whitespace);
+                    let followed_by_digit = bytes.get(end + 1).is_some_and(u8::is_ascii_digit);
+                    if !preceded_by_space && !followed_by_digit {
+                        return Some(Math {
+                            content: content_start..end,
+                            display_mode: DisplayMode::Inline,
+                            span: index..end + 1,
+                        });
+                    }
+                    blank_line = false;
+                    end += 1;
+                }
+                b'\n' if blank_line => break,
+                b'\n' => {
+                    blank_line = true;
+                    end += 1;
+                }
+                // Skip escaped characters, such as `\$`, but not line breaks.
+                b'\\'
+                    if bytes
+                        .get(end + 1)
+                        .is_some_and(|&b| b.is_ascii() && b != b'\n') =>
+                {
+                    blank_line = false;
+                    end += 2;
+                }
+                _ => {
+                    let c = self.input[end..].chars().next().expect("not at the end");
+                    blank_line &= c.is_whitespace();
+                    end += c.len_utf8();
+                }
+            }
+        }
+        // Inline math opened by a later `$` before this point would not be closed either, since
+        // whether a `$` closes inline math does not depend on where the math is opened.
+        self.unclosed_dollar_end = end;
+        None
+    }
+}
+
+/// Unescape the dollar signs (`\$`) and backslashes (`\\`) of text outside of math.
+fn unescape(text: &str) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text);
+    }
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(escaped @ ('$' | '\\'))) => {
+                output.push(escaped);
+                chars.next();
+            }
+            _ => output.push(c),
+        }
+    }
+    Cow::Owned(output)
+}
+
+impl<'a> Iterator for Splitter<'a> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let math = match self.next_math.take() {
+            Some(math) => math,
+            None if self.position >= self.input.len() => return None,
+            None => match self.find_math(self.position) {
+                Some(math) if math.span.start == self.position => math,
+                math => {
+                    let end = math.as_ref().map_or(self.input.len(), |m| m.span.start);
+                    self.next_math = math;
+                    let span = self.position..end;
+                    self.position = end;
+                    return Some(Segment::Text {
+                        content: &self.input[span.clone()],
+                        span,
+                    });
+                }
+            },
+        };
+        self.position = math.span.end;
+        Some(Segment::Math {
+            content: &self.input[math.content],
+            display_mode: math.display_mode,
+            span: math.span,
+        })
+    }
+}
+
+/// Takes a document mixing text and math, and writes it into the given string, with its math
+/// rendered to `MathML`.
+///
+/// The text is written as is, except for escaped dollar signs (`\$`) and backslashes (`\\`), which
+/// are unescaped. Math is parsed as the equations of a single [`Document`], so that macros defined
+/// in an equation are available in the following ones. The
+/// [`display_mode`](RenderConfig::display_mode) of the configuration is replaced by the one of each
+/// equation, and its [`annotation`](RenderConfig::annotation), if any, by the source of each
+/// equation.
+///
+/// Errors in the math are rendered in place of the equations, as with [`push_mathml`].
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{mixed::push_mixed, RenderConfig};
+///
+/// let mut output = String::new();
+/// push_mixed(&mut output, r"Let $x \in \mathbb{R}$, for \$5.", RenderConfig::default()).unwrap();
+/// assert!(output.starts_with(r#"Let <math display="inline">"#));
+/// assert!(output.ends_with("</math>, for $5."));
+/// ```
+///
+/// [`push_mathml`]: crate::mathml::push_mathml
+pub fn push_mixed(string: &mut String, input: &str, config: RenderConfig) -> io::Result<()> {
+    let mut document = Document::new();
+    for segment in Splitter::new(input) {
+        match segment {
+            Segment::Text { content, .. } => string.push_str(&unescape(content)),
+            Segment::Math {
+                content,
+                display_mode,
+                ..
+            } => mathml::push_mathml(
+                string,
+                document.parser(content),
+                equation_config(config, content, display_mode),
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// Takes a document mixing text and math, and writes it into the given writer, with its math
+/// rendered to `MathML`.
+///
+/// See [`push_mixed`] for how the document is rendered.
+pub fn write_mixed<W: io::Write>(
+    mut writer: W,
+    input: &str,
+    config: RenderConfig,
+) -> io::Result<()> {
+    let mut document = Document::new();
+    for segment in Splitter::new(input) {
+        match segment {
+            Segment::Text { content, .. } => writer.write_all(unescape(content).as_bytes())?,
+            Segment::Math {
+                content,
+                display_mode,
+                ..
+            } => mathml::write_mathml(
+                &mut writer,
+                document.parser(content),
+                equation_config(config, content, display_mode),
+            )?,
+        }
+    }
+    Ok(())
+}
+
+fn equation_config<'a>(
+    config: RenderConfig<'a>,
+    content: &'a str,
+    display_mode: DisplayMode,
+) -> RenderConfig<'a> {
+    RenderConfig {
+        display_mode,
+        annotation: config.annotation.map(|_| content),
+        ..config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{push_mixed, Segment, Splitter};
+    use crate::{config::DisplayMode, RenderConfig};
+
+    fn math(input: &str) -> Vec<(&str, DisplayMode)> {
+        Splitter::new(input)
+            .filter_map(|segment| match segment {
+                Segment::Math {
+                    content,
+                    display_mode,
+                    ..
+                } => Some((content, display_mode)),
+                Segment::Text { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn delimiters() {
+        use DisplayMode::{Block, Inline};
+
+        let cases: &[(&str, &[(&str, DisplayMode)])] = &[
+            (r"$a$ and $$b$$", &[("a", Inline), ("b", Block)]),
+            (r"\(a\) and \[b\]", &[("a", Inline), ("b", Block)]),
+            (
+                r"see \begin{align*} a &= b \end{align*}.",
+                &[(r"\begin{align*} a &= b \end{align*}", Block)],
+            ),
+            (r"\begin{itemize} $a$ \end{itemize}", &[("a", Inline)]),
+            (r"$\$5$ and $a\)$", &[(r"\$5", Inline), (r"a\)", Inline)]),
+            (r"\[a \\] b\]", &[(r"a \\] b", Block)]),
+            ("$é$ and $$α + β$$", &[("é", Inline), ("α + β", Block)]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(&math(input), expected, "input: {input}");
+        }
+    }
+
+    #[test]
+    fn not_math() {
+        let inputs = [
+            r"costs $5 and $10",
+            r"costs $5, or $ 10",
+            r"an escaped \$x$",
+            r"an escaped \\(x\)",
+            r"`$x$` and ``a `$b$` c``",
+            "```\n$x$\n```",
+            "$a\n\nb$",
+            r"$$ unclosed",
+            r"\begin{equation} unclosed",
+        ];
+        for input in inputs {
+            assert_eq!(math(input), [], "input: {input}");
+        }
+    }
+
+    #[test]
+    fn unclosed_dollars() {
+        let input = "$a ".repeat(100_000);
+        let start = std::time::Instant::now();
+        assert_eq!(math(&input), []);
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+
+        assert_eq!(math("$a $b\n \n$c$"), [("c", DisplayMode::Inline)]);
+        assert_eq!(math("$a\\\n\nb$"), []);
+    }
+
+    #[test]
+    fn escapes() {
+        let mut output = String::new();
+        push_mixed(
+            &mut output,
+            r"costs \$5, \\$x$ and \\(y\)",
+            RenderConfig::default(),
+        )
+        .unwrap();
+        assert!(output.starts_with(r#"costs $5, \<math"#), "{output}");
+        assert!(output.ends_with(r"</math> and \(y\)"), "{output}");
+    }
+
+    #[test]
+    fn segments_cover_input() {
+        let input = "Let $x$ be `code $y$`, and $$\\sum_i x_i$$\nthen \\(z\\)";
+        let mut end = 0;
+        for segment in Splitter::new(input) {
+            assert_eq!(segment.span().start, end);
+            end = segment.span().end;
+        }
+        assert_eq!(end, input.len());
+    }
+
+    #[test]
+    fn macros_are_shared() {
+        let mut output = String::new();
+        push_mixed(
+            &mut output,
+            r"$\def\R{\mathbb{R}}$ and $x \in \R$",
+            RenderConfig::default(),
+        )
+        .unwrap();
+        assert!(!output.contains("merror"), "{output}");
+    }
+}
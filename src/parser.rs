@@ -5,23 +5,33 @@
 //!
 //! [`push_mathml`]: crate::mathml::push_mathml
 //! [`write_mathml`]: crate::mathml::write_mathml
+pub mod document;
 pub mod error;
+pub mod extension;
 mod lex;
 mod macros;
 mod primitives;
 mod state;
 pub mod storage;
 mod tables;
+pub mod warning;
 
 use std::ops::Range;
 
-use macros::MacroContext;
+use macros::{MacroContext, TableRef};
 
-use crate::event::{Event, Grouping, ScriptPosition, ScriptType};
+use crate::{
+    config::{ParserConfig, Strictness},
+    event::{Event, Grouping, ScriptPosition, ScriptType},
+};
 
 use self::{state::ParserState, storage::Storage};
 
+use error::Span;
 pub(crate) use error::{ErrorKind, InnerResult, ParserError};
+pub(crate) use macros::defines_macros;
+pub use macros::MacroTable;
+use warning::{Warning, WarningKind};
 
 /// The parser completes the task of transforming the input `LaTeX` into a symbolic representation,
 /// namely a stream of [`Event`]s.
@@ -42,7 +52,9 @@ pub struct Parser<'store> {
     ///
     /// Instructions are stored backward in this stack, in the sense that the next event to be popped
     /// is the next event to be outputed.
-    instruction_stack: Vec<Instruction<'store>>,
+    ///
+    /// Each instruction is stored along with the range of the original input it comes from.
+    instruction_stack: Vec<(Instruction<'store>, Range<usize>)>,
 
     /// This buffer serves as a staging area when parsing a command.
     ///
@@ -58,6 +70,30 @@ pub struct Parser<'store> {
 
     /// A stack that serves to provide context when an error occurs.
     span_stack: SpanStack<'store>,
+
+    /// The configuration given by the user.
+    config: ParserConfig<'store>,
+
+    /// Resources used so far, checked against the limits in the configuration.
+    usage: Usage,
+
+    /// Whether a resource limit was exceeded, after which only the events left in the stack are
+    /// output.
+    stopped: bool,
+
+    /// An error that was found before parsing started, and that should be reported first.
+    pending_error: Option<(ParserError, Range<usize>)>,
+
+    /// Warnings found so far.
+    warnings: Vec<Warning>,
+
+    /// Warnings found while parsing the current chunk, with their location if it is known.
+    pending_warnings: Vec<(WarningKind, Option<Range<usize>>)>,
+
+    /// Whether every event is located in the input, which is only needed by an [`OffsetIter`].
+    ///
+    /// Otherwise, only the instructions whose location is needed to report an error are located.
+    track_spans: bool,
 }
 
 impl<'store> Parser<'store> {
@@ -66,115 +102,392 @@ impl<'store> Parser<'store> {
     where
         'input: 'store,
     {
+        Self::with_config(input, storage, ParserConfig::default())
+    }
+
+    /// Create a new parser which has access to the macros defined in the given [`MacroTable`].
+    ///
+    /// Macros defined in the input are local to this parser, and do not modify the table.
+    pub fn with_macros<'input>(
+        input: &'input str,
+        storage: &'store Storage,
+        macros: &'store MacroTable,
+    ) -> Self
+    where
+        'input: 'store,
+    {
+        Self::with_config(
+            input,
+            storage,
+            ParserConfig {
+                macros: Some(macros),
+                ..ParserConfig::default()
+            },
+        )
+    }
+
+    /// Create a new parser from the given input string, storage and configuration.
+    pub fn with_config<'input>(
+        input: &'input str,
+        storage: &'store Storage,
+        config: ParserConfig<'store>,
+    ) -> Self
+    where
+        'input: 'store,
+    {
+        let macro_context = match config.macros {
+            Some(macros) => MacroContext::with_table(TableRef::Shared(macros)),
+            None => MacroContext::new(),
+        };
+        Self::with_context(input, storage, macro_context, config)
+    }
+
+    fn with_context<'input>(
+        input: &'input str,
+        storage: &'store Storage,
+        macro_context: MacroContext<'store>,
+        config: ParserConfig<'store>,
+    ) -> Self
+    where
+        'input: 'store,
+    {
+        let mut span_stack = SpanStack::from_input(input);
         let mut instruction_stack = Vec::with_capacity(32);
-        instruction_stack.push(Instruction::SubGroup {
-            content: input,
-            allowed_alignment_count: None,
-        });
+        let mut pending_error = None;
+        if config
+            .limits
+            .max_input_length
+            .is_some_and(|max| input.len() > max)
+        {
+            let error = ParserError::new(
+                ErrorKind::InputTooLong,
+                input.as_ptr(),
+                0..input.len(),
+                &mut span_stack,
+            );
+            pending_error = Some((error, 0..input.len()));
+        } else {
+            instruction_stack.push((
+                Instruction::SubGroup {
+                    content: input,
+                    allowed_alignment_count: None,
+                },
+                0..input.len(),
+            ));
+        }
         let buffer = Vec::with_capacity(16);
         Self {
             instruction_stack,
             buffer,
-            macro_context: MacroContext::new(),
+            macro_context,
             storage: &storage.0,
-            span_stack: SpanStack::from_input(input),
+            span_stack,
+            config,
+            usage: Usage::default(),
+            stopped: false,
+            pending_error,
+            warnings: Vec::new(),
+            pending_warnings: Vec::new(),
+            track_spans: false,
         }
     }
 }
 
-impl<'store> Iterator for Parser<'store> {
-    type Item = Result<Event<'store>, ParserError>;
+impl<'store> Parser<'store> {
+    /// Consume the parser and return an iterator over the events along with their location in
+    /// the input.
+    ///
+    /// Each item is a byte range of the original input given to the parser. Events produced
+    /// together, such as a [`Event::Script`] and its base, or the [`Event::Begin`] and
+    /// [`Event::End`] of a group, share the range of the whole construct, while the content of a
+    /// group is located more precisely when it is parsed. Events produced by a macro expansion
+    /// are located at the call site of the macro in the original input.
+    ///
+    /// ### Examples
+    ///
+    /// ```rust
+    /// use pulldown_latex::{Parser, Storage};
+    ///
+    /// let storage = Storage::new();
+    /// let input = r"\def\half{\frac{1}{2}} x + \half";
+    /// let parser = Parser::new(input, &storage);
+    ///
+    /// for (event, range) in parser.into_offset_iter() {
+    ///     println!("{:?} comes from {:?}", event.unwrap(), &input[range]);
+    /// }
+    /// ```
+    pub fn into_offset_iter(mut self) -> OffsetIter<'store> {
+        self.track_spans = true;
+        OffsetIter { parser: self }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.instruction_stack.last_mut() {
-            Some(Instruction::Event(_)) => Some(Ok(self
-                .instruction_stack
-                .pop()
-                .and_then(|i| match i {
-                    Instruction::Event(e) => Some(e),
-                    _ => None,
-                })
-                .expect("there is something in the stack"))),
-            Some(Instruction::SubGroup { content, .. }) if content.trim_start().is_empty() => {
-                self.instruction_stack.pop();
-                self.next()
-            }
-            Some(Instruction::SubGroup {
-                content,
-                allowed_alignment_count,
-                ..
-            }) => {
-                let state = ParserState {
-                    allowed_alignment_count: allowed_alignment_count.as_mut(),
-                    ..Default::default()
-                };
-
-                let inner = InnerParser {
-                    content,
-                    buffer: &mut self.buffer,
-                    state,
-                    macro_context: &mut self.macro_context,
-                    storage: self.storage,
-                    span_stack: &mut self.span_stack,
-                };
-
-                let (desc, rest) = inner.parse_next();
-                *content = rest;
-
-                let script_event = match desc {
-                    Err(e) => {
-                        let content_str = *content;
-                        return Some(Err(ParserError::new(
-                            e,
-                            content_str.as_ptr(),
-                            &mut self.span_stack,
-                        )));
-                    }
-                    Ok(Some((e, desc))) => {
-                        if desc.subscript_start > desc.superscript_start {
-                            let content = self.buffer.drain(desc.superscript_start..).rev();
-                            let added_len = content.len();
-
-                            self.instruction_stack.reserve(added_len);
-                            let spare =
-                                &mut self.instruction_stack.spare_capacity_mut()[..added_len];
-                            let mut idx = desc.subscript_start - desc.superscript_start;
-
-                            for e in content {
-                                if idx == added_len {
-                                    idx = 0;
+    /// The warnings found so far, in the order in which they were found.
+    ///
+    /// Warnings are only collected when [`ParserConfig::strict`] is set to [`Strictness::Warn`] or
+    /// [`Strictness::Error`].
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    fn next_with_span(&mut self) -> Option<(Result<Event<'store>, ParserError>, Range<usize>)> {
+        if let Some((error, span)) = self.pending_error.take() {
+            return Some((Err(error), span));
+        }
+        loop {
+            match self.instruction_stack.last_mut() {
+                Some((Instruction::Event(_), _)) => {
+                    let Some((Instruction::Event(e), span)) = self.instruction_stack.pop() else {
+                        unreachable!("there is an event at the top of the stack")
+                    };
+                    match e {
+                        Event::Begin(_) => {
+                            self.usage.group_depth += 1;
+                            if !self.stopped
+                                && self
+                                    .config
+                                    .limits
+                                    .max_group_depth
+                                    .is_some_and(|max| self.usage.group_depth > max)
+                            {
+                                // The group is replaced by the error.
+                                self.usage.group_depth -= 1;
+                                let mut depth = 0usize;
+                                while let Some((instruction, _)) = self.instruction_stack.pop() {
+                                    match instruction {
+                                        Instruction::Event(Event::Begin(_)) => depth += 1,
+                                        Instruction::Event(Event::End) if depth == 0 => break,
+                                        Instruction::Event(Event::End) => depth -= 1,
+                                        _ => {}
+                                    }
                                 }
-                                spare[idx].write(e);
-                                idx += 1;
+                                let place = self.span_stack.input[span.start..].as_ptr();
+                                return Some(self.fatal_error(
+                                    ErrorKind::GroupTooDeep,
+                                    place,
+                                    span,
+                                ));
                             }
+                        }
+                        Event::End => {
+                            self.usage.group_depth = self.usage.group_depth.saturating_sub(1);
+                        }
+                        _ => {}
+                    }
+                    return Some((Ok(e), span));
+                }
+                Some((Instruction::SubGroup { content, .. }, _))
+                    if content.trim_start().is_empty() =>
+                {
+                    self.instruction_stack.pop();
+                }
+                Some((
+                    Instruction::SubGroup {
+                        content,
+                        allowed_alignment_count,
+                        ..
+                    },
+                    group_span,
+                )) => {
+                    let group_span = group_span.clone();
+                    let alignments = allowed_alignment_count.is_some();
+                    let state = ParserState {
+                        allowed_alignment_count: allowed_alignment_count.as_mut(),
+                        ..Default::default()
+                    };
+
+                    let chunk = content.trim_start();
+                    let start = chunk.as_ptr();
+                    let group_depth = self.usage.group_depth;
+                    let inner = InnerParser {
+                        content,
+                        buffer: &mut self.buffer,
+                        state,
+                        macro_context: &mut self.macro_context,
+                        storage: self.storage,
+                        span_stack: &mut self.span_stack,
+                        config: &self.config,
+                        usage: &mut self.usage,
+                        warnings: &mut self.pending_warnings,
+                    };
+
+                    let (desc, rest) = inner.parse_next();
+                    *content = rest;
+                    self.usage.group_depth = group_depth;
+                    // Groups are located even when spans are not tracked, since errors in their
+                    // content, and errors about their depth, are reported at their location.
+                    let locate = self.track_spans
+                        || desc.is_err()
+                        || !self.pending_warnings.is_empty()
+                        || self.buffer.iter().any(|instruction| {
+                            matches!(
+                                instruction,
+                                Instruction::SubGroup { .. } | Instruction::Event(Event::Begin(_))
+                            )
+                        });
+                    let span = if locate {
+                        self.span_stack
+                            .chunk_range(start, rest.as_ptr(), &group_span)
+                    } else {
+                        Range::default()
+                    };
+                    for (kind, range) in self.pending_warnings.drain(..) {
+                        let range = range.unwrap_or_else(|| span.clone());
+                        self.warnings.push(Warning {
+                            kind,
+                            span: Span::new(self.span_stack.input, range),
+                        });
+                    }
 
-                            // Safety: The new length is less than the vector's capacity because we
-                            // reserved `added_len` previously. Every element in the vector up to
-                            // that new length is also initialized by the loop.
-                            unsafe {
-                                self.instruction_stack
-                                    .set_len(self.instruction_stack.len() + added_len)
+                    let script_event = match desc {
+                        Err(e) if e.is_resource_limit() => {
+                            // The parser may have gone deep into macro expansions, so the error
+                            // is located at the whole chunk that caused it.
+                            let mut rest = chunk;
+                            lex::recovery_point(&mut rest, alignments);
+                            let span =
+                                self.span_stack
+                                    .chunk_range(start, rest.as_ptr(), &group_span);
+                            return Some(self.fatal_error(e, start, span));
+                        }
+                        Err(kind) if self.config.error_recovery => {
+                            self.buffer.clear();
+                            let mut rest = chunk;
+                            let source = lex::recovery_point(&mut rest, alignments);
+                            if let Some((Instruction::SubGroup { content, .. }, _)) =
+                                self.instruction_stack.last_mut()
+                            {
+                                *content = rest;
+                            }
+                            let span =
+                                self.span_stack
+                                    .chunk_range(start, rest.as_ptr(), &group_span);
+                            let error = Event::Error {
+                                kind,
+                                source: source.trim_end(),
+                                span: span.clone(),
                             };
-                        } else {
-                            self.instruction_stack
-                                .extend(self.buffer.drain(desc.subscript_start..).rev());
+                            return Some((Ok(error), span));
                         }
-                        Some(e)
-                    }
-                    Ok(None) => None,
-                };
+                        Err(e) => {
+                            return Some((
+                                Err(ParserError::new(
+                                    e,
+                                    rest.as_ptr(),
+                                    span.clone(),
+                                    &mut self.span_stack,
+                                )),
+                                span,
+                            ));
+                        }
+                        Ok(Some((e, desc))) => {
+                            if desc.subscript_start > desc.superscript_start {
+                                let content = self.buffer.drain(desc.superscript_start..).rev();
+                                let added_len = content.len();
+
+                                self.instruction_stack.reserve(added_len);
+                                let spare =
+                                    &mut self.instruction_stack.spare_capacity_mut()[..added_len];
+                                let mut idx = desc.subscript_start - desc.superscript_start;
+
+                                for e in content {
+                                    if idx == added_len {
+                                        idx = 0;
+                                    }
+                                    spare[idx].write((e, span.clone()));
+                                    idx += 1;
+                                }
+
+                                // Safety: The new length is less than the vector's capacity because we
+                                // reserved `added_len` previously. Every element in the vector up to
+                                // that new length is also initialized by the loop.
+                                unsafe {
+                                    self.instruction_stack
+                                        .set_len(self.instruction_stack.len() + added_len)
+                                };
+                            } else {
+                                self.instruction_stack.extend(
+                                    self.buffer
+                                        .drain(desc.subscript_start..)
+                                        .rev()
+                                        .map(|i| (i, span.clone())),
+                                );
+                            }
+                            Some(e)
+                        }
+                        Ok(None) => None,
+                    };
 
-                self.instruction_stack.extend(self.buffer.drain(..).rev());
-                if let Some(e) = script_event {
-                    self.instruction_stack.push(Instruction::Event(e));
+                    self.instruction_stack
+                        .extend(self.buffer.drain(..).rev().map(|i| (i, span.clone())));
+                    if let Some(e) = script_event {
+                        self.instruction_stack.push((Instruction::Event(e), span));
+                    }
                 }
-                self.next()
+                None => return None,
             }
-            None => None,
         }
     }
 }
 
+impl<'store> Parser<'store> {
+    /// Report an error after which the parser stops.
+    ///
+    /// The events that are already in the stack are kept, so that every group that was begun is
+    /// ended, but the input that has not been parsed yet is dropped.
+    fn fatal_error(
+        &mut self,
+        error: ErrorKind,
+        place: *const u8,
+        span: Range<usize>,
+    ) -> (Result<Event<'store>, ParserError>, Range<usize>) {
+        self.buffer.clear();
+        self.stopped = true;
+        self.instruction_stack
+            .retain(|(instruction, _)| matches!(instruction, Instruction::Event(_)));
+        if self.config.error_recovery {
+            let event = Event::Error {
+                kind: error,
+                source: self.span_stack.input[span.clone()].trim_end(),
+                span: span.clone(),
+            };
+            return (Ok(event), span);
+        }
+        let error = ParserError::new(error, place, span.clone(), &mut self.span_stack);
+        (Err(error), span)
+    }
+}
+
+impl<'store> Iterator for Parser<'store> {
+    type Item = Result<Event<'store>, ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with_span().map(|(item, _)| item)
+    }
+}
+
+/// An iterator over the events of a [`Parser`] along with their location in the input.
+///
+/// This struct is created by the [`Parser::into_offset_iter`] method.
+#[derive(Debug)]
+pub struct OffsetIter<'store> {
+    parser: Parser<'store>,
+}
+
+impl<'store> OffsetIter<'store> {
+    /// See [`Parser::warnings`].
+    pub fn warnings(&self) -> &[Warning] {
+        self.parser.warnings()
+    }
+}
+
+impl<'store> Iterator for OffsetIter<'store> {
+    type Item = (Result<Event<'store>, ParserError>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parser.next_with_span()
+    }
+}
+
 #[derive(Debug)]
 struct InnerParser<'b, 'store> {
     content: &'store str,
@@ -183,9 +496,37 @@ struct InnerParser<'b, 'store> {
     macro_context: &'b mut MacroContext<'store>,
     storage: &'store bumpalo::Bump,
     span_stack: &'b mut SpanStack<'store>,
+    config: &'b ParserConfig<'store>,
+    usage: &'b mut Usage,
+    warnings: &'b mut Vec<(WarningKind, Option<Range<usize>>)>,
 }
 
 impl<'b, 'store> InnerParser<'b, 'store> {
+    /// Report a dubious control sequence, according to the configured strictness.
+    fn warn_control_sequence(
+        &mut self,
+        kind: WarningKind,
+        control_sequence: &'store str,
+    ) -> InnerResult<()> {
+        let range = self.span_stack.control_sequence_range(control_sequence);
+        self.warn(kind, range)
+    }
+
+    /// Report dubious input, according to the configured strictness.
+    fn warn(&mut self, kind: WarningKind, range: Option<Range<usize>>) -> InnerResult<()> {
+        match self.config.strict {
+            Strictness::Ignore => Ok(()),
+            Strictness::Warn => {
+                self.warnings.push((kind, range));
+                Ok(())
+            }
+            Strictness::Error => {
+                self.warnings.push((kind, range));
+                Err(ErrorKind::Strict)
+            }
+        }
+    }
+
     /// Parse an arugment and pushes the argument to the stack surrounded by a
     /// group: [..., EndGroup, Argument, BeginGroup], when the argument is a subgroup.
     /// Otherwise, it pushes the argument to the stack ungrouped.
@@ -193,10 +534,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
         match argument {
             Argument::Token(token) => {
                 self.state.handling_argument = true;
-                match token {
-                    Token::ControlSequence(cs) => self.handle_primitive(cs)?,
-                    Token::Character(c) => self.handle_char_token(c)?,
-                };
+                self.handle_token_argument(token)?;
             }
             Argument::Group(group) => {
                 self.buffer.extend([
@@ -212,6 +550,27 @@ impl<'b, 'store> InnerParser<'b, 'store> {
         Ok(())
     }
 
+    /// Handle an argument given as a single token.
+    ///
+    /// These arguments are handled recursively, so they count as a level of group nesting.
+    fn handle_token_argument(&mut self, token: Token<'store>) -> InnerResult<()> {
+        self.usage.group_depth += 1;
+        if self
+            .config
+            .limits
+            .max_group_depth
+            .is_some_and(|max| self.usage.group_depth > max)
+        {
+            return Err(ErrorKind::GroupTooDeep);
+        }
+        match token {
+            Token::ControlSequence(cs) => self.handle_primitive(cs)?,
+            Token::Character(c) => self.handle_char_token(c)?,
+        };
+        self.usage.group_depth -= 1;
+        Ok(())
+    }
+
     /// ## Script parsing
     ///
     /// The script parser first checks for directives about script placement, i.e. `\limits` and `\nolimits`,
@@ -222,29 +581,33 @@ impl<'b, 'store> InnerParser<'b, 'store> {
     /// [amsdocs]: https://mirror.its.dal.ca/ctan/macros/latex/required/amsmath/amsldoc.pdf
     fn parse(&mut self) -> InnerResult<Option<(Event<'store>, ScriptDescriptor)>> {
         // 1. Parse the next token and output everything to the staging stack.
-        let original_content = self.content.trim_start();
-        let token = match lex::token(&mut self.content) {
-            Ok(token) => token,
-            Err(ErrorKind::Token) => return Ok(None),
-            Err(e) => return Err(e),
+        let token = loop {
+            let original_content = self.content.trim_start();
+            let token = match lex::token(&mut self.content) {
+                Ok(token) => token,
+                Err(ErrorKind::Token) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let Token::ControlSequence(cs) = token else {
+                break token;
+            };
+            let Some(result) = self
+                .macro_context
+                .try_expand_in(cs, self.content, self.storage)
+            else {
+                break token;
+            };
+
+            let (new_content, arguments_consumed_length) = result?;
+            let call_site_length =
+                original_content.len() - self.content.len() + arguments_consumed_length;
+            self.span_stack
+                .add(new_content, original_content, call_site_length);
+            self.content = new_content;
+            self.check_expansion_limits(new_content.len())?;
         };
         match token {
-            Token::ControlSequence(cs) => {
-                if let Some(result) =
-                    self.macro_context
-                        .try_expand_in(cs, self.content, self.storage)
-                {
-                    let (new_content, arguments_consumed_length) = result?;
-                    let call_site_length = cs.len() + arguments_consumed_length + 1;
-                    self.span_stack
-                        .add(new_content, original_content, call_site_length);
-
-                    self.content = new_content;
-                    return self.parse();
-                }
-
-                self.handle_primitive(cs)?
-            }
+            Token::ControlSequence(cs) => self.handle_primitive(cs)?,
             Token::Character(c) => self.handle_char_token(c)?,
         };
 
@@ -342,6 +705,31 @@ impl<'b, 'store> InnerParser<'b, 'store> {
         }))
     }
 
+    fn check_expansion_limits(&mut self, expanded_bytes: usize) -> InnerResult<()> {
+        let limits = &self.config.limits;
+        self.usage.expansions += 1;
+        self.usage.expanded_bytes += expanded_bytes;
+        if limits
+            .max_expansions
+            .is_some_and(|max| self.usage.expansions > max)
+        {
+            return Err(ErrorKind::TooManyExpansions);
+        }
+        if limits
+            .max_expanded_bytes
+            .is_some_and(|max| self.usage.expanded_bytes > max)
+        {
+            return Err(ErrorKind::ExpansionTooLarge);
+        }
+        if limits
+            .max_expansion_depth
+            .is_some_and(|max| self.span_stack.expansions.len() > max)
+        {
+            return Err(ErrorKind::ExpansionTooDeep);
+        }
+        Ok(())
+    }
+
     fn parse_next(
         mut self,
     ) -> (
@@ -352,6 +740,17 @@ impl<'b, 'store> InnerParser<'b, 'store> {
     }
 }
 
+/// Resources used by the parser so far.
+#[derive(Debug, Default)]
+struct Usage {
+    /// Number of macros expanded.
+    expansions: usize,
+    /// Number of bytes written to the storage by macro expansions.
+    expanded_bytes: usize,
+    /// Number of groups currently opened.
+    group_depth: usize,
+}
+
 struct ScriptDescriptor {
     subscript_start: usize,
     superscript_start: usize,
@@ -381,6 +780,12 @@ impl<'a> CharToken<'a> {
     fn as_str(&self) -> &'a str {
         self.char
     }
+
+    /// The character itself, without the rest of the string.
+    fn char_str(&self) -> &'a str {
+        let len = self.char.chars().next().map_or(0, char::len_utf8);
+        &self.char[..len]
+    }
 }
 
 impl From<CharToken<'_>> for char {
@@ -482,6 +887,103 @@ impl<'store> SpanStack<'store> {
         assert!(ptr_val >= input_start && ptr_val <= input_start + self.input.len() as isize);
         (ptr_val - input_start) as usize
     }
+
+    /// Map the substring between `start` and `end` to a range of the original input.
+    ///
+    /// A position inside of the expanded part of a macro is mapped to the call site of the
+    /// macro, so that the range always covers complete macro invocations. Trailing whitespace is
+    /// not included in the range.
+    fn input_range(&self, start: *const u8, end: *const u8) -> Range<usize> {
+        let start = self.map_to_input(start, false);
+        let end = self.map_to_input(end, true).max(start);
+        start..start + self.input[start..end].trim_end().len()
+    }
+
+    /// Like [`SpanStack::input_range`], but returns the range of the enclosing group if the
+    /// chunk does not come from the input or from a macro expansion, e.g., when it was produced
+    /// by a user-defined primitive.
+    fn chunk_range(
+        &self,
+        start: *const u8,
+        end: *const u8,
+        group_range: &Range<usize>,
+    ) -> Range<usize> {
+        if self.contains(start) {
+            self.input_range(start, end)
+        } else {
+            group_range.clone()
+        }
+    }
+
+    /// Whether the pointer is located in the input or in one of the macro expansions.
+    fn contains(&self, ptr: *const u8) -> bool {
+        let contains = |str: &str| {
+            let range = str.as_bytes().as_ptr_range();
+            range.start <= ptr && ptr <= range.end
+        };
+        contains(self.input) || self.expansions.iter().any(|e| contains(e.full_expansion))
+    }
+
+    /// Return the range of the input where the given source is located, if it comes from the
+    /// input or from a macro expansion.
+    fn source_range(&self, source: &str) -> Option<Range<usize>> {
+        let range = source.as_bytes().as_ptr_range();
+        (self.contains(range.start) && self.contains(range.end))
+            .then(|| self.input_range(range.start, range.end))
+    }
+
+    /// Return the range of the input where the given control sequence, including its leading
+    /// `\`, is located.
+    ///
+    /// The control sequence may not come from the input, e.g., when it is the target of `\let`,
+    /// in which case `None` is returned.
+    fn control_sequence_range(&self, control_sequence: &str) -> Option<Range<usize>> {
+        let start = control_sequence.as_ptr().wrapping_sub(1);
+        let comes_from_source = std::iter::once(self.input)
+            .chain(self.expansions.iter().map(|e| e.full_expansion))
+            .any(|source| {
+                let offset = (start as usize).wrapping_sub(source.as_ptr() as usize);
+                source.as_bytes().get(offset) == Some(&b'\\')
+                    && source[offset + 1..].as_ptr() == control_sequence.as_ptr()
+                    && source.len() >= offset + 1 + control_sequence.len()
+            });
+        comes_from_source.then(|| {
+            let end = control_sequence.as_bytes().as_ptr_range().end;
+            self.input_range(start, end)
+        })
+    }
+
+    fn map_to_input(&self, ptr: *const u8, is_end: bool) -> usize {
+        let ptr_val = ptr as isize;
+        let level = self.expansions.iter().rposition(|expansion| {
+            let expansion_ptr = expansion.full_expansion.as_ptr() as isize;
+            ptr_val >= expansion_ptr
+                && ptr_val <= expansion_ptr + expansion.full_expansion.len() as isize
+        });
+        let Some(level) = level else {
+            let input_start = self.input.as_ptr() as isize;
+            debug_assert!(
+                ptr_val >= input_start && ptr_val <= input_start + self.input.len() as isize
+            );
+            return (ptr_val - input_start).clamp(0, self.input.len() as isize) as usize;
+        };
+
+        let mut index =
+            (ptr_val - self.expansions[level].full_expansion.as_ptr() as isize) as usize;
+        for expansion in self.expansions[..=level].iter().rev() {
+            let call_site = &expansion.call_site_in_origin;
+            index = if index > expansion.expansion_length
+                || (index == expansion.expansion_length && !is_end)
+            {
+                call_site.end + index - expansion.expansion_length
+            } else if is_end && index > 0 {
+                call_site.end
+            } else {
+                call_site.start
+            };
+        }
+        index
+    }
 }
 
 /// A span of the input string. Used for error reporting.
@@ -510,7 +1012,9 @@ struct ExpansionSpan<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::event::{Content, DelimiterType, RelationContent, Visual};
+    use crate::event::{
+        Content, DelimiterType, EnvironmentFlow, RelationContent, StateChange, Style, Visual,
+    };
 
     use super::*;
 
@@ -763,6 +1267,129 @@ mod tests {
         );
     }
 
+    #[test]
+    fn offset_iter() {
+        let store = Storage::new();
+        let input = r"\def\sq#1{#1^2} x + \sq y \frac{1}{2}";
+        let parser = Parser::new(input, &store);
+        let spans = parser
+            .into_offset_iter()
+            .map(|(event, span)| (event.unwrap(), &input[span]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            spans,
+            vec![
+                (
+                    Event::Content(Content::Ordinary {
+                        content: 'x',
+                        stretchy: false
+                    }),
+                    "x"
+                ),
+                (
+                    Event::Content(Content::BinaryOp {
+                        content: '+',
+                        small: false
+                    }),
+                    "+"
+                ),
+                (
+                    Event::Script {
+                        ty: ScriptType::Superscript,
+                        position: ScriptPosition::Right
+                    },
+                    r"\sq y"
+                ),
+                (
+                    Event::Content(Content::Ordinary {
+                        content: 'y',
+                        stretchy: false
+                    }),
+                    r"\sq y"
+                ),
+                (Event::Content(Content::Number("2")), r"\sq y"),
+                (Event::Visual(Visual::Fraction(None)), r"\frac{1}{2}"),
+                (Event::Begin(Grouping::Normal), r"\frac{1}{2}"),
+                (Event::Content(Content::Number("1")), "1"),
+                (Event::End, r"\frac{1}{2}"),
+                (Event::Begin(Grouping::Normal), r"\frac{1}{2}"),
+                (Event::Content(Content::Number("2")), "2"),
+                (Event::End, r"\frac{1}{2}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_macros() {
+        let mut macros = MacroTable::new();
+        macros
+            .load(r"\newcommand{\R}{\mathbb{R}} \def\sq#1{#1^2}")
+            .unwrap();
+
+        let store = Storage::new();
+        let with_table = Parser::with_macros(r"\sq x \in \R", &store, &macros)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let inline = Parser::new(
+            r"\newcommand{\R}{\mathbb{R}} \def\sq#1{#1^2} \sq x \in \R",
+            &store,
+        )
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+        assert_eq!(with_table, inline);
+
+        let mut parser = Parser::with_macros(r"\newcommand{\R}{R}", &store, &macros);
+        assert!(parser.next().unwrap().is_err());
+        let parser = Parser::with_macros(r"\renewcommand{\R}{R} \R", &store, &macros);
+        assert_eq!(parser.count(), 1);
+        assert_eq!(macros.len(), 2);
+    }
+
+    #[test]
+    fn warnings() {
+        let input = "\\def\\b{\\bf }\\char`a + \\b x + ⚠ + {{y}}";
+        let store = Storage::new();
+        let config = |strict| ParserConfig {
+            strict,
+            ..ParserConfig::default()
+        };
+
+        let mut parser = Parser::with_config(input, &store, config(Strictness::Warn));
+        assert!(parser.by_ref().all(|event| event.is_ok()));
+        let warnings = parser
+            .warnings()
+            .iter()
+            .map(|warning| (warning.kind.clone(), &input[warning.span.range.clone()]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                (WarningKind::CharCommand, "\\char"),
+                (WarningKind::DeprecatedFontCommand("bf".into()), "\\b"),
+                (WarningKind::UnknownCharacterClass('⚠'), "⚠"),
+                (WarningKind::RedundantBraces, "{{y}}"),
+            ]
+        );
+
+        let mut parser = Parser::with_config(input, &store, config(Strictness::Ignore));
+        assert!(parser.by_ref().all(|event| event.is_ok()));
+        assert!(parser.warnings().is_empty());
+
+        let mut parser = Parser::with_config(input, &store, config(Strictness::Error));
+        let errors = parser
+            .by_ref()
+            .filter_map(|event| event.err().map(|e| e.kind().clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 4);
+        assert!(errors.iter().all(|e| *e == ErrorKind::Strict));
+        assert_eq!(parser.warnings()[0].kind, WarningKind::CharCommand);
+
+        let mut parser = Parser::with_config("x = ∞ + ∂ - ∑", &store, config(Strictness::Error));
+        assert!(parser.by_ref().all(|event| event.is_ok()));
+        assert!(parser.warnings().is_empty());
+    }
+
     #[test]
     fn expansions_in_groups() {
         let store = Storage::new();
@@ -773,6 +1400,45 @@ mod tests {
         assert!(parser.by_ref().collect::<Result<Vec<_>, _>>().is_err());
         assert!(parser.span_stack.expansions.is_empty());
     }
+
+    #[test]
+    fn display_environment_lines() {
+        let store = Storage::new();
+        for input in [
+            r"\begin{darray}{c} \hline a \end{darray}",
+            r"\begin{dcases} \hline a & b \end{dcases}",
+        ] {
+            let events = Parser::new(input, &store)
+                .collect::<Result<Vec<_>, ParserError>>()
+                .unwrap();
+            assert!(
+                matches!(
+                    events[1..3],
+                    [
+                        Event::EnvironmentFlow(EnvironmentFlow::StartLines { .. }),
+                        Event::StateChange(StateChange::Style(Style::Display)),
+                    ]
+                ),
+                "input: {input}"
+            );
+            let mut output = String::new();
+            crate::push_mathml(
+                &mut output,
+                events.into_iter().map(Ok::<_, ParserError>),
+                Default::default(),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn single_char_text_argument() {
+        let store = Storage::new();
+        let parser = Parser::new(r"\text ab \operatorname xy", &store);
+        let events = parser.collect::<Result<Vec<_>, ParserError>>().unwrap();
+        assert_eq!(events[0], Event::Content(Content::Text("a")));
+        assert_eq!(events.len(), 4);
+    }
 }
 
 // Token parsing procedure, as per TeXbook p. 46-47.
@@ -0,0 +1,120 @@
+//! A [`Document`] keeps macro definitions alive across multiple equations.
+
+use super::{
+    macros::{MacroContext, MacroTable, TableRef},
+    storage::Storage,
+    Parser,
+};
+use crate::config::ParserConfig;
+
+/// A sequence of equations that share the same macro definitions, like the equations of a paper.
+///
+/// Each call to [`Document::parser`] hands out a [`Parser`] for the next equation. Macros defined
+/// by an equation are visible to every equation parsed after it, and redefinitions follow the
+/// usual rules of `\newcommand`, `\renewcommand` and `\providecommand`.
+///
+/// The document owns the [`Storage`] used by its parsers, which is reset every time a new parser
+/// is created.
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{Document, RenderConfig, push_mathml};
+///
+/// let mut document = Document::new();
+/// let mut output = String::new();
+///
+/// let parser = document.parser(r"\newcommand{\R}{\mathbb{R}} f: \R \to \R");
+/// push_mathml(&mut output, parser, RenderConfig::default()).unwrap();
+///
+/// // `\R` is still defined in the next equation.
+/// let parser = document.parser(r"x \in \R");
+/// assert!(parser.collect::<Result<Vec<_>, _>>().is_ok());
+/// ```
+#[derive(Debug, Default)]
+pub struct Document {
+    macros: MacroTable,
+    storage: Storage,
+}
+
+impl Document {
+    /// Create a new document with no macros defined.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new document whose equations start with the macros of the given table.
+    pub fn with_macros(macros: MacroTable) -> Self {
+        Self {
+            macros,
+            storage: Storage::new(),
+        }
+    }
+
+    /// Create a parser for the next equation of the document.
+    ///
+    /// Definitions made while parsing the equation are kept by the document, even if the parser
+    /// is not driven to completion.
+    pub fn parser<'a>(&'a mut self, input: &'a str) -> Parser<'a> {
+        self.parser_with_config(input, ParserConfig::default())
+    }
+
+    /// Create a parser for the next equation of the document, using the given configuration.
+    ///
+    /// The [`macros`](ParserConfig::macros) field of the configuration is ignored, since the
+    /// parser always uses the macros of the document.
+    pub fn parser_with_config<'a>(
+        &'a mut self,
+        input: &'a str,
+        config: ParserConfig<'a>,
+    ) -> Parser<'a> {
+        self.storage.reset();
+        Parser::with_context(
+            input,
+            &self.storage,
+            MacroContext::with_table(TableRef::Exclusive(&mut self.macros)),
+            ParserConfig {
+                macros: None,
+                ..config
+            },
+        )
+    }
+
+    /// The macros defined so far in the document.
+    pub fn macros(&self) -> &MacroTable {
+        &self.macros
+    }
+
+    /// Consume the document and return the macros defined in it.
+    pub fn into_macros(self) -> MacroTable {
+        self.macros
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Document;
+
+    #[test]
+    fn definitions_persist() {
+        let mut document = Document::new();
+        let events = |document: &mut Document, input| {
+            document
+                .parser(input)
+                .collect::<Result<Vec<_>, _>>()
+                .map(|events| events.len())
+                .ok()
+        };
+
+        assert_eq!(
+            events(&mut document, r"\newcommand{\R}{\mathbb{R}} \R"),
+            Some(4)
+        );
+        assert_eq!(events(&mut document, r"\def\sq#1{#1^2} \sq\R"), Some(6));
+        assert!(events(&mut document, r"\newcommand{\R}{R}").is_none());
+        assert_eq!(events(&mut document, r"\providecommand{\R}{R} \R"), Some(4));
+        assert_eq!(events(&mut document, r"\renewcommand{\R}{R} \R"), Some(1));
+        assert_eq!(events(&mut document, r"\sq\R"), Some(3));
+        assert_eq!(document.macros().len(), 2);
+    }
+}
@@ -1,7 +1,7 @@
 //! Error type returned by the parser upon failure.
 //!
 //! This error type is used to provide context to an error which occurs during the parsing stage.
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, ops::Range};
 
 use super::SpanStack;
 use crate::event::GroupingKind;
@@ -19,15 +19,80 @@ pub struct ParserError {
 #[derive(Debug)]
 struct Inner {
     error: ErrorKind,
+    span: Span,
     context: Box<str>,
 }
 
+/// The location of an error or a warning in the original input given to the parser.
+///
+/// When an error occurs inside of a macro expansion, the span points to the call site of the
+/// macro in the original input.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The byte range of the input in which the error occurred.
+    pub range: Range<usize>,
+    /// The line on which the range starts, starting at 1.
+    pub line: usize,
+    /// The column at which the range starts, counted in characters and starting at 1.
+    pub column: usize,
+}
+
+impl Span {
+    pub(crate) fn new(input: &str, range: Range<usize>) -> Self {
+        let before = &input[..range.start];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            range,
+        }
+    }
+}
+
 impl ParserError {
-    pub(super) fn new(error: ErrorKind, place: *const u8, span_stack: &mut SpanStack) -> Self {
+    /// The kind of error that occurred.
+    pub fn kind(&self) -> &ErrorKind {
+        &self.inner.error
+    }
+
+    /// The location of the error in the original input.
+    ///
+    /// ### Examples
+    ///
+    /// ```rust
+    /// use pulldown_latex::{parser::error::ErrorKind, Parser, Storage};
+    ///
+    /// let storage = Storage::new();
+    /// let mut parser = Parser::new("x + y\n= \\unknown", &storage);
+    /// let error = parser.find_map(Result::err).unwrap();
+    ///
+    /// assert_eq!(error.kind(), &ErrorKind::UnknownPrimitive);
+    /// assert_eq!(error.span().range, 8..16);
+    /// assert_eq!((error.span().line, error.span().column), (2, 3));
+    /// ```
+    pub fn span(&self) -> &Span {
+        &self.inner.span
+    }
+
+    pub(super) fn new(
+        error: ErrorKind,
+        place: *const u8,
+        range: Range<usize>,
+        span_stack: &mut SpanStack,
+    ) -> Self {
         const CONTEXT_SIZE: usize = 12;
         const CONTEXT_PREFIX: &str = "╭─► context:\n";
         const EXPANSION_PREFIX: &str = "─► which was expanded from:\n";
+        // The number of expansions shown at each end of a long chain of expansions, such as the
+        // ones of a recursive macro.
+        const KEPT_EXPANSIONS: usize = 3;
 
+        if !span_stack.contains(place) {
+            // The error comes from content that is not part of the input, such as content produced
+            // by a user-defined primitive, so it is reported where that content was produced.
+            let place = span_stack.input[range.start..].as_ptr();
+            return Self::new(error, place, range, &mut span_stack.clone());
+        }
         let index = span_stack.reach_original_call_site(place);
         let mut context = String::from(CONTEXT_PREFIX);
 
@@ -42,6 +107,7 @@ impl ParserError {
             floor_char_boundary(first_string, index + CONTEXT_SIZE),
         );
 
+        let mut expansions = Vec::new();
         for (index, expansion) in span_stack.expansions.iter().rev().enumerate() {
             let next_string = (span_stack.expansions.len() - 1)
                 .checked_sub(index + 1)
@@ -57,8 +123,10 @@ impl ParserError {
             }
 
             let context_str = &expansion.full_expansion[lower_bound..upper_bound];
-            write_context_str(context_str, &mut context, false, lower_bound > 0);
-            context.push_str(EXPANSION_PREFIX);
+            let mut frame = String::new();
+            write_context_str(context_str, &mut frame, false, lower_bound > 0);
+            frame.push_str(EXPANSION_PREFIX);
+            expansions.push(frame);
 
             lower_bound = floor_char_boundary(
                 next_string,
@@ -72,6 +140,17 @@ impl ParserError {
                 expansion.call_site_in_origin.end + CONTEXT_SIZE,
             );
         }
+        // Replacing a single expansion by a note would not make the message any shorter.
+        if expansions.len() > 2 * KEPT_EXPANSIONS + 1 {
+            let skipped = expansions.len() - 2 * KEPT_EXPANSIONS;
+            expansions.splice(
+                KEPT_EXPANSIONS..KEPT_EXPANSIONS + skipped,
+                [format!(
+                    "│\n│ … and {skipped} more expansions\n├{EXPANSION_PREFIX}"
+                )],
+            );
+        }
+        expansions.iter().for_each(|frame| context.push_str(frame));
         write_context_str(
             &span_stack.input[lower_bound..upper_bound],
             &mut context,
@@ -83,6 +162,7 @@ impl ParserError {
         Self {
             inner: Box::new(Inner {
                 error,
+                span: Span::new(span_stack.input, range),
                 context: context.into_boxed_str(),
             }),
         }
@@ -136,47 +216,154 @@ impl Display for ParserError {
     }
 }
 
+/// The message of a [`ParserError`], for renderers which only need to display the error while the
+/// error itself is kept elsewhere.
+#[derive(Debug)]
+pub(crate) struct ErrorMessage(pub(crate) String);
+
+impl Display for ErrorMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ErrorMessage {}
+
 pub(crate) type InnerResult<T> = std::result::Result<T, ErrorKind>;
 
-#[derive(Debug)]
-pub(crate) enum ErrorKind {
+/// The kind of error that occurred while parsing.
+///
+/// This enum is non-exhaustive, more kinds of errors may be added in the future.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A group is not closed, or a group is closed without being opened.
+    ///
+    /// Contains the kind of group that was expected to be closed, if any.
     UnbalancedGroup(Option<GroupingKind>),
+    /// An unknown environment was given to `\begin`.
     Environment,
+    /// A `$` was found inside of math mode.
     MathShift,
+    /// A `#` was found outside of a macro definition.
     HashSign,
+    /// A command expected a dimension or glue argument.
     DimensionArgument,
+    /// A dimension is missing its unit, or the unit is unknown.
     DimensionUnit,
+    /// A dimension was expected to be in math units (`mu`).
     MathUnit,
+    /// A command expected a delimiter.
     Delimiter,
+    /// A control sequence was expected.
     ControlSequence,
+    /// A number was expected.
     Number,
+    /// A non-ASCII character was used after '`' to specify a number.
     CharacterNumber,
+    /// A command expected an argument.
     Argument,
+    /// A command expected an argument delimited by braces.
     GroupArgument,
+    /// An element has more than one subscript.
     DoubleSubscript,
+    /// An element has more than one superscript.
     DoubleSuperscript,
+    /// An unknown control sequence was found.
     UnknownPrimitive,
+    /// A control sequence was given as an argument to a command that does not accept them.
     ControlSequenceAsArgument,
+    /// A subscript or a superscript was given as an argument to a command.
     ScriptAsArgument,
+    /// A `\` was found without a control sequence name following it.
     EmptyControlSequence,
+    /// A color is neither predefined nor in the form `#RRGGBB`.
     UnknownColor,
+    /// The number given to `\char` is not in the range `0..=255`.
     InvalidCharNumber,
+    /// The `\relax` command was used where it is not allowed.
     Relax,
+    /// The parameter text of a macro definition contains braces.
     BracesInParamText,
+    /// The parameter text of a macro definition contains a comment.
     CommentInParamText,
+    /// The parameters of a macro definition are not numbered in order.
+    ///
+    /// Contains the parameter number found, and the one expected.
     IncorrectMacroParams(u8, u8),
+    /// The replacement text of a macro definition refers to a parameter that does not exist.
+    ///
+    /// Contains the parameter number found, and the number of parameters of the macro.
     IncorrectReplacementParams(u8, u8),
+    /// A macro definition has more than 9 parameters.
     TooManyParams,
+    /// A macro definition contains a `#` that is not followed by a parameter number.
     StandaloneHashSign,
+    /// A macro was used without the prefix specified in its definition.
     IncorrectMacroPrefix,
+    /// A macro was used without the suffix specified in its definition.
     MacroSuffixNotFound,
+    /// A macro defined with `\newcommand` already exists.
     MacroAlreadyDefined,
+    /// A macro redefined with `\renewcommand` does not exist.
     MacroNotDefined,
+    /// An alignment (`&`) was found where it is not allowed.
     Alignment,
+    /// A new line (`\\`) was found where it is not allowed.
     NewLine,
+    /// An array was specified without any column.
     ArrayNoColumns,
+    /// A macro definition is missing its replacement text.
     MissingExpansion,
+    /// A token was expected, but the end of the input was reached.
     Token,
+    /// More macros were expanded than allowed by [`Limits::max_expansions`].
+    ///
+    /// [`Limits::max_expansions`]: crate::config::Limits::max_expansions
+    TooManyExpansions,
+    /// Macro expansions were nested deeper than allowed by [`Limits::max_expansion_depth`].
+    ///
+    /// [`Limits::max_expansion_depth`]: crate::config::Limits::max_expansion_depth
+    ExpansionTooDeep,
+    /// Macro expansions produced more bytes than allowed by [`Limits::max_expanded_bytes`].
+    ///
+    /// [`Limits::max_expanded_bytes`]: crate::config::Limits::max_expanded_bytes
+    ExpansionTooLarge,
+    /// Groups were nested deeper than allowed by [`Limits::max_group_depth`].
+    ///
+    /// [`Limits::max_group_depth`]: crate::config::Limits::max_group_depth
+    GroupTooDeep,
+    /// The input is longer than allowed by [`Limits::max_input_length`].
+    ///
+    /// [`Limits::max_input_length`]: crate::config::Limits::max_input_length
+    InputTooLong,
+    /// A warning was found while [`ParserConfig::strict`] is set to [`Strictness::Error`].
+    ///
+    /// The warning itself can be retrieved with [`Parser::warnings`].
+    ///
+    /// [`ParserConfig::strict`]: crate::config::ParserConfig::strict
+    /// [`Strictness::Error`]: crate::config::Strictness::Error
+    /// [`Parser::warnings`]: crate::Parser::warnings
+    Strict,
+}
+
+impl ErrorKind {
+    /// Whether the error was caused by exceeding one of the parser's [`Limits`].
+    ///
+    /// The parser stops after reporting such an error.
+    ///
+    /// [`Limits`]: crate::config::Limits
+    pub fn is_resource_limit(&self) -> bool {
+        matches!(
+            self,
+            ErrorKind::TooManyExpansions
+                | ErrorKind::ExpansionTooDeep
+                | ErrorKind::ExpansionTooLarge
+                | ErrorKind::GroupTooDeep
+                | ErrorKind::InputTooLong
+        )
+    }
 }
 
 impl Display for ErrorKind {
@@ -229,6 +416,12 @@ impl Display for ErrorKind {
             ErrorKind::ArrayNoColumns => f.write_str("array must have at least one column of the type `c`, `l` or `r`"),
             ErrorKind::MissingExpansion => f.write_str("The macro definition is missing an expansion"),
             ErrorKind::Token => f.write_str("expected a token"),
+            ErrorKind::TooManyExpansions => f.write_str("too many macro expansions, the macro may be recursive"),
+            ErrorKind::ExpansionTooDeep => f.write_str("macro expansions are nested too deeply"),
+            ErrorKind::ExpansionTooLarge => f.write_str("macro expansions produced too much content"),
+            ErrorKind::GroupTooDeep => f.write_str("groups are nested too deeply"),
+            ErrorKind::InputTooLong => f.write_str("input is too long"),
+            ErrorKind::Strict => f.write_str("dubious input is not allowed in strict mode"),
         }
     }
 }
@@ -0,0 +1,340 @@
+//! Primitives implemented in Rust by users of the crate.
+//!
+//! Some commands cannot be written as macros, because they need a lookup table or data from the
+//! application. Such commands can be implemented with the [`Primitive`] trait, and made available
+//! to the parser through a [`PrimitiveSet`] given in the [`ParserConfig`]. Control sequences
+//! which are only known at runtime can instead be handled by an
+//! [`UnknownCommandPolicy::Callback`], which receives an [`UnknownCommand`].
+//!
+//! [`ParserConfig`]: crate::config::ParserConfig
+//! [`UnknownCommandPolicy::Callback`]: crate::config::UnknownCommandPolicy::Callback
+
+use std::collections::HashMap;
+
+use crate::event::{Dimension, Event, Glue, Grouping};
+
+use super::{lex, Argument, ErrorKind, Instruction};
+
+/// A control sequence whose behavior is implemented in Rust.
+///
+/// A primitive reads its arguments from the input through the [`PrimitiveContext`], and pushes
+/// the events it produces to it. Like built-in primitives, it should produce a single element,
+/// so that scripts following the control sequence apply to the whole of it. Several elements
+/// can be pushed as a group using [`PrimitiveContext::push_group`], or by surrounding them with
+/// [`Event::Begin`] and [`Event::End`].
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{
+///     config::ParserConfig,
+///     event::{Content, Event},
+///     parser::{
+///         error::ErrorKind,
+///         extension::{Primitive, PrimitiveContext, PrimitiveSet},
+///     },
+///     Parser, Storage,
+/// };
+///
+/// /// `\unit{name}` renders the symbol of a unit.
+/// struct Unit;
+///
+/// impl Primitive for Unit {
+///     fn parse<'a>(&self, context: &mut PrimitiveContext<'_, 'a>) -> Result<(), ErrorKind> {
+///         let symbol = match context.argument()? {
+///             "meter" => "m",
+///             "second" => "s",
+///             _ => return Err(ErrorKind::Argument),
+///         };
+///         context.push_event(Event::Content(Content::Text(symbol)));
+///         Ok(())
+///     }
+/// }
+///
+/// let mut primitives = PrimitiveSet::new();
+/// primitives.insert("unit", Unit);
+/// let config = ParserConfig {
+///     primitives: Some(&primitives),
+///     ..ParserConfig::default()
+/// };
+///
+/// let storage = Storage::new();
+/// let events = Parser::with_config(r"\unit{meter}", &storage, config)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(events, [Event::Content(Content::Text("m"))]);
+/// ```
+pub trait Primitive: Send + Sync {
+    /// Parse the arguments of the control sequence, and push the resulting events.
+    ///
+    /// Returning an error makes the parser report it, as it does for built-in primitives.
+    fn parse<'a>(&self, context: &mut PrimitiveContext<'_, 'a>) -> Result<(), ErrorKind>;
+}
+
+/// The state of the parser given to a [`Primitive`].
+///
+/// The input of the context starts right after the control sequence being handled.
+pub struct PrimitiveContext<'c, 'a> {
+    name: &'a str,
+    content: &'c mut &'a str,
+    buffer: &'c mut Vec<Instruction<'a>>,
+    storage: &'a bumpalo::Bump,
+}
+
+impl<'c, 'a> PrimitiveContext<'c, 'a> {
+    pub(super) fn new(
+        name: &'a str,
+        content: &'c mut &'a str,
+        buffer: &'c mut Vec<Instruction<'a>>,
+        storage: &'a bumpalo::Bump,
+    ) -> Self {
+        Self {
+            name,
+            content,
+            buffer,
+            storage,
+        }
+    }
+
+    /// The name of the control sequence being handled, without the leading `\`.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The input that has not been parsed yet.
+    pub fn rest(&self) -> &'a str {
+        self.content
+    }
+
+    /// Parse a mandatory argument, and return its source.
+    ///
+    /// The argument is either a group delimited by braces, in which case its content is returned,
+    /// or a single token.
+    pub fn argument(&mut self) -> Result<&'a str, ErrorKind> {
+        let before = self.content.trim_start();
+        match lex::argument(self.content)? {
+            Argument::Group(content) => Ok(content),
+            Argument::Token(_) => Ok(before[..before.len() - self.content.len()].trim_end()),
+        }
+    }
+
+    /// Parse an optional argument delimited by brackets, and return its content if present.
+    pub fn optional_argument(&mut self) -> Option<&'a str> {
+        lex::optional_argument(self.content)
+    }
+
+    /// Parse a control sequence, and return its name without the leading `\`.
+    pub fn control_sequence(&mut self) -> Result<&'a str, ErrorKind> {
+        lex::control_sequence(self.content)
+    }
+
+    /// Parse a dimension, such as `1.5em`.
+    pub fn dimension(&mut self) -> Result<Dimension, ErrorKind> {
+        lex::dimension(self.content)
+    }
+
+    /// Parse a glue, such as `1em plus 2pt minus 1pt`.
+    pub fn glue(&mut self) -> Result<Glue, ErrorKind> {
+        lex::glue(self.content)
+    }
+
+    /// Parse an integer, in any of the notations supported by TeX.
+    pub fn integer(&mut self) -> Result<isize, ErrorKind> {
+        lex::integer(self.content)
+    }
+
+    /// Push an event to the output.
+    pub fn push_event(&mut self, event: Event<'a>) {
+        self.buffer.push(Instruction::Event(event));
+    }
+
+    /// Push a group whose content is parsed as LaTeX.
+    ///
+    /// The content is parsed after the primitive returns, and can be a slice of the input, such
+    /// as an argument, or a string allocated with [`PrimitiveContext::alloc_str`].
+    pub fn push_group(&mut self, content: &'a str) {
+        self.buffer.extend([
+            Instruction::Event(Event::Begin(Grouping::Normal)),
+            Instruction::SubGroup {
+                content,
+                allowed_alignment_count: None,
+            },
+            Instruction::Event(Event::End),
+        ]);
+    }
+
+    /// Copy the string into the storage of the parser, so that it lives as long as the events.
+    pub fn alloc_str(&self, str: &str) -> &'a str {
+        self.storage.alloc_str(str)
+    }
+}
+
+/// The unknown control sequence given to an [`UnknownCommandPolicy::Callback`].
+///
+/// [`UnknownCommandPolicy::Callback`]: crate::config::UnknownCommandPolicy::Callback
+pub struct UnknownCommand<'a> {
+    name: &'a str,
+    storage: &'a bumpalo::Bump,
+}
+
+impl<'a> UnknownCommand<'a> {
+    pub(super) fn new(name: &'a str, storage: &'a bumpalo::Bump) -> Self {
+        Self { name, storage }
+    }
+
+    /// The name of the control sequence, without the leading `\`.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Copy the string into the storage of the parser, so that it lives as long as the events.
+    pub fn alloc_str(&self, str: &str) -> &'a str {
+        self.storage.alloc_str(str)
+    }
+}
+
+/// A set of [`Primitive`]s, indexed by the name of their control sequence.
+#[derive(Default)]
+pub struct PrimitiveSet {
+    primitives: HashMap<Box<str>, Box<dyn Primitive>>,
+}
+
+impl PrimitiveSet {
+    /// Create an empty set of primitives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the primitive for the control sequence with the given name, given without the
+    /// leading `\`.
+    ///
+    /// Primitives registered here take precedence over built-in primitives, but not over macros.
+    /// Returns the primitive previously registered under the name, if any.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        primitive: impl Primitive + 'static,
+    ) -> Option<Box<dyn Primitive>> {
+        self.primitives.insert(name.into(), Box::new(primitive))
+    }
+
+    /// Remove the primitive registered under the given name.
+    pub fn remove(&mut self, name: &str) -> Option<Box<dyn Primitive>> {
+        self.primitives.remove(name)
+    }
+
+    /// Whether a primitive is registered under the given name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.primitives.contains_key(name)
+    }
+
+    /// Return the primitive registered under the given name.
+    pub fn get(&self, name: &str) -> Option<&dyn Primitive> {
+        self.primitives.get(name).map(Box::as_ref)
+    }
+
+    /// The number of primitives in the set.
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+}
+
+impl std::fmt::Debug for PrimitiveSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.primitives.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        config::ParserConfig,
+        event::{Content, ScriptPosition, ScriptType},
+        Parser, Storage,
+    };
+
+    struct Ref(HashMap<&'static str, usize>);
+
+    impl Primitive for Ref {
+        fn parse<'a>(&self, context: &mut PrimitiveContext<'_, 'a>) -> Result<(), ErrorKind> {
+            let label = context.argument()?;
+            let number = self.0.get(label).ok_or(ErrorKind::Argument)?;
+            let content = context.alloc_str(&format!(r"\text{{({number})}}"));
+            context.push_group(content);
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl Primitive for Broken {
+        fn parse<'a>(&self, context: &mut PrimitiveContext<'_, 'a>) -> Result<(), ErrorKind> {
+            let content = context.alloc_str(r"x + \oops");
+            context.push_group(content);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn user_primitives() {
+        let mut primitives = PrimitiveSet::new();
+        primitives.insert("ref", Ref(HashMap::from([("euler", 3)])));
+        primitives.insert("sqrt", Ref(HashMap::new()));
+        let config = ParserConfig {
+            primitives: Some(&primitives),
+            ..ParserConfig::default()
+        };
+        let storage = Storage::new();
+        let events = Parser::with_config(r"\ref{euler}^2", &storage, config)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            events,
+            [
+                Event::Script {
+                    ty: ScriptType::Superscript,
+                    position: ScriptPosition::Right
+                },
+                Event::Begin(Grouping::Normal),
+                Event::Content(Content::Text("(3)")),
+                Event::End,
+                Event::Content(Content::Number("2")),
+            ]
+        );
+
+        let mut parser = Parser::with_config(r"\ref{pythagoras}", &storage, config);
+        assert_eq!(
+            parser.next().unwrap().unwrap_err().kind(),
+            &ErrorKind::Argument
+        );
+        let mut parser = Parser::with_config(r"\sqrt{x}", &storage, config);
+        assert!(parser.next().unwrap().is_err());
+        let mut parser = Parser::with_config(r"\def\sqrt{x}\sqrt", &storage, config);
+        assert!(parser.next().unwrap().is_ok());
+
+        primitives.insert("broken", Broken);
+        let config = ParserConfig {
+            primitives: Some(&primitives),
+            ..ParserConfig::default()
+        };
+        let input = r"\def\m{a \broken b}\m c";
+        let results = Parser::with_config(input, &storage, config)
+            .into_offset_iter()
+            .collect::<Vec<_>>();
+        let (error, span) = &results[4];
+        assert_eq!(
+            error.as_ref().unwrap_err().kind(),
+            &ErrorKind::UnknownPrimitive
+        );
+        assert_eq!(&input[span.clone()], r"\m");
+        assert!(matches!(results.last(), Some((Ok(Event::Content(_)), _))));
+    }
+}
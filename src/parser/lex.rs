@@ -14,7 +14,7 @@ pub fn definition<'a>(input: &mut &'a str) -> InnerResult<(&'a str, &'a str, &'a
     let control_sequence = control_sequence(input)?;
     let (parameter_text, rest) = input.split_once('{').ok_or(ErrorKind::MissingExpansion)?;
 
-    if let Some(idx) = parameter_text.find(|c: char| c == '%' || c == '}') {
+    if let Some(idx) = parameter_text.find(['%', '}']) {
         return Err(if parameter_text.as_bytes()[idx] == b'%' {
             ErrorKind::CommentInParamText
         } else {
@@ -213,11 +213,11 @@ pub fn rhs_control_sequence<'a>(input: &mut &'a str) -> InnerResult<&'a str> {
         return Err(ErrorKind::EmptyControlSequence);
     }
 
-    let len = input
-        .chars()
-        .take_while(|c| c.is_ascii_alphabetic())
-        .count()
-        .max(1);
+    let len = match input.find(|c: char| !c.is_ascii_alphabetic()) {
+        Some(0) => input.chars().next().map_or(0, char::len_utf8),
+        Some(len) => len,
+        None => input.len(),
+    };
 
     let (control_sequence, rest) = input.split_at(len);
     *input = rest.trim_start();
@@ -289,7 +289,7 @@ pub fn unsigned_integer(input: &mut &str) -> InnerResult<usize> {
     if next_char.is_ascii_digit() {
         return Ok(decimal(input));
     }
-    *input = &input[1..];
+    *input = &input[next_char.len_utf8()..];
     match next_char {
         '`' => {
             let mut next_byte = *input.as_bytes().first().ok_or(ErrorKind::Number)?;
@@ -409,38 +409,93 @@ pub fn octal(input: &mut &str) -> usize {
 
 /// Parse an optional space.
 pub fn one_optional_space(input: &mut &str) -> bool {
-    let mut chars = input.chars();
-    if chars.next().is_some_and(|c| c.is_whitespace()) {
-        *input = &input[1..];
-        true
-    } else {
-        false
+    match input.chars().next() {
+        Some(c) if c.is_whitespace() => {
+            *input = &input[c.len_utf8()..];
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Skip the input up to the next point where parsing can resume after an error, and return the
+/// skipped prefix.
+///
+/// This is the end of the input, or, if `alignments` is true, the next `&` or `\\` that is not
+/// nested inside of a group or an environment. At least one token is always skipped.
+pub fn recovery_point<'a>(input: &mut &'a str, alignments: bool) -> &'a str {
+    let bytes = input.as_bytes();
+    let mut depth = 0usize;
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'&' if alignments && depth == 0 && index > 0 => break,
+            b'\\'
+                if alignments
+                    && depth == 0
+                    && index > 0
+                    && bytes.get(index + 1) == Some(&b'\\') =>
+            {
+                break
+            }
+            b'\\' => {
+                let rest = &input[index + 1..];
+                let name_len = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+                    Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+                    Some(len) => len,
+                    None => rest.len(),
+                };
+                match &rest[..name_len] {
+                    "begin" => depth += 1,
+                    "end" => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                index += name_len + 1;
+                continue;
+            }
+            b'%' => {
+                index += bytes[index..]
+                    .iter()
+                    .position(|&c| c == b'\n')
+                    .unwrap_or(bytes.len() - index);
+                continue;
+            }
+            b'{' => depth += 1,
+            b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        index += 1;
     }
+    let (skipped, rest) = input.split_at(index);
+    *input = rest;
+    skipped
 }
 
 /// Return the next token in the input.
 ///
 /// A token will never be whitespace, and will never be inside of a comment.
 pub fn token<'a>(input: &mut &'a str) -> InnerResult<Token<'a>> {
-    *input = input.trim_start();
-    match input.chars().next() {
-        Some('\\') => {
-            *input = &input[1..];
-            Ok(Token::ControlSequence(rhs_control_sequence(input)?))
-        }
-        Some('%') => {
-            let (_, rest) = input
-                .split_once('\n')
-                .unwrap_or(("", &input[input.len()..]));
-            *input = rest;
-            token(input)
-        }
-        Some(c) => {
-            let context = *input;
-            *input = input.split_at(c.len_utf8()).1;
-            Ok(Token::Character(CharToken::from_str(context)))
-        }
-        None => Err(ErrorKind::Token),
+    loop {
+        *input = input.trim_start();
+        return match input.chars().next() {
+            Some('\\') => {
+                *input = &input[1..];
+                Ok(Token::ControlSequence(rhs_control_sequence(input)?))
+            }
+            Some('%') => {
+                let (_, rest) = input
+                    .split_once('\n')
+                    .unwrap_or(("", &input[input.len()..]));
+                *input = rest;
+                continue;
+            }
+            Some(c) => {
+                let context = *input;
+                *input = input.split_at(c.len_utf8()).1;
+                Ok(Token::Character(CharToken::from_str(context)))
+            }
+            None => Err(ErrorKind::Token),
+        };
     }
 }
 
@@ -551,6 +606,25 @@ mod tests {
         assert_eq!(input, "");
     }
 
+    #[test]
+    fn non_ascii_input() {
+        let mut input = "é<script>";
+        assert_eq!(lex::rhs_control_sequence(&mut input).unwrap(), "é");
+        assert_eq!(input, "<script>");
+
+        let mut input = "é12";
+        assert!(lex::unsigned_integer(&mut input).is_err());
+
+        let mut input = "12\u{3000}x";
+        assert_eq!(lex::unsigned_integer(&mut input).unwrap(), 12);
+        assert_eq!(input, "x");
+
+        let mut input = "1.2pt\u{a0}x";
+        let dim = lex::dimension(&mut input).unwrap();
+        assert_eq!(dim, Dimension::new(1.2, DimensionUnit::Pt));
+        assert_eq!(input, "x");
+    }
+
     #[test]
     fn complex_glue() {
         let mut input = "1.2 pt plus 3.4pt minus 5.6pt nope";
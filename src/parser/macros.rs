@@ -1,18 +1,57 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use crate::parser::{ErrorKind, InnerResult, Token};
+use crate::{
+    config::ParserConfig,
+    parser::{ErrorKind, InnerResult, ParserError, Token},
+};
 
-use super::{lex, Argument};
+use super::{lex, storage::Storage, Argument, CharToken, Parser};
 
 #[derive(Debug)]
 pub struct MacroContext<'input> {
     definitions: HashMap<&'input str, Definition<'input>>,
+    /// Definitions provided by the user before parsing.
+    ///
+    /// They are loaded lazily into `definitions` the first time they are used.
+    table: Option<TableRef<'input>>,
+}
+
+#[derive(Debug)]
+pub(crate) enum TableRef<'a> {
+    /// The table is only read from.
+    Shared(&'a MacroTable),
+    /// New definitions are also recorded in the table.
+    Exclusive(&'a mut MacroTable),
 }
 
 impl<'input> MacroContext<'input> {
     pub fn new() -> Self {
         Self {
             definitions: HashMap::new(),
+            table: None,
+        }
+    }
+
+    pub(crate) fn with_table(table: TableRef<'input>) -> Self {
+        Self {
+            definitions: HashMap::new(),
+            table: Some(table),
+        }
+    }
+
+    fn table(&self) -> Option<&MacroTable> {
+        match self.table.as_ref()? {
+            TableRef::Shared(table) => Some(table),
+            TableRef::Exclusive(table) => Some(table),
+        }
+    }
+
+    fn record(&mut self, name: &str, entry: impl FnOnce() -> TableEntry) {
+        if let Some(TableRef::Exclusive(table)) = &mut self.table {
+            table.insert(name, entry());
         }
     }
 
@@ -28,71 +67,31 @@ impl<'input> MacroContext<'input> {
     pub(crate) fn define(
         &mut self,
         name: &'input str,
-        mut parameter_text: &'input str,
+        parameter_text: &'input str,
         replacement_text: &'input str,
     ) -> InnerResult<()> {
-        // Check for the '#{' rule of the last parameter (TeXBook p. 204).
-        let last_param_brace_delimited = parameter_text.ends_with('#');
-        if last_param_brace_delimited {
-            // We know the parameter text is at least 1 character long, and the character in
-            // question is ASCII so we are fine slicing.
-            parameter_text = &parameter_text[..parameter_text.len() - 1];
-        };
-
-        let mut parameters = parameter_text.split('#').enumerate();
-
-        let prefix = parameters
-            .next()
-            .expect("split always yields at least one element")
-            .1;
-        let prefix = if prefix.is_empty() {
-            None
-        } else {
-            Some(prefix)
-        };
-
-        // Parse the arguments, making sure that they are in order and that the number of arguments
-        // is less than 10.
-        let parameters: Vec<_> = parameters
-            .map(|(i, arg)| -> InnerResult<Parameter> {
-                let mut chars = arg.chars();
-                let param_index = chars
-                    .next()
-                    .and_then(|c| c.is_ascii_digit().then_some(c as u8 - b'0'))
-                    .ok_or(ErrorKind::StandaloneHashSign)?;
-                if param_index != i as u8 {
-                    return Err(ErrorKind::IncorrectMacroParams(param_index, i as u8));
-                };
-                let suffix = chars.as_str();
-                Ok(if suffix.is_empty() {
-                    None
-                } else {
-                    Some(suffix)
-                })
-            })
-            .collect::<InnerResult<Vec<_>>>()?;
-
-        let replacement = parse_replacement_text(replacement_text, parameters.len() as u8)?;
-
-        self.definitions.insert(
-            name,
-            Definition::Macro(MacroDef {
-                prefix,
-                last_param_brace_delimited,
-                parameters,
-                replacement,
-            }),
-        );
+        let definition = parse_macro(parameter_text, replacement_text)?;
+        self.definitions.insert(name, Definition::Macro(definition));
+        self.record(name, || TableEntry::Macro {
+            parameter_text: parameter_text.into(),
+            replacement_text: replacement_text.into(),
+        });
         Ok(())
     }
 
     pub(crate) fn contains(&self, name: &str) -> bool {
-        self.definitions.contains_key(name)
+        self.definitions.contains_key(name) || self.table().is_some_and(|t| t.contains(name))
     }
 
     /// Assign a new control sequence to a token.
     pub(crate) fn assign(&mut self, name: &'input str, alias_for: Token<'input>) {
         self.definitions.insert(name, Definition::Alias(alias_for));
+        self.record(name, || {
+            TableEntry::Alias(match alias_for {
+                Token::ControlSequence(cs) => AliasTarget::ControlSequence(cs.into()),
+                Token::Character(c) => AliasTarget::Character(c.into()),
+            })
+        });
     }
 
     /// The argument count must be less than 9 if the optional argument is None, and less than 8 if
@@ -104,15 +103,20 @@ impl<'input> MacroContext<'input> {
         first_arg_default: Option<&'input str>,
         replacement: &'input str,
     ) -> InnerResult<()> {
-        let replacement = parse_replacement_text(replacement, argument_count)?;
+        let replacement_tokens = parse_replacement_text(replacement, argument_count)?;
         self.definitions.insert(
             name,
             Definition::Command(CommandDef {
                 argument_count,
                 first_arg_default,
-                replacement,
+                replacement: replacement_tokens,
             }),
         );
+        self.record(name, || TableEntry::Command {
+            argument_count,
+            first_arg_default: first_arg_default.map(Into::into),
+            replacement: replacement.into(),
+        });
         Ok(())
     }
 
@@ -122,11 +126,18 @@ impl<'input> MacroContext<'input> {
     /// Along with the expanded string, the function returns the number of characters consumed
     /// from the input.
     pub(crate) fn try_expand_in(
-        &self,
+        &mut self,
         name: &'input str,
         input_rest: &'input str,
         storage: &'input bumpalo::Bump,
     ) -> Option<InnerResult<(&'input str, usize)>> {
+        if !self.definitions.contains_key(name) {
+            let definition = match self.table()?.definitions.get(name)?.load(storage) {
+                Ok(definition) => definition,
+                Err(e) => return Some(Err(e)),
+            };
+            self.definitions.insert(name, definition);
+        }
         Some(self.expand_definition_in(self.definitions.get(name)?, input_rest, storage))
     }
 
@@ -218,10 +229,11 @@ impl<'input> MacroContext<'input> {
                     )));
                 }
 
-                (0..(*argument_count - first_arg_default.is_some() as u8)).try_for_each(|_| {
-                    arguments.push(Ok(lex::argument(&mut input_rest)?));
-                    Ok(())
-                })?;
+                (0..argument_count.saturating_sub(first_arg_default.is_some() as u8))
+                    .try_for_each(|_| {
+                        arguments.push(Ok(lex::argument(&mut input_rest)?));
+                        Ok(())
+                    })?;
 
                 (
                     expand_replacement(storage, replacement, &arguments, input_rest),
@@ -232,10 +244,66 @@ impl<'input> MacroContext<'input> {
     }
 }
 
+/// Parse the parameter text and replacement text of a macro definition.
+fn parse_macro<'a>(
+    mut parameter_text: &'a str,
+    replacement_text: &'a str,
+) -> InnerResult<MacroDef<'a>> {
+    // Check for the '#{' rule of the last parameter (TeXBook p. 204).
+    let last_param_brace_delimited = parameter_text.ends_with('#');
+    if last_param_brace_delimited {
+        // We know the parameter text is at least 1 character long, and the character in
+        // question is ASCII so we are fine slicing.
+        parameter_text = &parameter_text[..parameter_text.len() - 1];
+    };
+
+    let mut parameters = parameter_text.split('#').enumerate();
+
+    let prefix = parameters
+        .next()
+        .expect("split always yields at least one element")
+        .1;
+    let prefix = if prefix.is_empty() {
+        None
+    } else {
+        Some(prefix)
+    };
+
+    // Parse the arguments, making sure that they are in order and that the number of arguments
+    // is less than 10.
+    let parameters: Vec<_> = parameters
+        .map(|(i, arg)| -> InnerResult<Parameter> {
+            let mut chars = arg.chars();
+            let param_index = chars
+                .next()
+                .and_then(|c| c.is_ascii_digit().then_some(c as u8 - b'0'))
+                .ok_or(ErrorKind::StandaloneHashSign)?;
+            if param_index != i as u8 {
+                return Err(ErrorKind::IncorrectMacroParams(param_index, i as u8));
+            };
+            let suffix = chars.as_str();
+            Ok(if suffix.is_empty() {
+                None
+            } else {
+                Some(suffix)
+            })
+        })
+        .collect::<InnerResult<Vec<_>>>()?;
+
+    let replacement = parse_replacement_text(replacement_text, parameters.len() as u8)?;
+
+    Ok(MacroDef {
+        prefix,
+        last_param_brace_delimited,
+        parameters,
+        replacement,
+    })
+}
+
 fn parse_replacement_text(
     replacement_text: &str,
     parameter_count: u8,
-) -> InnerResult<Vec<ReplacementToken>> {
+) -> InnerResult<Vec<ReplacementToken<'_>>> {
     let mut replacement_splits = replacement_text.split_inclusive('#').peekable();
     let mut replacement_tokens: Vec<ReplacementToken> = Vec::new();
 
@@ -348,6 +416,250 @@ impl<'input> Default for MacroContext<'input> {
     }
 }
 
+/// A set of macro definitions that can be shared between parsers.
+///
+/// A [`MacroTable`] is typically built once from a preamble of `\newcommand` and `\def`
+/// definitions, and then given to every [`Parser`] through [`Parser::with_macros`]. This avoids
+/// prepending the preamble to each equation: a parser only loads the definitions it expands, the
+/// first time it expands them. The table owns its definitions, and is both [`Send`] and [`Sync`],
+/// so it can be stored in a `static` or shared between threads.
+///
+/// Definitions made in the input of a parser using the table are local to that parser, and never
+/// modify the table. The usual rules of `\newcommand`, `\renewcommand` and `\providecommand`
+/// apply to the macros defined in the table.
+///
+/// ### Examples
+///
+/// ```rust
+/// use pulldown_latex::{parser::MacroTable, Parser, Storage};
+///
+/// let mut macros = MacroTable::new();
+/// macros
+///     .load(r"\newcommand{\R}{\mathbb{R}} \def\abs#1{\left|#1\right|}")
+///     .unwrap();
+/// macros.new_command("half", 0, None, r"\frac{1}{2}").unwrap();
+///
+/// let mut storage = Storage::new();
+/// for input in [r"x \in \R", r"\abs{\half x}"] {
+///     let parser = Parser::with_macros(input, &storage, &macros);
+///     assert!(parser.collect::<Result<Vec<_>, _>>().is_ok());
+///     storage.reset();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MacroTable {
+    definitions: HashMap<Box<str>, TableEntry>,
+    /// The version of the definitions, which changes every time they do.
+    version: u64,
+}
+
+/// The last version given to the definitions of a [`MacroTable`].
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+impl Default for MacroTable {
+    fn default() -> Self {
+        Self {
+            definitions: HashMap::new(),
+            version: VERSION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl MacroTable {
+    /// Create an empty macro table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the definitions found in the given `LaTeX` source to the table.
+    ///
+    /// The source is parsed like any other input, so definitions may use `\newcommand`,
+    /// `\renewcommand`, `\providecommand`, `\def`, `\let` and `\futurelet`. Anything in the
+    /// source that is not a definition is ignored.
+    ///
+    /// Returns the first error encountered while parsing the source. Definitions made before the
+    /// error are kept in the table.
+    pub fn load(&mut self, source: &str) -> Result<(), ParserError> {
+        let storage = Storage::new();
+        let parser = Parser::with_context(
+            source,
+            &storage,
+            MacroContext::with_table(TableRef::Exclusive(self)),
+            ParserConfig::default(),
+        );
+        for event in parser {
+            event?;
+        }
+        Ok(())
+    }
+
+    /// Define a macro the same way `\def` does, from its name, parameter text and replacement
+    /// text.
+    ///
+    /// The name is given without the leading `\`, and must be a valid control sequence name. If a
+    /// macro with the same name already exists, it is replaced.
+    ///
+    /// ### Examples
+    ///
+    /// ```rust
+    /// use pulldown_latex::parser::MacroTable;
+    ///
+    /// let mut macros = MacroTable::new();
+    /// // Equivalent to `\def\pair(#1,#2){\langle #1, #2 \rangle}`
+    /// macros.def("pair", "(#1,#2)", r"\langle #1, #2 \rangle").unwrap();
+    /// ```
+    pub fn def(
+        &mut self,
+        name: &str,
+        parameter_text: &str,
+        replacement_text: &str,
+    ) -> Result<(), ErrorKind> {
+        validate_name(name)?;
+        if parameter_text.contains(['{', '}']) {
+            return Err(ErrorKind::BracesInParamText);
+        }
+        parse_macro(parameter_text, replacement_text)?;
+        self.insert(
+            name,
+            TableEntry::Macro {
+                parameter_text: parameter_text.into(),
+                replacement_text: replacement_text.into(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Define a macro the same way `\newcommand` does, from its name, number of arguments,
+    /// default value for the first argument, and replacement text.
+    ///
+    /// The name is given without the leading `\`, and must be a valid control sequence name. If a
+    /// macro with the same name already exists, it is replaced.
+    pub fn new_command(
+        &mut self,
+        name: &str,
+        argument_count: u8,
+        first_arg_default: Option<&str>,
+        replacement: &str,
+    ) -> Result<(), ErrorKind> {
+        validate_name(name)?;
+        if argument_count > 9 {
+            return Err(ErrorKind::TooManyParams);
+        }
+        parse_replacement_text(replacement, argument_count)?;
+        self.insert(
+            name,
+            TableEntry::Command {
+                argument_count,
+                first_arg_default: first_arg_default.map(Into::into),
+                replacement: replacement.into(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Whether a macro with the given name, without the leading `\`, is defined.
+    pub fn contains(&self, name: &str) -> bool {
+        self.definitions.contains_key(name)
+    }
+
+    /// Remove the macro with the given name, without the leading `\`, from the table.
+    ///
+    /// Returns whether the macro was defined.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.version = VERSION.fetch_add(1, Ordering::Relaxed);
+        self.definitions.remove(name).is_some()
+    }
+
+    /// The number of macros defined in the table.
+    pub fn len(&self) -> usize {
+        self.definitions.len()
+    }
+
+    /// Whether the table contains no macros.
+    pub fn is_empty(&self) -> bool {
+        self.definitions.is_empty()
+    }
+
+    fn insert(&mut self, name: &str, entry: TableEntry) {
+        self.version = VERSION.fetch_add(1, Ordering::Relaxed);
+        self.definitions.insert(name.into(), entry);
+    }
+
+    /// The version of the definitions of the table.
+    ///
+    /// Versions are unique across all tables, and change with every definition made or removed,
+    /// so that two tables share a version only if one is an unchanged clone of the other.
+    pub(crate) fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// Check that the name, given without the leading `\`, can be written as a control sequence.
+fn validate_name(name: &str) -> InnerResult<()> {
+    let mut rest = name;
+    let control_sequence = lex::rhs_control_sequence(&mut rest)?;
+    if control_sequence.len() != name.len() || control_sequence.starts_with('\\') {
+        return Err(ErrorKind::ControlSequence);
+    }
+    Ok(())
+}
+
+/// A definition owned by a [`MacroTable`], stored in the form it was written in.
+#[derive(Debug, Clone)]
+enum TableEntry {
+    Macro {
+        parameter_text: Box<str>,
+        replacement_text: Box<str>,
+    },
+    Command {
+        argument_count: u8,
+        first_arg_default: Option<Box<str>>,
+        replacement: Box<str>,
+    },
+    Alias(AliasTarget),
+}
+
+#[derive(Debug, Clone)]
+enum AliasTarget {
+    ControlSequence(Box<str>),
+    Character(char),
+}
+
+impl TableEntry {
+    /// Copy the entry into the storage, and parse it into a definition usable by the parser.
+    fn load<'store>(&self, storage: &'store bumpalo::Bump) -> InnerResult<Definition<'store>> {
+        Ok(match self {
+            TableEntry::Macro {
+                parameter_text,
+                replacement_text,
+            } => Definition::Macro(parse_macro(
+                storage.alloc_str(parameter_text),
+                storage.alloc_str(replacement_text),
+            )?),
+            TableEntry::Command {
+                argument_count,
+                first_arg_default,
+                replacement,
+            } => Definition::Command(CommandDef {
+                argument_count: *argument_count,
+                first_arg_default: first_arg_default
+                    .as_deref()
+                    .map(|default| &*storage.alloc_str(default)),
+                replacement: parse_replacement_text(
+                    storage.alloc_str(replacement),
+                    *argument_count,
+                )?,
+            }),
+            TableEntry::Alias(AliasTarget::ControlSequence(cs)) => {
+                Definition::Alias(Token::ControlSequence(storage.alloc_str(cs)))
+            }
+            TableEntry::Alias(AliasTarget::Character(c)) => Definition::Alias(Token::Character(
+                CharToken::from_str(storage.alloc_str(c.encode_utf8(&mut [0; 4]))),
+            )),
+        })
+    }
+}
+
 #[derive(Debug)]
 struct MacroDef<'a> {
     prefix: Option<&'a str>,
@@ -379,9 +691,30 @@ enum Definition<'a> {
     Command(CommandDef<'a>),
 }
 
+/// Whether the input contains a control sequence defining macros, such as `\def` or
+/// `\newcommand`.
+pub(crate) fn defines_macros(input: &str) -> bool {
+    let mut rest = input;
+    while let Some(index) = rest.find('\\') {
+        rest = &rest[index + 1..];
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        if matches!(
+            &rest[..name_len],
+            "def" | "let" | "futurelet" | "newcommand" | "renewcommand" | "providecommand"
+        ) {
+            return true;
+        }
+        // Skip the escaped character of control symbols such as `\\`.
+        rest = &rest[name_len.max(rest.chars().next().map_or(0, char::len_utf8))..];
+    }
+    false
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{MacroContext, ReplacementToken};
+    use super::{ErrorKind, MacroContext, MacroTable, ReplacementToken};
 
     #[test]
     fn no_params() {
@@ -478,4 +811,33 @@ mod tests {
         assert!(def.last_param_brace_delimited);
         assert_eq!(def.replacement, vec![ReplacementToken::String("2 + 2 = 4")]);
     }
+
+    #[test]
+    fn table_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MacroTable>();
+    }
+
+    #[test]
+    fn table_from_source() {
+        let mut table = MacroTable::new();
+        table
+            .load(r"\newcommand{\R}{\mathbb{R}} \def\abs#1{|#1|} \let\eps=\varepsilon x + y")
+            .unwrap();
+
+        assert_eq!(table.len(), 3);
+        assert!(table.contains("R") && table.contains("abs") && table.contains("eps"));
+        assert!(table.load(r"\newcommand{\R}{R}").is_err());
+        assert!(table.def("bad", "#2", "").is_err());
+        assert_eq!(table.def("", "", ""), Err(ErrorKind::EmptyControlSequence));
+        for name in ["two words", "R ", r"\R", "R2", "\\"] {
+            assert_eq!(table.def(name, "", ""), Err(ErrorKind::ControlSequence));
+            assert_eq!(
+                table.new_command(name, 0, None, ""),
+                Err(ErrorKind::ControlSequence)
+            );
+        }
+        assert!(table.def("@", "", "").is_ok() && table.new_command("RR", 0, None, "").is_ok());
+        assert_eq!(table.len(), 5);
+    }
 }
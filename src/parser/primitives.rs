@@ -3,18 +3,24 @@
 
 use core::panic;
 
-use crate::event::{
-    ArrayColumn as AC, ColorChange as CC, ColorTarget as CT, ColumnAlignment, Content as C,
-    DelimiterSize, DelimiterType, Dimension, DimensionUnit, EnvironmentFlow, Event as E, Font,
-    Grouping as G, GroupingKind, Line, MatrixType, RelationContent, ScriptPosition as SP,
-    ScriptType as ST, StateChange as SC, Style as S, Visual as V,
+use crate::{
+    config::UnknownCommandPolicy,
+    event::{
+        ArrayColumn as AC, ColorChange as CC, ColorTarget as CT, ColumnAlignment, Content as C,
+        DelimiterSize, DelimiterType, Dimension, DimensionUnit, EnvironmentFlow, Event as E, Font,
+        Grouping as G, GroupingKind, Line, MatrixType, RelationContent, ScriptPosition as SP,
+        ScriptType as ST, StateChange as SC, Style as S, Visual as V,
+    },
+    symbols,
 };
 
 use super::{
+    extension::{PrimitiveContext, UnknownCommand},
     lex,
     tables::{
         char_delimiter_map, control_sequence_delimiter_map, is_binary, is_relation, token_to_delim,
     },
+    warning::WarningKind,
     AlignmentCount, Argument, CharToken, ErrorKind, InnerParser, InnerResult, Instruction as I,
     Token,
 };
@@ -69,6 +75,12 @@ impl<'b, 'store> InnerParser<'b, 'store> {
             '{' => {
                 let str = &mut self.content;
                 let group = lex::group_content(str, GroupingKind::Normal)?;
+                if let Some(mut inner) = group.trim().strip_prefix('{') {
+                    if lex::group_content(&mut inner, GroupingKind::Normal).is_ok_and(|_| inner.is_empty()) {
+                        let range = self.span_stack.source_range(&token.as_str()[..group.len() + 2]);
+                        self.warn(WarningKind::RedundantBraces, range)?;
+                    }
+                }
                 self.buffer.extend([
                     I::Event(E::Begin(G::Normal)),
                     I::SubGroup { content: group, allowed_alignment_count: None },
@@ -81,7 +93,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
             },
 
             '~' => {
-                E::Content(C::Text("&nbsp;"))
+                E::Content(C::Text("\u{a0}"))
             },
 
             '0'..='9' => {
@@ -120,7 +132,17 @@ impl<'b, 'store> InnerParser<'b, 'store> {
                     })
                 }
             }
-            c => ordinary(c),
+            c => {
+                if !c.is_ascii()
+                    && !c.is_alphanumeric()
+                    && symbols::ordinary(c).is_none()
+                    && symbols::large_op(c).is_none()
+                {
+                    let range = self.span_stack.source_range(&token.as_str()[..c.len_utf8()]);
+                    self.warn(WarningKind::UnknownCharacterClass(c), range)?;
+                }
+                ordinary(c)
+            }
         });
         self.buffer.push(instruction);
         Ok(())
@@ -128,6 +150,20 @@ impl<'b, 'store> InnerParser<'b, 'store> {
 
     /// Handle a supported control sequence, pushing instructions to the provided stack.
     pub(super) fn handle_primitive(&mut self, control_sequence: &'store str) -> InnerResult<()> {
+        if let Some(primitive) = self
+            .config
+            .primitives
+            .and_then(|primitives| primitives.get(control_sequence))
+        {
+            let mut context = PrimitiveContext::new(
+                control_sequence,
+                &mut self.content,
+                self.buffer,
+                self.storage,
+            );
+            return primitive.parse(&mut context);
+        }
+
         let event = match control_sequence {
             "arccos" | "cos" | "csc" | "exp" | "ker" | "sinh" | "arcsin" | "cosh" | "deg"
             | "lg" | "ln" | "arctan" | "cot" | "det" | "hom" | "log" | "sec" | "tan" | "arg"
@@ -156,7 +192,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
                         return Err(ErrorKind::ControlSequenceAsArgument)
                     }
                     Argument::Token(Token::Character(char_)) => {
-                        E::Content(C::Function(char_.as_str()))
+                        E::Content(C::Function(char_.char_str()))
                     }
                     Argument::Group(content) => E::Content(C::Function(content)),
                 }
@@ -386,12 +422,17 @@ impl<'b, 'store> InnerParser<'b, 'store> {
             // Font state changes //
             ////////////////////////
             // LaTeX native absolute font changes (old behavior a.k.a NFSS 1)
-            "bf" => self.font_change(Font::Bold),
-            "cal" => self.font_change(Font::Script),
-            "it" => self.font_change(Font::Italic),
-            "rm" => self.font_change(Font::UpRight),
-            "sf" => self.font_change(Font::SansSerif),
-            "tt" => self.font_change(Font::Monospace),
+            "bf" | "cal" | "it" | "rm" | "sf" | "tt" => {
+                self.warn_deprecated_font(control_sequence)?;
+                self.font_change(match control_sequence {
+                    "bf" => Font::Bold,
+                    "cal" => Font::Script,
+                    "it" => Font::Italic,
+                    "rm" => Font::UpRight,
+                    "sf" => Font::SansSerif,
+                    _ => Font::Monospace,
+                })
+            }
             // amsfonts font changes (old behavior a.k.a NFSS 1)
             // unicode-math font changes (old behavior a.k.a NFSS 1)
             // changes, as described in https://mirror.csclub.uwaterloo.ca/CTAN/macros/unicodetex/latex/unicode-math/unicode-math.pdf
@@ -700,7 +741,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
                 width: None,
                 height: Some(Dimension::new(0.7, DimensionUnit::Em)),
             },
-            "~" | "nobreakspace" => E::Content(C::Text("&nbsp;")),
+            "~" | "nobreakspace" => E::Content(C::Text("\u{a0}")),
             // Variable spacing
             "kern" => {
                 let dimension = lex::dimension(&mut self.content)?;
@@ -1403,6 +1444,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
                 return Ok(());
             }
             "char" => {
+                self.warn_control_sequence(WarningKind::CharCommand, control_sequence)?;
                 let number = lex::unsigned_integer(&mut self.content)?;
                 if number > 255 {
                     return Err(ErrorKind::InvalidCharNumber);
@@ -1762,15 +1804,15 @@ impl<'b, 'store> InnerParser<'b, 'store> {
                 let horizontal_lines = lex::horizontal_lines(&mut self.content);
                 let content = lex::group_content(&mut self.content, grouping_kind)?;
                 self.buffer.push(I::Event(E::Begin(environment)));
-                if let Some(style) = style {
-                    self.buffer.push(I::Event(E::StateChange(SC::Style(style))));
-                }
-                if horizontal_lines.len() > 0 {
+                if !horizontal_lines.is_empty() {
                     self.buffer
                         .push(I::Event(E::EnvironmentFlow(EnvironmentFlow::StartLines {
                             lines: horizontal_lines,
                         })));
                 }
+                if let Some(style) = style {
+                    self.buffer.push(I::Event(E::StateChange(SC::Style(style))));
+                }
                 self.buffer.extend([
                     I::SubGroup {
                         content,
@@ -1816,7 +1858,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
             }
 
             // Spacing
-            c if c.trim_start().is_empty() => E::Content(C::Text("&nbsp;")),
+            c if c.trim_start().is_empty() => E::Content(C::Text("\u{a0}")),
 
             // Macros
             "def" => {
@@ -1843,12 +1885,53 @@ impl<'b, 'store> InnerParser<'b, 'store> {
             "newcommand" => return self.new_command(Some(false)),
             "renewcommand" => return self.new_command(Some(true)),
             "providecommand" => return self.new_command(None),
-            _ => return Err(ErrorKind::UnknownPrimitive),
+            _ => return self.unknown_primitive(control_sequence),
         };
         self.buffer.push(I::Event(event));
         Ok(())
     }
 
+    fn warn_deprecated_font(&mut self, control_sequence: &'store str) -> InnerResult<()> {
+        let kind = WarningKind::DeprecatedFontCommand(control_sequence.into());
+        self.warn_control_sequence(kind, control_sequence)
+    }
+
+    /// Handle a control sequence that is not supported, following the configured policy.
+    fn unknown_primitive(&mut self, control_sequence: &'store str) -> InnerResult<()> {
+        match self.config.unknown_commands {
+            UnknownCommandPolicy::Error => return Err(ErrorKind::UnknownPrimitive),
+            UnknownCommandPolicy::Function => {
+                self.buffer
+                    .push(I::Event(E::Content(C::Function(control_sequence))));
+            }
+            UnknownCommandPolicy::RawText => {
+                let source = bumpalo::format!(in self.storage, "\\{}", control_sequence);
+                let span = self
+                    .span_stack
+                    .control_sequence_range(control_sequence)
+                    .or_else(|| self.span_stack.source_range(&self.content[..0]))
+                    .unwrap_or_default();
+                self.buffer.push(I::Event(E::Error {
+                    kind: ErrorKind::UnknownPrimitive,
+                    source: source.into_bump_str(),
+                    span,
+                }));
+            }
+            UnknownCommandPolicy::Callback(callback) => {
+                let command = UnknownCommand::new(control_sequence, self.storage);
+                let events = callback(&command).ok_or(ErrorKind::UnknownPrimitive)?;
+                if events.len() == 1 {
+                    self.buffer.extend(events.into_iter().map(I::Event));
+                } else {
+                    self.buffer.push(I::Event(E::Begin(G::Normal)));
+                    self.buffer.extend(events.into_iter().map(I::Event));
+                    self.buffer.push(I::Event(E::End));
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Return a delimiter with the given size from the next character in the parser.
     fn sized_delim(&mut self, size: DelimiterSize) -> InnerResult<()> {
         let current = &mut self.content;
@@ -1869,12 +1952,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
             I::Event(E::StateChange(SC::Font(font))),
         ]);
         match argument {
-            Argument::Token(token) => {
-                match token {
-                    Token::ControlSequence(cs) => self.handle_primitive(cs)?,
-                    Token::Character(c) => self.handle_char_token(c)?,
-                };
-            }
+            Argument::Token(token) => self.handle_token_argument(token)?,
             Argument::Group(group) => {
                 self.buffer.push(I::SubGroup {
                     content: group,
@@ -1940,7 +2018,7 @@ impl<'b, 'store> InnerParser<'b, 'store> {
         let argument = lex::argument(&mut self.content)?;
         self.buffer
             .push(I::Event(E::Content(C::Text(match argument {
-                Argument::Token(Token::Character(c)) => c.as_str(),
+                Argument::Token(Token::Character(c)) => c.char_str(),
                 Argument::Group(inner) => inner,
                 _ => return Err(ErrorKind::ControlSequenceAsArgument),
             }))));
@@ -2045,8 +2123,8 @@ impl<'b, 'store> InnerParser<'b, 'store> {
             });
         }
 
-        let arg_count = (lex::optional_argument(&mut self.content).ok_or(ErrorKind::Argument)?)
-            .parse::<u8>()
+        let arg_count = lex::optional_argument(&mut self.content)
+            .map_or(Ok(0), |count| count.trim().parse::<u8>())
             .map_err(|_| ErrorKind::Number)?;
         let first_arg_default = lex::optional_argument(&mut self.content);
         if arg_count > 9 && arg_count >= first_arg_default.is_some() as u8 {
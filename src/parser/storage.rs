@@ -5,7 +5,7 @@
 ///
 /// [`Storage`] needs to exist because Rust does not allow self-referencial types. When Rust does
 /// (hopefully) gain support for self-referencial types, this storage will be removed.
-#[derive(Default)]
+#[derive(Debug, Default)]
 pub struct Storage(pub(super) bumpalo::Bump);
 
 impl Storage {
@@ -0,0 +1,70 @@
+//! Warnings reported by the parser for input that is accepted, but dubious.
+
+use std::fmt::Display;
+
+use super::error::Span;
+
+/// Input that the parser accepts, but that may not render the same way in LaTeX.
+///
+/// Warnings are only collected when [`ParserConfig::strict`] is set to [`Strictness::Warn`] or
+/// [`Strictness::Error`], and can then be retrieved with [`Parser::warnings`].
+///
+/// [`ParserConfig::strict`]: crate::config::ParserConfig::strict
+/// [`Strictness::Warn`]: crate::config::Strictness::Warn
+/// [`Strictness::Error`]: crate::config::Strictness::Error
+/// [`Parser::warnings`]: crate::Parser::warnings
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Warning {
+    /// The kind of warning.
+    pub kind: WarningKind,
+    /// The location of the dubious input.
+    pub span: Span,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "warning at {}:{}: {}",
+            self.span.line, self.span.column, self.kind
+        )
+    }
+}
+
+/// The kind of dubious input that was found.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// The `\char` command was used, whose result depends on the font encoding in LaTeX.
+    CharCommand,
+    /// A font command from LaTeX 2.09, such as `\bf` or `\rm`, was used.
+    ///
+    /// Contains the name of the command.
+    DeprecatedFontCommand(Box<str>),
+    /// A non-ASCII character was used, which is not the symbol of any supported control sequence,
+    /// and has no known math class.
+    ///
+    /// Contains the character.
+    UnknownCharacterClass(char),
+    /// A group only contains another group, such as in `{{x}}`.
+    RedundantBraces,
+}
+
+impl Display for WarningKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WarningKind::CharCommand => f.write_str(
+                "the `\\char` command depends on the font encoding, use the unicode character instead",
+            ),
+            WarningKind::DeprecatedFontCommand(name) => write!(
+                f,
+                "the `\\{name}` command is deprecated, use `\\math{name}` instead"
+            ),
+            WarningKind::UnknownCharacterClass(c) => {
+                write!(f, "the character `{c}` has no known math class")
+            }
+            WarningKind::RedundantBraces => f.write_str("redundant braces around a group"),
+        }
+    }
+}
@@ -0,0 +1,1175 @@
+//! The traversal of a stream of events shared by renderers.
+//!
+//! Events are in prefix notation: a `Script` or a `Visual` event is followed by the elements it
+//! applies to, which may themselves be groups, scripts, and so on. Rendering them requires keeping
+//! track of how many elements remain before each of these is complete, of the [`State`] set by
+//! `StateChange` events in every group, and of the class of the surrounding atoms, which
+//! determines the spacing of operators.
+//!
+//! The [`render`] function does this work, and calls the methods of a [`Renderer`] along the way.
+//! The `mathml` renderer of this crate is built this way, and other output formats can be written
+//! without dealing with the events directly.
+//!
+//! # Example
+//!
+//! A renderer writing equations in a linear notation:
+//!
+//! ```rust
+//! use std::convert::Infallible;
+//!
+//! use pulldown_latex::{
+//!     config::DisplayMode,
+//!     event::{Content, Dimension, Grouping, Line, ScriptType, Visual},
+//!     render::{render, Context, Parent, Renderer},
+//!     Parser, Storage,
+//! };
+//!
+//! struct Linear(String);
+//!
+//! impl<'a> Renderer<'a> for Linear {
+//!     type Group = ();
+//!     type Error = Infallible;
+//!
+//!     fn content(&mut self, content: Content<'a>, _: bool, _: &mut Context) -> Result<(), Infallible> {
+//!         match content {
+//!             Content::Text(text) | Content::Number(text) | Content::Function(text) => {
+//!                 self.0.push_str(text)
+//!             }
+//!             Content::Relation { content, .. } => {
+//!                 let mut buf = [0; 8];
+//!                 let content = content.encode_utf8_to_buf(&mut buf);
+//!                 self.0.push_str(std::str::from_utf8(content).unwrap());
+//!             }
+//!             Content::Ordinary { content, .. }
+//!             | Content::BinaryOp { content, .. }
+//!             | Content::LargeOp { content, .. }
+//!             | Content::Delimiter { content, .. }
+//!             | Content::Punctuation(content) => self.0.push(content),
+//!         }
+//!         Ok(())
+//!     }
+//!
+//!     fn enter_group(&mut self, _: Grouping, _: &[Line], _: &mut Context) -> Result<(), Infallible> {
+//!         self.0.push('(');
+//!         Ok(())
+//!     }
+//!
+//!     fn exit_group(&mut self, _: (), _: &mut Context) -> Result<(), Infallible> {
+//!         self.0.push(')');
+//!         Ok(())
+//!     }
+//!
+//!     fn enter_script(&mut self, _: ScriptType, _: bool, _: &mut Context) -> Result<(), Infallible> {
+//!         Ok(())
+//!     }
+//!
+//!     fn exit_script(
+//!         &mut self,
+//!         _: ScriptType,
+//!         _: bool,
+//!         _: bool,
+//!         _: &mut Context,
+//!     ) -> Result<(), Infallible> {
+//!         Ok(())
+//!     }
+//!
+//!     fn enter_visual(&mut self, visual: Visual, _: &mut Context) -> Result<(), Infallible> {
+//!         if visual == Visual::SquareRoot {
+//!             self.0.push('√');
+//!         }
+//!         Ok(())
+//!     }
+//!
+//!     fn exit_visual(&mut self, _: Visual, _: &mut Context) -> Result<(), Infallible> {
+//!         Ok(())
+//!     }
+//!
+//!     fn next_argument(&mut self, cx: &mut Context) -> Result<(), Infallible> {
+//!         self.0.push(match cx.parent() {
+//!             Some(Parent::Visual { .. }) => '/',
+//!             Some(Parent::Script { ty: ScriptType::Superscript, .. }) => '^',
+//!             Some(Parent::Script { argument: 2, .. }) => '^',
+//!             _ => '_',
+//!         });
+//!         Ok(())
+//!     }
+//!
+//!     fn space(
+//!         &mut self,
+//!         _: Option<Dimension>,
+//!         _: Option<Dimension>,
+//!         _: &mut Context,
+//!     ) -> Result<(), Infallible> {
+//!         self.0.push(' ');
+//!         Ok(())
+//!     }
+//!
+//!     fn new_line(
+//!         &mut self,
+//!         _: &mut (),
+//!         _: Option<Dimension>,
+//!         _: &[Line],
+//!         _: &mut Context,
+//!     ) -> Result<(), Infallible> {
+//!         self.0.push_str("; ");
+//!         Ok(())
+//!     }
+//!
+//!     fn alignment(&mut self, _: &mut (), _: &mut Context) -> Result<(), Infallible> {
+//!         self.0.push_str(", ");
+//!         Ok(())
+//!     }
+//!
+//!     fn error(&mut self, source: &'a str, _: &mut Context) -> Result<(), Infallible> {
+//!         self.0.push_str(source);
+//!         Ok(())
+//!     }
+//!
+//!     fn parse_error(&mut self, _: &dyn std::error::Error, _: &mut Context) -> Result<(), Infallible> {
+//!         self.0.push('?');
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let storage = Storage::new();
+//! let parser = Parser::new(r"\frac{a}{b_1^2} + \sqrt x", &storage);
+//! let mut linear = Linear(String::new());
+//! render(parser, &mut linear, DisplayMode::Inline).unwrap();
+//! assert_eq!(linear.0, "(a)/(b_1^2)+√x");
+//! ```
+
+use std::{collections::VecDeque, ops::ControlFlow};
+
+use crate::{
+    config::DisplayMode,
+    event::{
+        ColorChange, ColorTarget, Content, DelimiterType, Dimension, EnvironmentFlow, Event, Font,
+        Grouping, Line, ScriptPosition, ScriptType, StateChange, Style, Visual,
+    },
+};
+
+/// The methods called by [`render`] while traversing a stream of events.
+///
+/// Every method receives a [`Context`], describing the [`State`] in effect, the element
+/// containing the one being rendered, and the atoms around it.
+pub trait Renderer<'a> {
+    /// The data kept by the renderer for each group, from the moment it is entered to the moment
+    /// it is exited.
+    type Group;
+    /// The error returned by the renderer, which stops the traversal.
+    type Error;
+
+    /// Render a content event.
+    ///
+    /// `negated` is true if the content follows a `Visual::Negation` event, in which case it is
+    /// up to the renderer to negate it. [`Context::atom`] is the class of the content.
+    fn content(
+        &mut self,
+        content: Content<'a>,
+        negated: bool,
+        cx: &mut Context,
+    ) -> Result<(), Self::Error>;
+
+    /// Enter a group, returning the data kept until it is exited.
+    ///
+    /// `lines` are the horizontal lines at the start of an environment. The `StateChange` events
+    /// directly following the start of a `Grouping::Normal` or a `Grouping::LeftRight` are already
+    /// part of the state of the context, which is reset after this method returns.
+    fn enter_group(
+        &mut self,
+        grouping: Grouping,
+        lines: &[Line],
+        cx: &mut Context,
+    ) -> Result<Self::Group, Self::Error>;
+
+    /// Exit a group, once all of its content is rendered.
+    ///
+    /// The state of the context is the one of the element containing the group.
+    fn exit_group(&mut self, group: Self::Group, cx: &mut Context) -> Result<(), Self::Error>;
+
+    /// Enter a script, whose arguments follow.
+    ///
+    /// `above_below` is true if the scripts must be rendered above and below the base, which is
+    /// already resolved for `ScriptPosition::Movable`.
+    fn enter_script(
+        &mut self,
+        ty: ScriptType,
+        above_below: bool,
+        cx: &mut Context,
+    ) -> Result<(), Self::Error>;
+
+    /// Exit a script, once all of its arguments are rendered.
+    ///
+    /// `function` is true if one of its arguments is a `Content::Function`, such as `\sin` in
+    /// `\sin^2 x`.
+    fn exit_script(
+        &mut self,
+        ty: ScriptType,
+        above_below: bool,
+        function: bool,
+        cx: &mut Context,
+    ) -> Result<(), Self::Error>;
+
+    /// Enter a visual element, whose arguments follow.
+    ///
+    /// This method is not called for a `Visual::Negation` followed by a content event, which is
+    /// rendered through [`Renderer::content`] instead.
+    fn enter_visual(&mut self, visual: Visual, cx: &mut Context) -> Result<(), Self::Error>;
+
+    /// Exit a visual element, once all of its arguments are rendered.
+    fn exit_visual(&mut self, visual: Visual, cx: &mut Context) -> Result<(), Self::Error>;
+
+    /// Called between two arguments of a script or a visual element.
+    ///
+    /// [`Context::parent`] is the script or visual element, along with the index of the argument
+    /// which follows. The default implementation does nothing.
+    fn next_argument(&mut self, cx: &mut Context) -> Result<(), Self::Error> {
+        let _ = cx;
+        Ok(())
+    }
+
+    /// Render some space.
+    fn space(
+        &mut self,
+        width: Option<Dimension>,
+        height: Option<Dimension>,
+        cx: &mut Context,
+    ) -> Result<(), Self::Error>;
+
+    /// Start a new row of the environment in which `group` was entered.
+    fn new_line(
+        &mut self,
+        group: &mut Self::Group,
+        spacing: Option<Dimension>,
+        lines: &[Line],
+        cx: &mut Context,
+    ) -> Result<(), Self::Error>;
+
+    /// Start a new column of the environment in which `group` was entered.
+    fn alignment(&mut self, group: &mut Self::Group, cx: &mut Context) -> Result<(), Self::Error>;
+
+    /// Render an `Event::Error`, given the source that was skipped.
+    fn error(&mut self, source: &'a str, cx: &mut Context) -> Result<(), Self::Error>;
+
+    /// Render an error returned by the stream of events instead of an event.
+    fn parse_error(
+        &mut self,
+        error: &dyn std::error::Error,
+        cx: &mut Context,
+    ) -> Result<(), Self::Error>;
+}
+
+/// The class of an atom, which determines the spacing around it.
+///
+/// See the TeXbook, p. 158 and 170.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Atom {
+    /// An ordinary atom, such as a variable or a number.
+    Ord,
+    /// A large operator, such as `\sum`, or a function, such as `\sin`.
+    Op,
+    /// A binary operation, such as `+`.
+    Bin,
+    /// A relation, such as `=`.
+    Rel,
+    /// An opening delimiter.
+    Open,
+    /// A closing delimiter.
+    Close,
+    /// A punctuation mark.
+    Punct,
+    /// A group, a fraction, or another element made of several atoms.
+    Inner,
+}
+
+/// The state changed by `StateChange` events.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[non_exhaustive]
+pub struct State {
+    /// The font of the content, if it is not the default one.
+    pub font: Option<Font>,
+    /// The color of the content.
+    pub text_color: Option<(u8, u8, u8)>,
+    /// The color of the border around the group.
+    pub border_color: Option<(u8, u8, u8)>,
+    /// The color of the background of the group.
+    pub background_color: Option<(u8, u8, u8)>,
+    /// The style of the content, if it was set explicitly.
+    pub style: Option<Style>,
+}
+
+/// The element directly containing the one being rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parent {
+    /// A group, entered through [`Renderer::enter_group`].
+    Group,
+    /// A script, entered through [`Renderer::enter_script`].
+    Script {
+        /// The type of the script.
+        ty: ScriptType,
+        /// Whether the scripts are rendered above and below the base.
+        above_below: bool,
+        /// The index of the argument being rendered, `0` being the base.
+        argument: u8,
+    },
+    /// A visual element, entered through [`Renderer::enter_visual`].
+    Visual {
+        /// The visual element.
+        visual: Visual,
+        /// The index of the argument being rendered, in the order of the events.
+        argument: u8,
+    },
+}
+
+/// What a [`Renderer`] knows about the position of the element it renders.
+pub struct Context<'c> {
+    state: &'c State,
+    parent: Option<Parent>,
+    previous_atom: Option<Atom>,
+    atom: Option<Atom>,
+    lookahead: &'c mut dyn Lookahead,
+}
+
+impl Context<'_> {
+    /// The state in effect.
+    pub fn state(&self) -> &State {
+        self.state
+    }
+
+    /// The element containing the one being rendered, or `None` at the top level.
+    pub fn parent(&self) -> Option<Parent> {
+        self.parent
+    }
+
+    /// The class of the atom preceding the one being rendered in the same row, if any.
+    ///
+    /// Atoms in scripts, in the denominator of fractions and in the index of roots are not
+    /// accounted for, since they do not affect the spacing of what follows them.
+    pub fn previous_atom(&self) -> Option<Atom> {
+        self.previous_atom
+    }
+
+    /// The class of the content being rendered, if it affects spacing.
+    ///
+    /// A `Content::BinaryOp` is an [`Atom::Ord`] where `TeX` would not space it as a binary
+    /// operation, such as at the start of a row.
+    pub fn atom(&self) -> Option<Atom> {
+        self.atom
+    }
+
+    /// The class of the atom following the one being rendered, if any.
+    ///
+    /// This looks ahead in the stream of events, without consuming them.
+    pub fn next_atom(&mut self) -> Option<Atom> {
+        self.lookahead.next_atom()
+    }
+}
+
+/// Traverse the stream of events, calling the methods of the renderer along the way.
+///
+/// `display_mode` is used to position scripts whose position is `ScriptPosition::Movable`.
+///
+/// # Panics
+///
+/// This function panics if the events do not describe a tree, for example when a group is never
+/// ended. Events produced by a [`Parser`](crate::Parser) always do. Other streams of events can be
+/// checked with a [`Validator`](crate::event::validate::Validator) first.
+pub fn render<'a, I, R, E>(
+    events: I,
+    renderer: &mut R,
+    display_mode: DisplayMode,
+) -> Result<(), R::Error>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    R: Renderer<'a> + ?Sized,
+    E: std::error::Error,
+{
+    let balanced = traverse(
+        events,
+        renderer,
+        display_mode,
+        Checkpoint::default(),
+        &mut |_, _| ControlFlow::Continue(()),
+    )?;
+    if !balanced {
+        panic!("unbalanced environment stack or state stack");
+    }
+    Ok(())
+}
+
+/// The position of the traversal between two top-level elements, from which it can be resumed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Checkpoint {
+    state: State,
+    previous_atom: Option<Atom>,
+}
+
+/// A position of the traversal reported by [`render_from`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Boundary {
+    /// The number of events consumed so far.
+    pub(crate) events: usize,
+    /// The checkpoint following a top-level element, or `None` at the start of a row of a
+    /// top-level mathematical environment.
+    pub(crate) checkpoint: Option<Checkpoint>,
+}
+
+/// Like [`render`], but starting from the given checkpoint, and calling `boundary` after every
+/// top-level element and at the start of every row of a top-level mathematical environment.
+///
+/// The traversal stops when `boundary` breaks, or when the events run out, even in the middle of
+/// an element.
+pub(crate) fn render_from<'a, I, R, E>(
+    events: I,
+    renderer: &mut R,
+    display_mode: DisplayMode,
+    checkpoint: Checkpoint,
+    mut boundary: impl FnMut(&mut R, Boundary) -> ControlFlow<()>,
+) -> Result<(), R::Error>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    R: Renderer<'a> + ?Sized,
+    E: std::error::Error,
+{
+    traverse(events, renderer, display_mode, checkpoint, &mut boundary).map(drop)
+}
+
+/// Traverse the events from the checkpoint, returning whether the traversal ended at the top
+/// level.
+fn traverse<'a, I, R, E>(
+    events: I,
+    renderer: &mut R,
+    display_mode: DisplayMode,
+    checkpoint: Checkpoint,
+    boundary: &mut dyn FnMut(&mut R, Boundary) -> ControlFlow<()>,
+) -> Result<bool, R::Error>
+where
+    I: IntoIterator<Item = Result<Event<'a>, E>>,
+    R: Renderer<'a> + ?Sized,
+    E: std::error::Error,
+{
+    let mut cursor = Cursor {
+        input: ManyPeek::new(events.into_iter()),
+        display_mode,
+        env_stack: Vec::with_capacity(16),
+        state_stack: Vec::with_capacity(16),
+        previous_atom: checkpoint.previous_atom,
+    };
+    cursor.state_stack.push(checkpoint.state);
+
+    while let Some(event) = cursor.input.next() {
+        let row = matches!(
+            event,
+            Ok(Event::Begin(ref grouping)) if grouping.is_math_env() && cursor.env_stack.is_empty()
+        ) || matches!(
+            event,
+            Ok(Event::EnvironmentFlow(EnvironmentFlow::NewLine { .. }))
+                if cursor.env_stack.len() == 1
+        );
+        if cursor.event(renderer, event)? {
+            // The event started a script or a visual element, whose arguments follow.
+            continue;
+        }
+        if row {
+            let boundary = boundary(
+                renderer,
+                Boundary {
+                    events: cursor.input.consumed,
+                    checkpoint: None,
+                },
+            );
+            if boundary.is_break() {
+                return Ok(true);
+            }
+            continue;
+        }
+
+        // The event completed an element, which may be the last argument of one or several
+        // scripts and visual elements.
+        while let Some(count) = cursor.env_stack.last_mut().and_then(|env| match env {
+            Environment::Group { .. } => None,
+            Environment::Visual { count, .. } | Environment::Script { count, .. } => Some(count),
+        }) {
+            if *count != 0 {
+                *count -= 1;
+                renderer.next_argument(&mut cursor.context(None))?;
+                break;
+            }
+            cursor.set_previous_atom(Atom::Inner);
+            let env = cursor.env_stack.pop().expect("environment stack is empty");
+            let cx = &mut cursor.context(None);
+            match env {
+                Environment::Visual { visual, .. } => renderer.exit_visual(visual, cx)?,
+                Environment::Script {
+                    ty,
+                    above_below,
+                    function,
+                    ..
+                } => renderer.exit_script(ty, above_below, function, cx)?,
+                Environment::Group { .. } => unreachable!(),
+            }
+        }
+
+        if cursor.env_stack.is_empty() {
+            let checkpoint = Checkpoint {
+                state: *cursor.state(),
+                previous_atom: cursor.previous_atom,
+            };
+            let boundary = boundary(
+                renderer,
+                Boundary {
+                    events: cursor.input.consumed,
+                    checkpoint: Some(checkpoint),
+                },
+            );
+            if boundary.is_break() {
+                return Ok(true);
+            }
+        }
+    }
+
+    Ok(cursor.env_stack.is_empty() && cursor.state_stack.len() == 1)
+}
+
+/// The position of the traversal in the stream of events.
+struct Cursor<I: Iterator, G> {
+    input: ManyPeek<I>,
+    display_mode: DisplayMode,
+    env_stack: Vec<Environment<G>>,
+    state_stack: Vec<State>,
+    previous_atom: Option<Atom>,
+}
+
+impl<'a, I, G, E> Cursor<I, G>
+where
+    I: Iterator<Item = Result<Event<'a>, E>>,
+    E: std::error::Error,
+{
+    /// Handle an event, returning whether it started a script or a visual element.
+    fn event<R>(&mut self, renderer: &mut R, event: Result<Event<'a>, E>) -> Result<bool, R::Error>
+    where
+        R: Renderer<'a, Group = G> + ?Sized,
+    {
+        match event {
+            Ok(Event::Content(content)) => self.content(renderer, content, false)?,
+            Ok(Event::Begin(grouping)) => {
+                // Mathematical environments do something different with state compared to things
+                // like left/right and {}.
+                //
+                // - They do not inherit state from their parent, their state is reset to default
+                // upon entering.
+                // - State changes occuring within them are also reset when crossing alignments or
+                // newlines (`&` or `\\`).
+                // TODO: Make `StateChange::Style` be maintained across math_env groups.
+                self.previous_atom = None;
+                let math_env = grouping.is_math_env();
+                let left_right = matches!(grouping, Grouping::LeftRight(..));
+                let mut lines = Box::default();
+                if math_env {
+                    self.state_stack.push(State::default());
+                    if let Some(Ok(Event::EnvironmentFlow(EnvironmentFlow::StartLines {
+                        ..
+                    }))) = self.input.peek_first()
+                    {
+                        let Some(Ok(Event::EnvironmentFlow(EnvironmentFlow::StartLines {
+                            lines: start_lines,
+                        }))) = self.input.next()
+                        else {
+                            unreachable!()
+                        };
+                        lines = start_lines;
+                    }
+                } else {
+                    let last_state = *self.state();
+                    self.state_stack.push(last_state);
+                    while let Some(Ok(Event::StateChange(state_change))) = self.input.peek_first() {
+                        let state_change = *state_change;
+                        self.handle_state_change(state_change);
+                        self.input.next();
+                    }
+                }
+
+                let group = renderer.enter_group(grouping, &lines, &mut self.context(None))?;
+                if !math_env {
+                    // Every state appliable to the style of the group is reset, i.e., everything
+                    // except font.
+                    *self.state_stack.last_mut().expect("state stack is empty") = State {
+                        font: self.state().font,
+                        ..State::default()
+                    };
+                }
+                if left_right {
+                    self.previous_atom = Some(Atom::Open);
+                }
+                self.env_stack
+                    .push(Environment::Group { group, left_right });
+            }
+            Ok(Event::End) => {
+                let Some(Environment::Group { group, left_right }) = self.env_stack.pop() else {
+                    panic!("unexpected environment in group end");
+                };
+                self.state_stack
+                    .pop()
+                    .expect("cannot pop a state in group end");
+                self.previous_atom = Some(Atom::Inner);
+                renderer.exit_group(group, &mut self.context(None))?;
+                if left_right {
+                    self.previous_atom = Some(Atom::Close);
+                }
+            }
+            Ok(Event::Visual(visual)) => {
+                if visual == Visual::Negation {
+                    if let Some(Ok(Event::Content(
+                        content @ (Content::Ordinary { .. }
+                        | Content::Relation { .. }
+                        | Content::BinaryOp { .. }
+                        | Content::LargeOp { .. }
+                        | Content::Delimiter { .. }
+                        | Content::Punctuation(_)),
+                    ))) = self.input.peek_first()
+                    {
+                        let content = *content;
+                        self.content(renderer, content, true)?;
+                        self.input.next();
+                        return Ok(false);
+                    }
+                }
+                renderer.enter_visual(visual, &mut self.context(None))?;
+                self.env_stack.push(Environment::Visual {
+                    visual,
+                    count: visual_argument_count(visual) - 1,
+                });
+                return Ok(true);
+            }
+            Ok(Event::Script { ty, position }) => {
+                let state = self.state();
+                let above_below = match position {
+                    ScriptPosition::Right => false,
+                    ScriptPosition::AboveBelow => true,
+                    ScriptPosition::Movable => {
+                        state.style == Some(Style::Display)
+                            || (state.style.is_none() && self.display_mode == DisplayMode::Block)
+                    }
+                };
+                renderer.enter_script(ty, above_below, &mut self.context(None))?;
+                self.env_stack.push(Environment::Script {
+                    ty,
+                    above_below,
+                    count: script_argument_count(ty) - 1,
+                    function: false,
+                });
+                return Ok(true);
+            }
+            Ok(Event::Space { width, height }) => {
+                renderer.space(width, height, &mut self.context(None))?
+            }
+            Ok(Event::StateChange(state_change)) => self.handle_state_change(state_change),
+            Ok(Event::EnvironmentFlow(EnvironmentFlow::NewLine {
+                spacing,
+                horizontal_lines,
+            })) => {
+                *self.state_stack.last_mut().expect("state stack is empty") = State::default();
+                self.previous_atom = None;
+                let Some(Environment::Group {
+                    mut group,
+                    left_right,
+                }) = self.env_stack.pop()
+                else {
+                    panic!("newline not allowed in current environment");
+                };
+                renderer.new_line(
+                    &mut group,
+                    spacing,
+                    &horizontal_lines,
+                    &mut self.context(Some(Parent::Group)),
+                )?;
+                self.env_stack
+                    .push(Environment::Group { group, left_right });
+            }
+            Ok(Event::EnvironmentFlow(EnvironmentFlow::Alignment)) => {
+                *self.state_stack.last_mut().expect("state stack is empty") = State::default();
+                self.previous_atom = None;
+                let Some(Environment::Group {
+                    mut group,
+                    left_right,
+                }) = self.env_stack.pop()
+                else {
+                    panic!("alignment not allowed in current environment");
+                };
+                renderer.alignment(&mut group, &mut self.context(Some(Parent::Group)))?;
+                self.env_stack
+                    .push(Environment::Group { group, left_right });
+            }
+            Ok(Event::EnvironmentFlow(EnvironmentFlow::StartLines { .. })) => {
+                panic!("unexpected StartLines event found")
+            }
+            Ok(Event::Error { source, .. }) => {
+                let mut cx = self.context(None);
+                cx.atom = Some(Atom::Ord);
+                renderer.error(source, &mut cx)?;
+                self.set_previous_atom(Atom::Ord);
+            }
+            Err(e) => renderer.parse_error(&e, &mut self.context(None))?,
+        }
+        Ok(false)
+    }
+
+    fn content<R>(
+        &mut self,
+        renderer: &mut R,
+        content: Content<'a>,
+        negated: bool,
+    ) -> Result<(), R::Error>
+    where
+        R: Renderer<'a, Group = G> + ?Sized,
+    {
+        // TexBook p. 153 and 157 for math classes.
+        // TexBook p. 170 for spacing.
+        // TexBook p. 438-443 for type setting rules (especially important for Binary Ops)
+        let atom = match content {
+            Content::Text(_) | Content::Number(_) => Some(Atom::Ord),
+            Content::Function(_) => Some(Atom::Op),
+            Content::Ordinary { stretchy: true, .. } => None,
+            Content::Ordinary { .. } => Some(Atom::Ord),
+            Content::BinaryOp { .. } => {
+                if matches!(
+                    self.previous_atom,
+                    Some(Atom::Inner | Atom::Close | Atom::Ord)
+                ) && !matches!(
+                    self.env_stack.last(),
+                    Some(
+                        Environment::Script { .. }
+                            | Environment::Visual {
+                                visual: Visual::Root | Visual::Fraction(_) | Visual::SquareRoot,
+                                ..
+                            }
+                    )
+                ) && matches!(
+                    self.input.next_atom(),
+                    Some(Atom::Inner | Atom::Bin | Atom::Op | Atom::Ord | Atom::Open)
+                ) {
+                    Some(Atom::Bin)
+                } else {
+                    Some(Atom::Ord)
+                }
+            }
+            Content::Relation { .. } => Some(Atom::Rel),
+            Content::LargeOp { .. } => Some(Atom::Op),
+            Content::Delimiter { ty, .. } => Some(match ty {
+                DelimiterType::Open => Atom::Open,
+                DelimiterType::Fence => Atom::Punct,
+                DelimiterType::Close => Atom::Close,
+            }),
+            Content::Punctuation(_) => Some(Atom::Punct),
+        };
+
+        let mut cx = self.context(None);
+        cx.atom = atom;
+        renderer.content(content, negated, &mut cx)?;
+        if let Some(atom) = atom {
+            self.set_previous_atom(atom);
+        }
+        if let (Content::Function(_), Some(Environment::Script { function, .. })) =
+            (content, self.env_stack.last_mut())
+        {
+            *function = true;
+        }
+        Ok(())
+    }
+
+    /// The context of the current position, given its parent, or the innermost element if `None`.
+    fn context(&mut self, parent: Option<Parent>) -> Context<'_> {
+        Context {
+            parent: parent.or_else(|| self.env_stack.last().map(Environment::parent)),
+            state: self.state_stack.last().expect("state stack is empty"),
+            previous_atom: self.previous_atom,
+            atom: None,
+            lookahead: &mut self.input,
+        }
+    }
+
+    fn handle_state_change(&mut self, state_change: StateChange) {
+        let state = self.state_stack.last_mut().expect("state stack is empty");
+        match state_change {
+            StateChange::Font(font) => state.font = font,
+            StateChange::Color(ColorChange { color, target }) => match target {
+                ColorTarget::Text => state.text_color = Some(color),
+                ColorTarget::Border => state.border_color = Some(color),
+                ColorTarget::Background => state.background_color = Some(color),
+            },
+            StateChange::Style(style) => state.style = Some(style),
+        }
+    }
+
+    fn set_previous_atom(&mut self, atom: Atom) {
+        if !matches!(
+            self.env_stack.last().map(Environment::parent),
+            Some(
+                Parent::Visual {
+                    visual: Visual::Root | Visual::Fraction(_),
+                    argument: 1,
+                } | Parent::Script { argument: 1.., .. }
+            )
+        ) {
+            self.previous_atom = Some(atom);
+        }
+    }
+
+    fn state(&self) -> &State {
+        self.state_stack.last().expect("state stack is empty")
+    }
+}
+
+enum Environment<G> {
+    Group {
+        group: G,
+        left_right: bool,
+    },
+    /// `count` is the number of arguments remaining after the current one.
+    Visual {
+        visual: Visual,
+        count: u8,
+    },
+    Script {
+        ty: ScriptType,
+        above_below: bool,
+        count: u8,
+        function: bool,
+    },
+}
+
+impl<G> Environment<G> {
+    fn parent(&self) -> Parent {
+        match *self {
+            Environment::Group { .. } => Parent::Group,
+            Environment::Visual { visual, count } => Parent::Visual {
+                visual,
+                argument: visual_argument_count(visual) - 1 - count,
+            },
+            Environment::Script {
+                ty,
+                above_below,
+                count,
+                ..
+            } => Parent::Script {
+                ty,
+                above_below,
+                argument: script_argument_count(ty) - 1 - count,
+            },
+        }
+    }
+}
+
+fn visual_argument_count(visual: Visual) -> u8 {
+    match visual {
+        Visual::SquareRoot | Visual::Negation => 1,
+        Visual::Root | Visual::Fraction(_) => 2,
+    }
+}
+
+fn script_argument_count(ty: ScriptType) -> u8 {
+    match ty {
+        ScriptType::Subscript | ScriptType::Superscript => 2,
+        ScriptType::SubSuperscript => 3,
+    }
+}
+
+/// The lookahead needed by [`Context::next_atom`], independently of the type of the iterator.
+trait Lookahead {
+    fn next_atom(&mut self) -> Option<Atom>;
+}
+
+impl<'a, I, E> Lookahead for ManyPeek<I>
+where
+    I: Iterator<Item = Result<Event<'a>, E>>,
+{
+    fn next_atom(&mut self) -> Option<Atom> {
+        let mut index = 0;
+        loop {
+            let next = match self.peeked_nth(index) {
+                None => self.peek_next()?,
+                Some(next) => {
+                    index += 1;
+                    next
+                }
+            };
+
+            break match next {
+                Ok(
+                    Event::StateChange(_)
+                    | Event::Space { .. }
+                    | Event::Visual(Visual::Negation)
+                    | Event::Script { .. },
+                ) => continue,
+                Ok(Event::End | Event::EnvironmentFlow(_)) | Err(_) => None,
+                Ok(Event::Visual(_) | Event::Begin(_)) => Some(Atom::Inner),
+                Ok(Event::Error { .. }) => Some(Atom::Ord),
+                Ok(Event::Content(content)) => match content {
+                    Content::BinaryOp { .. } => Some(Atom::Bin),
+                    Content::LargeOp { .. } => Some(Atom::Op),
+                    Content::Relation { .. } => Some(Atom::Rel),
+                    Content::Delimiter {
+                        ty: DelimiterType::Open,
+                        ..
+                    } => Some(Atom::Open),
+                    Content::Delimiter {
+                        ty: DelimiterType::Close,
+                        ..
+                    } => Some(Atom::Close),
+                    Content::Punctuation(_) => Some(Atom::Punct),
+                    _ => Some(Atom::Ord),
+                },
+            };
+        }
+    }
+}
+
+struct ManyPeek<I: Iterator> {
+    iter: I,
+    peeked: VecDeque<I::Item>,
+    /// The number of items returned by `next`.
+    consumed: usize,
+}
+
+impl<I: Iterator> ManyPeek<I> {
+    fn new(iter: I) -> Self {
+        Self {
+            iter,
+            peeked: VecDeque::new(),
+            consumed: 0,
+        }
+    }
+
+    fn peek_next(&mut self) -> Option<&I::Item> {
+        self.peeked.push_back(self.iter.next()?);
+        self.peeked.back()
+    }
+
+    fn peeked_nth(&self, n: usize) -> Option<&I::Item> {
+        self.peeked.get(n)
+    }
+
+    fn peek_first(&mut self) -> Option<&I::Item> {
+        if self.peeked.is_empty() {
+            self.peek_next()
+        } else {
+            self.peeked.front()
+        }
+    }
+}
+
+impl<I: Iterator> Iterator for ManyPeek<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.peeked.pop_front().or_else(|| self.iter.next());
+        self.consumed += next.is_some() as usize;
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::{render, Context, Renderer};
+    use crate::{
+        config::DisplayMode,
+        event::{Content, Dimension, Grouping, Line, ScriptType, Visual},
+        Parser, Storage,
+    };
+
+    /// A renderer recording the callbacks it receives.
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl<'a> Renderer<'a> for Recorder {
+        type Group = usize;
+        type Error = Infallible;
+
+        fn content(
+            &mut self,
+            content: Content<'a>,
+            negated: bool,
+            cx: &mut Context,
+        ) -> Result<(), Infallible> {
+            let content = match content {
+                Content::Ordinary { content, .. }
+                | Content::BinaryOp { content, .. }
+                | Content::Punctuation(content) => content.to_string(),
+                Content::Number(text) | Content::Function(text) => text.to_string(),
+                _ => String::from("?"),
+            };
+            self.0.push(format!(
+                "{}{content} {:?} {:?} {:?}",
+                if negated { "!" } else { "" },
+                cx.atom(),
+                cx.parent(),
+                cx.previous_atom()
+            ));
+            Ok(())
+        }
+
+        fn enter_group(
+            &mut self,
+            _: Grouping,
+            lines: &[Line],
+            _: &mut Context,
+        ) -> Result<usize, Infallible> {
+            self.0.push(format!("group {}", lines.len()));
+            Ok(0)
+        }
+
+        fn exit_group(&mut self, alignments: usize, _: &mut Context) -> Result<(), Infallible> {
+            self.0.push(format!("end {alignments}"));
+            Ok(())
+        }
+
+        fn enter_script(
+            &mut self,
+            ty: ScriptType,
+            above_below: bool,
+            _: &mut Context,
+        ) -> Result<(), Infallible> {
+            self.0.push(format!("{ty:?} {above_below}"));
+            Ok(())
+        }
+
+        fn exit_script(
+            &mut self,
+            ty: ScriptType,
+            _: bool,
+            function: bool,
+            _: &mut Context,
+        ) -> Result<(), Infallible> {
+            self.0.push(format!("/{ty:?} {function}"));
+            Ok(())
+        }
+
+        fn enter_visual(&mut self, visual: Visual, _: &mut Context) -> Result<(), Infallible> {
+            self.0.push(format!("{visual:?}"));
+            Ok(())
+        }
+
+        fn exit_visual(&mut self, visual: Visual, _: &mut Context) -> Result<(), Infallible> {
+            self.0.push(format!("/{visual:?}"));
+            Ok(())
+        }
+
+        fn next_argument(&mut self, cx: &mut Context) -> Result<(), Infallible> {
+            self.0.push(format!("next {:?}", cx.parent()));
+            Ok(())
+        }
+
+        fn space(
+            &mut self,
+            _: Option<Dimension>,
+            _: Option<Dimension>,
+            _: &mut Context,
+        ) -> Result<(), Infallible> {
+            self.0.push(String::from("space"));
+            Ok(())
+        }
+
+        fn new_line(
+            &mut self,
+            alignments: &mut usize,
+            _: Option<Dimension>,
+            lines: &[Line],
+            _: &mut Context,
+        ) -> Result<(), Infallible> {
+            self.0.push(format!("newline {alignments} {}", lines.len()));
+            *alignments = 0;
+            Ok(())
+        }
+
+        fn alignment(&mut self, alignments: &mut usize, _: &mut Context) -> Result<(), Infallible> {
+            *alignments += 1;
+            Ok(())
+        }
+
+        fn error(&mut self, source: &'a str, _: &mut Context) -> Result<(), Infallible> {
+            self.0.push(format!("error {source}"));
+            Ok(())
+        }
+
+        fn parse_error(
+            &mut self,
+            _: &dyn std::error::Error,
+            _: &mut Context,
+        ) -> Result<(), Infallible> {
+            self.0.push(String::from("parse error"));
+            Ok(())
+        }
+    }
+
+    fn record(input: &str, display_mode: DisplayMode) -> Vec<String> {
+        let storage = Storage::new();
+        let mut recorder = Recorder::default();
+        render(Parser::new(input, &storage), &mut recorder, display_mode).unwrap();
+        recorder.0
+    }
+
+    #[test]
+    fn scripts_and_visuals() {
+        assert_eq!(
+            record(r"\sin^2 \frac ab", DisplayMode::Inline),
+            [
+                "Superscript false",
+                "sin Some(Op) Some(Script { ty: Superscript, above_below: false, argument: 0 }) None",
+                "next Some(Script { ty: Superscript, above_below: false, argument: 1 })",
+                "2 Some(Ord) Some(Script { ty: Superscript, above_below: false, argument: 1 }) Some(Op)",
+                "/Superscript true",
+                "Fraction(None)",
+                "a Some(Ord) Some(Visual { visual: Fraction(None), argument: 0 }) Some(Op)",
+                "next Some(Visual { visual: Fraction(None), argument: 1 })",
+                "b Some(Ord) Some(Visual { visual: Fraction(None), argument: 1 }) Some(Ord)",
+                "/Fraction(None)",
+            ]
+        );
+        assert_eq!(record(r"\sum_i", DisplayMode::Block)[0], "Subscript true");
+        assert_eq!(record(r"\sum_i", DisplayMode::Inline)[0], "Subscript false");
+    }
+
+    #[test]
+    fn binary_operators() {
+        let atoms = |input| {
+            record(input, DisplayMode::Inline)
+                .into_iter()
+                .filter(|record| record.starts_with(['+', '\u{2212}']))
+                .map(|record| record.contains("Some(Bin)"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(atoms("a + b"), [true]);
+        assert_eq!(atoms("-a + (-b)"), [false, true, false]);
+        assert_eq!(atoms("a + = b"), [false]);
+        assert_eq!(atoms("a^+ b"), [false]);
+    }
+
+    #[test]
+    fn negation() {
+        let records = record(r"\not= \not{ab}", DisplayMode::Inline);
+        assert!(records[0].starts_with("!? Some(Rel)"), "{records:?}");
+        assert_eq!(records[1], "Negation");
+        assert_eq!(records.last().unwrap(), "/Negation");
+    }
+
+    #[test]
+    fn environments() {
+        assert_eq!(
+            record(
+                r"\begin{array}{cc} \hline a & b \\ \hline c & d \end{array}",
+                DisplayMode::Inline
+            ),
+            [
+                "group 1",
+                "a Some(Ord) Some(Group) None",
+                "b Some(Ord) Some(Group) None",
+                "newline 1 1",
+                "c Some(Ord) Some(Group) None",
+                "d Some(Ord) Some(Group) None",
+                "end 1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,621 @@
+//! Reverse lookup tables, from the content of events to the control sequences producing them.
+//!
+//! The [`latex`](crate::latex) writer uses them to write symbols back as control sequences, and
+//! the parser to tell which characters it knows the class of. These tables mirror the primitives
+//! of the parser, and must be kept in sync with them.
+
+/// The control sequence producing the ordinary character.
+pub fn ordinary(c: char) -> Option<&'static str> {
+    Some(match c {
+        'α' => "alpha",
+        'β' => "beta",
+        'γ' => "gamma",
+        'δ' => "delta",
+        'ϵ' => "epsilon",
+        'ζ' => "zeta",
+        'η' => "eta",
+        'θ' => "theta",
+        'ι' => "iota",
+        'κ' => "kappa",
+        'λ' => "lambda",
+        'µ' => "mu",
+        'ν' => "nu",
+        'ξ' => "xi",
+        'π' => "pi",
+        'ρ' => "rho",
+        'σ' => "sigma",
+        'τ' => "tau",
+        'υ' => "upsilon",
+        'ϕ' => "phi",
+        'χ' => "chi",
+        'ψ' => "psi",
+        'ω' => "omega",
+        'ο' => "omicron",
+        'Α' => "Alpha",
+        'Β' => "Beta",
+        'Γ' => "Gamma",
+        'Δ' => "Delta",
+        'Ε' => "Epsilon",
+        'Ζ' => "Zeta",
+        'Η' => "Eta",
+        'Θ' => "Theta",
+        'Ι' => "Iota",
+        'Κ' => "Kappa",
+        'Λ' => "Lambda",
+        'Μ' => "Mu",
+        'Ν' => "Nu",
+        'Ξ' => "Xi",
+        'Π' => "Pi",
+        'Ρ' => "Rho",
+        'Σ' => "Sigma",
+        'Τ' => "Tau",
+        'Υ' => "Upsilon",
+        'Φ' => "Phi",
+        'Χ' => "Chi",
+        'Ψ' => "Psi",
+        'Ω' => "Omega",
+        'Ο' => "Omicron",
+        'ε' => "varepsilon",
+        'ϑ' => "vartheta",
+        'ϰ' => "varkappa",
+        'ϱ' => "varrho",
+        'ς' => "varsigma",
+        'ϖ' => "varpi",
+        'φ' => "varphi",
+        '𝛤' => "varGamma",
+        '𝛥' => "varDelta",
+        '𝛩' => "varTheta",
+        '𝛬' => "varLambda",
+        '𝛯' => "varXi",
+        '𝛱' => "varPi",
+        '𝛴' => "varSigma",
+        '𝛶' => "varUpsilon",
+        '𝛷' => "varPhi",
+        '𝛹' => "varPsi",
+        '𝛺' => "varOmega",
+        'ℵ' => "aleph",
+        'ℶ' => "beth",
+        'ℷ' => "gimel",
+        'ℸ' => "daleth",
+        'ϝ' => "digamma",
+        'ð' => "eth",
+        'ℓ' => "ell",
+        '∇' => "nabla",
+        '∂' => "partial",
+        'Ⅎ' => "Finv",
+        'ℏ' => "hbar",
+        'ı' => "imath",
+        'ȷ' => "jmath",
+        'ℑ' => "Im",
+        'ℜ' => "Re",
+        '℘' => "wp",
+        '𝕜' => "Bbbk",
+        'Å' => "Angstrom",
+        '϶' => "backepsilon",
+        '…' => "ldots",
+        '⋯' => "cdots",
+        '⋱' => "ddots",
+        '⋰' => "iddots",
+        '⋮' => "vdots",
+        '∞' => "infty",
+        '✓' => "checkmark",
+        '✗' => "ballotx",
+        '†' => "dagger",
+        '‡' => "ddagger",
+        '∠' => "angle",
+        '∡' => "measuredangle",
+        '‘' => "lq",
+        '□' => "square",
+        '∢' => "sphericalangle",
+        '⊤' => "top",
+        '′' => "prime",
+        '■' => "blacksquare",
+        '⊥' => "bot",
+        '▽' => "triangledown",
+        '⫫' => "Bot",
+        '◃' => "triangleleft",
+        '▹' => "triangleright",
+        '¢' => "cent",
+        ':' => "colon",
+        '£' => "pounds",
+        '△' => "triangle",
+        '▲' => "blacktriangle",
+        '▼' => "blacktriangledown",
+        '¥' => "yen",
+        '◀' => "blacktriangleleft",
+        '€' => "euro",
+        '▶' => "blacktriangleright",
+        '◊' => "lozenge",
+        '°' => "degree",
+        '⧫' => "blacklozenge",
+        '℧' => "mho",
+        '★' => "bigstar",
+        '╲' => "diagdown",
+        '✠' => "maltese",
+        '╱' => "diagup",
+        '¶' => "P",
+        '♣' => "clubsuit",
+        '♧' => "varclubsuit",
+        '§' => "S",
+        '♢' => "diamondsuit",
+        '♦' => "vardiamondsuit",
+        '©' => "copyright",
+        '♡' => "heartsuit",
+        '♥' => "varheartsuit",
+        '®' => "circledR",
+        '♠' => "spadesuit",
+        '♤' => "varspadesuit",
+        'Ⓢ' => "circledS",
+        '♀' => "female",
+        '♂' => "male",
+        '☉' => "astrosun",
+        '☼' => "sun",
+        '☾' => "leftmoon",
+        '☽' => "rightmoon",
+        '☺' => "smiley",
+        '⊕' => "Earth",
+        '♭' => "flat",
+        '⦵' => "standardstate",
+        '♮' => "natural",
+        '♯' => "sharp",
+        '‰' => "permil",
+        '∎' => "QED",
+        '↯' => "lightning",
+        '⌀' => "varnothing",
+        '⟕' => "leftouterjoin",
+        '⟖' => "rightouterjoin",
+        '⟡' => "concavediamond",
+        '⟢' => "concavediamondtickleft",
+        '⟣' => "concavediamondtickright",
+        '⟗' => "fullouterjoin",
+        '⟤' => "whitesquaretickleft",
+        '⟥' => "whitesquaretickright",
+        '″' => "dprime",
+        '‴' => "trprime",
+        '⁗' => "qprime",
+        '‵' => "backprime",
+        '‶' => "backdprime",
+        '‷' => "backtrprime",
+        '∀' => "forall",
+        '∃' => "exists",
+        '∁' => "complement",
+        '∄' => "nexists",
+        '¬' => "neg",
+        '∅' => "emptyset",
+        '\\' => "backslash",
+        '∥' => "|",
+        '#' => "#",
+        '%' => "%",
+        '&' => "&",
+        '$' => "$",
+        '_' => "_",
+        _ => return None,
+    })
+}
+
+/// The control sequence producing the binary operator.
+pub fn binary(c: char) -> Option<&'static str> {
+    Some(match c {
+        '∧' => "wedge",
+        '.' => "ldotp",
+        '·' => "cdotp",
+        '⋅' => "cdot",
+        '∘' => "circ",
+        '∙' => "bullet",
+        '⊛' => "circledast",
+        '⊚' => "circledcirc",
+        '⊝' => "circleddash",
+        '◯' => "bigcirc",
+        '⋋' => "leftthreetimes",
+        '⊳' => "rhd",
+        '⊲' => "lhd",
+        '⋌' => "rightthreetimes",
+        '⋊' => "rtimes",
+        '⋉' => "ltimes",
+        '⊨' => "leftmodels",
+        '⨿' => "amalg",
+        '*' => "ast",
+        '≍' => "asymp",
+        '&' => "And",
+        '∨' => "vee",
+        '∖' => "setminus",
+        '⋓' => "Cup",
+        '∪' => "cup",
+        '⊔' => "sqcup",
+        '⊓' => "sqcap",
+        '⋖' => "lessdot",
+        '⌅' => "barwedge",
+        '⋎' => "curlyvee",
+        '⋏' => "curlywedge",
+        '⫽' => "sslash",
+        '÷' => "div",
+        '∓' => "mp",
+        '×' => "times",
+        '⊡' => "boxdot",
+        '⋇' => "divideontimes",
+        '⊙' => "odot",
+        '⊴' => "unlhd",
+        '⊟' => "boxminus",
+        '∔' => "dotplus",
+        '⊖' => "ominus",
+        '⊵' => "unrhd",
+        '⊞' => "boxplus",
+        '⩞' => "doublebarwedge",
+        '⊕' => "oplus",
+        '⊎' => "uplus",
+        '⊠' => "boxtimes",
+        '⋒' => "Cap",
+        '⊗' => "otimes",
+        '⊻' => "veebar",
+        '⅋' => "parr",
+        '∩' => "cap",
+        '⋗' => "gtrdot",
+        '±' => "pm",
+        '⊺' => "intercal",
+        '≀' => "wr",
+        '⦶' => "circledvert",
+        '⧗' => "blackhourglass",
+        '⦵' => "circlehbar",
+        '⦹' => "operp",
+        '⧆' => "boxast",
+        '⧈' => "boxbox",
+        '⊘' => "oslash",
+        '⧇' => "boxcircle",
+        '⋄' => "diamond",
+        '⨷' => "Otimes",
+        '⧖' => "hourglass",
+        '⨶' => "otimeshat",
+        '⨻' => "triangletimes",
+        '⟠' => "lozengeminus",
+        '⋆' => "star",
+        '⌽' => "obar",
+        '⦸' => "obslash",
+        '⨺' => "triangleminus",
+        '⨸' => "odiv",
+        '⨹' => "triangleplus",
+        '⊜' => "circledequal",
+        '⧁' => "ogreaterthan",
+        '⦷' => "circledparallel",
+        '⧀' => "olessthan",
+        _ => return None,
+    })
+}
+
+/// The control sequence producing the relation made of a single character.
+pub fn relation(c: char) -> Option<&'static str> {
+    Some(match c {
+        '∴' => "therefore",
+        '∵' => "because",
+        '⊂' => "subset",
+        '⊃' => "supset",
+        '⥽' => "strictif",
+        '⥼' => "strictfi",
+        '↦' => "mapsto",
+        '⟹' => "implies",
+        '∣' => "mid",
+        '→' => "rightarrow",
+        '⟸' => "impliedby",
+        '∈' => "in",
+        '∋' => "ni",
+        '←' => "leftarrow",
+        '⟺' => "iff",
+        '∌' => "notni",
+        '≖' => "eqcirc",
+        '≶' => "lessgtr",
+        '⌣' => "smile",
+        '∹' => "eqcolon",
+        '≲' => "lesssim",
+        '⊏' => "sqsubset",
+        '≪' => "ll",
+        '⊑' => "sqsubseteq",
+        '≕' => "eqqcolon",
+        '⋘' => "lll",
+        '⊐' => "sqsupset",
+        '⊒' => "sqsupseteq",
+        '≈' => "approx",
+        '≝' => "eqdef",
+        '<' => "lt",
+        '≛' => "stareq",
+        '≊' => "approxeq",
+        '≂' => "eqsim",
+        '≞' => "measeq",
+        '⋐' => "Subset",
+        '≘' => "arceq",
+        '⪖' => "eqslantgtr",
+        '⪕' => "eqslantless",
+        '⊨' => "models",
+        '⊆' => "subseteq",
+        '≌' => "backcong",
+        '≡' => "equiv",
+        '⊸' => "multimap",
+        '⫅' => "subseteqq",
+        '≒' => "fallingdotseq",
+        '⧟' => "multimapboth",
+        '≻' => "succ",
+        '∽' => "backsim",
+        '⌢' => "frown",
+        '⟜' => "multimapinv",
+        '⪸' => "succapprox",
+        '⋍' => "backsimeq",
+        '≥' => "geq",
+        '⊶' => "origof",
+        '≽' => "succcurlyeq",
+        '≬' => "between",
+        '⪰' => "succeq",
+        '≏' => "bumpeq",
+        '≧' => "geqq",
+        '∥' => "parallel",
+        '≿' => "succsim",
+        '≎' => "Bumpeq",
+        '⩾' => "geqslant",
+        '⟂' => "perp",
+        '⋑' => "Supset",
+        '≗' => "circeq",
+        '≫' => "gg",
+        '⫫' => "Perp",
+        '⋙' => "ggg",
+        '⋔' => "pitchfork",
+        '⊇' => "supseteq",
+        '≺' => "prec",
+        '⫆' => "supseteqq",
+        '>' => "gt",
+        '⪷' => "precapprox",
+        '⪆' => "gtrapprox",
+        '≼' => "preccurlyeq",
+        '∼' => "sim",
+        '⋛' => "gtreqless",
+        '⪯' => "preceq",
+        '⊴' => "trianglelefteq",
+        '≔' => "coloneqq",
+        '⪌' => "gtreqqless",
+        '≾' => "precsim",
+        '≜' => "triangleq",
+        '⩴' => "Coloneqq",
+        '≷' => "gtrless",
+        '∝' => "propto",
+        '⊵' => "trianglerighteq",
+        '≳' => "gtrsim",
+        '≟' => "questeq",
+        '⊷' => "imageof",
+        '≅' => "cong",
+        '≓' => "risingdotseq",
+        '⊲' => "vartriangleleft",
+        '⋞' => "curlyeqprec",
+        '⊳' => "vartriangleright",
+        '⋟' => "curlyeqsucc",
+        '≤' => "leq",
+        '⊢' => "vdash",
+        '⊣' => "dashv",
+        '∷' => "dblcolon",
+        '≦' => "leqq",
+        '⊩' => "Vdash",
+        '≐' => "doteq",
+        '⩽' => "leqslant",
+        '≃' => "simeq",
+        '⊫' => "Dash",
+        '≑' => "doteqdot",
+        '⪅' => "lessapprox",
+        '⊪' => "Vvdash",
+        '⋚' => "lesseqgtr",
+        '≚' => "veeeq",
+        '⩵' => "eqeq",
+        '⪋' => "lesseqqgtr",
+        '≙' => "wedgeq",
+        '⋈' => "bowtie",
+        '⪊' => "gnapprox",
+        '≱' => "ngeq",
+        '⊄' => "nsubset",
+        '⊮' => "nVdash",
+        '⪈' => "gneq",
+        '≯' => "ngtr",
+        '⊈' => "nsubseteq",
+        '⪹' => "precnapprox",
+        '≩' => "gneqq",
+        '≰' => "nleq",
+        '⪵' => "precneqq",
+        '⋧' => "gnsim",
+        '⊁' => "nsucc",
+        '⋨' => "precnsim",
+        '⋡' => "nsucceq",
+        '⊊' => "subsetneq",
+        '⪉' => "lnapprox",
+        '≮' => "nless",
+        '⊅' => "nsupset",
+        '⫋' => "subsetneqq",
+        '⪇' => "lneq",
+        '∤' => "nmid",
+        '⊉' => "nsupseteq",
+        '⪺' => "succnapprox",
+        '≨' => "lneqq",
+        '∉' => "notin",
+        '⪶' => "succneqq",
+        '⋦' => "lnsim",
+        '⋪' => "ntriangleleft",
+        '⋩' => "succnsim",
+        '∦' => "nparallel",
+        '⋬' => "ntrianglelefteq",
+        '⊋' => "supsetneq",
+        '≆' => "ncong",
+        '⊀' => "nprec",
+        '⋫' => "ntriangleright",
+        '⫌' => "supsetneqq",
+        '≠' => "neq",
+        '⋠' => "npreceq",
+        '⋭' => "ntrianglerighteq",
+        '⊬' => "nvdash",
+        '⊭' => "nvDash",
+        '≁' => "nsim",
+        '⊯' => "nVDash",
+        '↺' => "circlearrowleft",
+        '⇔' => "Leftrightarrow",
+        '↾' => "upharpoonright",
+        '↻' => "circlearrowright",
+        '⇆' => "leftrightarrows",
+        '↶' => "curvearrowleft",
+        '⇋' => "leftrightharpoons",
+        '⇒' => "Rightarrow",
+        '↷' => "curvearrowright",
+        '↭' => "leftrightsquigarrow",
+        '↣' => "rightarrowtail",
+        '⇠' => "dashleftarrow",
+        '⇚' => "Lleftarrow",
+        '⇁' => "rightharpoondown",
+        '⇢' => "dashrightarrow",
+        '⟵' => "longleftarrow",
+        '⇀' => "rightharpoonup",
+        '↓' => "downarrow",
+        '⇄' => "rightleftarrows",
+        '⇓' => "Downarrow",
+        '⟷' => "longleftrightarrow",
+        '⇌' => "rightleftharpoons",
+        '⇊' => "downdownarrows",
+        '⇉' => "rightrightarrows",
+        '⇃' => "downharpoonleft",
+        '⟼' => "longmapsto",
+        '⇝' => "rightsquigarrow",
+        '⇂' => "downharpoonright",
+        '⟶' => "longrightarrow",
+        '⇛' => "Rrightarrow",
+        '↱' => "Rsh",
+        '↩' => "hookleftarrow",
+        '↫' => "looparrowleft",
+        '↘' => "searrow",
+        '↪' => "hookrightarrow",
+        '↬' => "looparrowright",
+        '↙' => "swarrow",
+        '↰' => "Lsh",
+        '↤' => "mapsfrom",
+        '↞' => "twoheadleftarrow",
+        '↠' => "twoheadrightarrow",
+        '↗' => "nearrow",
+        '↑' => "uparrow",
+        '↚' => "nleftarrow",
+        '⇑' => "Uparrow",
+        '⇐' => "Leftarrow",
+        '⇍' => "nLeftarrow",
+        '↕' => "updownarrow",
+        '↢' => "leftarrowtail",
+        '↮' => "nleftrightarrow",
+        '⇕' => "Updownarrow",
+        '↽' => "leftharpoondown",
+        '⇎' => "nLeftrightarrow",
+        '↿' => "upharpoonleft",
+        '↼' => "leftharpoonup",
+        '↛' => "nrightarrow",
+        '⇇' => "leftleftarrows",
+        '⇏' => "nRightarrow",
+        '⇈' => "upuparrows",
+        '↔' => "leftrightarrow",
+        '↖' => "nwarrow",
+        _ => return None,
+    })
+}
+
+/// The control sequence producing the relation made of two characters.
+pub fn multirelation(first: char, second: char) -> Option<&'static str> {
+    Some(match (first, second) {
+        ('⫌', '︀') => "varsupsetneqq",
+        ('⫋', '︀') => "varsubsetneqq",
+        ('⊊', '︀') => "varsubsetneq",
+        ('⊋', '︀') => "varsupsetneq",
+        ('≩', '︀') => "gvertneqq",
+        ('≨', '︀') => "lvertneqq",
+        ('−', '∷') => "Eqcolon",
+        ('=', '∷') => "Eqqcolon",
+        ('≈', ':') => "approxcolon",
+        (':', '≈') => "colonapprox",
+        ('≈', '∷') => "approxcoloncolon",
+        ('∷', '≈') => "Colonapprox",
+        (':', '−') => "coloneq",
+        ('∷', '−') => "Coloneq",
+        (':', '∼') => "colonsim",
+        ('∷', '∼') => "Colonsim",
+        _ => return None,
+    })
+}
+
+/// The control sequence producing the large operator, and whether its scripts are movable by
+/// default.
+pub fn large_op(c: char) -> Option<(&'static str, bool)> {
+    Some(match c {
+        '∑' => ("sum", true),
+        '∏' => ("prod", true),
+        '∐' => ("coprod", true),
+        '⋁' => ("bigvee", true),
+        '⋀' => ("bigwedge", true),
+        '⋃' => ("bigcup", true),
+        '⋂' => ("bigcap", true),
+        '⨄' => ("biguplus", true),
+        '⨁' => ("bigoplus", true),
+        '⨂' => ("bigotimes", true),
+        '⨀' => ("bigodot", true),
+        '⨆' => ("bigsqcup", true),
+        '⨅' => ("bigsqcap", true),
+        '⨉' => ("bigtimes", true),
+        '∫' => ("int", false),
+        '∬' => ("iint", false),
+        '∭' => ("iiint", false),
+        '⨌' => ("iiiint", false),
+        '⨙' => ("intcap", false),
+        '⨚' => ("intcup", false),
+        '∮' => ("oint", false),
+        '∲' => ("varointclockwise", false),
+        '∱' => ("intclockwise", false),
+        '∯' => ("oiint", false),
+        '⨕' => ("pointint", false),
+        '⨒' => ("rppolint", false),
+        '⨓' => ("scpolint", false),
+        '∰' => ("oiiint", false),
+        '⨗' => ("intlarhk", false),
+        '⨖' => ("sqint", false),
+        '⨘' => ("intx", false),
+        '⨍' => ("intbar", false),
+        '⨎' => ("intBar", false),
+        '⨏' => ("fint", false),
+        _ => return None,
+    })
+}
+
+/// The accent command producing the overscript, given whether it is stretchy.
+pub fn accent(c: char, stretchy: bool) -> Option<&'static str> {
+    Some(match (c, stretchy) {
+        ('´', false) => "acute",
+        ('‾', false) => "overline",
+        ('˘', false) => "breve",
+        ('ˇ', false) => "check",
+        ('˙', false) => "dot",
+        ('¨', false) => "ddot",
+        ('`', false) => "grave",
+        ('^', false) => "hat",
+        ('~', false) => "tilde",
+        ('→', false) => "vec",
+        ('˚', false) => "mathring",
+        ('←', true) => "overleftarrow",
+        ('→', true) => "overrightarrow",
+        ('⇒', true) => "Overrightarrow",
+        ('↔', true) => "overleftrightarrow",
+        ('↼', true) => "overleftharpoon",
+        ('⇀', true) => "overrightharpoon",
+        ('ˇ', true) => "widecheck",
+        ('^', true) => "widehat",
+        ('~', true) => "widetilde",
+        ('⏜', true) => "wideparen",
+        ('⏠', true) => "overgroup",
+        ('⏞', true) => "overbrace",
+        _ => return None,
+    })
+}
+
+/// The command producing the stretchy underscript.
+pub fn underscript(c: char) -> Option<&'static str> {
+    Some(match c {
+        '_' => "underline",
+        '←' => "underleftarrow",
+        '→' => "underrightarrow",
+        '↔' => "underleftrightarrow",
+        '⏡' => "undergroup",
+        '⏟' => "underbrace",
+        '⏝' => "underparen",
+        _ => return None,
+    })
+}
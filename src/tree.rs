@@ -0,0 +1,676 @@
+//! An owned syntax tree, built from a stream of events.
+//!
+//! The events produced by the [`Parser`] are in prefix notation: an [`Event::Script`] comes before
+//! its base and its scripts, and an [`Event::Visual`] before the elements it applies to. This is
+//! convenient for renderers, but makes analyzing the content of an equation tedious. A [`Tree`]
+//! gathers these elements into [`Node`]s, such as [`Node::Script`] or [`Node::Fraction`], which own
+//! their children.
+//!
+//! A tree can be traversed with a [`Visitor`], transformed with a [`Fold`], and turned back into
+//! events, so that it can be given to any renderer.
+//!
+//! ### Examples
+//!
+//! ```rust
+//! use pulldown_latex::{
+//!     push_mathml,
+//!     tree::{Node, Tree, Visitor},
+//!     Parser, RenderConfig, Storage,
+//! };
+//!
+//! struct CountFractions(usize);
+//!
+//! impl<'a> Visitor<'a> for CountFractions {
+//!     fn visit_node(&mut self, node: &Node<'a>) {
+//!         if let Node::Fraction { .. } = node {
+//!             self.0 += 1;
+//!         }
+//!         pulldown_latex::tree::walk_node(self, node);
+//!     }
+//! }
+//!
+//! let storage = Storage::new();
+//! let parser = Parser::new(r"\frac{1}{1 + \frac{1}{x}}", &storage);
+//! let tree = Tree::from_events(parser.collect::<Result<Vec<_>, _>>().unwrap()).unwrap();
+//!
+//! let mut count = CountFractions(0);
+//! tree.visit(&mut count);
+//! assert_eq!(count.0, 2);
+//!
+//! let mut output = String::new();
+//! push_mathml(&mut output, &tree, RenderConfig::default()).unwrap();
+//! ```
+//!
+//! [`Parser`]: crate::parser::Parser
+
+use std::{convert::Infallible, error::Error, fmt::Display, ops::Range};
+
+use crate::{
+    event::{
+        Content, Dimension, EnvironmentFlow, Event, Grouping, Line, ScriptPosition, ScriptType,
+        StateChange, Visual,
+    },
+    parser::error::ErrorKind,
+};
+
+/// A sequence of nodes, representing a whole equation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tree<'a> {
+    /// The nodes at the top level of the equation.
+    pub nodes: Vec<Node<'a>>,
+}
+
+/// A single _element_ of the event stream, along with its children.
+///
+/// Each variant corresponds to the [`Event`] starting the element. Variants holding children
+/// are documented with the events they stand for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node<'a> {
+    /// An [`Event::Content`].
+    Content(Content<'a>),
+    /// A `Grouping::Normal` or `Grouping::LeftRight` group, and the nodes it contains.
+    Group {
+        /// The kind of group.
+        grouping: Grouping,
+        /// The nodes inside of the group.
+        children: Vec<Node<'a>>,
+    },
+    /// A group whose grouping is an environment, such as `Grouping::Matrix` or
+    /// `Grouping::Align`, split into rows and cells.
+    Environment {
+        /// The kind of environment.
+        grouping: Grouping,
+        /// The horizontal lines drawn before the first row, given by
+        /// [`EnvironmentFlow::StartLines`].
+        start_lines: Option<Box<[Line]>>,
+        /// The rows of the environment.
+        ///
+        /// There is always at least one row. The last row is not ended by a line break, unless
+        /// the environment ends with one, in which case the last row is empty.
+        rows: Vec<Row<'a>>,
+    },
+    /// An [`Event::Script`], along with its base and its scripts.
+    ///
+    /// At least one of `sub` and `sup` must be `Some`. Otherwise, only the base is written
+    /// back as events.
+    Script {
+        /// The position of the scripts.
+        position: ScriptPosition,
+        /// The base of the scripts.
+        base: Box<Node<'a>>,
+        /// The subscript, if any.
+        sub: Option<Box<Node<'a>>>,
+        /// The superscript, if any.
+        sup: Option<Box<Node<'a>>>,
+    },
+    /// A `Visual::Fraction`, along with its numerator and denominator.
+    Fraction {
+        /// The size of the fraction bar, given by `Visual::Fraction`.
+        bar_size: Option<Dimension>,
+        /// The numerator of the fraction.
+        num: Box<Node<'a>>,
+        /// The denominator of the fraction.
+        den: Box<Node<'a>>,
+    },
+    /// A `Visual::SquareRoot`, along with its content.
+    SquareRoot(Box<Node<'a>>),
+    /// A `Visual::Root`, along with its radicand and its index.
+    Root {
+        /// The content of the root.
+        radicand: Box<Node<'a>>,
+        /// The index of the root.
+        index: Box<Node<'a>>,
+    },
+    /// A `Visual::Negation`, along with the negated node.
+    Negation(Box<Node<'a>>),
+    /// An [`Event::Space`].
+    Space {
+        /// The width of the space.
+        width: Option<Dimension>,
+        /// The height of the space.
+        height: Option<Dimension>,
+    },
+    /// An [`Event::StateChange`].
+    ///
+    /// The state change applies to the nodes following it in the same group, or in the same cell
+    /// of an environment.
+    StateChange(StateChange),
+    /// An [`Event::Error`].
+    Error {
+        /// The kind of error that was encountered.
+        kind: ErrorKind,
+        /// The source that was skipped.
+        source: &'a str,
+        /// The range of the original input where the error is located.
+        span: Range<usize>,
+    },
+}
+
+/// A row of an environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row<'a> {
+    /// The cells of the row, separated by [`EnvironmentFlow::Alignment`]s.
+    ///
+    /// There is always at least one cell, which may be empty.
+    pub cells: Vec<Vec<Node<'a>>>,
+    /// The line break ending the row, if any.
+    pub end: Option<RowEnd>,
+}
+
+impl Default for Row<'_> {
+    fn default() -> Self {
+        Self {
+            cells: vec![Vec::new()],
+            end: None,
+        }
+    }
+}
+
+/// The content of an [`EnvironmentFlow::NewLine`] ending a row.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RowEnd {
+    /// The amount of space to add after the line break.
+    pub spacing: Option<Dimension>,
+    /// The horizontal lines to draw after the line break.
+    pub horizontal_lines: Box<[Line]>,
+}
+
+/// A stream of events which does not describe a tree.
+///
+/// Streams produced by the [`Parser`](crate::Parser) always describe a tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TreeError {
+    /// An element was expected, e.g., after an `Event::Script`, but the group or the stream
+    /// ended instead.
+    MissingElement,
+    /// An `Event::End` was found outside of any group.
+    UnbalancedEnd,
+    /// The stream ended before all groups were closed.
+    UnclosedGroup,
+    /// An `Event::EnvironmentFlow` was found outside of an environment, or an
+    /// `EnvironmentFlow::StartLines` was found after the start of an environment.
+    MisplacedFlow,
+}
+
+impl Display for TreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TreeError::MissingElement => "expected an element",
+            TreeError::UnbalancedEnd => "found the end of a group that was not opened",
+            TreeError::UnclosedGroup => "the events ended before all groups were closed",
+            TreeError::MisplacedFlow => "found an environment flow event outside of its place",
+        })
+    }
+}
+
+impl Error for TreeError {}
+
+impl<'a> Tree<'a> {
+    /// Build a tree from a stream of events.
+    ///
+    /// Fallible streams, such as a [`Parser`](crate::Parser), must first be collected into a
+    /// `Result<Vec<Event>, _>`.
+    pub fn from_events<I>(events: I) -> Result<Self, TreeError>
+    where
+        I: IntoIterator<Item = Event<'a>>,
+    {
+        let mut builder = Builder {
+            events: events.into_iter(),
+        };
+        let mut nodes = Vec::new();
+        while let Some(event) = builder.events.next() {
+            match event {
+                Event::End => return Err(TreeError::UnbalancedEnd),
+                Event::EnvironmentFlow(_) => return Err(TreeError::MisplacedFlow),
+                event => nodes.push(builder.node(event)?),
+            }
+        }
+        Ok(Self { nodes })
+    }
+
+    /// Turn the tree back into a stream of events.
+    pub fn to_events(&self) -> Vec<Event<'a>> {
+        let mut events = Vec::new();
+        for node in &self.nodes {
+            node.push_events(&mut events);
+        }
+        events
+    }
+
+    /// Visit every node of the tree, in the order of the events.
+    pub fn visit<V: Visitor<'a> + ?Sized>(&self, visitor: &mut V) {
+        for node in &self.nodes {
+            visitor.visit_node(node);
+        }
+    }
+
+    /// Transform every node at the top level of the tree.
+    pub fn fold<F: Fold<'a> + ?Sized>(self, folder: &mut F) -> Self {
+        Self {
+            nodes: self
+                .nodes
+                .into_iter()
+                .map(|node| folder.fold_node(node))
+                .collect(),
+        }
+    }
+}
+
+/// Renderers take fallible events, so iterating over a reference to the tree yields events
+/// wrapped in `Ok`.
+impl<'a> IntoIterator for &Tree<'a> {
+    type Item = Result<Event<'a>, Infallible>;
+    type IntoIter = std::iter::Map<
+        std::vec::IntoIter<Event<'a>>,
+        fn(Event<'a>) -> Result<Event<'a>, Infallible>,
+    >;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.to_events().into_iter().map(Ok)
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Append the events describing the node to `events`.
+    pub fn push_events(&self, events: &mut Vec<Event<'a>>) {
+        match self {
+            Node::Content(content) => events.push(Event::Content(*content)),
+            Node::Group { grouping, children } => {
+                events.push(Event::Begin(grouping.clone()));
+                children.iter().for_each(|node| node.push_events(events));
+                events.push(Event::End);
+            }
+            Node::Environment {
+                grouping,
+                start_lines,
+                rows,
+            } => {
+                events.push(Event::Begin(grouping.clone()));
+                if let Some(lines) = start_lines {
+                    events.push(Event::EnvironmentFlow(EnvironmentFlow::StartLines {
+                        lines: lines.clone(),
+                    }));
+                }
+                for row in rows {
+                    for (index, cell) in row.cells.iter().enumerate() {
+                        if index != 0 {
+                            events.push(Event::EnvironmentFlow(EnvironmentFlow::Alignment));
+                        }
+                        cell.iter().for_each(|node| node.push_events(events));
+                    }
+                    if let Some(end) = &row.end {
+                        events.push(Event::EnvironmentFlow(EnvironmentFlow::NewLine {
+                            spacing: end.spacing,
+                            horizontal_lines: end.horizontal_lines.clone(),
+                        }));
+                    }
+                }
+                events.push(Event::End);
+            }
+            Node::Script {
+                position,
+                base,
+                sub,
+                sup,
+            } => {
+                let ty = match (sub, sup) {
+                    (Some(_), Some(_)) => ScriptType::SubSuperscript,
+                    (Some(_), None) => ScriptType::Subscript,
+                    (None, Some(_)) => ScriptType::Superscript,
+                    (None, None) => return base.push_events(events),
+                };
+                events.push(Event::Script {
+                    ty,
+                    position: *position,
+                });
+                base.push_events(events);
+                for script in [sub, sup].into_iter().flatten() {
+                    script.push_events(events);
+                }
+            }
+            Node::Fraction { bar_size, num, den } => {
+                events.push(Event::Visual(Visual::Fraction(*bar_size)));
+                num.push_events(events);
+                den.push_events(events);
+            }
+            Node::SquareRoot(content) => {
+                events.push(Event::Visual(Visual::SquareRoot));
+                content.push_events(events);
+            }
+            Node::Root { radicand, index } => {
+                events.push(Event::Visual(Visual::Root));
+                radicand.push_events(events);
+                index.push_events(events);
+            }
+            Node::Negation(content) => {
+                events.push(Event::Visual(Visual::Negation));
+                content.push_events(events);
+            }
+            Node::Space { width, height } => events.push(Event::Space {
+                width: *width,
+                height: *height,
+            }),
+            Node::StateChange(state_change) => events.push(Event::StateChange(*state_change)),
+            Node::Error { kind, source, span } => events.push(Event::Error {
+                kind: kind.clone(),
+                source,
+                span: span.clone(),
+            }),
+        }
+    }
+}
+
+struct Builder<I> {
+    events: I,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Builder<I> {
+    /// Build the next element of the stream.
+    fn element(&mut self) -> Result<Box<Node<'a>>, TreeError> {
+        match self.events.next() {
+            None | Some(Event::End) => Err(TreeError::MissingElement),
+            Some(event) => self.node(event).map(Box::new),
+        }
+    }
+
+    /// Build the node starting with `event`, which is neither an `End` nor an `EnvironmentFlow`.
+    fn node(&mut self, event: Event<'a>) -> Result<Node<'a>, TreeError> {
+        Ok(match event {
+            Event::Content(content) => Node::Content(content),
+            Event::Begin(grouping) if grouping.is_math_env() => self.environment(grouping)?,
+            Event::Begin(grouping) => {
+                let mut children = Vec::new();
+                loop {
+                    match self.events.next() {
+                        None => return Err(TreeError::UnclosedGroup),
+                        Some(Event::End) => break,
+                        Some(Event::EnvironmentFlow(_)) => return Err(TreeError::MisplacedFlow),
+                        Some(event) => children.push(self.node(event)?),
+                    }
+                }
+                Node::Group { grouping, children }
+            }
+            Event::End | Event::EnvironmentFlow(_) => unreachable!("handled by the caller"),
+            Event::Visual(Visual::Fraction(bar_size)) => Node::Fraction {
+                bar_size,
+                num: self.element()?,
+                den: self.element()?,
+            },
+            Event::Visual(Visual::SquareRoot) => Node::SquareRoot(self.element()?),
+            Event::Visual(Visual::Root) => Node::Root {
+                radicand: self.element()?,
+                index: self.element()?,
+            },
+            Event::Visual(Visual::Negation) => Node::Negation(self.element()?),
+            Event::Script { ty, position } => {
+                let base = self.element()?;
+                let (sub, sup) = match ty {
+                    ScriptType::Subscript => (Some(self.element()?), None),
+                    ScriptType::Superscript => (None, Some(self.element()?)),
+                    ScriptType::SubSuperscript => (Some(self.element()?), Some(self.element()?)),
+                };
+                Node::Script {
+                    position,
+                    base,
+                    sub,
+                    sup,
+                }
+            }
+            Event::Space { width, height } => Node::Space { width, height },
+            Event::StateChange(state_change) => Node::StateChange(state_change),
+            Event::Error { kind, source, span } => Node::Error { kind, source, span },
+        })
+    }
+
+    fn environment(&mut self, grouping: Grouping) -> Result<Node<'a>, TreeError> {
+        let mut start_lines = None;
+        let mut rows = Vec::new();
+        let mut row = Row::default();
+        loop {
+            match self.events.next() {
+                None => return Err(TreeError::UnclosedGroup),
+                Some(Event::End) => break,
+                Some(Event::EnvironmentFlow(EnvironmentFlow::StartLines { lines })) => {
+                    if start_lines.is_some() || !rows.is_empty() || row != Row::default() {
+                        return Err(TreeError::MisplacedFlow);
+                    }
+                    start_lines = Some(lines);
+                }
+                Some(Event::EnvironmentFlow(EnvironmentFlow::Alignment)) => {
+                    row.cells.push(Vec::new());
+                }
+                Some(Event::EnvironmentFlow(EnvironmentFlow::NewLine {
+                    spacing,
+                    horizontal_lines,
+                })) => {
+                    row.end = Some(RowEnd {
+                        spacing,
+                        horizontal_lines,
+                    });
+                    rows.push(std::mem::take(&mut row));
+                }
+                Some(event) => {
+                    let node = self.node(event)?;
+                    row.cells
+                        .last_mut()
+                        .expect("rows have at least one cell")
+                        .push(node);
+                }
+            }
+        }
+        rows.push(row);
+        Ok(Node::Environment {
+            grouping,
+            start_lines,
+            rows,
+        })
+    }
+}
+
+/// A traversal of the nodes of a [`Tree`].
+///
+/// The default implementation of [`Visitor::visit_node`] visits the children of the node through
+/// [`walk_node`]. Implementors overriding it should call [`walk_node`] themselves to keep
+/// visiting deeper nodes.
+pub trait Visitor<'a> {
+    /// Visit a node.
+    fn visit_node(&mut self, node: &Node<'a>) {
+        walk_node(self, node)
+    }
+}
+
+/// Visit the children of `node`, in the order of the events.
+pub fn walk_node<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, node: &Node<'a>) {
+    match node {
+        Node::Group { children, .. } => children.iter().for_each(|node| visitor.visit_node(node)),
+        Node::Environment { rows, .. } => rows
+            .iter()
+            .flat_map(|row| row.cells.iter().flatten())
+            .for_each(|node| visitor.visit_node(node)),
+        Node::Script { base, sub, sup, .. } => {
+            visitor.visit_node(base);
+            for script in [sub, sup].into_iter().flatten() {
+                visitor.visit_node(script);
+            }
+        }
+        Node::Fraction { num, den, .. } => {
+            visitor.visit_node(num);
+            visitor.visit_node(den);
+        }
+        Node::Root { radicand, index } => {
+            visitor.visit_node(radicand);
+            visitor.visit_node(index);
+        }
+        Node::SquareRoot(content) | Node::Negation(content) => visitor.visit_node(content),
+        Node::Content(_) | Node::Space { .. } | Node::StateChange(_) | Node::Error { .. } => {}
+    }
+}
+
+/// A transformation of the nodes of a [`Tree`].
+///
+/// The default implementation of [`Fold::fold_node`] transforms the children of the node through
+/// [`fold_children`], and keeps the node itself as is.
+pub trait Fold<'a> {
+    /// Transform a node.
+    fn fold_node(&mut self, node: Node<'a>) -> Node<'a> {
+        fold_children(self, node)
+    }
+}
+
+/// Transform the children of `node`, in the order of the events.
+pub fn fold_children<'a, F: Fold<'a> + ?Sized>(folder: &mut F, node: Node<'a>) -> Node<'a> {
+    let mut fold = |node: Box<Node<'a>>| Box::new(folder.fold_node(*node));
+    match node {
+        Node::Group { grouping, children } => Node::Group {
+            grouping,
+            children: children
+                .into_iter()
+                .map(|node| *fold(Box::new(node)))
+                .collect(),
+        },
+        Node::Environment {
+            grouping,
+            start_lines,
+            rows,
+        } => Node::Environment {
+            grouping,
+            start_lines,
+            rows: rows
+                .into_iter()
+                .map(|row| Row {
+                    cells: row
+                        .cells
+                        .into_iter()
+                        .map(|cell| cell.into_iter().map(|node| *fold(Box::new(node))).collect())
+                        .collect(),
+                    end: row.end,
+                })
+                .collect(),
+        },
+        Node::Script {
+            position,
+            base,
+            sub,
+            sup,
+        } => Node::Script {
+            position,
+            base: fold(base),
+            sub: sub.map(&mut fold),
+            sup: sup.map(&mut fold),
+        },
+        Node::Fraction { bar_size, num, den } => Node::Fraction {
+            bar_size,
+            num: fold(num),
+            den: fold(den),
+        },
+        Node::SquareRoot(content) => Node::SquareRoot(fold(content)),
+        Node::Root { radicand, index } => Node::Root {
+            radicand: fold(radicand),
+            index: fold(index),
+        },
+        Node::Negation(content) => Node::Negation(fold(content)),
+        node @ (Node::Content(_)
+        | Node::Space { .. }
+        | Node::StateChange(_)
+        | Node::Error { .. }) => node,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fold, Node, Tree, TreeError};
+    use crate::{
+        event::{Content, Event, Grouping},
+        Parser, Storage,
+    };
+
+    fn events<'a>(input: &'a str, storage: &'a Storage) -> Vec<Event<'a>> {
+        Parser::new(input, storage)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let inputs = [
+            r"x_1^2 + \frac{a}{b} - \sqrt[3]{x} \not= \sqrt{2}",
+            r"\left( \sum\limits_{i=0}^n i \right) \text{hello} \color{red} x \, y",
+            r"\begin{array}{|c|c|} \hline a & b \\[2pt] \hline c & \end{array}",
+            r"\begin{align} x &= 1 \\ y &= 2 \\ \end{align}",
+            r"\begin{matrix}\end{matrix} \overbrace{a+b}^{n} \hat{x}",
+        ];
+        let storage = Storage::new();
+        for input in inputs {
+            let events = events(input, &storage);
+            let tree = Tree::from_events(events.clone()).unwrap();
+            assert_eq!(tree.to_events(), events, "input: {input}");
+        }
+    }
+
+    #[test]
+    fn rows_and_cells() {
+        let storage = Storage::new();
+        let input = r"\begin{matrix} a & b \\ c \end{matrix}";
+        let tree = Tree::from_events(events(input, &storage)).unwrap();
+        let [Node::Environment { rows, .. }] = &tree.nodes[..] else {
+            panic!("expected a single environment, got {:?}", tree.nodes);
+        };
+        let cells = rows.iter().map(|row| row.cells.len()).collect::<Vec<_>>();
+        assert_eq!(cells, [2, 1]);
+        assert!(rows[0].end.is_some() && rows[1].end.is_none());
+    }
+
+    #[test]
+    fn invalid_streams() {
+        let x = Event::Content(Content::Ordinary {
+            content: 'x',
+            stretchy: false,
+        });
+        let cases = [
+            (vec![Event::End], TreeError::UnbalancedEnd),
+            (
+                vec![Event::Begin(Grouping::Normal), x.clone()],
+                TreeError::UnclosedGroup,
+            ),
+            (
+                vec![Event::Visual(crate::event::Visual::Root), x.clone()],
+                TreeError::MissingElement,
+            ),
+            (
+                vec![Event::EnvironmentFlow(
+                    crate::event::EnvironmentFlow::Alignment,
+                )],
+                TreeError::MisplacedFlow,
+            ),
+        ];
+        for (events, error) in cases {
+            assert_eq!(Tree::from_events(events), Err(error));
+        }
+    }
+
+    #[test]
+    fn fold() {
+        struct SwapFractions;
+
+        impl<'a> Fold<'a> for SwapFractions {
+            fn fold_node(&mut self, node: Node<'a>) -> Node<'a> {
+                match super::fold_children(self, node) {
+                    Node::Fraction { bar_size, num, den } => Node::Fraction {
+                        bar_size,
+                        num: den,
+                        den: num,
+                    },
+                    node => node,
+                }
+            }
+        }
+
+        let storage = Storage::new();
+        let tree = Tree::from_events(events(r"\frac{a}{\frac{b}{c}}", &storage)).unwrap();
+        let swapped = tree.fold(&mut SwapFractions);
+        assert_eq!(
+            swapped.to_events(),
+            events(r"\frac{\frac{c}{b}}{a}", &storage)
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+use pulldown_latex::{
+    config::{DisplayMode, MathStyle, Profile},
+    push_mathml, Parser, RenderConfig, Storage,
+};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pulldown-latex"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn mathml(input: &str, config: RenderConfig) -> String {
+    let storage = Storage::new();
+    let mut output = String::new();
+    push_mathml(&mut output, Parser::new(input, &storage), config).unwrap();
+    output
+}
+
+#[test]
+fn stdin() {
+    let output = run(&[], r"\frac{a}{b}");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        mathml(r"\frac{a}{b}", RenderConfig::default()) + "\n"
+    );
+}
+
+#[test]
+fn render_config_flags() {
+    let input = r"\alpha + B";
+    let output = run(
+        &[
+            "--display",
+            "block",
+            "--math-style=iso",
+            "--xml-prefix",
+            "--annotation",
+            "--error-color",
+            "#0000ff",
+            "--safe",
+            "--self-contained",
+            "--profile",
+            "mathml3",
+            "-e",
+            input,
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    let config = RenderConfig {
+        display_mode: DisplayMode::Block,
+        annotation: Some(input),
+        error_color: (0, 0, 255),
+        xml: true,
+        xml_prefix: true,
+        math_style: MathStyle::ISO,
+        safe: true,
+        profile: Profile::MathML3,
+        self_contained: true,
+    };
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        mathml(input, config) + "\n"
+    );
+}
+
+#[test]
+fn files() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.tex");
+    let second = dir.path().join("second.tex");
+    std::fs::write(&first, "x^2").unwrap();
+    std::fs::write(&second, r"\sqrt{y}").unwrap();
+
+    let output = run(&[first.to_str().unwrap(), second.to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "{}\n{}\n",
+            mathml("x^2", RenderConfig::default()),
+            mathml(r"\sqrt{y}", RenderConfig::default())
+        )
+    );
+}
+
+#[test]
+fn lines() {
+    let output = run(&["--lines"], "a\n\n\\unknown\nb\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], mathml("a", RenderConfig::default()));
+    assert!(lines[1].contains("<merror"));
+    assert_eq!(lines[2], mathml("b", RenderConfig::default()));
+}
+
+#[test]
+fn fail_on_error() {
+    let output = run(&["--lines", "--fail-on-error"], "a\n\nx + \\unknown\n");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("<stdin>:3:5: parsing error: unknown primitive command found"));
+
+    let output = run(&["--fail-on-error", "-e", "a + b"], "");
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn events() {
+    let output = run(&["--events", "-e", r"\sqrt x"], "");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Visual(SquareRoot)\nContent(Ordinary { content: 'x', stretchy: false })\n"
+    );
+}
+
+#[test]
+fn usage_errors() {
+    for args in [
+        &["--bogus"][..],
+        &["--display", "wide"],
+        &["--xml=yes"],
+        &["-e"],
+    ] {
+        let output = run(args, "");
+        assert_eq!(output.status.code(), Some(2), "args: {args:?}");
+        assert!(output.stdout.is_empty());
+    }
+}
@@ -0,0 +1,83 @@
+use pulldown_cmark::{html, Options, Parser};
+use pulldown_latex::{
+    cmark::MathEvents,
+    config::{DisplayMode, ParserConfig},
+    push_mathml, RenderConfig, Storage,
+};
+
+fn mathml(input: &str, display_mode: DisplayMode) -> String {
+    let storage = Storage::new();
+    let mut output = String::new();
+    push_mathml(
+        &mut output,
+        pulldown_latex::Parser::new(input, &storage),
+        RenderConfig {
+            display_mode,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    output
+}
+
+fn render(markdown: &str) -> String {
+    let mut output = String::new();
+    html::push_html(
+        &mut output,
+        MathEvents::new(Parser::new_ext(markdown, Options::ENABLE_MATH)),
+    );
+    output
+}
+
+#[test]
+fn display_modes() {
+    let output = render(r"Inline $x^2$ and display $$\frac{1}{2}$$.");
+    assert_eq!(
+        output,
+        format!(
+            "<p>Inline {} and display {}.</p>\n",
+            mathml("x^2", DisplayMode::Inline),
+            mathml(r"\frac{1}{2}", DisplayMode::Block),
+        )
+    );
+}
+
+#[test]
+fn storage_reset_between_equations() {
+    let equations = (0..64)
+        .map(|i| format!(r"${i} + \mathrm{{abc}}_{{{i}}}$"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let output = render(&equations);
+    for i in 0..64 {
+        let expected = mathml(
+            &format!(r"{i} + \mathrm{{abc}}_{{{i}}}"),
+            DisplayMode::Inline,
+        );
+        assert!(output.contains(&expected), "equation {i}");
+    }
+}
+
+#[test]
+fn error_hook() {
+    let mut errors = Vec::new();
+    let parser = Parser::new_ext(r"$a$, $\frac{1}$ and $\unknown$", Options::ENABLE_MATH);
+    let events = MathEvents::with_config(parser, ParserConfig::default(), RenderConfig::default())
+        .on_error(|source, _| errors.push(source.to_owned()));
+
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    assert_eq!(errors, [r"\frac{1}", r"\unknown"]);
+    assert!(output.contains("<merror"));
+}
+
+#[test]
+fn non_math_events_untouched() {
+    let markdown = "# Title\n\nSome `code` and *emphasis*.\n";
+    let mut expected = String::new();
+    html::push_html(
+        &mut expected,
+        Parser::new_ext(markdown, Options::ENABLE_MATH),
+    );
+    assert_eq!(render(markdown), expected);
+}
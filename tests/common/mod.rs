@@ -1,4 +1,4 @@
-#![allow(clippy::await_holding_lock)]
+#![allow(clippy::await_holding_lock, dead_code)]
 
 use std::{io::Write, mem::MaybeUninit, process::Stdio, sync::Mutex, time::Duration};
 
@@ -6,7 +6,12 @@ use fantoccini::{Client, ClientBuilder, Locator};
 use heck::ToTitleCase;
 use inventory::collect;
 use libtest_mimic::{Arguments, Conclusion, Failed, Trial};
-use pulldown_latex::{config::RenderConfig, mathml::push_mathml, Parser, Storage};
+use pulldown_latex::{
+    config::{Profile, RenderConfig},
+    event::validate::Validator,
+    mathml::push_mathml,
+    Parser, Storage,
+};
 use tokio::process::Command;
 
 #[allow(clippy::type_complexity)]
@@ -38,9 +43,26 @@ pub fn round_trip(
         .iter()
         .map(|input| -> Result<_, Failed> {
             show_errors(Parser::new(input, &storage))?;
-            let parser = Parser::new(input, &storage);
+            let parser = Validator::new(Parser::new(input, &storage));
             let mut output = String::new();
             push_mathml(&mut output, parser, config)?;
+            for (xml_prefix, profile, self_contained) in [
+                (false, Profile::Core, false),
+                (true, Profile::Core, false),
+                (false, Profile::MathML3, false),
+                (true, Profile::Core, true),
+            ] {
+                let config = RenderConfig {
+                    xml: true,
+                    xml_prefix,
+                    profile,
+                    self_contained,
+                    ..config
+                };
+                let mut xml = String::new();
+                push_mathml(&mut xml, Parser::new(input, &storage), config)?;
+                check_xml(&xml)?;
+            }
             storage.reset();
             Ok((*input, output))
         })
@@ -51,6 +73,22 @@ pub fn round_trip(
     Ok(())
 }
 
+/// Check that the output is well-formed XML, whose elements are all in the `MathML` namespace.
+pub fn check_xml(output: &str) -> Result<(), Failed> {
+    let document = roxmltree::Document::parse(output)?;
+    if document.root_element().tag_name().name() != "math" {
+        return Err(format!("root element is not `math`: {output}").into());
+    }
+    match document
+        .descendants()
+        .filter(|node| node.is_element())
+        .find(|node| node.tag_name().namespace() != Some("http://www.w3.org/1998/Math/MathML"))
+    {
+        Some(node) => Err(format!("element outside of the MathML namespace: {node:?}").into()),
+        None => Ok(()),
+    }
+}
+
 pub fn show_errors(parser: Parser) -> Result<(), usize> {
     let mut error_count = 0;
 
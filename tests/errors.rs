@@ -1,4 +1,9 @@
-use pulldown_latex::{push_mathml, Parser, Storage};
+use pulldown_latex::{
+    config::{Limits, ParserConfig, UnknownCommandPolicy},
+    event::{Content, Event, Grouping, ScriptPosition, ScriptType},
+    parser::{error::ErrorKind, extension::UnknownCommand},
+    push_mathml, Parser, Storage,
+};
 
 macro_rules! should_error {
     ($name:ident, $($input:literal),+ $(,)?) => {
@@ -24,6 +29,32 @@ fn error_rendering() {
     push_mathml(&mut out, parser, Default::default()).unwrap();
 }
 
+#[test]
+fn error_span_in_expansion() {
+    let input = "\\def\\bad{\\unknown}\nx + \\bad + y";
+    let storage = Storage::new();
+    let error = Parser::new(input, &storage)
+        .find_map(Result::err)
+        .expect("the input should error");
+
+    assert_eq!(error.kind(), &ErrorKind::UnknownPrimitive);
+    assert_eq!(&input[error.span().range.clone()], "\\bad");
+    assert_eq!((error.span().line, error.span().column), (2, 5));
+}
+
+#[test]
+fn recursive_expansion_context() {
+    let storage = Storage::new();
+    let error = Parser::new(r"\def\a{x\a}\a", &storage)
+        .find_map(Result::err)
+        .expect("the recursion should be stopped");
+
+    assert_eq!(error.kind(), &ErrorKind::ExpansionTooDeep);
+    let message = error.to_string();
+    assert!(message.contains("… and 250 more expansions"), "{message}");
+    assert!(message.len() < 1024, "{message}");
+}
+
 should_error! {
     double_scripts,
     r"a^b^c",
@@ -47,3 +78,263 @@ fn comments() {
         Err(e) => eprintln!("Error while rendering: {}", e),
     }
 }
+
+#[test]
+fn resource_limits() {
+    fn first_error(input: &str, limits: Limits) -> Option<ErrorKind> {
+        let storage = Storage::new();
+        let config = ParserConfig {
+            limits,
+            ..ParserConfig::default()
+        };
+        let mut parser = Parser::with_config(input, &storage, config);
+        let error = parser.find_map(Result::err)?;
+        assert!(error.kind().is_resource_limit());
+        // Only the ends of the open groups, and the groups of arguments already queued, follow
+        // the error, without their content.
+        let rest = parser
+            .collect::<Result<Vec<_>, _>>()
+            .expect("the parser should stop at the first error");
+        assert!(
+            rest.iter()
+                .all(|e| matches!(e, Event::Begin(_) | Event::End)),
+            "the parser should stop: {rest:?}"
+        );
+
+        // The events before the error are balanced by the ones after it.
+        let events = Parser::with_config(input, &storage, config).collect::<Vec<_>>();
+        let begins = events
+            .iter()
+            .filter(|e| matches!(e, Ok(Event::Begin(_))))
+            .count();
+        let ends = events
+            .iter()
+            .filter(|e| matches!(e, Ok(Event::End)))
+            .count();
+        assert_eq!(begins, ends);
+        let mut mathml = String::new();
+        push_mathml(
+            &mut mathml,
+            Parser::with_config(input, &storage, config),
+            Default::default(),
+        )
+        .unwrap();
+        assert!(mathml.ends_with("</math>"));
+        Some(error.kind().clone())
+    }
+    fn default_depth() -> usize {
+        Limits::default().max_group_depth.unwrap()
+    }
+    let default = Limits::default();
+
+    assert_eq!(
+        first_error(
+            r"\def\a{x\a}\a",
+            Limits {
+                max_expansions: Some(100),
+                ..Limits::none()
+            }
+        ),
+        Some(ErrorKind::TooManyExpansions)
+    );
+    assert!(first_error(r"\def\a{\a\a}\a", default).is_some());
+    assert_eq!(
+        first_error(r"\def\a{\a x}\a", default),
+        Some(ErrorKind::ExpansionTooDeep)
+    );
+    assert_eq!(
+        first_error(
+            r"\def\a#1{#1#1#1#1}\a{\a{\a{\a{\a{\a{\a{\a{xxxxxxxx}}}}}}}}",
+            Limits {
+                max_expanded_bytes: Some(1024),
+                ..default
+            }
+        ),
+        Some(ErrorKind::ExpansionTooLarge)
+    );
+    assert!(first_error(
+        r"\def\a#1{#1#1#1#1}\a{\a{\a{\a{\a{\a{\a{\a{xxxxxxxx}}}}}}}}",
+        default
+    )
+    .is_some());
+    assert_eq!(
+        first_error(&("{".repeat(100_000) + &"}".repeat(100_000)), default),
+        Some(ErrorKind::GroupTooDeep)
+    );
+    let depth = default_depth();
+    for count in [depth + 1, depth + 2] {
+        assert_eq!(
+            first_error(&("{".repeat(count) + "x" + &"}".repeat(count)), default),
+            Some(ErrorKind::GroupTooDeep)
+        );
+    }
+    assert_eq!(
+        first_error(
+            &(r"\frac{".repeat(depth + 1) + "x" + &"}{y}".repeat(depth + 1)),
+            default
+        ),
+        Some(ErrorKind::GroupTooDeep)
+    );
+    for command in ["\\sqrt", "\\mathrm"] {
+        assert_eq!(
+            first_error(&command.repeat(100_000), default),
+            Some(ErrorKind::GroupTooDeep)
+        );
+    }
+    assert_eq!(
+        first_error(
+            "x + y",
+            Limits {
+                max_input_length: Some(4),
+                ..default
+            }
+        ),
+        Some(ErrorKind::InputTooLong)
+    );
+    assert_eq!(first_error(&"%\n".repeat(100_000), Limits::none()), None);
+}
+
+#[test]
+fn error_recovery() {
+    let input =
+        r"a + \begin{matrix} x & \unknown{y & z} & w \\ \sqrt \end{matrix} + {b \foo c} + \frac{1}";
+    let storage = Storage::new();
+    let config = ParserConfig {
+        error_recovery: true,
+        ..ParserConfig::default()
+    };
+    let events = Parser::with_config(input, &storage, config)
+        .collect::<Result<Vec<_>, _>>()
+        .expect("errors should be recovered from");
+
+    let errors = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Error { kind, source, span } => {
+                assert_eq!(&input[span.clone()], *source);
+                Some((kind.clone(), *source))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        [
+            (ErrorKind::UnknownPrimitive, r"\unknown{y & z}"),
+            (ErrorKind::Token, r"\sqrt"),
+            (ErrorKind::UnknownPrimitive, r"\foo c"),
+            (ErrorKind::Token, r"\frac{1}"),
+        ]
+    );
+
+    let begins = events
+        .iter()
+        .filter(|e| matches!(e, Event::Begin(_)))
+        .count();
+    let ends = events.iter().filter(|e| matches!(e, Event::End)).count();
+    assert_eq!(begins, ends);
+
+    let mut mathml = String::new();
+    let parser = Parser::with_config(input, &storage, config);
+    push_mathml(&mut mathml, parser, Default::default()).unwrap();
+    assert!(mathml.contains(r"<mtext>\sqrt</mtext></merror>"));
+}
+
+#[test]
+fn error_recovery_with_limits() {
+    let input = r"x^{\def\a{\a\a}\a} + {{{{y}}}}";
+    let storage = Storage::new();
+    let config = ParserConfig {
+        error_recovery: true,
+        limits: Limits {
+            max_group_depth: Some(3),
+            ..Limits::default()
+        },
+        ..ParserConfig::default()
+    };
+    let events = Parser::with_config(input, &storage, config)
+        .collect::<Result<Vec<_>, _>>()
+        .expect("errors should be recovered from");
+
+    assert!(matches!(
+        events.iter().find(|e| matches!(e, Event::Error { .. })),
+        Some(Event::Error { kind, source: r"\a", .. }) if kind.is_resource_limit()
+    ));
+    let begins = events
+        .iter()
+        .filter(|e| matches!(e, Event::Begin(_)))
+        .count();
+    let ends = events.iter().filter(|e| matches!(e, Event::End)).count();
+    assert_eq!(begins, ends);
+}
+
+#[test]
+fn unknown_command_policy() {
+    fn vendor<'a>(command: &UnknownCommand<'a>) -> Option<Vec<Event<'a>>> {
+        command.name().strip_prefix("vendor").map(|rest| {
+            vec![
+                Event::Content(Content::Text("vendor: ")),
+                Event::Content(Content::Function(rest)),
+            ]
+        })
+    }
+    fn events<'a>(
+        input: &'a str,
+        storage: &'a Storage,
+        unknown_commands: UnknownCommandPolicy<'a>,
+    ) -> Vec<Result<Event<'a>, ErrorKind>> {
+        let config = ParserConfig {
+            unknown_commands,
+            ..ParserConfig::default()
+        };
+        Parser::with_config(input, storage, config)
+            .map(|event| event.map_err(|e| e.kind().clone()))
+            .collect()
+    }
+    let storage = Storage::new();
+
+    let input = r"\def\x{\foo}a + \x";
+    assert_eq!(
+        events(input, &storage, UnknownCommandPolicy::RawText)[2],
+        Ok(Event::Error {
+            kind: ErrorKind::UnknownPrimitive,
+            source: r"\foo",
+            span: 16..18,
+        })
+    );
+    assert_eq!(
+        events(
+            r"\vendorsize^2 \other",
+            &storage,
+            UnknownCommandPolicy::Callback(&vendor)
+        ),
+        [
+            Ok(Event::Script {
+                ty: ScriptType::Superscript,
+                position: ScriptPosition::Right,
+            }),
+            Ok(Event::Begin(Grouping::Normal)),
+            Ok(Event::Content(Content::Text("vendor: "))),
+            Ok(Event::Content(Content::Function("size"))),
+            Ok(Event::End),
+            Ok(Event::Content(Content::Number("2"))),
+            Err(ErrorKind::UnknownPrimitive),
+        ]
+    );
+
+    fn placeholder<'a>(command: &UnknownCommand<'a>) -> Option<Vec<Event<'a>>> {
+        let text = command.alloc_str(&format!("[{}]", command.name().to_uppercase()));
+        Some(vec![Event::Content(Content::Text(text))])
+    }
+    assert_eq!(
+        events(
+            r"\foo\qux",
+            &storage,
+            UnknownCommandPolicy::Callback(&placeholder)
+        ),
+        [
+            Ok(Event::Content(Content::Text("[FOO]"))),
+            Ok(Event::Content(Content::Text("[QUX]"))),
+        ]
+    );
+}
@@ -0,0 +1,320 @@
+use pulldown_latex::{
+    config::{ParserConfig, Profile, UnknownCommandPolicy},
+    event::{Content, Event},
+    parser::extension::UnknownCommand,
+    push_mathml, Parser, RenderConfig, Storage,
+};
+
+/// The elements written by the renderer.
+const ELEMENTS: &[&str] = &[
+    "annotation",
+    "math",
+    "menclose",
+    "merror",
+    "mfrac",
+    "mi",
+    "mlabeledtr",
+    "mn",
+    "mo",
+    "mover",
+    "mroot",
+    "mrow",
+    "mspace",
+    "msqrt",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+    "semantics",
+];
+
+const HOSTILE: &[&str] = &[
+    r"\text{<img src=x onerror=alert(1)>}",
+    r"\text{<script>alert(1)</script>}",
+    r"\text{</mtext></math><script>alert(1)</script>}",
+    r#"\text{" onmouseover="alert(1)}"#,
+    r"\text{&amp; &lt; &#60; &#x3C;}",
+    r"\text{ <b> }",
+    r"\operatorname{<img src=x onerror=alert(1)>}",
+    r"\operatorname*{</mi><script>}",
+    r"\mathrm{<script>}",
+    r"a < b > c \& d",
+    r"\left< x \right>",
+    r"\frac{<}{>}_{&}",
+    r"\text{</annotation><script>alert(1)</script>}",
+    r"\unknown<script>alert(1)</script>",
+    r"\frac{<script>",
+    r"\text{<script>",
+    "\\text{\u{0}\u{8}\u{1b}}",
+    r"\é<script>",
+    r"\text{]]><script>alert(1)</script>}",
+    r"\begin{array}{c<}a\end{array}",
+    r"\def\x{<script>}\text{\x}",
+    r"\color{<script>}{x}",
+    "\\text{\u{fffe}\u{ffff}}",
+    r"a~b\ c \text{ d }",
+];
+
+fn render(input: &str, parser_config: ParserConfig, render_config: RenderConfig) -> String {
+    let storage = Storage::new();
+    let mut output = String::new();
+    push_mathml(
+        &mut output,
+        Parser::with_config(input, &storage, parser_config),
+        render_config,
+    )
+    .unwrap();
+    output
+}
+
+/// Check that the output only contains the elements of the renderer, that attribute values contain
+/// no quotes, and that text contains no markup.
+fn assert_inert(input: &str, output: &str) {
+    let mut rest = output;
+    while let Some(start) = rest.find('<') {
+        check_text(input, &rest[..start]);
+        let end = rest[start..]
+            .find('>')
+            .unwrap_or_else(|| panic!("unclosed tag for {input:?}: {output}"))
+            + start;
+        let tag = &rest[start + 1..end];
+        let name = tag
+            .trim_start_matches('/')
+            .split([' ', '/'])
+            .next()
+            .unwrap();
+        assert!(
+            ELEMENTS.contains(&name),
+            "unexpected element `{name}` for {input:?}: {output}"
+        );
+        assert!(
+            !tag.contains(['<', '&']) && tag.matches('"').count() % 2 == 0,
+            "unexpected attribute value for {input:?}: {output}"
+        );
+        rest = &rest[end + 1..];
+    }
+    check_text(input, rest);
+}
+
+fn check_text(input: &str, text: &str) {
+    assert!(!text.contains('>'), "unescaped `>` for {input:?}: {text}");
+    assert!(
+        !text.chars().any(|c| c.is_control() && c != '\n'),
+        "control character for {input:?}: {text:?}"
+    );
+    for (index, _) in text.match_indices('&') {
+        let entity = &text[index..];
+        assert!(
+            ["&amp;", "&lt;", "&gt;", "&quot;", "&#160;"]
+                .iter()
+                .any(|escaped| entity.starts_with(escaped)),
+            "unescaped `&` for {input:?}: {text}"
+        );
+    }
+}
+
+#[test]
+fn hostile_inputs() {
+    let function_policy = ParserConfig {
+        unknown_commands: UnknownCommandPolicy::Function,
+        ..ParserConfig::default()
+    };
+    let raw_text_policy = ParserConfig {
+        unknown_commands: UnknownCommandPolicy::RawText,
+        error_recovery: true,
+        ..ParserConfig::default()
+    };
+
+    for input in HOSTILE {
+        for parser_config in [ParserConfig::default(), function_policy, raw_text_policy] {
+            for (safe, profile, self_contained) in [
+                (false, Profile::Core, false),
+                (true, Profile::Core, false),
+                (false, Profile::MathML3, false),
+                (true, Profile::MathML3, false),
+                (false, Profile::Core, true),
+                (true, Profile::Core, true),
+            ] {
+                let render_config = RenderConfig {
+                    annotation: Some(input),
+                    safe,
+                    profile,
+                    self_contained,
+                    ..RenderConfig::default()
+                };
+                let output = render(input, parser_config, render_config);
+                assert_inert(input, &output);
+                assert!(!output.contains("<script"), "{output}");
+            }
+        }
+    }
+}
+
+#[test]
+fn escaped_text() {
+    let output = render(
+        r"\text{<b>&amp;</b>} \operatorname{a<b}",
+        ParserConfig::default(),
+        RenderConfig::default(),
+    );
+    assert!(output.contains("<mtext>&lt;b&gt;&amp;amp;&lt;/b&gt;</mtext>"));
+    assert!(output.contains("<mi>a&lt;b</mi>"));
+
+    let input = r#"x</annotation><script>"#;
+    let output = render(
+        input,
+        ParserConfig::default(),
+        RenderConfig::with_annotation(input),
+    );
+    assert!(output.ends_with(
+        "<annotation encoding=\"application/x-tex\">x&lt;/annotation&gt;&lt;script&gt;\
+         </annotation></semantics></math>"
+    ));
+}
+
+#[test]
+fn callback_events() {
+    fn vendor<'a>(_: &UnknownCommand<'a>) -> Option<Vec<Event<'a>>> {
+        Some(vec![
+            Event::Content(Content::Text("<script>alert(1)</script>")),
+            Event::Content(Content::Function("\"><img>")),
+            Event::Content(Content::Number("<1>")),
+        ])
+    }
+    let parser_config = ParserConfig {
+        unknown_commands: UnknownCommandPolicy::Callback(&vendor),
+        ..ParserConfig::default()
+    };
+    for safe in [false, true] {
+        let render_config = RenderConfig {
+            safe,
+            ..RenderConfig::default()
+        };
+        let output = render(r"\vendor", parser_config, render_config);
+        assert_inert(r"\vendor", &output);
+    }
+}
+
+#[test]
+fn safe_output_is_unchanged() {
+    let inputs = [
+        r"\sum_{i=0}^n i^2 = \frac{n(n+1)(2n+1)}{6}",
+        r"{\color{red} x} + \colorbox{blue}{y} + \fcolorbox{red}{green}{z}",
+        r"\begin{array}{c|c} a & b \\[2pt] \hline c & d \end{array}",
+        r"\begin{align} a &= b \\ c &= d \end{align}",
+        r"\sqrt[3]{x} \quad \! \hspace{-1em} \mathbb{R} \mathrm{d}x",
+        r"\text{ a < b } \operatorname{sn} x \unknown",
+        r"\displaystyle \lim_{x \to 0} \binom{n}{k}",
+    ];
+    for input in inputs {
+        for (xml, xml_prefix, profile, self_contained) in [
+            (false, false, Profile::Core, false),
+            (true, false, Profile::Core, false),
+            (true, true, Profile::Core, false),
+            (false, false, Profile::MathML3, false),
+            (true, true, Profile::MathML3, false),
+            (false, false, Profile::Core, true),
+            (true, true, Profile::Core, true),
+        ] {
+            let config = RenderConfig {
+                annotation: Some(input),
+                xml,
+                xml_prefix,
+                profile,
+                self_contained,
+                ..RenderConfig::default()
+            };
+            let safe = RenderConfig {
+                safe: true,
+                ..config
+            };
+            assert_eq!(
+                render(input, ParserConfig::default(), safe),
+                render(input, ParserConfig::default(), config),
+                "{input}"
+            );
+        }
+    }
+}
+
+#[test]
+fn far_negative_spaces() {
+    let inputs = [
+        r"x \kern-99999999999999999999pt y",
+        r"x \hspace{-1000em} y",
+        r"x \mkern-99999mu y",
+        r"x \kern-1in y",
+    ];
+    for input in inputs {
+        for (profile, self_contained) in [
+            (Profile::Core, false),
+            (Profile::MathML3, false),
+            (Profile::Core, true),
+        ] {
+            let render_config = RenderConfig {
+                safe: true,
+                profile,
+                self_contained,
+                ..RenderConfig::default()
+            };
+            let output = render(input, ParserConfig::default(), render_config);
+            assert_inert(input, &output);
+            assert!(output.contains("<mspace />"), "{input}: {output}");
+            assert!(
+                !output.contains("margin-left") && !output.contains("width=\"-"),
+                "{input}: {output}"
+            );
+        }
+    }
+}
+
+#[test]
+fn xml_output() {
+    for input in HOSTILE.iter().chain([&r"\big( x \Bigr] \text{a~b}"]) {
+        for (xml_prefix, safe) in [(false, false), (true, false), (false, true), (true, true)] {
+            for (profile, self_contained) in [
+                (Profile::Core, false),
+                (Profile::MathML3, false),
+                (Profile::Core, true),
+            ] {
+                let render_config = RenderConfig {
+                    annotation: Some(input),
+                    xml: true,
+                    xml_prefix,
+                    safe,
+                    profile,
+                    self_contained,
+                    ..RenderConfig::default()
+                };
+                let output = render(input, ParserConfig::default(), render_config);
+                let document = roxmltree::Document::parse(&output)
+                    .unwrap_or_else(|e| panic!("{e} for {input:?}: {output}"));
+                assert!(document
+                    .descendants()
+                    .filter(|node| node.is_element())
+                    .all(|node| node.tag_name().namespace()
+                        == Some("http://www.w3.org/1998/Math/MathML")));
+                assert_eq!(output.starts_with("<m:math "), xml_prefix, "{output}");
+            }
+        }
+    }
+
+    let output = render(
+        r"a~b\ c",
+        ParserConfig::default(),
+        RenderConfig {
+            xml: true,
+            ..RenderConfig::default()
+        },
+    );
+    assert_eq!(
+        output,
+        "<math display=\"inline\" xmlns=\"http://www.w3.org/1998/Math/MathML\"><mi>a</mi>\
+         <mtext>&#160;</mtext><mi>b</mi><mtext>&#160;</mtext><mi>c</mi></math>"
+    );
+}
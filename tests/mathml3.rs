@@ -0,0 +1,91 @@
+use pulldown_latex::{config::Profile, push_mathml, Parser, RenderConfig, Storage};
+
+fn render(input: &str) -> String {
+    let storage = Storage::new();
+    let mut output = String::new();
+    push_mathml(
+        &mut output,
+        Parser::new(input, &storage),
+        RenderConfig {
+            profile: Profile::MathML3,
+            ..RenderConfig::default()
+        },
+    )
+    .unwrap();
+    output
+}
+
+/// The output must not depend on the stylesheet of the crate.
+fn assert_unstyled(output: &str) {
+    assert!(
+        !output.contains("class=") && !output.contains("style="),
+        "{output}"
+    );
+}
+
+#[test]
+fn numbered_rows() {
+    let output =
+        render(r"\begin{align} a &= b \\ c &= d \\ \end{align} \begin{equation} x \end{equation}");
+    assert_unstyled(&output);
+    assert_eq!(output.matches("<mlabeledtr>").count(), 3, "{output}");
+    assert!(output.contains(r#"<mtable columnalign="right left" columnspacing="0em">"#));
+    assert!(output.contains("<mlabeledtr><mtd><mtext>(2)</mtext></mtd><mtd><mi>c</mi></mtd>"));
+    assert!(output.contains("<mtd><mtext>(3)</mtext></mtd><mtd><mi>x</mi></mtd>"));
+
+    let output = render(r"\begin{align*} a &= b \\ c &= d \end{align*}");
+    assert!(!output.contains("<mlabeledtr>"), "{output}");
+}
+
+#[test]
+fn array_lines() {
+    let output = render(
+        r"\begin{array}{|c|l||r|} \hline a & b & c \\[2pt] \hline d & e & f \\ \hline \end{array}",
+    );
+    assert_unstyled(&output);
+    assert!(output.starts_with(
+        r#"<math display="inline"><menclose notation="top bottom left right"><mtable columnalign="center left right" columnlines="solid solid" rowlines="solid">"#
+    ), "{output}");
+    assert!(output.contains(r#"<mspace depth="#), "{output}");
+    assert_eq!(output.matches("<mtr>").count(), 2, "{output}");
+
+    let output = render(r"\begin{array}{cc} a & b \\ \hdashline c & d \end{array}");
+    assert!(output.contains(r#"<mtable rowlines="dashed">"#));
+}
+
+#[test]
+fn native_attributes() {
+    let output = render(
+        r"\fcolorbox{red}{green}{z} {\color{blue} x} \mathbf{x} \mathbb{R} \not{ab} \hspace{-1em}",
+    );
+    assert_unstyled(&output);
+    assert!(output.contains(r##"<menclose mathbackground="#008000" notation="box">"##));
+    assert!(output.contains(r##"<mrow mathcolor="#0000ff">"##));
+    assert!(output.contains(r#"<mi mathvariant="bold">x</mi>"#));
+    assert!(output.contains(r#"<mi mathvariant="double-struck">R</mi>"#));
+    assert!(output.contains(
+        r#"<menclose notation="updiagonalstrike"><mrow><mi>a</mi><mi>b</mi></mrow></menclose>"#
+    ));
+    assert!(output.contains(r#"<mspace width="-1em" />"#));
+}
+
+#[test]
+fn multline_and_cases() {
+    let output = render(r"\begin{multline} a \\ b \\ c \end{multline}");
+    assert_unstyled(&output);
+    assert!(output.contains(r#"<mtr columnalign="left"><mtd><mi>a</mi>"#));
+    assert!(output.contains(r#"<mtr columnalign="right"><mtd><mi>c</mi>"#));
+
+    let output = render(r"\begin{cases} a & b \\ c & d \end{cases}");
+    assert_unstyled(&output);
+    assert!(output.starts_with(
+        r#"<math display="inline"><mrow><mo stretchy="true">{</mo><mtable columnalign="left" columnspacing="1em">"#
+    ), "{output}");
+}
+
+#[test]
+fn alignat_rows() {
+    let output = render(r"\begin{alignat}{2} a &= b &&= c \\ d &= e &&= f \end{alignat}");
+    assert_eq!(output.matches("<mlabeledtr>").count(), 2, "{output}");
+    assert_eq!(output.matches("<mtd>").count(), 10, "{output}");
+}
@@ -0,0 +1,104 @@
+use pulldown_latex::{
+    config::{ParserConfig, UnknownCommandPolicy},
+    push_mathml, Event, EventBuffer, Parser, RenderConfig, Storage,
+};
+
+const INPUTS: &[&str] = &[
+    r"x^2_{\text{max}} + \frac{1}{2}",
+    r"\mathbf{A} \cdot \vec{v} \leq \left( \sqrt[3]{y} \right)",
+    r"\color{red} a \neq \colorbox{#00ff00}{b} \hspace{1.5em} \kern-3mu c",
+    r"\begin{pmatrix} 1 & 2 \\ 3 & 4 \end{pmatrix}",
+    r"\begin{array}{|c:l|} \hline a & b \\ \hdashline c & d \end{array}",
+    r"\def\f#1{f(#1)} \f{x} = \operatorname{arccotan} 3.14",
+    r"\displaystyle \sum_{i=0}^\infty \int\limits_a^b \mathcal{L} \, dx",
+];
+
+fn mathml(events: &[Event]) -> String {
+    let mut output = String::new();
+    push_mathml(
+        &mut output,
+        events
+            .iter()
+            .cloned()
+            .map(Ok::<_, std::convert::Infallible>),
+        RenderConfig::default(),
+    )
+    .unwrap();
+    output
+}
+
+#[test]
+fn round_trip() {
+    let storage = Storage::new();
+    for input in INPUTS {
+        let events = Parser::new(input, &storage)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let json = serde_json::to_string(&events).unwrap();
+        let deserialized: Vec<Event> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(events, deserialized, "input: {input}");
+        assert_eq!(mathml(&events), mathml(&deserialized), "input: {input}");
+    }
+}
+
+#[test]
+fn round_trip_errors() {
+    let storage = Storage::new();
+    let config = ParserConfig {
+        error_recovery: true,
+        ..ParserConfig::default()
+    };
+    let events = Parser::with_config("a^b^c + {x_1_2}", &storage, config)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert!(events.iter().any(|e| matches!(e, Event::Error { .. })));
+
+    let json = serde_json::to_string(&events).unwrap();
+    let deserialized: Vec<Event> = serde_json::from_str(&json).unwrap();
+    assert_eq!(events, deserialized);
+}
+
+#[test]
+fn escaped_strings() {
+    let storage = Storage::new();
+    let config = ParserConfig {
+        unknown_commands: UnknownCommandPolicy::RawText,
+        ..ParserConfig::default()
+    };
+    for input in [r#"\text{say "hi"} + \text{a\\b}"#, r"x + \foo"] {
+        let events = Parser::with_config(input, &storage, config)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let json = serde_json::to_string(&events).unwrap();
+        assert!(json.contains('\\'), "input: {input}");
+
+        let buffer: EventBuffer = serde_json::from_str(&json).unwrap();
+        assert!(buffer.iter().eq(events.iter().cloned()), "input: {input}");
+        assert_eq!(serde_json::to_string(&buffer).unwrap(), json);
+        let buffer: EventBuffer = serde_json::from_reader(json.as_bytes()).unwrap();
+        assert!(buffer.iter().eq(events.iter().cloned()), "input: {input}");
+
+        let mut output = String::new();
+        push_mathml(&mut output, &buffer, RenderConfig::default()).unwrap();
+        assert_eq!(output, mathml(&events), "input: {input}");
+    }
+}
+
+#[test]
+fn stable_representation() {
+    let storage = Storage::new();
+    let events = Parser::new(r"x^{2} \leq 1.5", &storage)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        serde_json::to_string(&events).unwrap(),
+        concat!(
+            r#"[{"Script":{"ty":"Superscript","position":"Right"}},"#,
+            r#"{"Content":{"Ordinary":{"content":"x","stretchy":false}}},"#,
+            r#"{"Begin":"Normal"},{"Content":{"Number":"2"}},"End","#,
+            r#"{"Content":{"Relation":{"content":{"content":["≤",null]},"small":false}}},"#,
+            r#"{"Content":{"Number":"1.5"}}]"#,
+        )
+    );
+}